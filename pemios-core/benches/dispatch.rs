@@ -0,0 +1,46 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// This Source Code Form is "Incompatible With Secondary Licenses", as
+// defined by the Mozilla Public License, v. 2.0.
+//
+// Copyright © 2022 mumblingdrunkard
+
+//! Measures instruction dispatch on the inner loop of the fib test program.
+//!
+//! Compare the match-based and the direct-threaded dispatch with
+//! `cargo +nightly bench -p pemios-core --bench dispatch` and
+//! `cargo +nightly bench -p pemios-core --bench dispatch --features threaded`.
+
+#![allow(incomplete_features)]
+#![feature(generic_const_exprs, test)]
+
+extern crate test;
+
+use std::sync::atomic::AtomicU32;
+
+use pemios_core::{
+    bus::Bus,
+    hart::{step::RunResult, Hart, Reg},
+};
+use test::Bencher;
+
+#[bench]
+fn fib(b: &mut Bencher) {
+    let program = std::fs::read("resources/test_programs/fib").unwrap();
+    let bus = Bus::builder().with_main_memory(2).build();
+    bus.set_mm(&program).unwrap();
+
+    let reservation = AtomicU32::new(u32::MAX);
+    let mut hart = Hart::new(&bus, &reservation);
+    hart.reg[Reg::SP] = 0x1000;
+
+    b.iter(|| {
+        // start over once fib is done
+        if let RunResult::Trapped { .. } = hart.run(Some(10_000)) {
+            hart.pc = 0;
+            hart.reg[Reg::SP] = 0x1000;
+        }
+    });
+}
@@ -0,0 +1,66 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// This Source Code Form is "Incompatible With Secondary Licenses", as
+// defined by the Mozilla Public License, v. 2.0.
+//
+// Copyright © 2022 mumblingdrunkard
+
+//! Measures instruction fetch through the instruction cache, both for code
+//! that misses on every fetch and for a tight loop that always hits.
+//!
+//! Run with `cargo +nightly bench -p pemios-core`.
+
+#![allow(incomplete_features)]
+#![feature(generic_const_exprs, test)]
+
+extern crate test;
+
+use std::sync::atomic::AtomicU32;
+
+use pemios_core::{bus::Bus, hart::Hart};
+use test::Bencher;
+
+/// Twice the size of the instruction cache in 64-byte lines
+const LINES: usize = 1024;
+
+const BEQ_NEXT_LINE: u32 = 0x04000063; // beq x0, x0, 64
+const JUMP_TO_START: u32 = 0x00000067; // jalr x0, 0(x0)
+const ADDI: u32 = 0x00150513; // addi a0, a0, 1
+
+fn bench_program(b: &mut Bencher, program: &[u32], steps: usize) {
+    let bytes = program
+        .iter()
+        .flat_map(|i| i.to_le_bytes())
+        .collect::<Vec<_>>();
+
+    let bus = Bus::builder()
+        .with_main_memory((bytes.len() as u32).div_ceil(0x1000))
+        .build();
+    bus.set_mm(&bytes).unwrap();
+
+    let reservation = AtomicU32::new(u32::MAX);
+    let mut hart = Hart::new(&bus, &reservation);
+
+    b.iter(|| hart.run(Some(steps)));
+}
+
+/// Branches from one line to the next, so every fetch misses
+#[bench]
+fn sparse(b: &mut Bencher) {
+    let mut program = vec![0; LINES * 16];
+    (0..LINES - 1).for_each(|l| program[l * 16] = BEQ_NEXT_LINE);
+    program[(LINES - 1) * 16] = JUMP_TO_START;
+
+    bench_program(b, &program, LINES);
+}
+
+/// Runs straight through a loop that fits in the cache, so every fetch hits
+#[bench]
+fn dense(b: &mut Bencher) {
+    let mut program = vec![ADDI; LINES];
+    program[LINES - 1] = JUMP_TO_START;
+
+    bench_program(b, &program, LINES);
+}
@@ -0,0 +1,67 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// This Source Code Form is "Incompatible With Secondary Licenses", as
+// defined by the Mozilla Public License, v. 2.0.
+//
+// Copyright © 2022 mumblingdrunkard
+
+//! Compares the locked and the atomic main memory backings on a
+//! read-dominated loop, both alone and with several threads sharing a frame.
+//!
+//! Run with `cargo +nightly bench -p pemios-core`.
+
+#![feature(test)]
+
+extern crate test;
+
+use pemios_core::memory::{atomic_main::AtomicMain, main::Main, mapping::Mapping};
+use test::{black_box, Bencher};
+
+const THREADS: usize = 4;
+
+/// Sums the first frame word by word, storing to one word in every 16
+fn read_mostly<'a, M: Mapping<'a>>(m: &M) -> u32 {
+    let mut sum = 0u32;
+    for i in 0..0x400 {
+        let word = m.load_word(i * 4).unwrap();
+        sum = sum.wrapping_add(word);
+        if i & 0xf == 0 {
+            m.store_word(i * 4, word.wrapping_add(1)).unwrap();
+        }
+    }
+    sum
+}
+
+fn shared<'a, M: Mapping<'a> + Sync>(b: &mut Bencher, m: &M) {
+    b.iter(|| {
+        std::thread::scope(|s| {
+            for _ in 0..THREADS {
+                s.spawn(|| black_box(read_mostly(m)));
+            }
+        })
+    });
+}
+
+#[bench]
+fn locked(b: &mut Bencher) {
+    let m = Main::new(0, 1);
+    b.iter(|| black_box(read_mostly(&m)));
+}
+
+#[bench]
+fn atomic(b: &mut Bencher) {
+    let m = AtomicMain::new(0, 1);
+    b.iter(|| black_box(read_mostly(&m)));
+}
+
+#[bench]
+fn locked_shared(b: &mut Bencher) {
+    shared(b, &Main::new(0, 1));
+}
+
+#[bench]
+fn atomic_shared(b: &mut Bencher) {
+    shared(b, &AtomicMain::new(0, 1));
+}
@@ -0,0 +1,334 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// This Source Code Form is "Incompatible With Secondary Licenses", as
+// defined by the Mozilla Public License, v. 2.0.
+//
+// Copyright © 2022 mumblingdrunkard
+
+//! A small assembler for building test programs without an external toolchain.
+//!
+//! Instructions are appended with one method per mnemonic, and branches and jumps refer to
+//! [`Label`]s that are resolved when the program is assembled.
+//!
+//! ```
+//! use pemios_core::{asm::Assembler, hart::Reg};
+//!
+//! let mut asm = Assembler::new();
+//! let done = asm.label();
+//! asm.beq(Reg::A0, Reg::ZERO, done)
+//!     .addi(Reg::A0, Reg::A0, -1)
+//!     .bind(done)
+//!     .ebreak();
+//!
+//! assert_eq!(asm.assemble(), [0x00050463, 0xfff50513, 0x00100073]);
+//! ```
+
+use crate::hart::{
+    csr::Csr,
+    instruction::{Instruction, InstructionKind},
+    Reg,
+};
+
+/// A position in the program, which may be referred to before it is bound
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Label(usize);
+
+#[derive(Clone, Copy, Debug)]
+enum Item {
+    Done(Instruction),
+    Branch {
+        kind: InstructionKind,
+        rs1: Reg,
+        rs2: Reg,
+        target: Label,
+    },
+    Jal {
+        rd: Reg,
+        target: Label,
+    },
+}
+
+#[derive(Debug, Default)]
+pub struct Assembler {
+    items: Vec<Item>,
+    labels: Vec<Option<usize>>,
+}
+
+impl Assembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a new label that has to be bound with [`Assembler::bind`] before assembling
+    pub fn label(&mut self) -> Label {
+        self.labels.push(None);
+        Label(self.labels.len() - 1)
+    }
+
+    /// Binds `label` to the next instruction
+    pub fn bind(&mut self, label: Label) -> &mut Self {
+        assert!(self.labels[label.0].is_none(), "label bound twice");
+        self.labels[label.0] = Some(self.items.len());
+        self
+    }
+
+    /// Appends an already built instruction
+    pub fn instruction(&mut self, instruction: Instruction) -> &mut Self {
+        self.items.push(Item::Done(instruction));
+        self
+    }
+
+    /// Resolves all labels and encodes the program, which is assumed to start at address 0.
+    ///
+    /// Panics if a label that is used was never bound.
+    pub fn assemble(&self) -> Vec<u32> {
+        self.items
+            .iter()
+            .enumerate()
+            .map(|(i, item)| {
+                let offset = |target: Label| {
+                    let target = self.labels[target.0].expect("label was never bound");
+                    (target as i32 - i as i32) * 4
+                };
+
+                match *item {
+                    Item::Done(instruction) => instruction,
+                    Item::Branch {
+                        kind,
+                        rs1,
+                        rs2,
+                        target,
+                    } => {
+                        let imm = offset(target).into();
+                        match kind {
+                            InstructionKind::Beq => Instruction::Beq { rs1, rs2, imm },
+                            InstructionKind::Bne => Instruction::Bne { rs1, rs2, imm },
+                            InstructionKind::Blt => Instruction::Blt { rs1, rs2, imm },
+                            InstructionKind::Bge => Instruction::Bge { rs1, rs2, imm },
+                            InstructionKind::Bltu => Instruction::Bltu { rs1, rs2, imm },
+                            InstructionKind::Bgeu => Instruction::Bgeu { rs1, rs2, imm },
+                            _ => unreachable!(),
+                        }
+                    }
+                    Item::Jal { rd, target } => Instruction::Jal {
+                        rd,
+                        imm: offset(target).into(),
+                    },
+                }
+                .encode()
+            })
+            .collect()
+    }
+
+    /// Assembles the program into little-endian bytes that can be written to memory
+    pub fn assemble_bytes(&self) -> Vec<u8> {
+        self.assemble()
+            .iter()
+            .flat_map(|i| i.to_le_bytes())
+            .collect()
+    }
+
+    fn branch(&mut self, kind: InstructionKind, rs1: Reg, rs2: Reg, target: Label) -> &mut Self {
+        self.items.push(Item::Branch {
+            kind,
+            rs1,
+            rs2,
+            target,
+        });
+        self
+    }
+}
+
+macro_rules! upper {
+    ($($name:ident => $variant:ident),* $(,)?) => {
+        impl Assembler {
+            $(
+                /// `imm` is the value of the upper 20 bits
+                pub fn $name(&mut self, rd: Reg, imm: u32) -> &mut Self {
+                    let imm = (imm << 12) as i32;
+                    self.instruction(Instruction::$variant { rd, imm: imm.into() })
+                }
+            )*
+        }
+    };
+}
+
+macro_rules! branch {
+    ($($name:ident => $variant:ident),* $(,)?) => {
+        impl Assembler {
+            $(
+                pub fn $name(&mut self, rs1: Reg, rs2: Reg, target: Label) -> &mut Self {
+                    self.branch(InstructionKind::$variant, rs1, rs2, target)
+                }
+            )*
+        }
+    };
+}
+
+macro_rules! imm {
+    ($($name:ident => $variant:ident),* $(,)?) => {
+        impl Assembler {
+            $(
+                pub fn $name(&mut self, rd: Reg, rs1: Reg, imm: i32) -> &mut Self {
+                    self.instruction(Instruction::$variant { rd, rs1, imm: imm.into() })
+                }
+            )*
+        }
+    };
+}
+
+macro_rules! store {
+    ($($name:ident => $variant:ident),* $(,)?) => {
+        impl Assembler {
+            $(
+                pub fn $name(&mut self, rs2: Reg, rs1: Reg, imm: i32) -> &mut Self {
+                    self.instruction(Instruction::$variant { rs1, rs2, imm: imm.into() })
+                }
+            )*
+        }
+    };
+}
+
+macro_rules! shift {
+    ($($name:ident => $variant:ident),* $(,)?) => {
+        impl Assembler {
+            $(
+                pub fn $name(&mut self, rd: Reg, rs1: Reg, shamt: u32) -> &mut Self {
+                    self.instruction(Instruction::$variant { rd, rs1, shamt: shamt.into() })
+                }
+            )*
+        }
+    };
+}
+
+macro_rules! reg {
+    ($($name:ident => $variant:ident),* $(,)?) => {
+        impl Assembler {
+            $(
+                pub fn $name(&mut self, rd: Reg, rs1: Reg, rs2: Reg) -> &mut Self {
+                    self.instruction(Instruction::$variant { rd, rs1, rs2 })
+                }
+            )*
+        }
+    };
+}
+
+macro_rules! csr {
+    ($($name:ident => $variant:ident),* $(,)?) => {
+        impl Assembler {
+            $(
+                pub fn $name(&mut self, rd: Reg, csr: Csr, rs1: Reg) -> &mut Self {
+                    self.instruction(Instruction::$variant { rd, rs1, csr })
+                }
+            )*
+        }
+    };
+}
+
+macro_rules! csri {
+    ($($name:ident => $variant:ident),* $(,)?) => {
+        impl Assembler {
+            $(
+                pub fn $name(&mut self, rd: Reg, csr: Csr, uimm: u32) -> &mut Self {
+                    self.instruction(Instruction::$variant { rd, uimm: uimm.into(), csr })
+                }
+            )*
+        }
+    };
+}
+
+upper! { lui => Lui, auipc => Auipc }
+branch! { beq => Beq, bne => Bne, blt => Blt, bge => Bge, bltu => Bltu, bgeu => Bgeu }
+imm! {
+    jalr => Jalr,
+    lb => Lb, lh => Lh, lw => Lw, lbu => Lbu, lhu => Lhu,
+    addi => Addi, slti => Slti, sltiu => Sltiu, xori => Xori, ori => Ori, andi => Andi,
+}
+store! { sb => Sb, sh => Sh, sw => Sw }
+shift! { slli => Slli, srli => Srli, srai => Srai }
+reg! {
+    add => Add, sub => Sub, sll => Sll, slt => Slt, sltu => Sltu,
+    xor => Xor, srl => Srl, sra => Sra, or => Or, and => And,
+}
+csr! { csrrw => CsrRw, csrrs => CsrRs, csrrc => CsrRc }
+csri! { csrrwi => CsrRwi, csrrsi => CsrRsi, csrrci => CsrRci }
+
+impl Assembler {
+    pub fn jal(&mut self, rd: Reg, target: Label) -> &mut Self {
+        self.items.push(Item::Jal { rd, target });
+        self
+    }
+
+    pub fn ecall(&mut self) -> &mut Self {
+        self.instruction(Instruction::Ecall)
+    }
+
+    pub fn ebreak(&mut self) -> &mut Self {
+        self.instruction(Instruction::Ebreak)
+    }
+
+    pub fn mret(&mut self) -> &mut Self {
+        self.instruction(Instruction::Mret)
+    }
+
+    pub fn sret(&mut self) -> &mut Self {
+        self.instruction(Instruction::Sret)
+    }
+
+    /// `addi zero, zero, 0`
+    pub fn nop(&mut self) -> &mut Self {
+        self.addi(Reg::ZERO, Reg::ZERO, 0)
+    }
+
+    /// Loads a 12-bit signed immediate with `addi rd, zero, imm`
+    pub fn li(&mut self, rd: Reg, imm: i32) -> &mut Self {
+        self.addi(rd, Reg::ZERO, imm)
+    }
+
+    /// `jal zero, target`
+    pub fn j(&mut self, target: Label) -> &mut Self {
+        self.jal(Reg::ZERO, target)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::hart::{csr::Csr, Reg};
+
+    use super::Assembler;
+
+    #[test]
+    fn labels() {
+        let mut asm = Assembler::new();
+        let top = asm.label();
+        let end = asm.label();
+        asm.bind(top)
+            .bge(Reg::A0, Reg::A1, end)
+            .addi(Reg::A0, Reg::A0, 1)
+            .nop()
+            .bne(Reg::A0, Reg::A1, top)
+            .bind(end)
+            .csrrw(Reg::ZERO, Csr::MScratch, Reg::A0);
+
+        assert_eq!(
+            asm.assemble(),
+            [
+                0x00b55863, // bge a0, a1, 16
+                0x00150513, // addi a0, a0, 1
+                0x00000013, // nop
+                0xfeb51ae3, // bne a0, a1, -12
+                0x34051073, // csrw mscratch, a0
+            ]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "never bound")]
+    fn unbound_label() {
+        let mut asm = Assembler::new();
+        let nowhere = asm.label();
+        asm.beq(Reg::A0, Reg::A0, nowhere).assemble();
+    }
+}
@@ -7,14 +7,24 @@
 //
 // Copyright © 2022 mumblingdrunkard
 
-use std::{collections::HashMap, sync::atomic::AtomicU32};
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    ops::Range,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Mutex, RwLock,
+    },
+};
 
 use fnv::{FnvHashMap, FnvHashSet};
 
 use crate::memory::{
-    self,
     main::Main,
-    mapping::{Mapping, MemoryError, MemoryResult, Properties, Reservability, SendSyncMapping},
+    mapping::{
+        AmoClass, Mapping, MemoryError, MemoryKind, MemoryResult, Pma, Properties, Reservability,
+        SendSyncMapping, StoreCallback,
+    },
 };
 
 #[derive(Debug)]
@@ -28,28 +38,88 @@ impl From<MemoryError> for BusError {
     }
 }
 
-pub struct Builder<'a> {
-    main: Option<Main<'a>>,
-    map: FnvHashMap<u32, (u32, &'a dyn SendSyncMapping<'a>)>,
+/// Whether a logged access read or wrote memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    Write,
 }
 
-impl<'a> Builder<'a> {
-    pub fn with_mapping(mut self, mapping: &'a dyn SendSyncMapping<'a>) -> Self {
-        let props = mapping.properties();
+/// An access that went through the bus
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccessEvent {
+    pub addr: u32,
 
-        // the range of frame numbers that are being mapped to
-        let range = (0..props.frame_count()).map(|i| props.base_frame() + i);
+    /// The width of the access in bytes, or the length of a block
+    pub width: u32,
 
-        // the mapping overlaps an already established mapping
-        let overlaps = range.clone().any(|i| self.map.contains_key(&i));
+    /// The value that was loaded or stored, or 0 for blocks
+    pub value: u32,
+    pub kind: AccessKind,
 
-        if overlaps {
-            panic!("Tried to build bus with overlapping mappings!");
-        }
+    /// The kind of the mapping that served the access
+    pub memory: MemoryKind,
+}
+
+/// Called with every access that goes through the bus
+///
+/// The logger may not borrow anything, as mappings on the bus may borrow the
+/// bus itself.
+pub type AccessLogger = Box<dyn FnMut(AccessEvent) + Send>;
+
+type MemoryMap<'a> = FnvHashMap<u32, (u32, &'a dyn SendSyncMapping<'a>)>;
 
-        let pairs = range.clone().map(|i| (i, (props.base_frame(), mapping)));
-        self.map.extend(pairs);
+/// Maps `mapping` at `base_frame` in `map`.
+///
+/// Panics if the mapping overlaps one that is already established.
+fn insert_mapping<'a>(
+    map: &mut MemoryMap<'a>,
+    base_frame: u32,
+    mapping: &'a dyn SendSyncMapping<'a>,
+) {
+    let frame_count = mapping.properties().frame_count();
+
+    // the range of frame numbers that are being mapped to
+    let range = (0..frame_count).map(|i| base_frame + i);
+
+    // the mapping overlaps an already established mapping
+    let overlaps = range.clone().any(|i| map.contains_key(&i));
+
+    if overlaps {
+        panic!("Tried to build bus with overlapping mappings!");
+    }
+
+    mapping.relocate(base_frame);
+    let pairs = range.clone().map(|i| (i, (base_frame, mapping)));
+    map.extend(pairs);
+}
 
+pub struct Builder<'a> {
+    /// The number of frames of main memory
+    main: Option<u32>,
+    /// The word main memory is filled with
+    poison: u32,
+    map: MemoryMap<'a>,
+}
+
+impl<'a> Builder<'a> {
+    /// Maps `mapping` at the base frame given by its properties.
+    pub fn with_mapping(self, mapping: &'a dyn SendSyncMapping<'a>) -> Self {
+        let base_frame = mapping.properties().base_frame();
+        self.with_mapping_at(base_frame, mapping)
+    }
+
+    /// Maps `mapping` at `base_frame` instead of the base frame given by its
+    /// properties, so several devices of the same kind can be placed at
+    /// different addresses.
+    ///
+    /// Panics if the mapping overlaps one that is already established.
+    pub fn with_mapping_at(
+        mut self,
+        base_frame: u32,
+        mapping: &'a dyn SendSyncMapping<'a>,
+    ) -> Self {
+        insert_mapping(&mut self.map, base_frame, mapping);
         self
     }
 
@@ -58,19 +128,30 @@ impl<'a> Builder<'a> {
             panic!("Tried to build bus with main memory twice!");
         }
 
-        self.main.replace(Main::new(0, frame_count));
+        self.main.replace(frame_count);
+
+        self
+    }
 
+    /// Fills main memory with `pattern` instead of zeros, so that programs
+    /// reading memory they never wrote are easier to spot.
+    ///
+    /// Only main memory is affected, not the mappings.
+    pub fn with_poisoned_memory(mut self, pattern: u32) -> Self {
+        self.poison = pattern;
         self
     }
 
     pub fn build(self) -> Bus<'a> {
-        if self.main.is_none() {
+        let Some(frame_count) = self.main else {
             panic!("Tried to build bus without main memory!")
-        }
+        };
 
         Bus {
-            main: self.main.unwrap(),
-            map: self.map,
+            main: Main::poisoned(0, frame_count, self.poison),
+            map: RwLock::new(self.map),
+            logging: AtomicBool::new(false),
+            logger: Mutex::new(None),
         }
     }
 }
@@ -99,17 +180,30 @@ pub struct Bus<'a> {
     /// at different frame numbers may have different implementations.
     /// We also require that these mappings are safe to interact with across
     /// threads, hence the &'a dyn SendSyncMapping.
-    map: FnvHashMap<u32, (u32, &'a dyn SendSyncMapping<'a>)>,
+    ///
+    /// Mappings can be added and removed after the bus is built, so the map
+    /// is behind a lock.
+    map: RwLock<MemoryMap<'a>>,
+
+    /// Whether `logger` is set, so accesses don't take the lock when it isn't.
+    logging: AtomicBool,
+    logger: Mutex<Option<AccessLogger>>,
 }
 
 impl<'a> Bus<'a> {
     pub fn builder() -> Builder<'a> {
         Builder {
             main: None,
+            poison: 0,
             map: HashMap::default(),
         }
     }
 
+    /// The main memory segment at address 0
+    pub fn main_memory(&self) -> &Main<'a> {
+        &self.main
+    }
+
     pub fn main_memory_size(&self) -> u32 {
         self.main.properties().frame_count() * 4096
     }
@@ -117,44 +211,374 @@ impl<'a> Bus<'a> {
     pub fn set_mm(&self, data: &[u8]) -> MemoryResult<usize> {
         self.main.block_write(0, data)
     }
-}
 
-impl<'a> Mapping<'a> for Bus<'a> {
-    fn block_write(&self, offset: u32, src: &[u8]) -> MemoryResult<usize> {
-        if offset & 0x80000000 == 0 {
-            self.main.block_write(offset, src)
+    /// Reads the `len` bytes at `addr` through the block-read path, so regions
+    /// backed by devices are included.
+    ///
+    /// Fails with `OutOfBoundsAccess` if any of them are not mapped.
+    pub fn read_range(&self, addr: u32, len: usize) -> MemoryResult<Vec<u8>> {
+        let mut buf = vec![0; len];
+        let read = self.block_read(addr, &mut buf)?;
+        if read < len {
+            return Err(MemoryError::OutOfBoundsAccess {
+                offset: addr.wrapping_add(read as u32),
+            });
+        }
+
+        Ok(buf)
+    }
+
+    /// Formats the `len` bytes at `addr` like `xxd`, with 16 bytes per line
+    /// in groups of two, followed by their printable ASCII characters.
+    ///
+    /// Bytes that cannot be read are shown as `--`.
+    pub fn hexdump(&self, addr: u32, len: usize) -> String {
+        let mut out = String::new();
+        for line in (0..len).step_by(16) {
+            let start = addr.wrapping_add(line as u32);
+            let count = std::cmp::min(16, len - line);
+            let bytes = match self.read_range(start, count) {
+                Ok(bytes) => bytes.into_iter().map(Some).collect(),
+                Err(_) => (start..)
+                    .take(count)
+                    .map(|addr| self.read_range(addr, 1).ok().map(|b| b[0]))
+                    .collect::<Vec<_>>(),
+            };
+
+            let mut hex = String::new();
+            let mut ascii = String::new();
+            for (i, byte) in bytes.into_iter().enumerate() {
+                if i % 2 == 0 {
+                    hex.push(' ');
+                }
+
+                match byte {
+                    Some(b) => {
+                        hex += &format!("{b:02x}");
+                        ascii.push(if b.is_ascii_graphic() || b == b' ' {
+                            b as char
+                        } else {
+                            '.'
+                        });
+                    }
+                    None => {
+                        hex += "--";
+                        ascii.push('.');
+                    }
+                }
+            }
+
+            out += &format!("{start:08x}:{hex:<40}  {ascii}\n");
+        }
+
+        out
+    }
+
+    fn map(&self) -> std::sync::RwLockReadGuard<'_, MemoryMap<'a>> {
+        self.map.read().expect(
+            "Tried to read the memory map, but the lock was poisoned.\
+Did a thread exit unexpectedly while holding this RwLock?",
+        )
+    }
+
+    /// Sets the logger that is called with every load, store, block, and stream
+    /// access that goes through the bus, or removes it with `None`.
+    ///
+    /// Accesses are logged after they succeed.
+    /// Harts access memory through their caches, so a cached access is only
+    /// seen here when the line is filled or written back.
+    pub fn set_access_logger(&self, logger: Option<AccessLogger>) {
+        let mut guard = self.logger.lock().expect(
+            "Tried to set the access logger, but the lock was poisoned.\
+Did a thread exit unexpectedly while holding this Mutex?",
+        );
+        self.logging.store(logger.is_some(), Ordering::Relaxed);
+        *guard = logger;
+    }
+
+    /// Passes an access to the logger, if there is one.
+    fn log(
+        &self,
+        addr: u32,
+        width: u32,
+        value: u32,
+        kind: AccessKind,
+        mapping: &dyn SendSyncMapping<'a>,
+    ) {
+        if !self.logging.load(Ordering::Relaxed) {
+            return;
+        }
+
+        let mut guard = self.logger.lock().expect(
+            "Tried to log an access, but the lock was poisoned.\
+Did a thread exit unexpectedly while holding this Mutex?",
+        );
+        if let Some(logger) = guard.as_mut() {
+            logger(AccessEvent {
+                addr,
+                width,
+                value,
+                kind,
+                memory: mapping.attributes().kind(),
+            });
+        }
+    }
+
+    /// Finds the mapping that owns frame `frame_number` along with the frame
+    /// number it is based at.
+    fn mapping_at(&self, frame_number: u32) -> Option<(u32, &'a dyn SendSyncMapping<'a>)> {
+        self.map().get(&frame_number).copied()
+    }
+
+    /// Finds the mapping that owns `addr` along with the offset of `addr` local
+    /// to that mapping, or `None` if `addr` is in an unmapped hole.
+    ///
+    /// The lower half of the address space belongs to main memory.
+    fn resolve(&self, addr: u32) -> Option<(&dyn SendSyncMapping<'a>, u32)> {
+        if addr & 0x80000000 == 0 {
+            Some((&self.main, addr))
         } else {
-            todo!("Block write to a mapping")
+            self.mapping_at(addr >> 12)
+                .map(|(base, mapping)| (mapping, addr - (base << 12)))
         }
     }
 
-    fn block_write_masked(&self, _offset: u32, _src: &[u8], _mask: &[u8]) -> MemoryResult<usize> {
-        todo!()
+    /// Maps `mapping` at `base_frame` on a bus that is already built, e.g. to
+    /// plug in a device while harts are running.
+    ///
+    /// Reservation sets registered on the bus earlier are not registered on
+    /// `mapping`.
+    ///
+    /// Panics if the mapping overlaps one that is already established.
+    pub fn add_mapping(&self, base_frame: u32, mapping: &'a dyn SendSyncMapping<'a>) {
+        let mut map = self.map.write().expect(
+            "Tried to write the memory map, but the lock was poisoned.\
+Did a thread exit unexpectedly while holding this RwLock?",
+        );
+        insert_mapping(&mut map, base_frame, mapping);
     }
 
-    fn block_read(&self, offset: u32, dst: &mut [u8]) -> MemoryResult<usize> {
-        if offset & 0x80000000 == 0 {
-            self.main.block_read(offset, dst)
+    /// Unmaps the mapping based at `base_frame`, returning it, or `None` if no
+    /// mapping is based there.
+    ///
+    /// Harts may still hold stores to the mapping in their caches and
+    /// write-combining buffers.
+    /// Remove mappings through `Hart::remove_mapping` so they are written back
+    /// first, and make sure other harts that used the mapping have been
+    /// stopped and written back.
+    pub fn remove_mapping(&self, base_frame: u32) -> Option<&'a dyn SendSyncMapping<'a>> {
+        let mut map = self.map.write().expect(
+            "Tried to write the memory map, but the lock was poisoned.\
+Did a thread exit unexpectedly while holding this RwLock?",
+        );
+        let &(base, mapping) = map.get(&base_frame)?;
+        if base != base_frame {
+            return None;
+        }
+
+        map.retain(|_, &mut (base, _)| base != base_frame);
+        Some(mapping)
+    }
+
+    /// The attributes of the memory at `addr`, or `None` if nothing is mapped
+    /// there.
+    pub fn attributes_at(&self, addr: u32) -> Option<Pma> {
+        self.resolve(addr).map(|(mapping, _)| mapping.attributes())
+    }
+
+    /// Copies `len` bytes from `src` to `dst`, e.g. for a DMA controller,
+    /// returning the number of bytes written.
+    ///
+    /// The regions may overlap and may span several mappings.
+    /// The copy is done a frame at a time through a buffer, starting from the
+    /// end when `dst` is above `src`, so the result is as if the whole source
+    /// had been read before anything was written.
+    /// The writes invalidate reservations over `dst` like any block write.
+    pub fn block_copy(&self, dst: u32, src: u32, len: usize) -> MemoryResult<usize> {
+        const CHUNK: usize = 0x1000;
+
+        let mut buf = [0u8; CHUNK];
+        let chunk = |written: usize, at: usize| -> MemoryResult<usize> {
+            let n = std::cmp::min(CHUNK, len - at);
+            let buf = &mut buf[..n];
+            self.block_read(src.wrapping_add(at as u32), buf)?;
+            Ok(written + self.block_write(dst.wrapping_add(at as u32), buf)?)
+        };
+
+        let mut chunks = (0..len).step_by(CHUNK);
+        if dst > src {
+            chunks.rev().try_fold(0, chunk)
         } else {
-            todo!("Block read from a mapping")
+            chunks.try_fold(0, chunk)
+        }
+    }
+
+    /// Finds the mapping that an access of width `W` at `offset` goes to, along
+    /// with the offset local to that mapping.
+    ///
+    /// Misaligned accesses are rejected here, before they reach a mapping.
+    fn access<const W: u32, const STORE: bool>(
+        &self,
+        offset: u32,
+    ) -> MemoryResult<(&dyn SendSyncMapping<'a>, u32)> {
+        if offset & (W - 1) != 0 {
+            return Err(if STORE {
+                MemoryError::StoreMisaligned {
+                    offset,
+                    alignment: W,
+                }
+            } else {
+                MemoryError::LoadMisaligned {
+                    offset,
+                    alignment: W,
+                }
+            });
         }
+
+        self.resolve(offset)
+            .ok_or(MemoryError::OutOfBoundsAccess { offset })
     }
 
-    fn block_read_masked(
+    /// Performs an atomic memory operation of class `class` at `offset`, if the
+    /// targeted mapping supports it.
+    fn amo<F>(&self, offset: u32, class: AmoClass, op: F) -> MemoryResult<u32>
+    where
+        F: FnOnce(&dyn SendSyncMapping<'a>, u32) -> MemoryResult<u32>,
+    {
+        if offset & 3 != 0 {
+            return Err(MemoryError::AmoMisaligned { offset, amo: class });
+        }
+
+        let (mapping, local) = self.access::<4, true>(offset)?;
+        let amo = mapping.attributes().amo();
+        if amo < class {
+            return Err(MemoryError::AmoUnsupported { amo });
+        }
+
+        op(mapping, local)
+    }
+
+    /// Splits the block `[offset, offset + len)` wherever it crosses from one
+    /// mapping into another.
+    ///
+    /// `f` is called for each piece with the mapping owning it, the offset
+    /// local to that mapping, and the range of the block the piece covers.
+    /// Each piece is logged as a `kind` access.
+    /// Returns the sum of what `f` returns, or `OutOfBoundsAccess` if part of
+    /// the block lands in an unmapped hole.
+    fn split_block<F>(
         &self,
-        _offset: u32,
-        _dst: &mut [u8],
-        _mask: &[u8],
-    ) -> MemoryResult<usize> {
-        todo!()
+        offset: u32,
+        len: usize,
+        kind: AccessKind,
+        mut f: F,
+    ) -> MemoryResult<usize>
+    where
+        F: FnMut(&dyn SendSyncMapping<'a>, u32, Range<usize>) -> MemoryResult<usize>,
+    {
+        let mut done = 0;
+        let mut total = 0;
+
+        while done < len {
+            let addr = u32::try_from(offset as u64 + done as u64)
+                .map_err(|_| MemoryError::OutOfBoundsAccess { offset })?;
+
+            let (mapping, local) = self
+                .resolve(addr)
+                .ok_or(MemoryError::OutOfBoundsAccess { offset: addr })?;
+
+            // main memory owns the lower half no matter its size
+            let end = if addr & 0x80000000 == 0 {
+                0x80000000
+            } else {
+                (addr - local) as u64 + ((mapping.properties().frame_count() as u64) << 12)
+            };
+
+            let n = std::cmp::min((end - addr as u64) as usize, len - done);
+            total += f(mapping, local, done..done + n)?;
+            self.log(addr, n as u32, 0, kind, mapping);
+            done += n;
+        }
+
+        Ok(total)
+    }
+}
+
+/// The bits of `mask` that cover the bytes in `range` of a masked block,
+/// shifted so that bit 0 covers the first of them.
+///
+/// Pieces of a block that start on a byte boundary of the mask borrow it
+/// instead of copying.
+fn slice_mask(mask: &[u8], range: Range<usize>) -> Cow<'_, [u8]> {
+    assert!(mask.len() * 8 >= range.end, "Mask is too short");
+
+    let bytes = &mask[range.start >> 3..];
+    let shift = range.start & 7;
+    if shift == 0 {
+        return Cow::Borrowed(bytes);
+    }
+
+    (0..range.len().div_ceil(8))
+        .map(|i| bytes[i] >> shift | bytes.get(i + 1).map_or(0, |b| b << (8 - shift)))
+        .collect()
+}
+
+impl<'a> Mapping<'a> for Bus<'a> {
+    fn block_write(&self, offset: u32, src: &[u8]) -> MemoryResult<usize> {
+        self.split_block(
+            offset,
+            src.len(),
+            AccessKind::Write,
+            |mapping, local, range| mapping.block_write(local, &src[range]),
+        )
+    }
+
+    fn block_write_masked(&self, offset: u32, src: &[u8], mask: &[u8]) -> MemoryResult<usize> {
+        self.split_block(
+            offset,
+            src.len(),
+            AccessKind::Write,
+            |mapping, local, range| {
+                let mask = slice_mask(mask, range.clone());
+                mapping.block_write_masked(local, &src[range], &mask)
+            },
+        )
+    }
+
+    fn block_read(&self, offset: u32, dst: &mut [u8]) -> MemoryResult<usize> {
+        self.split_block(
+            offset,
+            dst.len(),
+            AccessKind::Read,
+            |mapping, local, range| mapping.block_read(local, &mut dst[range]),
+        )
+    }
+
+    fn block_read_masked(&self, offset: u32, dst: &mut [u8], mask: &[u8]) -> MemoryResult<usize> {
+        self.split_block(
+            offset,
+            dst.len(),
+            AccessKind::Read,
+            |mapping, local, range| {
+                let mask = slice_mask(mask, range.clone());
+                mapping.block_read_masked(local, &mut dst[range], &mask)
+            },
+        )
     }
 
     fn stream_write(&self, frame_number: u32, writes: &[(u16, u8, u32)]) -> MemoryResult<usize> {
-        if frame_number & 0x00080000 == 0 {
-            self.main.stream_write(frame_number, writes)
-        } else {
-            todo!("Stream write to a mapping")
+        let (mapping, local) =
+            self.resolve(frame_number << 12)
+                .ok_or(MemoryError::OutOfBoundsAccess {
+                    offset: frame_number << 12,
+                })?;
+        let n = mapping.stream_write(local >> 12, writes)?;
+
+        for &(offset, width, value) in writes {
+            let addr = frame_number << 12 | offset as u32;
+            self.log(addr, width as u32, value, AccessKind::Write, mapping);
         }
+        Ok(n)
     }
 
     fn stream_read(
@@ -163,95 +587,155 @@ impl<'a> Mapping<'a> for Bus<'a> {
         reads: &[(u16 /* offset */, u8 /* width */)],
         dst: &mut [u32],
     ) -> MemoryResult<usize> {
-        if frame_number & 0x00080000 == 0 {
-            self.main.stream_read(frame_number, reads, dst)
-        } else {
-            todo!("Stream read from a mapping")
+        let (mapping, local) =
+            self.resolve(frame_number << 12)
+                .ok_or(MemoryError::OutOfBoundsAccess {
+                    offset: frame_number << 12,
+                })?;
+        let n = mapping.stream_read(local >> 12, reads, dst)?;
+
+        for (&(offset, width), &value) in reads.iter().zip(dst.iter()) {
+            let addr = frame_number << 12 | offset as u32;
+            self.log(addr, width as u32, value, AccessKind::Read, mapping);
         }
+        Ok(n)
     }
 
-    fn store_byte(&self, _offset: u32, _byte: u8) -> MemoryResult<()> {
-        todo!()
+    fn store_byte(&self, offset: u32, byte: u8) -> MemoryResult<()> {
+        let (mapping, local) = self.access::<1, true>(offset)?;
+        mapping.store_byte(local, byte)?;
+        self.log(offset, 1, byte as u32, AccessKind::Write, mapping);
+        Ok(())
     }
 
-    fn store_half_word(&self, _offset: u32, _jalf_word: u16) -> MemoryResult<()> {
-        todo!()
+    fn store_half_word(&self, offset: u32, half_word: u16) -> MemoryResult<()> {
+        let (mapping, local) = self.access::<2, true>(offset)?;
+        mapping.store_half_word(local, half_word)?;
+        self.log(offset, 2, half_word as u32, AccessKind::Write, mapping);
+        Ok(())
     }
 
-    fn store_word(&self, _offset: u32, _word: u32) -> MemoryResult<()> {
-        todo!()
+    fn store_word(&self, offset: u32, word: u32) -> MemoryResult<()> {
+        let (mapping, local) = self.access::<4, true>(offset)?;
+        mapping.store_word(local, word)?;
+        self.log(offset, 4, word, AccessKind::Write, mapping);
+        Ok(())
     }
 
-    fn load_byte(&self, _offset: u32) -> MemoryResult<u8> {
-        todo!()
+    fn load_byte(&self, offset: u32) -> MemoryResult<u8> {
+        let (mapping, local) = self.access::<1, false>(offset)?;
+        let value = mapping.load_byte(local)?;
+        self.log(offset, 1, value as u32, AccessKind::Read, mapping);
+        Ok(value)
     }
 
-    fn load_half_word(&self, _offset: u32) -> MemoryResult<u16> {
-        todo!()
+    fn load_half_word(&self, offset: u32) -> MemoryResult<u16> {
+        let (mapping, local) = self.access::<2, false>(offset)?;
+        let value = mapping.load_half_word(local)?;
+        self.log(offset, 2, value as u32, AccessKind::Read, mapping);
+        Ok(value)
     }
 
-    fn load_word(&self, _offset: u32) -> MemoryResult<u32> {
-        todo!()
+    fn load_word(&self, offset: u32) -> MemoryResult<u32> {
+        let (mapping, local) = self.access::<4, false>(offset)?;
+        let value = mapping.load_word(local)?;
+        self.log(offset, 4, value, AccessKind::Read, mapping);
+        Ok(value)
     }
 
+    /// Fails the store without performing it if the mapping at `offset` does
+    /// not support reservations.
     fn store_conditional(
         &self,
-        _offset: u32,
-        _src: u32,
-        _reservation: &AtomicU32,
-        _should_be: u32,
+        offset: u32,
+        src: u32,
+        reservation: &AtomicU32,
+        should_be: u32,
     ) -> MemoryResult<u32> {
-        todo!()
+        let (mapping, local) = self.access::<4, true>(offset)?;
+        if mapping.attributes().reservability() == Reservability::None {
+            return Ok(1);
+        }
+
+        mapping.store_conditional(local, src, reservation, should_be)
     }
 
-    fn amoswap_w(&self, _offset: u32, _src: u32) -> MemoryResult<u32> {
-        todo!()
+    fn amoswap_w(&self, offset: u32, src: u32) -> MemoryResult<u32> {
+        self.amo(offset, AmoClass::Swap, |m, offset| m.amoswap_w(offset, src))
     }
 
-    fn amoadd_w(&self, _offset: u32, _src: u32) -> MemoryResult<u32> {
-        todo!()
+    fn amoadd_w(&self, offset: u32, src: u32) -> MemoryResult<u32> {
+        self.amo(offset, AmoClass::Arithmetic, |m, offset| {
+            m.amoadd_w(offset, src)
+        })
     }
 
-    fn amoand_w(&self, _offset: u32, _src: u32) -> MemoryResult<u32> {
-        todo!()
+    fn amoand_w(&self, offset: u32, src: u32) -> MemoryResult<u32> {
+        self.amo(offset, AmoClass::Logical, |m, offset| {
+            m.amoand_w(offset, src)
+        })
     }
 
-    fn amoor_w(&self, _offset: u32, _src: u32) -> MemoryResult<u32> {
-        todo!()
+    fn amoor_w(&self, offset: u32, src: u32) -> MemoryResult<u32> {
+        self.amo(offset, AmoClass::Logical, |m, offset| {
+            m.amoor_w(offset, src)
+        })
     }
 
-    fn amoxor_w(&self, _offset: u32, _src: u32) -> MemoryResult<u32> {
-        todo!()
+    fn amoxor_w(&self, offset: u32, src: u32) -> MemoryResult<u32> {
+        self.amo(offset, AmoClass::Logical, |m, offset| {
+            m.amoxor_w(offset, src)
+        })
     }
 
-    fn amomax_w(&self, _offset: u32, _src: u32) -> MemoryResult<u32> {
-        todo!()
+    fn amomax_w(&self, offset: u32, src: u32) -> MemoryResult<u32> {
+        self.amo(offset, AmoClass::Arithmetic, |m, offset| {
+            m.amomax_w(offset, src)
+        })
     }
 
-    fn amomaxu_w(&self, _offset: u32, _src: u32) -> MemoryResult<u32> {
-        todo!()
+    fn amomaxu_w(&self, offset: u32, src: u32) -> MemoryResult<u32> {
+        self.amo(offset, AmoClass::Arithmetic, |m, offset| {
+            m.amomaxu_w(offset, src)
+        })
     }
 
-    fn amomin_w(&self, _offset: u32, _src: u32) -> MemoryResult<u32> {
-        todo!()
+    fn amomin_w(&self, offset: u32, src: u32) -> MemoryResult<u32> {
+        self.amo(offset, AmoClass::Arithmetic, |m, offset| {
+            m.amomin_w(offset, src)
+        })
     }
 
-    fn amominu_w(&self, _offset: u32, _src: u32) -> MemoryResult<u32> {
-        todo!()
+    fn amominu_w(&self, offset: u32, src: u32) -> MemoryResult<u32> {
+        self.amo(offset, AmoClass::Arithmetic, |m, offset| {
+            m.amominu_w(offset, src)
+        })
     }
 
-    fn attributes(&self) -> memory::mapping::Pma {
-        todo!()
+    /// The attributes of main memory, which makes up most of the bus.
+    ///
+    /// Mappings may have other attributes, so use `attributes_at` to find the
+    /// attributes of a particular address.
+    fn attributes(&self) -> Pma {
+        self.main.attributes()
     }
 
+    /// Spans from address 0 to the end of main memory or of the highest
+    /// mapping, whichever is further.
     fn properties(&self) -> Properties {
-        Properties::new(0, 0xfffff)
+        let frame_count = self
+            .map()
+            .keys()
+            .fold(self.main.properties().frame_count(), |count, &frame| {
+                count.max(frame + 1)
+            });
+        Properties::new(0, frame_count)
     }
 
     fn register_reservation_set(&'a self, set: &'a AtomicU32) {
         self.main.register_reservation_set(set);
         let mut seen = FnvHashSet::default();
-        self.map
+        self.map()
             .iter()
             .filter(|(_, (base, mapping))| {
                 let contains = seen.contains(base);
@@ -262,4 +746,457 @@ impl<'a> Mapping<'a> for Bus<'a> {
                 mapping.register_reservation_set(set);
             });
     }
+
+    /// Registers `callback` on main memory only, as one callback can not be
+    /// shared between mappings.
+    /// Callbacks for other mappings should be registered on them directly.
+    fn register_store_callback(&'a self, callback: StoreCallback<'a>) {
+        self.main.register_store_callback(callback);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use crate::{
+        hart::mmu::addr_to_reservation_set,
+        memory::{
+            endian::{read_bytes, MemoryOrder, BIG_ENDIAN},
+            main::Main,
+            mapping::{AmoClass, Mapping, MemoryError, MemoryResult, Pma},
+            test_device::{Access, TestDevice},
+        },
+    };
+
+    use super::Bus;
+
+    #[test]
+    fn block_ops_on_mappings() -> MemoryResult<()> {
+        let low = Main::new(0x80000, 1);
+        let high = Main::new(0x80001, 2);
+        let bus = Bus::builder()
+            .with_main_memory(1)
+            .with_mapping(&low)
+            .with_mapping(&high)
+            .build();
+
+        // crosses from `low` into `high`, and from the first frame of `high`
+        // into the second
+        let src = (0..0x1100).map(|i| i as u8).collect::<Vec<_>>();
+        bus.block_write(0x80000f80, &src)?;
+
+        assert_eq!(low.load_word(0xf80)?, read_bytes(&[0, 1, 2, 3]));
+        assert_eq!(high.load_word(0x0)?, read_bytes(&[0x80, 0x81, 0x82, 0x83]));
+        assert_eq!(
+            high.load_word(0x1000)?,
+            read_bytes(&[0x80, 0x81, 0x82, 0x83])
+        );
+
+        let mut dst = vec![0; src.len()];
+        bus.block_read(0x80000f80, &mut dst)?;
+        assert_eq!(dst, src);
+        Ok(())
+    }
+
+    #[test]
+    fn hexdump() {
+        let bus = Bus::builder().with_main_memory(1).build();
+        let mut data = b"\x7fELF\x01\x01\x01\0\0\0\0\0\0\0\0\0hello, world".to_vec();
+        data.splice(0..0, [0; 0x20]);
+        bus.set_mm(&data).unwrap();
+
+        assert_eq!(bus.read_range(0x24, 4).unwrap(), [1, 1, 1, 0]);
+        assert!(matches!(
+            bus.read_range(0xffe, 4),
+            Err(MemoryError::OutOfBoundsAccess { offset: 0x1000 })
+        ));
+
+        assert_eq!(
+            bus.hexdump(0x20, 0x1c),
+            "00000020: 7f45 4c46 0101 0100 0000 0000 0000 0000  .ELF............\n\
+             00000030: 6865 6c6c 6f2c 2077 6f72 6c64            hello, world\n"
+        );
+        assert_eq!(
+            bus.hexdump(0xffd, 5),
+            "00000ffd: 0000 00-- --                             .....\n"
+        );
+    }
+
+    #[test]
+    fn block_ops_on_unmapped_holes() {
+        let device = Main::new(0x80000, 1);
+        let bus = Bus::builder()
+            .with_main_memory(1)
+            .with_mapping(&device)
+            .build();
+
+        let mut dst = [0; 8];
+        assert!(matches!(
+            bus.block_read(0x80000ffc, &mut dst),
+            Err(MemoryError::OutOfBoundsAccess { offset: 0x80001000 })
+        ));
+        assert!(matches!(
+            bus.block_write(0x90000000, &dst),
+            Err(MemoryError::OutOfBoundsAccess { offset: 0x90000000 })
+        ));
+    }
+
+    #[test]
+    fn word_ops_on_mappings() -> MemoryResult<()> {
+        let device = TestDevice::new(0x80001, 1);
+        let bus = Bus::builder()
+            .with_main_memory(1)
+            .with_mapping(&device)
+            .build();
+
+        bus.store_word(0x80001010, 0xdeadbeef)?;
+        bus.store_half_word(0x80001014, 0xcafe)?;
+        bus.store_byte(0x80001016, 0x42)?;
+        assert_eq!(bus.load_word(0x80001010)?, 0xdeadbeef);
+        assert_eq!(bus.load_half_word(0x80001014)?, 0xcafe);
+        assert_eq!(bus.load_byte(0x80001016)?, 0x42);
+
+        assert_eq!(
+            device.take_log(),
+            [
+                Access::Store {
+                    offset: 0x10,
+                    width: 4,
+                    value: 0xdeadbeef
+                },
+                Access::Store {
+                    offset: 0x14,
+                    width: 2,
+                    value: 0xcafe
+                },
+                Access::Store {
+                    offset: 0x16,
+                    width: 1,
+                    value: 0x42
+                },
+                Access::Load {
+                    offset: 0x10,
+                    width: 4
+                },
+                Access::Load {
+                    offset: 0x14,
+                    width: 2
+                },
+                Access::Load {
+                    offset: 0x16,
+                    width: 1
+                },
+            ]
+        );
+
+        // main memory is still reachable
+        bus.store_word(0x10, 69)?;
+        assert_eq!(bus.load_word(0x10)?, 69);
+        Ok(())
+    }
+
+    #[test]
+    fn word_ops_faults() {
+        let device = TestDevice::new(0x80001, 1);
+        let bus = Bus::builder()
+            .with_main_memory(1)
+            .with_mapping(&device)
+            .build();
+
+        assert!(matches!(
+            bus.load_word(0x80000000),
+            Err(MemoryError::OutOfBoundsAccess { offset: 0x80000000 })
+        ));
+        assert!(matches!(
+            bus.store_word(0x80001002, 0),
+            Err(MemoryError::StoreMisaligned { .. })
+        ));
+        assert!(matches!(
+            bus.load_half_word(0x80001001),
+            Err(MemoryError::LoadMisaligned { .. })
+        ));
+        assert!(device.take_log().is_empty());
+    }
+
+    #[test]
+    fn mappings_at_explicit_bases() -> MemoryResult<()> {
+        // both claim the same frame, so only one could be mapped at its own base
+        let first = Main::new(0x80000, 1);
+        let second = Main::new(0x80000, 1);
+        let bus = Bus::builder()
+            .with_main_memory(1)
+            .with_mapping_at(0x80010, &first)
+            .with_mapping_at(0x80020, &second)
+            .build();
+
+        bus.store_word(0x80010008, 1)?;
+        bus.store_word(0x80020008, 2)?;
+        assert_eq!(first.load_word(0x8)?, 1);
+        assert_eq!(second.load_word(0x8)?, 2);
+        assert!(bus.load_word(0x80000008).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn relocated_reservations() -> MemoryResult<()> {
+        let device = Main::new(0x80000, 1);
+        let bus = Bus::builder()
+            .with_main_memory(1)
+            .with_mapping_at(0x80010, &device)
+            .build();
+        let reservation = AtomicU32::new(u32::MAX);
+        bus.register_reservation_set(&reservation);
+        let set = addr_to_reservation_set(0x80010040);
+
+        // harts name reservation sets by the address the memory is mapped at
+        reservation.store(set, Ordering::Relaxed);
+        bus.amoadd_w(0x80010044, 1)?;
+        assert_eq!(bus.store_conditional(0x80010040, 1, &reservation, set)?, 1);
+
+        reservation.store(set, Ordering::Relaxed);
+        bus.block_write(0x8001007c, &[1, 2])?;
+        assert_eq!(bus.store_conditional(0x80010040, 1, &reservation, set)?, 1);
+        assert_eq!(device.load_word(0x40)?, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn block_copy() -> MemoryResult<()> {
+        let device = Main::new(0x80000, 2);
+        let bus = Bus::builder()
+            .with_main_memory(2)
+            .with_mapping(&device)
+            .build();
+
+        let data = (0..0x1800).map(|i| i as u8).collect::<Vec<_>>();
+        let read = |addr, len| bus.read_range(addr, len).unwrap();
+
+        // from main memory into the device
+        bus.block_write(0x100, &data)?;
+        assert_eq!(bus.block_copy(0x80000200, 0x100, data.len())?, data.len());
+        assert_eq!(read(0x80000200, data.len()), data);
+
+        // overlapping, upwards and then back down
+        assert_eq!(bus.block_copy(0x180, 0x100, data.len())?, data.len());
+        assert_eq!(read(0x180, data.len()), data);
+        assert_eq!(bus.block_copy(0x100, 0x180, data.len())?, data.len());
+        assert_eq!(read(0x100, data.len()), data);
+
+        // byte 0xe00 of the data
+        assert_eq!(device.load_word(0x1000)?, read_bytes(&[0, 1, 2, 3]));
+
+        assert!(matches!(
+            bus.block_copy(0x100, 0x80001f00, 0x200),
+            Err(MemoryError::OutOfBoundsAccess { offset: 0x80002000 })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn resolve() {
+        let device = TestDevice::new(0x80010, 2);
+        let bus = Bus::builder()
+            .with_main_memory(1)
+            .with_mapping(&device)
+            .build();
+
+        // main memory owns the lower half even past its end
+        for addr in [0x123, 0x7fffffff] {
+            let (mapping, local) = bus.resolve(addr).unwrap();
+            assert!(std::ptr::addr_eq(mapping, bus.main_memory()));
+            assert_eq!(local, addr);
+        }
+
+        let (mapping, local) = bus.resolve(0x80011234).unwrap();
+        assert!(std::ptr::addr_eq(mapping, &device));
+        assert_eq!(local, 0x1234);
+
+        assert!(bus.resolve(0x80000000).is_none());
+        assert!(bus.resolve(0x80012000).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "overlapping")]
+    fn overlapping_mappings() {
+        let device = Main::new(0x80000, 2);
+        Bus::builder()
+            .with_main_memory(1)
+            .with_mapping_at(0x80010, &device)
+            .with_mapping_at(0x80011, &device);
+    }
+
+    #[test]
+    fn attributes() {
+        let device = TestDevice::with_attributes(0x80010, 2, Pma::io());
+        let bus = Bus::builder()
+            .with_main_memory(1)
+            .with_mapping(&device)
+            .build();
+
+        assert_eq!(bus.attributes_at(0x1234), Some(Pma::main()));
+        assert_eq!(bus.attributes_at(0x80010000), Some(Pma::io()));
+        assert_eq!(bus.attributes_at(0x80011ffc), Some(Pma::io()));
+        assert_eq!(bus.attributes_at(0x80012000), None);
+        assert_eq!(bus.attributes(), Pma::main());
+    }
+
+    #[test]
+    fn poisoned_memory() -> MemoryResult<()> {
+        let device = Main::new(0x80000, 1);
+        let bus = Bus::builder()
+            .with_poisoned_memory(0xdeadbeef)
+            .with_main_memory(2)
+            .with_mapping(&device)
+            .build();
+
+        assert_eq!(bus.load_word(0x1ffc)?, 0xdeadbeef);
+        assert_eq!(
+            bus.load_half_word(0x102)?,
+            if BIG_ENDIAN { 0xbeef } else { 0xdead }
+        );
+        assert_eq!(bus.load_word(0x80000000)?, 0, "Mappings are not poisoned");
+
+        bus.store_byte(0x100, 0)?;
+        assert_eq!(
+            bus.load_word(0x100)?,
+            if BIG_ENDIAN { 0x00adbeef } else { 0xdeadbe00 }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn extent() {
+        let bus = Bus::builder().with_main_memory(4).build();
+        assert_eq!(bus.properties().frame_count(), 4);
+
+        let device = TestDevice::new(0x80010, 2);
+        let bus = Bus::builder()
+            .with_main_memory(4)
+            .with_mapping(&device)
+            .build();
+        assert_eq!(bus.properties().base_frame(), 0);
+        assert_eq!(bus.properties().frame_count(), 0x80012);
+    }
+
+    #[test]
+    fn amo_classes() -> MemoryResult<()> {
+        let device = TestDevice::with_attributes(0x80000, 1, Pma::main().with_amo(AmoClass::Swap));
+        let bus = Bus::builder()
+            .with_main_memory(1)
+            .with_mapping(&device)
+            .build();
+
+        bus.store_word(0x80000040, 1)?;
+        assert_eq!(bus.amoswap_w(0x80000040, 2)?, 1);
+        assert!(matches!(
+            bus.amoxor_w(0x80000040, 3),
+            Err(MemoryError::AmoUnsupported {
+                amo: AmoClass::Swap
+            })
+        ));
+        assert!(matches!(
+            bus.amoadd_w(0x80000040, 3),
+            Err(MemoryError::AmoUnsupported {
+                amo: AmoClass::Swap
+            })
+        ));
+        assert_eq!(device.mem().load_word(0x40)?, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn masked_blocks_across_mappings() -> MemoryResult<()> {
+        let low = Main::new(0x80000, 1);
+        let device = TestDevice::new(0x80001, 1);
+        let bus = Bus::builder()
+            .with_main_memory(1)
+            .with_mapping(&low)
+            .with_mapping(&device)
+            .build();
+
+        // the first three bytes land in `low`, so the mask of the rest is shifted
+        let src = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+        let mask = [0b1010_0101, 0b10];
+        assert_eq!(bus.block_write_masked(0x80000ffd, &src, &mask)?, 5);
+        let bytes = |word: u32| word.to_memory().to_ne_bytes();
+        assert_eq!(bytes(low.load_word(0xffc)?), [0, 1, 0, 3]);
+        assert_eq!(bytes(device.mem().load_word(0)?), [0, 0, 6, 0]);
+        assert_eq!(bytes(device.mem().load_word(4)?), [8, 0, 10, 0]);
+        assert_eq!(
+            device.take_log(),
+            [Access::BlockWrite { offset: 0, len: 7 }]
+        );
+
+        let mut dst = [0xff; 10];
+        assert_eq!(
+            bus.block_read_masked(0x80000ffd, &mut dst, &[!0b100, 0b11])?,
+            10
+        );
+        assert_eq!(dst, [1, 0, 0xff, 0, 0, 6, 0, 8, 0, 10]);
+        Ok(())
+    }
+
+    #[test]
+    fn hot_plug() -> MemoryResult<()> {
+        let device = TestDevice::new(0x80001, 2);
+        let bus = Bus::builder().with_main_memory(1).build();
+        assert!(bus.store_word(0x80002000, 1).is_err());
+
+        bus.add_mapping(0x80001, &device);
+        bus.store_word(0x80002000, 1)?;
+        assert_eq!(bus.load_word(0x80002000)?, 1);
+        assert_eq!(bus.attributes_at(0x80001000), Some(Pma::main()));
+
+        // only the base frame identifies the mapping
+        assert!(bus.remove_mapping(0x80002).is_none());
+        assert!(bus.remove_mapping(0x80001).is_some());
+        assert!(matches!(
+            bus.load_word(0x80002000),
+            Err(MemoryError::OutOfBoundsAccess { .. })
+        ));
+        assert_eq!(bus.attributes_at(0x80001000), None);
+
+        // the frames can be reused
+        bus.add_mapping(0x80002, &device);
+        assert_eq!(bus.load_word(0x80002000)?, 0);
+        Ok(())
+    }
+
+    #[test]
+    #[should_panic]
+    fn hot_plug_overlapping() {
+        let (a, b) = (Main::new(0x80000, 2), Main::new(0x80001, 1));
+        let bus = Bus::builder().with_main_memory(1).with_mapping(&a).build();
+        bus.add_mapping(0x80001, &b);
+    }
+
+    #[test]
+    fn atomics_on_mappings() -> MemoryResult<()> {
+        let device = Main::new(0x80001, 1);
+        let bus = Bus::builder()
+            .with_main_memory(1)
+            .with_mapping(&device)
+            .build();
+        let reservation = AtomicU32::new(u32::MAX);
+        bus.register_reservation_set(&reservation);
+
+        bus.store_word(0x80001040, 1)?;
+        assert_eq!(bus.amoadd_w(0x80001040, 2)?, 1);
+        assert_eq!(device.load_word(0x40)?, 3);
+
+        let set = addr_to_reservation_set(0x80001040);
+        reservation.store(set, Ordering::Relaxed);
+        assert_eq!(bus.store_conditional(0x80001040, 4, &reservation, set)?, 0);
+        assert_eq!(bus.store_conditional(0x80001040, 5, &reservation, set)?, 1);
+        assert_eq!(device.load_word(0x40)?, 4);
+
+        // reservations are not honored by mappings that do not support them
+        let io = TestDevice::with_attributes(0x80000, 1, Pma::io());
+        let bus = Bus::builder().with_main_memory(1).with_mapping(&io).build();
+        reservation.store(set, Ordering::Relaxed);
+        assert_eq!(bus.store_conditional(0x80000040, 6, &reservation, set)?, 1);
+        assert_eq!(io.mem().load_word(0x40)?, 0);
+        Ok(())
+    }
 }
@@ -0,0 +1,460 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// This Source Code Form is "Incompatible With Secondary Licenses", as
+// defined by the Mozilla Public License, v. 2.0.
+//
+// Copyright © 2022 mumblingdrunkard
+
+//! A stub for the GDB remote serial protocol, letting `gdb` debug a hart
+//! over TCP.
+//!
+//! ```text
+//! (gdb) target remote localhost:1234
+//! ```
+//!
+//! Breakpoints are either inserted by `gdb` with `Z0` packets, or written to
+//! memory as `ebreak` instructions; execution stops before either is
+//! executed.
+
+use std::{
+    collections::BTreeSet,
+    io::{self, BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::hart::{
+    instruction::{Conclusion, ExceptionKind, Instruction},
+    step::Step,
+    Hart, Reg,
+};
+
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGBUS: u8 = 7;
+const SIGSEGV: u8 = 11;
+
+/// How many instructions are executed between checks for an interrupt from
+/// the debugger
+const POLL_INTERVAL: usize = 0x1000;
+
+/// The largest packet the debugger may send, and the most hex digits a reply
+/// may hold
+const PACKET_SIZE: usize = 0x1000;
+
+pub struct GdbStub<'h, 'a> {
+    hart: &'h mut Hart<'a>,
+    breakpoints: BTreeSet<u32>,
+}
+
+/// What to do after a packet has been handled
+enum Reply {
+    Packet(String),
+    Detach,
+    Kill,
+}
+
+impl<'h, 'a> GdbStub<'h, 'a> {
+    pub fn new(hart: &'h mut Hart<'a>) -> Self {
+        Self {
+            hart,
+            breakpoints: BTreeSet::new(),
+        }
+    }
+
+    /// Waits for a debugger to connect on `listener` and serves it until it
+    /// detaches or kills the target.
+    pub fn serve(&mut self, listener: &TcpListener) -> io::Result<()> {
+        let (stream, _) = listener.accept()?;
+        self.session(stream)
+    }
+
+    fn session(&mut self, mut stream: TcpStream) -> io::Result<()> {
+        stream.set_nodelay(true)?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+
+        while let Some(packet) = read_packet(&mut reader, &mut stream)? {
+            match self.handle(&packet, &mut reader)? {
+                Reply::Packet(reply) => write_packet(&mut stream, &reply)?,
+                Reply::Detach => return write_packet(&mut stream, "OK"),
+                Reply::Kill => return Ok(()),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn handle(&mut self, packet: &str, reader: &mut BufReader<TcpStream>) -> io::Result<Reply> {
+        let (command, args) = packet.split_at(packet.len().min(1));
+
+        let reply = match command {
+            "?" => stop_reply(SIGTRAP),
+            "g" => self.read_registers(),
+            "G" => self.write_registers(args),
+            "m" => self.read_memory(args),
+            "M" => self.write_memory(args),
+            "s" => {
+                self.resume_at(args);
+                match signal(self.hart.step()) {
+                    0 => stop_reply(SIGTRAP),
+                    signal => stop_reply(signal),
+                }
+            }
+            "c" => {
+                self.resume_at(args);
+                let signal = self.resume(reader)?;
+                stop_reply(signal)
+            }
+            "Z" | "z" => self.breakpoint(command == "Z", args),
+            "H" => "OK".to_string(),
+            "D" => return Ok(Reply::Detach),
+            "k" => return Ok(Reply::Kill),
+            "q" if args == "Attached" => "1".to_string(),
+            "q" if args.starts_with("Supported") => format!("PacketSize={PACKET_SIZE:x}"),
+            _ => String::new(),
+        };
+
+        Ok(Reply::Packet(reply))
+    }
+
+    /// All 32 general purpose registers followed by `pc`, in target byte order
+    fn read_registers(&self) -> String {
+        (0..32)
+            .map(|r| self.hart.reg[Reg::from(r)])
+            .chain([self.hart.pc])
+            .map(|val| hex(&val.to_le_bytes()))
+            .collect()
+    }
+
+    fn write_registers(&mut self, args: &str) -> String {
+        let Some(bytes) = unhex(args) else {
+            return "E01".to_string();
+        };
+
+        for (r, val) in bytes.chunks_exact(4).take(33).enumerate() {
+            let val = u32::from_le_bytes([val[0], val[1], val[2], val[3]]);
+            match r {
+                0 => {}
+                32 => self.hart.pc = val,
+                r => self.hart.reg[Reg::from(r as u32)] = val,
+            }
+        }
+
+        "OK".to_string()
+    }
+
+    /// `m addr,length`
+    ///
+    /// The bytes must fit in a packet, as two hex digits each.
+    fn read_memory(&mut self, args: &str) -> String {
+        let Some((addr, len)) = parse_range(args) else {
+            return "E01".to_string();
+        };
+        if len as usize > PACKET_SIZE / 2 {
+            return "E01".to_string();
+        }
+
+        let mut buf = vec![0; len as usize];
+        match self.hart.read_memory(addr, &mut buf) {
+            Ok(()) => hex(&buf),
+            Err(_) => "E14".to_string(),
+        }
+    }
+
+    /// `M addr,length:XX...`
+    fn write_memory(&mut self, args: &str) -> String {
+        let Some((range, data)) = args.split_once(':') else {
+            return "E01".to_string();
+        };
+        let (Some((addr, len)), Some(data)) = (parse_range(range), unhex(data)) else {
+            return "E01".to_string();
+        };
+        if data.len() != len as usize {
+            return "E01".to_string();
+        }
+
+        match self.hart.write_memory(addr, &data) {
+            Ok(()) => "OK".to_string(),
+            Err(_) => "E14".to_string(),
+        }
+    }
+
+    /// `Z0,addr,kind` and `z0,addr,kind`.
+    ///
+    /// Only software breakpoints are supported.
+    fn breakpoint(&mut self, insert: bool, args: &str) -> String {
+        let mut fields = args.split(',');
+        let (Some("0"), Some(addr)) = (fields.next(), fields.next()) else {
+            return String::new();
+        };
+        let Ok(addr) = u32::from_str_radix(addr, 16) else {
+            return "E01".to_string();
+        };
+
+        if insert {
+            self.breakpoints.insert(addr);
+        } else {
+            self.breakpoints.remove(&addr);
+        }
+
+        "OK".to_string()
+    }
+
+    /// `s` and `c` may be given an address to resume at
+    fn resume_at(&mut self, args: &str) {
+        if let Ok(addr) = u32::from_str_radix(args, 16) {
+            self.hart.pc = addr;
+        }
+    }
+
+    /// Whether execution should stop before executing the instruction at `pc`
+    ///
+    /// `ebreak`s are only found in memory that can be read without side
+    /// effects.
+    fn at_breakpoint(&mut self) -> bool {
+        self.breakpoints.contains(&self.hart.pc)
+            || matches!(self.hart.peek_instruction(), Some(Instruction::Ebreak))
+    }
+
+    /// Runs the hart until it reaches a breakpoint, raises an exception, or
+    /// the debugger sends an interrupt, and returns the signal to report.
+    ///
+    /// The instruction at `pc` is always executed, so that execution can
+    /// continue from a breakpoint.
+    fn resume(&mut self, reader: &mut BufReader<TcpStream>) -> io::Result<u8> {
+        for i in 1usize.. {
+            let signal = signal(self.hart.step());
+            if signal != 0 {
+                return Ok(signal);
+            }
+
+            if self.at_breakpoint() {
+                return Ok(SIGTRAP);
+            }
+
+            if i % POLL_INTERVAL == 0 && interrupted(reader)? {
+                return Ok(SIGTRAP);
+            }
+        }
+
+        unreachable!()
+    }
+}
+
+/// The signal reported for the outcome of a step, or 0 if execution can
+/// continue
+fn signal(conclusion: Conclusion) -> u8 {
+    use ExceptionKind::*;
+    match conclusion {
+        Conclusion::None | Conclusion::Jumped => 0,
+        Conclusion::Watchpoint { .. } => SIGTRAP,
+        Conclusion::Exception(IllegalInstruction { .. }) => SIGILL,
+        Conclusion::Exception(
+            InstructionMisaligned { .. } | LoadMisaligned { .. } | StoreMisaligned { .. },
+        ) => SIGBUS,
+        Conclusion::Exception(
+            InstructionAccessFault { .. }
+            | LoadAccessFault { .. }
+            | StoreAccessFault { .. }
+            | InstructionPageFault { .. }
+            | LoadPageFault { .. }
+            | StorePageFault { .. },
+        ) => SIGSEGV,
+        Conclusion::Exception(_) => SIGTRAP,
+    }
+}
+
+fn stop_reply(signal: u8) -> String {
+    format!("S{signal:02x}")
+}
+
+/// Checks, without blocking, whether the debugger has sent an interrupt
+/// (`0x03`)
+fn interrupted(reader: &mut BufReader<TcpStream>) -> io::Result<bool> {
+    reader.get_ref().set_nonblocking(true)?;
+    let buffered = match reader.fill_buf() {
+        Ok(buf) => buf.first().copied(),
+        Err(e) if e.kind() == io::ErrorKind::WouldBlock => None,
+        Err(e) => return Err(e),
+    };
+    reader.get_ref().set_nonblocking(false)?;
+
+    if buffered == Some(0x03) {
+        reader.consume(1);
+        return Ok(true);
+    }
+
+    Ok(false)
+}
+
+fn checksum(data: &str) -> u8 {
+    data.bytes().fold(0, u8::wrapping_add)
+}
+
+fn write_packet(stream: &mut impl Write, data: &str) -> io::Result<()> {
+    write!(stream, "${data}#{:02x}", checksum(data))?;
+    stream.flush()
+}
+
+/// Reads the next packet, acknowledging it, or returns `None` if the
+/// connection was closed.
+///
+/// Acknowledgements from the debugger are skipped, and so is an interrupt
+/// received while the hart is stopped.
+fn read_packet(reader: &mut impl BufRead, stream: &mut impl Write) -> io::Result<Option<String>> {
+    loop {
+        let mut skipped = Vec::new();
+        if reader.read_until(b'$', &mut skipped)? == 0 || skipped.last() != Some(&b'$') {
+            return Ok(None);
+        }
+
+        let mut data = Vec::new();
+        reader.read_until(b'#', &mut data)?;
+        if data.pop() != Some(b'#') {
+            return Ok(None);
+        }
+
+        let mut sum = [0; 2];
+        reader.read_exact(&mut sum)?;
+
+        let data = String::from_utf8_lossy(&data).into_owned();
+        let valid = std::str::from_utf8(&sum)
+            .ok()
+            .and_then(|sum| u8::from_str_radix(sum, 16).ok())
+            == Some(checksum(&data));
+
+        if valid {
+            stream.write_all(b"+")?;
+            return Ok(Some(data));
+        }
+
+        stream.write_all(b"-")?;
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn unhex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// `addr,length`
+fn parse_range(args: &str) -> Option<(u32, u32)> {
+    let (addr, len) = args.split_once(',')?;
+    Some((
+        u32::from_str_radix(addr, 16).ok()?,
+        u32::from_str_radix(len, 16).ok()?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        net::{TcpListener, TcpStream},
+        sync::atomic::AtomicU32,
+        thread,
+    };
+
+    use crate::{asm::Assembler, bus::Bus, hart::Hart, hart::Reg};
+
+    use super::{checksum, GdbStub};
+
+    /// Sends `command` and returns the reply
+    fn command(stream: &mut TcpStream, command: &str) -> String {
+        write!(stream, "${command}#{:02x}", checksum(command)).unwrap();
+
+        let mut byte = [0];
+        stream.read_exact(&mut byte).unwrap();
+        assert_eq!(byte[0], b'+');
+
+        let mut reply = Vec::new();
+        loop {
+            stream.read_exact(&mut byte).unwrap();
+            match byte[0] {
+                b'$' => {}
+                b'#' => break,
+                b => reply.push(b),
+            }
+        }
+        let mut sum = [0; 2];
+        stream.read_exact(&mut sum).unwrap();
+        stream.write_all(b"+").unwrap();
+
+        String::from_utf8(reply).unwrap()
+    }
+
+    fn registers(reply: &str) -> Vec<u32> {
+        (0..reply.len())
+            .step_by(8)
+            .map(|i| {
+                u32::from_str_radix(&reply[i..i + 8], 16)
+                    .unwrap()
+                    .swap_bytes()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn session() {
+        let mut asm = Assembler::new();
+        asm.li(Reg::A0, 1)
+            .addi(Reg::A0, Reg::A0, 1)
+            .addi(Reg::A0, Reg::A0, 1)
+            .addi(Reg::A0, Reg::A0, 1)
+            .ebreak();
+
+        let bus = Bus::builder().with_main_memory(1).build();
+        bus.set_mm(&asm.assemble_bytes()).unwrap();
+        let reservation = AtomicU32::new(u32::MAX);
+        let mut hart = Hart::new(&bus, &reservation);
+        hart.reg[Reg::SP] = 0x1000;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        thread::scope(|s| {
+            s.spawn(|| {
+                let mut stream = TcpStream::connect(addr).unwrap();
+
+                assert_eq!(command(&mut stream, "?"), "S05");
+
+                let regs = registers(&command(&mut stream, "g"));
+                assert_eq!(regs.len(), 33);
+                assert_eq!(regs[2], 0x1000);
+                assert_eq!(regs[32], 0);
+
+                assert_eq!(command(&mut stream, "m0,4"), "13051000");
+                assert_eq!(command(&mut stream, "m0,801"), "E01");
+
+                assert_eq!(command(&mut stream, "s"), "S05");
+                let regs = registers(&command(&mut stream, "g"));
+                assert_eq!((regs[10], regs[32]), (1, 4));
+
+                assert_eq!(command(&mut stream, "Z0,c,4"), "OK");
+                assert_eq!(command(&mut stream, "c"), "S05");
+                let regs = registers(&command(&mut stream, "g"));
+                assert_eq!((regs[10], regs[32]), (3, 12));
+
+                // stops before the ebreak
+                assert_eq!(command(&mut stream, "c"), "S05");
+                let regs = registers(&command(&mut stream, "g"));
+                assert_eq!((regs[10], regs[32]), (4, 16));
+
+                assert_eq!(command(&mut stream, "D"), "OK");
+            });
+
+            GdbStub::new(&mut hart).serve(&listener).unwrap();
+        });
+    }
+}
@@ -8,36 +8,124 @@
 // Copyright © 2022 mumblingdrunkard
 
 pub mod csr;
+mod fpu;
 pub mod instruction;
 pub mod mmu;
+pub mod pmp;
 pub mod register;
 pub mod step;
 pub mod sv32;
 mod utils;
 
-use std::sync::atomic::AtomicU32;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
 
-pub use register::Reg;
+pub use register::{FReg, Reg};
 
-use register::RegisterFile;
+use csr::{Csr, CsrFile, MStatus};
+use register::{FRegisterFile, RegisterFile};
 
-use crate::bus::Bus;
+use crate::{bus::Bus, memory::mapping::SendSyncMapping};
 
-use self::mmu::Mmu;
+use self::{
+    instruction::Instruction,
+    mmu::{Access, Mmu, MmuResult},
+    pmp::Pmp,
+};
+
+/// Called with the pc and instruction of every instruction before it is executed
+pub type TraceHook<'a> = Box<dyn FnMut(u32, &Instruction) + Send + 'a>;
+
+/// The privilege mode an access is performed in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrivilegeMode {
+    User = 0,
+    Supervisor = 1,
+    Machine = 3,
+}
+
+impl From<u32> for PrivilegeMode {
+    /// The mode encoded in the two lowest bits of `bits`, as in
+    /// `mstatus.MPP`, with the reserved encoding read as machine mode
+    fn from(bits: u32) -> Self {
+        match bits & 3 {
+            0 => Self::User,
+            1 => Self::Supervisor,
+            _ => Self::Machine,
+        }
+    }
+}
+
+/// The bits of `mstatus` that are visible through `sstatus`
+const SSTATUS_MASK: u32 = 0x800de762;
+
+/// The exceptions that can be delegated, which is all but environment calls
+/// from machine mode and the reserved causes
+const MEDELEG_MASK: u32 = 0xb3ff;
+
+/// The interrupts that can be delegated, which are the supervisor interrupts
+const MIDELEG_MASK: u32 = 0x222;
+
+/// How a hart starts out, as given to [`Hart::with_config`]
+#[derive(Debug, Clone, Default)]
+pub struct HartConfig {
+    /// The address execution starts at, and continues at after a reset
+    pub reset_pc: u32,
+    /// The value of `mhartid`, which should be unique among the harts of a
+    /// machine
+    pub hart_id: u32,
+    /// The initial integer registers, which a reset zeroes like usual
+    pub reg: RegisterFile,
+}
+
+/// The architectural state of a hart, as captured by [`Hart::snapshot`]
+#[derive(Clone)]
+pub struct HartState {
+    pc: u32,
+    reg: RegisterFile,
+    freg: FRegisterFile,
+    csr: CsrFile,
+    reservation: u32,
+    privilege: PrivilegeMode,
+}
 
 pub struct Hart<'a> {
     pub pc: u32,
     pub reg: RegisterFile,
+    pub freg: FRegisterFile,
+    /// The address of the instruction following the one being executed,
+    /// which depends on whether it is compressed
+    next_pc: u32,
     mmu: Mmu<'a>,
-    // csr: [u32; 4096],
+    csr: CsrFile,
+    trace: Option<TraceHook<'a>>,
+    mtime: Option<&'a AtomicU64>,
+    interrupt_line: Option<&'a AtomicU32>,
+    reset_vector: u32,
+    hart_id: u32,
+    privilege: PrivilegeMode,
 }
 
 impl<'a> Hart<'a> {
     pub fn new(bus: &'a Bus<'a>, reservation: &'a AtomicU32) -> Self {
+        Self::with_config(bus, reservation, HartConfig::default())
+    }
+
+    /// Creates a hart that starts at `config.reset_pc` with the registers and
+    /// id in `config`.
+    pub fn with_config(bus: &'a Bus<'a>, reservation: &'a AtomicU32, config: HartConfig) -> Self {
         let hart = Self {
-            pc: 0,
-            reg: RegisterFile::new(),
+            pc: config.reset_pc,
+            reg: config.reg,
+            freg: FRegisterFile::new(),
+            next_pc: 0,
             mmu: Mmu::new(bus, reservation),
+            csr: CsrFile::new(),
+            trace: None,
+            mtime: None,
+            interrupt_line: None,
+            reset_vector: config.reset_pc,
+            hart_id: config.hart_id,
+            privilege: PrivilegeMode::Machine,
         };
 
         // can't register here because hart gets moved at the end
@@ -49,4 +137,752 @@ impl<'a> Hart<'a> {
     pub fn reservation(&self) -> &AtomicU32 {
         self.mmu.reservation()
     }
+
+    /// The id of this hart, as read from `mhartid`
+    pub fn hart_id(&self) -> u32 {
+        self.hart_id
+    }
+
+    /// The privilege mode the hart is executing in
+    pub fn privilege(&self) -> PrivilegeMode {
+        self.privilege
+    }
+
+    /// Switches the hart to privilege mode `mode` from the host side, e.g. to
+    /// run a test program in user mode without a trap handler returning to
+    /// it.
+    pub fn set_privilege(&mut self, mode: PrivilegeMode) {
+        self.privilege = mode;
+        self.mmu.set_privilege(mode);
+    }
+
+    /// Sets the address execution continues at after a reset.
+    pub fn set_reset_vector(&mut self, pc: u32) {
+        self.reset_vector = pc;
+    }
+
+    /// Returns the hart to a known initial state without reconstructing it.
+    ///
+    /// Registers and CSRs other than `mhartid` are zeroed, the reservation is
+    /// cleared, and execution continues in machine mode at the reset vector.
+    /// The MMU caches are written back and emptied, but memory itself is left
+    /// as it is.
+    /// The trace hook is kept.
+    pub fn reset(&mut self) -> MmuResult<()> {
+        self.mmu.reset()?;
+        self.reg = RegisterFile::new();
+        self.freg = FRegisterFile::new();
+        self.csr = CsrFile::new();
+        self.pc = self.reset_vector;
+        self.set_privilege(PrivilegeMode::Machine);
+        Ok(())
+    }
+
+    /// Captures the state of the hart so it can be restored with
+    /// [`Hart::restore`].
+    ///
+    /// Dirty lines are written back first, so a snapshot of memory taken
+    /// right after this includes every store the hart has performed.
+    pub fn snapshot(&mut self) -> MmuResult<HartState> {
+        self.mmu.write_back_all()?;
+        Ok(HartState {
+            pc: self.pc,
+            reg: self.reg.clone(),
+            freg: self.freg.clone(),
+            csr: self.csr.clone(),
+            reservation: self.reservation().load(Ordering::Relaxed),
+            privilege: self.privilege,
+        })
+    }
+
+    /// Returns the hart to the state captured in `state`.
+    ///
+    /// The MMU caches are discarded, so memory should be restored first, as
+    /// that also invalidates the reservation restored here.
+    pub fn restore(&mut self, state: &HartState) {
+        self.mmu.invalidate_all();
+        self.pc = state.pc;
+        self.reg = state.reg.clone();
+        self.freg = state.freg.clone();
+        self.csr = state.csr.clone();
+        self.set_privilege(state.privilege);
+        self.mmu.set_satp(self.csr[Csr::Satp]);
+        self.mmu.set_mstatus(self.mstatus());
+
+        // addresses first, as they cannot be written once their entry is locked
+        let mut pmp = Pmp::new();
+        (0..pmp::ENTRIES).for_each(|i| pmp.set_addr(i, self.csr[Csr::from(0x3b0 + i as u32)]));
+        (0..pmp::ENTRIES / 4).for_each(|n| pmp.set_cfg(n, self.csr[Csr::from(0x3a0 + n as u32)]));
+        self.mmu.set_pmp(pmp);
+
+        self.reservation()
+            .store(state.reservation, Ordering::Relaxed);
+    }
+
+    /// Sets a hook that is called on every step with the pc and the fetched
+    /// instruction, before it is executed.
+    ///
+    /// The hook is also called for instructions that raise an exception, but
+    /// not when the fetch itself fails or an interrupt is taken instead.
+    pub fn set_trace_hook(&mut self, hook: TraceHook<'a>) {
+        self.trace = Some(hook);
+    }
+
+    pub fn clear_trace_hook(&mut self) {
+        self.trace = None;
+    }
+
+    /// Sets the platform timer read through the `time` and `timeh` CSRs, such
+    /// as the `mtime` register of a CLINT shared by all harts.
+    ///
+    /// Without one, `time` counts the cycles of this hart instead.
+    pub fn set_mtime(&mut self, mtime: &'a AtomicU64) {
+        self.mtime = Some(mtime);
+    }
+
+    /// Sets an interrupt line driven by devices such as
+    /// [`crate::memory::timer::Timer`].
+    ///
+    /// Every bit set in `line` reads as set in `mip`, on top of the bits
+    /// written by software, and is taken like any other pending interrupt.
+    pub fn set_interrupt_line(&mut self, line: &'a AtomicU32) {
+        self.interrupt_line = Some(line);
+    }
+
+    /// The interrupts pending in `mip`, including those raised by devices on
+    /// the interrupt line
+    fn pending_interrupts(&self) -> u32 {
+        let line = self
+            .interrupt_line
+            .map_or(0, |line| line.load(Ordering::Relaxed));
+        self.csr[Csr::Mip] | line
+    }
+
+    /// Reads the CSR with address `addr` from the host side.
+    ///
+    /// This bypasses privilege checks and is meant for debuggers and test
+    /// harnesses.
+    /// Unimplemented CSRs read as 0.
+    pub fn read_csr(&self, addr: u16) -> u32 {
+        match Csr::from(addr as u32) {
+            Csr::Invalid => 0,
+            csr => self.get_csr(csr),
+        }
+    }
+
+    /// Writes `val` to the CSR with address `addr` from the host side.
+    ///
+    /// This bypasses privilege checks, including those for read-only CSRs,
+    /// and is meant for debuggers and test harnesses.
+    /// Writes to unimplemented CSRs are ignored, as are writes to `mhartid`,
+    /// which always reads as the id the hart was configured with.
+    pub fn write_csr(&mut self, addr: u16, val: u32) {
+        match Csr::from(addr as u32) {
+            Csr::Invalid => {}
+            csr => self.set_csr(csr, val),
+        }
+    }
+
+    /// Reads the integer register named `name`, either by number, as in `x8`,
+    /// or by ABI name, as in `s0` or `fp`.
+    ///
+    /// Returns `None` if `name` is not a register.
+    pub fn read_reg(&self, name: &str) -> Option<u32> {
+        Reg::from_abi_name(name).map(|reg| self.reg[reg])
+    }
+
+    /// Writes `val` to the integer register named `name`, returning whether
+    /// `name` is a register.
+    ///
+    /// Writes to `x0` are ignored like for any other write.
+    pub fn write_reg(&mut self, name: &str, val: u32) -> bool {
+        Reg::from_abi_name(name)
+            .map(|reg| self.reg[reg] = val)
+            .is_some()
+    }
+
+    /// Reads the bytes at the virtual address `addr` into `buf` from the
+    /// host side.
+    ///
+    /// The bytes are read as if loaded by the hart, so they are translated
+    /// and include stores that have not been written back yet.
+    /// This is meant for debuggers and test harnesses.
+    pub fn read_memory(&mut self, addr: u32, buf: &mut [u8]) -> MmuResult<()> {
+        let result = (addr..).zip(buf).try_for_each(|(addr, b)| {
+            *b = self.mmu.load_byte(addr)? as u8;
+            Ok(())
+        });
+
+        // the host does not hit watchpoints
+        self.mmu.take_watchpoint_hit();
+        result
+    }
+
+    /// Writes `data` to the virtual address `addr` from the host side.
+    ///
+    /// The bytes are written as if stored by the hart, followed by a
+    /// `fence.i`, so patched instructions are fetched on the next step.
+    /// This is meant for debuggers and test harnesses.
+    pub fn write_memory(&mut self, addr: u32, data: &[u8]) -> MmuResult<()> {
+        let result = (addr..)
+            .zip(data)
+            .try_for_each(|(addr, &b)| self.mmu.store_byte(addr, b));
+
+        self.mmu.take_watchpoint_hit();
+        result?;
+        self.mmu.synchronize_instructions()
+    }
+
+    /// Decodes the instruction at `pc` from the host side without executing
+    /// it, or returns `None` if it can not be fetched.
+    ///
+    /// Memory where reads could have side effects is not read, so `None` is
+    /// returned for it as well.
+    /// This is meant for debuggers.
+    pub fn peek_instruction(&mut self) -> Option<Instruction> {
+        self.mmu.peek_instruction(self.pc)
+    }
+
+    /// Makes stores visible to instruction fetches without a `fence.i`, for
+    /// running programs that modify their own code without one.
+    ///
+    /// This costs a check of the instruction cache on every store, so it is
+    /// off by default.
+    pub fn set_coherent_instructions(&mut self, coherent: bool) {
+        self.mmu.set_coherent_instructions(coherent);
+    }
+
+    /// Stops the hart when the virtual address `addr` is accessed by an
+    /// access of kind `kind`.
+    ///
+    /// Loads and stores complete before `step` returns
+    /// `Conclusion::Watchpoint`, but a watched instruction is not executed, so
+    /// the watchpoint has to be removed to continue past it.
+    pub fn add_watchpoint(&mut self, addr: u32, kind: Access) {
+        self.mmu.add_watchpoint(addr, kind);
+    }
+
+    /// Removes a watchpoint, returning whether it existed.
+    pub fn remove_watchpoint(&mut self, addr: u32, kind: Access) -> bool {
+        self.mmu.remove_watchpoint(addr, kind)
+    }
+
+    /// Unmaps the mapping based at `base_frame` from the bus, returning it, or
+    /// `None` if no mapping is based there.
+    ///
+    /// Stores this hart buffered for the bus are written back first.
+    pub fn remove_mapping(
+        &mut self,
+        base_frame: u32,
+    ) -> MmuResult<Option<&'a dyn SendSyncMapping<'a>>> {
+        self.mmu.remove_mapping(base_frame)
+    }
+
+    /// Reads `csr`, with the unprivileged counters reading their machine-mode
+    /// counterparts, `time` reading the platform timer if there is one, and
+    /// `mip` including the interrupt line.
+    fn get_csr(&self, csr: Csr) -> u32 {
+        let csr = match (csr, self.mtime) {
+            (Csr::Time, Some(mtime)) => return mtime.load(Ordering::Relaxed) as u32,
+            (Csr::Timeh, Some(mtime)) => return (mtime.load(Ordering::Relaxed) >> 32) as u32,
+            (Csr::Cycle | Csr::Time, _) => Csr::MCycle,
+            (Csr::Cycleh | Csr::Timeh, _) => Csr::MCycleh,
+            (Csr::InstRet, _) => Csr::MInstRet,
+            (Csr::InstReth, _) => Csr::MInstReth,
+            // `fflags` and `frm` are fields of `fcsr`
+            (Csr::FFlags, _) => return self.csr[Csr::FCsr] & 0x1f,
+            (Csr::Frm, _) => return self.csr[Csr::FCsr] >> 5 & 7,
+            (Csr::Mip, _) => return self.pending_interrupts(),
+            // the supervisor views of the machine-mode registers
+            (Csr::SStatus, _) => return self.csr[Csr::MStatus] & SSTATUS_MASK,
+            (Csr::SSie, _) => return self.csr[Csr::Mie] & self.csr[Csr::MIDeleg],
+            (Csr::Sip, _) => return self.pending_interrupts() & self.csr[Csr::MIDeleg],
+            (Csr::MHartId, _) => return self.hart_id,
+            (csr, _) => csr,
+        };
+        self.csr[csr]
+    }
+
+    /// Adds 1 to the 64-bit counter split across `lo` and `hi`.
+    #[inline(always)]
+    fn increment_counter(&mut self, lo: Csr, hi: Csr) {
+        let (val, carry) = self.csr[lo].overflowing_add(1);
+        self.csr[lo] = val;
+        if carry {
+            self.csr[hi] = self.csr[hi].wrapping_add(1);
+        }
+    }
+
+    /// Writes `val` to `csr`, updating any state that depends on it.
+    fn set_csr(&mut self, csr: Csr, val: u32) {
+        let fcsr = self.csr[Csr::FCsr];
+        let mideleg = self.csr[Csr::MIDeleg];
+        let masked = |old: u32, mask: u32| old & !mask | val & mask;
+        match csr {
+            Csr::Satp => self.set_satp(val),
+            // read from `hart_id`, which is fixed when the hart is created
+            Csr::MHartId => {}
+            Csr::MStatus => self.set_mstatus(MStatus::from(val)),
+            Csr::SStatus => {
+                let mstatus = masked(self.csr[Csr::MStatus], SSTATUS_MASK);
+                self.set_mstatus(MStatus::from(mstatus));
+            }
+            Csr::MEDeleg => self.csr[Csr::MEDeleg] = val & MEDELEG_MASK,
+            Csr::MIDeleg => self.csr[Csr::MIDeleg] = val & MIDELEG_MASK,
+            Csr::SSie => self.csr[Csr::Mie] = masked(self.csr[Csr::Mie], mideleg),
+            // only the supervisor software interrupt can be raised by software
+            Csr::Sip => self.csr[Csr::Mip] = masked(self.csr[Csr::Mip], mideleg & 1 << 1),
+            csr @ (Csr::PmpCfg0 | Csr::PmpCfg1 | Csr::PmpCfg2 | Csr::PmpCfg3) => {
+                let n = (u32::from(csr) - 0x3a0) as usize;
+                self.mmu.set_pmp_cfg(n, val);
+                self.csr[csr] = self.mmu.pmp().cfg(n);
+            }
+            // only the first 16 PMP entries are implemented, the rest are zero
+            csr if (0x3a4..0x3b0).contains(&u32::from(csr)) => {}
+            csr if (0x3b0..0x3c0).contains(&u32::from(csr)) => {
+                let i = (u32::from(csr) - 0x3b0) as usize;
+                self.mmu.set_pmp_addr(i, val);
+                self.csr[csr] = self.mmu.pmp().addr(i);
+            }
+            csr if (0x3c0..0x3f0).contains(&u32::from(csr)) => {}
+            Csr::FFlags => self.csr[Csr::FCsr] = fcsr & !0x1f | val & 0x1f,
+            Csr::Frm => self.csr[Csr::FCsr] = fcsr & 0x1f | (val & 7) << 5,
+            Csr::FCsr => self.csr[Csr::FCsr] = val & 0xff,
+            csr => self.csr[csr] = val,
+        }
+    }
+
+    pub fn satp(&self) -> u32 {
+        self.csr[Csr::Satp]
+    }
+
+    /// Sets `satp`, which enables sv32 translation when `satp.MODE` is set.
+    pub fn set_satp(&mut self, satp: u32) {
+        self.csr[Csr::Satp] = satp;
+        self.mmu.set_satp(satp);
+    }
+
+    /// The fields of `mstatus`
+    pub fn mstatus(&self) -> MStatus {
+        MStatus::from(self.csr[Csr::MStatus])
+    }
+
+    /// Sets `mstatus`, whose `MPRV` field makes loads and stores use the
+    /// privilege mode in `MPP`.
+    pub fn set_mstatus(&mut self, mstatus: MStatus) {
+        self.csr[Csr::MStatus] = mstatus.into();
+        self.mmu.set_mstatus(mstatus);
+    }
+
+    /// Enters the trap handler, as if the instruction at `pc` raised `cause`.
+    ///
+    /// Traps taken below machine mode are delegated to supervisor mode if
+    /// their bit is set in `medeleg`, or `mideleg` for interrupts.
+    /// All other traps are taken in machine mode.
+    ///
+    /// `cause` is written to `mcause` as is, so the interrupt bit must be set
+    /// for interrupts.
+    /// `pc` is saved in `mepc`, `mstatus.MIE` is pushed onto `mstatus.MPIE`,
+    /// the current privilege mode is saved in `mstatus.MPP`, and execution
+    /// continues in machine mode at `mtvec`.
+    /// Delegated traps use `scause`, `sepc`, `sstatus.SIE`, `sstatus.SPIE`,
+    /// `sstatus.SPP` and `stvec` the same way.
+    /// In vectored mode, interrupts continue at `BASE + 4 * cause` instead.
+    pub fn take_trap(&mut self, cause: u32, tval: u32) {
+        let interrupt = cause & 0x80000000 != 0;
+        let delegation = if interrupt {
+            self.csr[Csr::MIDeleg]
+        } else {
+            self.csr[Csr::MEDeleg]
+        };
+        let delegated =
+            self.privilege != PrivilegeMode::Machine && delegation >> (cause & 0x1f) & 1 == 1;
+
+        let mut mstatus = self.mstatus();
+        let tvec = if delegated {
+            self.csr[Csr::Sepc] = self.pc;
+            self.csr[Csr::SCause] = cause;
+            self.csr[Csr::STVal] = tval;
+
+            mstatus.push_supervisor(self.privilege);
+            self.set_mstatus(mstatus);
+            self.set_privilege(PrivilegeMode::Supervisor);
+            self.csr[Csr::STVec]
+        } else {
+            self.csr[Csr::Mepc] = self.pc;
+            self.csr[Csr::MCause] = cause;
+            self.csr[Csr::MTVal] = tval;
+
+            mstatus.push_machine(self.privilege);
+            self.set_mstatus(mstatus);
+            self.set_privilege(PrivilegeMode::Machine);
+            self.csr[Csr::MTVec]
+        };
+
+        let base = tvec & !3;
+        self.pc = if tvec & 3 == 1 && interrupt {
+            base.wrapping_add(4 * (cause & 0x7fffffff))
+        } else {
+            base
+        };
+    }
+
+    /// Takes the highest-priority interrupt that is both pending in `mip` and
+    /// enabled in `mie`, if interrupts are enabled for the mode it is taken in.
+    ///
+    /// Interrupts taken in machine mode are enabled by `mstatus.MIE` while
+    /// running in machine mode, and always enabled below it.
+    /// Interrupts delegated by `mideleg` are enabled the same way by
+    /// `sstatus.SIE`, and never taken while running in machine mode.
+    /// Those taken in machine mode have priority over delegated ones.
+    ///
+    /// Returns `true` if a trap was taken.
+    pub fn check_interrupts(&mut self) -> bool {
+        // machine external, software, and timer, then the same for supervisor
+        const PRIORITY: [u32; 6] = [11, 3, 7, 9, 1, 5];
+
+        let mstatus = self.mstatus();
+        let (m_enabled, s_enabled) = match self.privilege {
+            PrivilegeMode::Machine => (mstatus.mie(), false),
+            PrivilegeMode::Supervisor => (true, mstatus.sie()),
+            PrivilegeMode::User => (true, true),
+        };
+
+        let pending = self.pending_interrupts() & self.csr[Csr::Mie];
+        let mideleg = self.csr[Csr::MIDeleg];
+        let takeable = [
+            if m_enabled { pending & !mideleg } else { 0 },
+            if s_enabled { pending & mideleg } else { 0 },
+        ];
+
+        let interrupt = takeable
+            .into_iter()
+            .find_map(|pending| PRIORITY.into_iter().find(|&i| pending & 1 << i != 0));
+        match interrupt {
+            Some(i) => {
+                self.take_trap(0x80000000 | i, 0);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns from a machine-mode trap handler, popping `mstatus.MPIE` back
+    /// into `mstatus.MIE` and continuing at `mepc` in the privilege mode
+    /// saved in `mstatus.MPP`.
+    ///
+    /// `mstatus.MPP` is left as user mode, and `mstatus.MPRV` is cleared when
+    /// returning to a less privileged mode.
+    pub fn mret(&mut self) {
+        let mut mstatus = self.mstatus();
+        let mpp = mstatus.pop_machine();
+        self.set_mstatus(mstatus);
+        self.set_privilege(mpp);
+        self.pc = self.csr[Csr::Mepc];
+    }
+
+    /// Returns from a supervisor-mode trap handler, popping `sstatus.SPIE`
+    /// back into `sstatus.SIE` and continuing at `sepc` in the privilege mode
+    /// saved in `sstatus.SPP`.
+    ///
+    /// `sstatus.SPP` is left as user mode, and `mstatus.MPRV` is cleared.
+    pub fn sret(&mut self) {
+        let mut mstatus = self.mstatus();
+        let spp = mstatus.pop_supervisor();
+        self.set_mstatus(mstatus);
+        self.set_privilege(spp);
+        self.pc = self.csr[Csr::Sepc];
+    }
+
+    /// Performs `sfence.vma`, making page table updates visible to later
+    /// translations.
+    ///
+    /// With `vaddr`, only translations for the page containing it are
+    /// flushed, and with `asid`, only those of that address space, keeping
+    /// translations of global pages.
+    pub fn sfence_vma(&mut self, vaddr: Option<u32>, asid: Option<u32>) {
+        self.mmu.invalidate_translations(vaddr, asid);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use crate::{
+        bus::Bus,
+        memory::{endian::MemoryOrder, mapping::Mapping},
+    };
+
+    use super::{
+        csr::Csr,
+        instruction::{Conclusion, ExceptionKind},
+        register::RegisterFile,
+        step::Step,
+        Hart, HartConfig, PrivilegeMode, Reg,
+    };
+
+    #[test]
+    fn host_csr_access() {
+        let bus = Bus::builder().with_main_memory(1).build();
+        let reservation = AtomicU32::new(u32::MAX);
+        let mut hart = Hart::new(&bus, &reservation);
+
+        hart.write_csr(0x305, 0x800); // mtvec
+        hart.write_csr(0x340, 0xdeadbeef); // mscratch
+        assert_eq!(hart.read_csr(0x305), 0x800);
+        assert_eq!(hart.read_csr(0x340), 0xdeadbeef);
+
+        // unimplemented CSRs ignore writes
+        hart.write_csr(0x7ff, 1);
+        assert_eq!(hart.read_csr(0x7ff), 0);
+
+        // the hart traps to the mtvec written by the host
+        bus.store_word(0, u32::from_memory(0x00000073u32.to_le())) // ecall
+            .unwrap();
+        assert!(matches!(
+            hart.step(),
+            Conclusion::Exception(ExceptionKind::EcallFromM)
+        ));
+        assert_eq!(hart.pc, 0x800);
+        assert_eq!(hart.read_csr(0x342), 11); // mcause
+        assert_eq!(hart.read_csr(0x341), 0); // mepc
+    }
+
+    #[test]
+    fn host_register_access() {
+        let bus = Bus::builder().with_main_memory(1).build();
+        let reservation = AtomicU32::new(u32::MAX);
+        let mut hart = Hart::new(&bus, &reservation);
+
+        assert!(hart.write_reg("sp", 0x1000));
+        assert!(hart.write_reg("x10", 5));
+        assert!(hart.write_reg("zero", 1));
+        assert!(!hart.write_reg("pc", 0));
+
+        assert_eq!(hart.reg[Reg::SP], 0x1000);
+        assert_eq!(hart.read_reg("a0"), Some(5));
+        assert_eq!(hart.read_reg("x0"), Some(0));
+        assert_eq!(hart.read_reg("x32"), None);
+    }
+
+    #[test]
+    fn config() {
+        let bus = Bus::builder().with_main_memory(1).build();
+        let reservations = [AtomicU32::new(u32::MAX), AtomicU32::new(u32::MAX)];
+
+        let mut reg = RegisterFile::new();
+        reg[Reg::A0] = 7;
+        let first = Hart::new(&bus, &reservations[0]);
+        let mut second = Hart::with_config(
+            &bus,
+            &reservations[1],
+            HartConfig {
+                reset_pc: 0x80000000,
+                hart_id: 1,
+                reg,
+            },
+        );
+
+        assert_eq!((first.pc, first.read_csr(0xf14)), (0, 0));
+        assert_eq!((second.pc, second.read_csr(0xf14)), (0x80000000, 1));
+        assert_eq!(second.reg[Reg::A0], 7);
+
+        // mhartid is read-only, so the write is dropped rather than kept
+        // where reads never see it
+        second.write_csr(0xf14, 5);
+        assert_eq!(second.read_csr(0xf14), 1);
+        assert_eq!(second.csr[Csr::MHartId], 0);
+
+        second.pc = 0x40;
+        second.reset().unwrap();
+        assert_eq!((second.pc, second.read_csr(0xf14)), (0x80000000, 1));
+        assert_eq!(second.reg[Reg::A0], 0);
+    }
+
+    #[test]
+    fn reset() {
+        let bus = Bus::builder().with_main_memory(1).build();
+        let reservation = AtomicU32::new(u32::MAX);
+        let mut hart = Hart::new(&bus, &reservation);
+
+        hart.pc = 0x40;
+        hart.reg[Reg::A0] = 1;
+        hart.write_csr(0x340, 2); // mscratch
+        hart.mmu.load_reserved(0x100, Ordering::Relaxed).unwrap();
+        hart.mmu.store_word(0x100, 3).unwrap();
+        hart.write_csr(0x180, 0x80000001); // satp
+
+        hart.set_reset_vector(0x200);
+        hart.reset().unwrap();
+
+        assert_eq!(hart.pc, 0x200);
+        assert_eq!(hart.reg[Reg::A0], 0);
+        assert_eq!(hart.read_csr(0x340), 0);
+        assert_eq!(hart.satp(), 0);
+        assert_eq!(reservation.load(Ordering::Relaxed), u32::MAX);
+        assert_eq!(bus.load_word(0x100).unwrap(), 3, "Dirty line was dropped");
+    }
+
+    #[test]
+    fn satp_enables_translation() {
+        let bus = Bus::builder().with_main_memory(8).build();
+        let reservation = AtomicU32::new(u32::MAX);
+        let mut hart = Hart::new(&bus, &reservation);
+
+        // 0x40003000 -> 0x5000, through a root table at 0x1000 and a leaf
+        // table at 0x2000
+        bus.store_word(0x1000 + 0x100 * 4, (2 << 10) | 1).unwrap();
+        bus.store_word(0x2000 + 3 * 4, (5 << 10) | 0x43).unwrap(); // A, R, V
+        bus.store_word(0x5008, 0xdeadbeef).unwrap();
+
+        hart.write_csr(0x180, 0x80000001);
+        assert_eq!(hart.satp(), 0x80000001);
+        assert_eq!(
+            hart.mmu.load_word(0x5008).unwrap(),
+            0xdeadbeef,
+            "Machine mode accesses are not translated"
+        );
+
+        // loads in machine mode are translated with MPRV set and MPP = S
+        hart.write_csr(0x3b0, u32::MAX);
+        hart.write_csr(0x3a0, 0x1f);
+        hart.write_csr(0x300, 1 << 17 | 1 << 11);
+        assert_eq!(hart.mmu.load_word(0x40003008).unwrap(), 0xdeadbeef);
+
+        hart.write_csr(0x300, 0);
+        hart.set_privilege(PrivilegeMode::Supervisor);
+        assert_eq!(hart.mmu.load_word(0x40003008).unwrap(), 0xdeadbeef);
+
+        hart.set_satp(0);
+        assert_eq!(hart.mmu.load_word(0x5008).unwrap(), 0xdeadbeef);
+    }
+
+    #[test]
+    fn pmp_csrs() {
+        let bus = Bus::builder().with_main_memory(1).build();
+        let reservation = AtomicU32::new(u32::MAX);
+        let mut hart = Hart::new(&bus, &reservation);
+
+        hart.write_csr(0x3b0, 0x100); // pmpaddr0
+        hart.write_csr(0x3a0, 0x890e); // pmpcfg0, entry 1 locked
+        hart.write_csr(0x3a4, 0xff); // pmpcfg4
+        hart.write_csr(0x3c0, 0xff); // pmpaddr16
+        hart.write_csr(0x3b0, 0x200);
+
+        assert_eq!(hart.read_csr(0x3a0), 0x890c, "W without R is reserved");
+        assert_eq!(hart.read_csr(0x3b0), 0x100, "Bottom of a locked TOR entry");
+        assert_eq!(hart.read_csr(0x3a4), 0);
+        assert_eq!(hart.read_csr(0x3c0), 0);
+
+        let state = hart.snapshot().unwrap();
+        hart.reset().unwrap();
+        assert_eq!(hart.mmu.pmp().cfg(0), 0);
+        hart.restore(&state);
+        assert_eq!(hart.mmu.pmp().cfg(0), 0x890c);
+        assert_eq!(hart.mmu.pmp().addr(0), 0x100);
+    }
+
+    #[test]
+    fn sfence_vma() {
+        let bus = Bus::builder().with_main_memory(8).build();
+        let reservation = AtomicU32::new(u32::MAX);
+        let mut hart = Hart::new(&bus, &reservation);
+
+        // 0x40003000 -> 0x5000, later remapped to 0x6000
+        let pte = 0x2000 + 3 * 4;
+        bus.store_word(0x1000 + 0x100 * 4, (2 << 10) | 1).unwrap();
+        bus.store_word(pte, (5 << 10) | 0x43).unwrap();
+        bus.store_word(0x5000, 1).unwrap();
+        bus.store_word(0x6000, 2).unwrap();
+
+        hart.write_csr(0x3b0, u32::MAX);
+        hart.write_csr(0x3a0, 0x1f);
+        hart.set_privilege(PrivilegeMode::Supervisor);
+        hart.set_satp(0x80000001);
+        assert_eq!(hart.mmu.load_word(0x40003000).unwrap(), 1);
+
+        hart.set_satp(0);
+        hart.mmu.store_word(pte, (6 << 10) | 0x43).unwrap();
+        hart.set_satp(0x80000001);
+        assert_eq!(
+            hart.mmu.load_word(0x40003000).unwrap(),
+            1,
+            "Translation should still be cached"
+        );
+
+        hart.sfence_vma(Some(0x40003000), None);
+        assert_eq!(hart.mmu.load_word(0x40003000).unwrap(), 2);
+    }
+
+    #[test]
+    fn delegated_interrupts() {
+        let bus = Bus::builder().with_main_memory(1).build();
+        let reservation = AtomicU32::new(u32::MAX);
+        let mut hart = Hart::new(&bus, &reservation);
+
+        hart.write_csr(0x302, u32::MAX); // medeleg
+        hart.write_csr(0x303, u32::MAX); // mideleg
+        assert_eq!(
+            hart.read_csr(0x302),
+            0xb3ff,
+            "ecall from M is never delegated"
+        );
+        assert_eq!(
+            hart.read_csr(0x303),
+            0x222,
+            "Only S interrupts are delegated"
+        );
+
+        hart.write_csr(0x105, 0x100); // stvec
+        hart.write_csr(0x305, 0x200); // mtvec
+        hart.write_csr(0x304, 1 << 5); // mie.STIE
+        hart.write_csr(0x344, 1 << 5); // mip.STIP
+
+        // delegated interrupts are masked in machine mode, even with MIE set
+        hart.write_csr(0x300, 1 << 3);
+        assert!(!hart.check_interrupts());
+
+        // and in supervisor mode without SIE
+        hart.set_privilege(PrivilegeMode::Supervisor);
+        hart.write_csr(0x300, 0);
+        assert!(!hart.check_interrupts());
+
+        // but always taken from user mode
+        hart.set_privilege(PrivilegeMode::User);
+        assert!(hart.check_interrupts());
+        assert_eq!(hart.pc, 0x100);
+        assert_eq!(hart.privilege(), PrivilegeMode::Supervisor);
+        assert_eq!(hart.read_csr(0x142), 0x80000005); // scause
+
+        // exceptions in machine mode stay there, whatever medeleg says
+        hart.set_privilege(PrivilegeMode::Machine);
+        hart.take_trap(2, 0);
+        assert_eq!((hart.pc, hart.read_csr(0x342)), (0x200, 2));
+    }
+
+    #[test]
+    fn take_trap() {
+        let bus = Bus::builder().with_main_memory(1).build();
+        let reservation = AtomicU32::new(u32::MAX);
+        let mut hart = Hart::new(&bus, &reservation);
+
+        hart.pc = 0x40;
+        hart.write_csr(0x300, 1 << 3); // mstatus.MIE
+        hart.write_csr(0x305, 0x201); // vectored, base 0x200
+
+        hart.take_trap(2, 0xdead);
+        assert_eq!(hart.pc, 0x200, "Exceptions should not be vectored");
+        assert_eq!(hart.read_csr(0x341), 0x40);
+        assert_eq!(hart.read_csr(0x342), 2);
+        assert_eq!(hart.read_csr(0x343), 0xdead);
+        assert_eq!(hart.read_csr(0x300), 1 << 7 | 3 << 11, "MIE not pushed");
+
+        hart.mret();
+        assert_eq!(hart.pc, 0x40);
+        assert_eq!(hart.read_csr(0x300), 1 << 3 | 1 << 7, "MIE not popped");
+
+        hart.take_trap(0x80000007, 0);
+        assert_eq!(hart.pc, 0x200 + 4 * 7, "Interrupts should be vectored");
+    }
 }
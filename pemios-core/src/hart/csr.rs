@@ -7,8 +7,11 @@
 //
 // Copyright © 2022 mumblingdrunkard
 
+use crate::hart::PrivilegeMode;
+
 #[allow(unused)]
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(u16)]
 pub enum Csr {
     FFlags = 0,
@@ -606,10 +609,395 @@ impl From<u32> for Csr {
     }
 }
 
+impl From<Csr> for u32 {
+    /// The address of `csr`, where `Csr::Invalid` has the address 0, which is not a CSR
+    fn from(csr: Csr) -> Self {
+        use Csr::*;
+        match csr {
+            FFlags => 0x001,
+            Frm => 0x002,
+            FCsr => 0x003,
+
+            Cycle => 0xC00,
+            Time => 0xC01,
+            InstRet => 0xC02,
+            HpmCounter3 => 0xC03,
+            HpmCounter4 => 0xC04,
+            HpmCounter5 => 0xC05,
+            HpmCounter6 => 0xC06,
+            HpmCounter7 => 0xC07,
+            HpmCounter8 => 0xC08,
+            HpmCounter9 => 0xC09,
+            HpmCounter10 => 0xC0A,
+            HpmCounter11 => 0xC0B,
+            HpmCounter12 => 0xC0C,
+            HpmCounter13 => 0xC0D,
+            HpmCounter14 => 0xC0E,
+            HpmCounter15 => 0xC0F,
+            HpmCounter16 => 0xC10,
+            HpmCounter17 => 0xC11,
+            HpmCounter18 => 0xC12,
+            HpmCounter19 => 0xC13,
+            HpmCounter20 => 0xC14,
+            HpmCounter21 => 0xC15,
+            HpmCounter22 => 0xC16,
+            HpmCounter23 => 0xC17,
+            HpmCounter24 => 0xC18,
+            HpmCounter25 => 0xC19,
+            HpmCounter26 => 0xC1A,
+            HpmCounter27 => 0xC1B,
+            HpmCounter28 => 0xC1C,
+            HpmCounter29 => 0xC1D,
+            HpmCounter30 => 0xC1E,
+            HpmCounter31 => 0xC1F,
+
+            Cycleh => 0xC80,
+            Timeh => 0xC81,
+            InstReth => 0xC82,
+            HpmCounter3h => 0xC83,
+            HpmCounter4h => 0xC84,
+            HpmCounter5h => 0xC85,
+            HpmCounter6h => 0xC86,
+            HpmCounter7h => 0xC87,
+            HpmCounter8h => 0xC88,
+            HpmCounter9h => 0xC89,
+            HpmCounter10h => 0xC8A,
+            HpmCounter11h => 0xC8B,
+            HpmCounter12h => 0xC8C,
+            HpmCounter13h => 0xC8D,
+            HpmCounter14h => 0xC8E,
+            HpmCounter15h => 0xC8F,
+            HpmCounter16h => 0xC90,
+            HpmCounter17h => 0xC91,
+            HpmCounter18h => 0xC92,
+            HpmCounter19h => 0xC93,
+            HpmCounter20h => 0xC94,
+            HpmCounter21h => 0xC95,
+            HpmCounter22h => 0xC96,
+            HpmCounter23h => 0xC97,
+            HpmCounter24h => 0xC98,
+            HpmCounter25h => 0xC99,
+            HpmCounter26h => 0xC9A,
+            HpmCounter27h => 0xC9B,
+            HpmCounter28h => 0xC9C,
+            HpmCounter29h => 0xC9D,
+            HpmCounter30h => 0xC9E,
+            HpmCounter31h => 0xC9F,
+
+            SStatus => 0x100,
+            SSie => 0x104,
+            STVec => 0x105,
+            SCounterEn => 0x106,
+
+            SEnvCfg => 0x10A,
+
+            SScratch => 0x140,
+            Sepc => 0x141,
+            SCause => 0x142,
+            STVal => 0x143,
+            Sip => 0x144,
+
+            Satp => 0x180,
+            SContext => 0x5A8,
+
+            MVendorId => 0xF11,
+            MArchId => 0xF12,
+            MImpId => 0xF13,
+            MHartId => 0xF14,
+            MConfigPtr => 0xF15,
+
+            MStatus => 0x300,
+            Misa => 0x301,
+            MEDeleg => 0x302,
+            MIDeleg => 0x303,
+            Mie => 0x304,
+            MTVec => 0x305,
+            MCounterEn => 0x306,
+            MStatusH => 0x310,
+
+            MScratch => 0x340,
+            Mepc => 0x341,
+            MCause => 0x342,
+            MTVal => 0x343,
+            Mip => 0x344,
+            MTInst => 0x34A,
+            MTVal2 => 0x34B,
+
+            MEnvCfg => 0x30A,
+            MEnvCfgh => 0x31A,
+            MSecCfg => 0x747,
+            MSecCfgh => 0x757,
+
+            PmpCfg0 => 0x3A0,
+            PmpCfg1 => 0x3A1,
+            PmpCfg2 => 0x3A2,
+            PmpCfg3 => 0x3A3,
+            PmpCfg4 => 0x3A4,
+            PmpCfg5 => 0x3A5,
+            PmpCfg6 => 0x3A6,
+            PmpCfg7 => 0x3A7,
+            PmpCfg08 => 0x3A8,
+            PmpCfg09 => 0x3A9,
+            PmpCfg10 => 0x3AA,
+            PmpCfg11 => 0x3AB,
+            PmpCfg12 => 0x3AC,
+            PmpCfg13 => 0x3AD,
+            PmpCfg14 => 0x3AE,
+            PmpCfg15 => 0x3AF,
+
+            PmpAddr0 => 0x3B0,
+            PmpAddr1 => 0x3B1,
+            PmpAddr2 => 0x3B2,
+            PmpAddr3 => 0x3B3,
+            PmpAddr4 => 0x3B4,
+            PmpAddr5 => 0x3B5,
+            PmpAddr6 => 0x3B6,
+            PmpAddr7 => 0x3B7,
+            PmpAddr8 => 0x3B8,
+            PmpAddr9 => 0x3B9,
+            PmpAddr10 => 0x3BA,
+            PmpAddr11 => 0x3BB,
+            PmpAddr12 => 0x3BC,
+            PmpAddr13 => 0x3BD,
+            PmpAddr14 => 0x3BE,
+            PmpAddr15 => 0x3BF,
+            PmpAddr16 => 0x3C0,
+            PmpAddr17 => 0x3C1,
+            PmpAddr18 => 0x3C2,
+            PmpAddr19 => 0x3C3,
+            PmpAddr20 => 0x3C4,
+            PmpAddr21 => 0x3C5,
+            PmpAddr22 => 0x3C6,
+            PmpAddr23 => 0x3C7,
+            PmpAddr24 => 0x3C8,
+            PmpAddr25 => 0x3C9,
+            PmpAddr26 => 0x3CA,
+            PmpAddr27 => 0x3CB,
+            PmpAddr28 => 0x3CC,
+            PmpAddr29 => 0x3CD,
+            PmpAddr30 => 0x3CE,
+            PmpAddr31 => 0x3CF,
+            PmpAddr32 => 0x3D0,
+            PmpAddr33 => 0x3D1,
+            PmpAddr34 => 0x3D2,
+            PmpAddr35 => 0x3D3,
+            PmpAddr36 => 0x3D4,
+            PmpAddr37 => 0x3D5,
+            PmpAddr38 => 0x3D6,
+            PmpAddr39 => 0x3D7,
+            PmpAddr40 => 0x3D8,
+            PmpAddr41 => 0x3D9,
+            PmpAddr42 => 0x3DA,
+            PmpAddr43 => 0x3DB,
+            PmpAddr44 => 0x3DC,
+            PmpAddr45 => 0x3DD,
+            PmpAddr46 => 0x3DE,
+            PmpAddr47 => 0x3DF,
+            PmpAddr48 => 0x3E0,
+            PmpAddr49 => 0x3E1,
+            PmpAddr50 => 0x3E2,
+            PmpAddr51 => 0x3E3,
+            PmpAddr52 => 0x3E4,
+            PmpAddr53 => 0x3E5,
+            PmpAddr54 => 0x3E6,
+            PmpAddr55 => 0x3E7,
+            PmpAddr56 => 0x3E8,
+            PmpAddr57 => 0x3E9,
+            PmpAddr58 => 0x3EA,
+            PmpAddr59 => 0x3EB,
+            PmpAddr60 => 0x3EC,
+            PmpAddr61 => 0x3ED,
+            PmpAddr62 => 0x3EE,
+            PmpAddr63 => 0x3EF,
+
+            MCycle => 0xB00,
+            MInstRet => 0xB02,
+            MHpmCounter3 => 0xB03,
+            MHpmCounter4 => 0xB04,
+            MHpmCounter5 => 0xB05,
+            MHpmCounter6 => 0xB06,
+            MHpmCounter7 => 0xB07,
+            MHpmCounter8 => 0xB08,
+            MHpmCounter9 => 0xB09,
+            MHpmCounter10 => 0xB0A,
+            MHpmCounter11 => 0xB0B,
+            MHpmCounter12 => 0xB0C,
+            MHpmCounter13 => 0xB0D,
+            MHpmCounter14 => 0xB0E,
+            MHpmCounter15 => 0xB0F,
+            MHpmCounter16 => 0xB10,
+            MHpmCounter17 => 0xB11,
+            MHpmCounter18 => 0xB12,
+            MHpmCounter19 => 0xB13,
+            MHpmCounter20 => 0xB14,
+            MHpmCounter21 => 0xB15,
+            MHpmCounter22 => 0xB16,
+            MHpmCounter23 => 0xB17,
+            MHpmCounter24 => 0xB18,
+            MHpmCounter25 => 0xB19,
+            MHpmCounter26 => 0xB1A,
+            MHpmCounter27 => 0xB1B,
+            MHpmCounter28 => 0xB1C,
+            MHpmCounter29 => 0xB1D,
+            MHpmCounter30 => 0xB1E,
+            MHpmCounter31 => 0xB1F,
+
+            MCycleh => 0xB80,
+            MInstReth => 0xB82,
+            MHpmCounter3h => 0xB83,
+            MHpmCounter4h => 0xB84,
+            MHpmCounter5h => 0xB85,
+            MHpmCounter6h => 0xB86,
+            MHpmCounter7h => 0xB87,
+            MHpmCounter8h => 0xB88,
+            MHpmCounter9h => 0xB89,
+            MHpmCounter10h => 0xB8A,
+            MHpmCounter11h => 0xB8B,
+            MHpmCounter12h => 0xB8C,
+            MHpmCounter13h => 0xB8D,
+            MHpmCounter14h => 0xB8E,
+            MHpmCounter15h => 0xB8F,
+            MHpmCounter16h => 0xB90,
+            MHpmCounter17h => 0xB91,
+            MHpmCounter18h => 0xB92,
+            MHpmCounter19h => 0xB93,
+            MHpmCounter20h => 0xB94,
+            MHpmCounter21h => 0xB95,
+            MHpmCounter22h => 0xB96,
+            MHpmCounter23h => 0xB97,
+            MHpmCounter24h => 0xB98,
+            MHpmCounter25h => 0xB99,
+            MHpmCounter26h => 0xB9A,
+            MHpmCounter27h => 0xB9B,
+            MHpmCounter28h => 0xB9C,
+            MHpmCounter29h => 0xB9D,
+            MHpmCounter30h => 0xB9E,
+            MHpmCounter31h => 0xB9F,
+
+            MCountInhibit => 0x320,
+            MHpmEvent3 => 0x323,
+            MHpmEvent4 => 0x324,
+            MHpmEvent5 => 0x325,
+            MHpmEvent6 => 0x326,
+            MHpmEvent7 => 0x327,
+            MHpmEvent8 => 0x328,
+            MHpmEvent9 => 0x329,
+            MHpmEvent10 => 0x32A,
+            MHpmEvent11 => 0x32B,
+            MHpmEvent12 => 0x32C,
+            MHpmEvent13 => 0x32D,
+            MHpmEvent14 => 0x32E,
+            MHpmEvent15 => 0x32F,
+            MHpmEvent16 => 0x330,
+            MHpmEvent17 => 0x331,
+            MHpmEvent18 => 0x332,
+            MHpmEvent19 => 0x333,
+            MHpmEvent20 => 0x334,
+            MHpmEvent21 => 0x335,
+            MHpmEvent22 => 0x336,
+            MHpmEvent23 => 0x337,
+            MHpmEvent24 => 0x338,
+            MHpmEvent25 => 0x339,
+            MHpmEvent26 => 0x33A,
+            MHpmEvent27 => 0x33B,
+            MHpmEvent28 => 0x33C,
+            MHpmEvent29 => 0x33D,
+            MHpmEvent30 => 0x33E,
+            MHpmEvent31 => 0x33F,
+            Invalid => 0x000,
+        }
+    }
+}
+
+impl Csr {
+    /// The standard name of the CSR, as used by assemblers
+    pub fn name(&self) -> &'static str {
+        #[rustfmt::skip]
+        const NAMES: [&str; CSR_SIZE] = [
+            "fflags", "frm", "fcsr", "cycle", "time", "instret", "hpmcounter3", "hpmcounter4",
+            "hpmcounter5", "hpmcounter6", "hpmcounter7", "hpmcounter8", "hpmcounter9",
+            "hpmcounter10", "hpmcounter11", "hpmcounter12", "hpmcounter13", "hpmcounter14",
+            "hpmcounter15", "hpmcounter16", "hpmcounter17", "hpmcounter18", "hpmcounter19",
+            "hpmcounter20", "hpmcounter21", "hpmcounter22", "hpmcounter23", "hpmcounter24",
+            "hpmcounter25", "hpmcounter26", "hpmcounter27", "hpmcounter28", "hpmcounter29",
+            "hpmcounter30", "hpmcounter31", "cycleh", "timeh", "instreth", "hpmcounter3h",
+            "hpmcounter4h", "hpmcounter5h", "hpmcounter6h", "hpmcounter7h", "hpmcounter8h",
+            "hpmcounter9h", "hpmcounter10h", "hpmcounter11h", "hpmcounter12h", "hpmcounter13h",
+            "hpmcounter14h", "hpmcounter15h", "hpmcounter16h", "hpmcounter17h", "hpmcounter18h",
+            "hpmcounter19h", "hpmcounter20h", "hpmcounter21h", "hpmcounter22h", "hpmcounter23h",
+            "hpmcounter24h", "hpmcounter25h", "hpmcounter26h", "hpmcounter27h", "hpmcounter28h",
+            "hpmcounter29h", "hpmcounter30h", "hpmcounter31h", "sstatus", "sie", "stvec",
+            "scounteren", "senvcfg", "sscratch", "sepc", "scause", "stval", "sip", "satp",
+            "scontext", "mvendorid", "marchid", "mimpid", "mhartid", "mconfigptr", "mstatus",
+            "misa", "medeleg", "mideleg", "mie", "mtvec", "mcounteren", "mstatush", "mscratch",
+            "mepc", "mcause", "mtval", "mip", "mtinst", "mtval2", "menvcfg", "menvcfgh", "mseccfg",
+            "mseccfgh", "pmpcfg0", "pmpcfg1", "pmpcfg2", "pmpcfg3", "pmpcfg4", "pmpcfg5", "pmpcfg6",
+            "pmpcfg7", "pmpcfg8", "pmpcfg9", "pmpcfg10", "pmpcfg11", "pmpcfg12", "pmpcfg13",
+            "pmpcfg14", "pmpcfg15", "pmpaddr0", "pmpaddr1", "pmpaddr2", "pmpaddr3", "pmpaddr4",
+            "pmpaddr5", "pmpaddr6", "pmpaddr7", "pmpaddr8", "pmpaddr9", "pmpaddr10", "pmpaddr11",
+            "pmpaddr12", "pmpaddr13", "pmpaddr14", "pmpaddr15", "pmpaddr16", "pmpaddr17",
+            "pmpaddr18", "pmpaddr19", "pmpaddr20", "pmpaddr21", "pmpaddr22", "pmpaddr23",
+            "pmpaddr24", "pmpaddr25", "pmpaddr26", "pmpaddr27", "pmpaddr28", "pmpaddr29",
+            "pmpaddr30", "pmpaddr31", "pmpaddr32", "pmpaddr33", "pmpaddr34", "pmpaddr35",
+            "pmpaddr36", "pmpaddr37", "pmpaddr38", "pmpaddr39", "pmpaddr40", "pmpaddr41",
+            "pmpaddr42", "pmpaddr43", "pmpaddr44", "pmpaddr45", "pmpaddr46", "pmpaddr47",
+            "pmpaddr48", "pmpaddr49", "pmpaddr50", "pmpaddr51", "pmpaddr52", "pmpaddr53",
+            "pmpaddr54", "pmpaddr55", "pmpaddr56", "pmpaddr57", "pmpaddr58", "pmpaddr59",
+            "pmpaddr60", "pmpaddr61", "pmpaddr62", "pmpaddr63", "mcycle", "minstret",
+            "mhpmcounter3", "mhpmcounter4", "mhpmcounter5", "mhpmcounter6", "mhpmcounter7",
+            "mhpmcounter8", "mhpmcounter9", "mhpmcounter10", "mhpmcounter11", "mhpmcounter12",
+            "mhpmcounter13", "mhpmcounter14", "mhpmcounter15", "mhpmcounter16", "mhpmcounter17",
+            "mhpmcounter18", "mhpmcounter19", "mhpmcounter20", "mhpmcounter21", "mhpmcounter22",
+            "mhpmcounter23", "mhpmcounter24", "mhpmcounter25", "mhpmcounter26", "mhpmcounter27",
+            "mhpmcounter28", "mhpmcounter29", "mhpmcounter30", "mhpmcounter31", "mcycleh",
+            "minstreth", "mhpmcounter3h", "mhpmcounter4h", "mhpmcounter5h", "mhpmcounter6h",
+            "mhpmcounter7h", "mhpmcounter8h", "mhpmcounter9h", "mhpmcounter10h", "mhpmcounter11h",
+            "mhpmcounter12h", "mhpmcounter13h", "mhpmcounter14h", "mhpmcounter15h",
+            "mhpmcounter16h", "mhpmcounter17h", "mhpmcounter18h", "mhpmcounter19h",
+            "mhpmcounter20h", "mhpmcounter21h", "mhpmcounter22h", "mhpmcounter23h",
+            "mhpmcounter24h", "mhpmcounter25h", "mhpmcounter26h", "mhpmcounter27h",
+            "mhpmcounter28h", "mhpmcounter29h", "mhpmcounter30h", "mhpmcounter31h", "mcountinhibit",
+            "mhpmevent3", "mhpmevent4", "mhpmevent5", "mhpmevent6", "mhpmevent7", "mhpmevent8",
+            "mhpmevent9", "mhpmevent10", "mhpmevent11", "mhpmevent12", "mhpmevent13", "mhpmevent14",
+            "mhpmevent15", "mhpmevent16", "mhpmevent17", "mhpmevent18", "mhpmevent19",
+            "mhpmevent20", "mhpmevent21", "mhpmevent22", "mhpmevent23", "mhpmevent24",
+            "mhpmevent25", "mhpmevent26", "mhpmevent27", "mhpmevent28", "mhpmevent29",
+            "mhpmevent30", "mhpmevent31", "invalid",
+        ];
+
+        NAMES[*self as usize]
+    }
+
+    /// Whether the CSR is read-only, which is encoded in the top two bits of
+    /// its address
+    pub fn is_read_only(&self) -> bool {
+        u32::from(*self) >> 10 == 3
+    }
+
+    /// The lowest privilege level that may access the CSR, which is encoded
+    /// in bits 9:8 of its address
+    pub fn privilege(&self) -> u32 {
+        (u32::from(*self) >> 8) & 3
+    }
+}
+
+impl std::fmt::Display for Csr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Clone)]
 pub struct CsrFile {
     reg: [u32; CSR_SIZE],
 }
 
+impl Default for CsrFile {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[allow(unused)]
 impl CsrFile {
     pub fn new() -> Self {
@@ -623,7 +1011,169 @@ pub struct MIsa(u32);
 pub struct MVendorId(u32);
 // SD(1) WPRI(8) TSR(1) TW(1) MXR(1) SUM(1) MPRV(1) XS(2) FS(2) MPP(2) VS(2)
 // SPP(1) MPIE(1) UBE(1) SPIE(1) WPRI(1) MIE(1) WPRI(1) SIE(1) WPRI(1)
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct MStatus(u32);
+
+impl MStatus {
+    const SIE: u32 = 1 << 1;
+    const MIE: u32 = 1 << 3;
+    const SPIE: u32 = 1 << 5;
+    const MPIE: u32 = 1 << 7;
+    const SPP: u32 = 1 << 8;
+    const MPP: u32 = 3 << 11;
+    const MPRV: u32 = 1 << 17;
+    const SUM: u32 = 1 << 18;
+    const MXR: u32 = 1 << 19;
+    const TSR: u32 = 1 << 22;
+
+    fn bit(&self, mask: u32) -> bool {
+        self.0 & mask != 0
+    }
+
+    fn set_bit(&mut self, mask: u32, val: bool) {
+        self.0 = if val { self.0 | mask } else { self.0 & !mask };
+    }
+
+    pub fn sie(&self) -> bool {
+        self.bit(Self::SIE)
+    }
+
+    pub fn set_sie(&mut self, val: bool) {
+        self.set_bit(Self::SIE, val);
+    }
+
+    pub fn mie(&self) -> bool {
+        self.bit(Self::MIE)
+    }
+
+    pub fn set_mie(&mut self, val: bool) {
+        self.set_bit(Self::MIE, val);
+    }
+
+    pub fn spie(&self) -> bool {
+        self.bit(Self::SPIE)
+    }
+
+    pub fn set_spie(&mut self, val: bool) {
+        self.set_bit(Self::SPIE, val);
+    }
+
+    pub fn mpie(&self) -> bool {
+        self.bit(Self::MPIE)
+    }
+
+    pub fn set_mpie(&mut self, val: bool) {
+        self.set_bit(Self::MPIE, val);
+    }
+
+    /// The mode a supervisor-mode trap was taken from, which is never machine
+    /// mode
+    pub fn spp(&self) -> PrivilegeMode {
+        PrivilegeMode::from(u32::from(self.bit(Self::SPP)))
+    }
+
+    /// Sets `SPP`, which only holds whether `mode` is supervisor mode.
+    pub fn set_spp(&mut self, mode: PrivilegeMode) {
+        self.set_bit(Self::SPP, mode != PrivilegeMode::User);
+    }
+
+    /// The mode a machine-mode trap was taken from
+    pub fn mpp(&self) -> PrivilegeMode {
+        PrivilegeMode::from(self.0 >> 11)
+    }
+
+    pub fn set_mpp(&mut self, mode: PrivilegeMode) {
+        self.0 = (self.0 & !Self::MPP) | (mode as u32) << 11;
+    }
+
+    pub fn mprv(&self) -> bool {
+        self.bit(Self::MPRV)
+    }
+
+    pub fn set_mprv(&mut self, val: bool) {
+        self.set_bit(Self::MPRV, val);
+    }
+
+    /// Whether supervisor mode may load from and store to user pages
+    pub fn sum(&self) -> bool {
+        self.bit(Self::SUM)
+    }
+
+    pub fn set_sum(&mut self, val: bool) {
+        self.set_bit(Self::SUM, val);
+    }
+
+    /// Whether loads may read from executable pages that are not readable
+    pub fn mxr(&self) -> bool {
+        self.bit(Self::MXR)
+    }
+
+    pub fn set_mxr(&mut self, val: bool) {
+        self.set_bit(Self::MXR, val);
+    }
+
+    /// Whether `sret` is illegal in supervisor mode
+    pub fn tsr(&self) -> bool {
+        self.bit(Self::TSR)
+    }
+
+    /// Enters a machine-mode trap taken from `mode`, pushing `MIE` onto
+    /// `MPIE` and `mode` into `MPP`.
+    pub fn push_machine(&mut self, mode: PrivilegeMode) {
+        self.set_mpie(self.mie());
+        self.set_mie(false);
+        self.set_mpp(mode);
+    }
+
+    /// Returns from a machine-mode trap, popping `MPIE` back into `MIE` and
+    /// returning the mode in `MPP`.
+    ///
+    /// `MPIE` is set, `MPP` is left as user mode, and `MPRV` is cleared when
+    /// returning below machine mode.
+    pub fn pop_machine(&mut self) -> PrivilegeMode {
+        let mode = self.mpp();
+        self.set_mie(self.mpie());
+        self.set_mpie(true);
+        self.set_mpp(PrivilegeMode::User);
+        if mode != PrivilegeMode::Machine {
+            self.set_mprv(false);
+        }
+        mode
+    }
+
+    /// Enters a supervisor-mode trap taken from `mode`, pushing `SIE` onto
+    /// `SPIE` and `mode` into `SPP`.
+    pub fn push_supervisor(&mut self, mode: PrivilegeMode) {
+        self.set_spie(self.sie());
+        self.set_sie(false);
+        self.set_spp(mode);
+    }
+
+    /// Returns from a supervisor-mode trap, popping `SPIE` back into `SIE` and
+    /// returning the mode in `SPP`.
+    ///
+    /// `SPIE` is set, `SPP` is left as user mode, and `MPRV` is cleared.
+    pub fn pop_supervisor(&mut self) -> PrivilegeMode {
+        let mode = self.spp();
+        self.set_sie(self.spie());
+        self.set_spie(true);
+        self.set_spp(PrivilegeMode::User);
+        self.set_mprv(false);
+        mode
+    }
+}
+
+impl From<u32> for MStatus {
+    fn from(bits: u32) -> Self {
+        Self(bits)
+    }
+}
+
+impl From<MStatus> for u32 {
+    fn from(mstatus: MStatus) -> Self {
+        mstatus.0
+    }
+}
 // WPRI(26) MBE(1) SBE(1) WPRI(4)
 pub struct MStatush(u32);
 
@@ -640,3 +1190,80 @@ impl std::ops::IndexMut<Csr> for CsrFile {
         unsafe { self.reg.get_unchecked_mut(index as usize) }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::hart::PrivilegeMode;
+
+    use super::{Csr, MStatus};
+
+    #[test]
+    fn names() {
+        for (addr, name) in [
+            (0x300, "mstatus"),
+            (0x305, "mtvec"),
+            (0x341, "mepc"),
+            (0x342, "mcause"),
+            (0x343, "mtval"),
+            (0x304, "mie"),
+            (0x344, "mip"),
+            (0x340, "mscratch"),
+            (0x180, "satp"),
+            (0xf14, "mhartid"),
+            (0x104, "sie"),
+            (0xc00, "cycle"),
+            (0xc01, "time"),
+            (0xc02, "instret"),
+            (0xc83, "hpmcounter3h"),
+            (0x3a0, "pmpcfg0"),
+            (0x3a8, "pmpcfg8"),
+        ] {
+            assert_eq!(Csr::from(addr).name(), name);
+            assert_eq!(Csr::from(addr).to_string(), name);
+        }
+    }
+
+    #[test]
+    fn read_only() {
+        assert!(Csr::MHartId.is_read_only());
+        assert!(Csr::Cycle.is_read_only());
+        assert!(!Csr::MCycle.is_read_only());
+        assert!(!Csr::MStatus.is_read_only());
+    }
+
+    #[test]
+    fn mstatus_trap_stack() {
+        let mut mstatus = MStatus::from(0);
+        mstatus.set_mie(true);
+
+        // a trap from supervisor mode into machine mode
+        mstatus.push_machine(PrivilegeMode::Supervisor);
+        assert!(!mstatus.mie());
+        assert!(mstatus.mpie());
+        assert_eq!(mstatus.mpp(), PrivilegeMode::Supervisor);
+        assert_eq!(u32::from(mstatus), 1 << 7 | 1 << 11);
+
+        // the handler returns with mret
+        mstatus.set_mprv(true);
+        assert_eq!(mstatus.pop_machine(), PrivilegeMode::Supervisor);
+        assert!(mstatus.mie());
+        assert!(mstatus.mpie());
+        assert_eq!(mstatus.mpp(), PrivilegeMode::User);
+        assert!(!mstatus.mprv(), "MPRV is cleared below machine mode");
+
+        // a trap from user mode into supervisor mode, with interrupts off
+        mstatus.push_supervisor(PrivilegeMode::User);
+        assert!(!mstatus.spie());
+        assert_eq!(mstatus.spp(), PrivilegeMode::User);
+        assert_eq!(mstatus.pop_supervisor(), PrivilegeMode::User);
+        assert!(!mstatus.sie());
+        assert!(mstatus.spie());
+
+        mstatus.set_sie(true);
+        mstatus.push_supervisor(PrivilegeMode::Supervisor);
+        assert!(!mstatus.sie());
+        assert_eq!(mstatus.spp(), PrivilegeMode::Supervisor);
+        assert_eq!(mstatus.pop_supervisor(), PrivilegeMode::Supervisor);
+        assert!(mstatus.sie());
+    }
+}
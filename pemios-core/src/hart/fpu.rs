@@ -0,0 +1,377 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// This Source Code Form is "Incompatible With Secondary Licenses", as
+// defined by the Mozilla Public License, v. 2.0.
+//
+// Copyright © 2022 mumblingdrunkard
+
+//! Single-precision arithmetic with the rounding modes and exception flags of
+//! the F extension.
+//!
+//! The host only rounds to nearest, so operations are carried out in double
+//! precision, which holds every single-precision value exactly.
+//! The double-precision result is then rounded to single precision in the
+//! requested mode, using the sign of its own rounding error to break ties
+//! between it and the exact result.
+//!
+//! Values are passed around as the bits of an `f32`, as they are stored in the
+//! floating-point registers.
+
+use crate::hart::instruction::RoundingMode;
+
+/// The exception flags accrued in `fflags`
+pub mod flags {
+    /// Inexact
+    pub const NX: u32 = 1;
+    /// Underflow
+    pub const UF: u32 = 2;
+    /// Overflow
+    pub const OF: u32 = 4;
+    /// Divide by zero
+    pub const DZ: u32 = 8;
+    /// Invalid operation
+    pub const NV: u32 = 16;
+}
+
+use flags::*;
+
+/// The NaN produced by any operation that produces a NaN
+pub const CANONICAL_NAN: u32 = 0x7fc00000;
+
+const SIGN: u32 = 0x80000000;
+
+fn is_nan(a: u32) -> bool {
+    a & !SIGN > 0x7f800000
+}
+
+fn is_signaling(a: u32) -> bool {
+    is_nan(a) && a & 0x00400000 == 0
+}
+
+fn float(a: u32) -> f64 {
+    f32::from_bits(a) as f64
+}
+
+/// Rounds the exact result `value + error` to single precision.
+///
+/// `value` is the exact result rounded to double precision, so `error` is
+/// smaller than half an ulp of it and only its sign matters.
+/// `rm` must not be dynamic.
+fn round(value: f64, error: f64, rm: RoundingMode) -> (u32, u32) {
+    use RoundingMode::*;
+
+    let sign = if value.is_sign_negative() { SIGN } else { 0 };
+    if value.is_infinite() {
+        return (sign | 0x7f800000, 0);
+    } else if value == 0.0 {
+        return (sign, 0);
+    }
+
+    // every single-precision result is a normal double, with a significand of
+    // 53 bits where the leading one weighs 2^exp
+    let bits = value.to_bits();
+    let exp = ((bits >> 52) & 0x7ff) as i32 - 1023;
+    let significand = bits & 0x000fffffffffffff | 0x0010000000000000;
+
+    // two more bits below the significand place the exact result strictly
+    // between `value` and its neighbours, so it never lands on a tie or a
+    // representable value unless `value` is exact
+    let towards = match error.partial_cmp(&0.0) {
+        Some(std::cmp::Ordering::Equal) | None => 0,
+        _ if (error < 0.0) == (value < 0.0) => 1,
+        _ => -1,
+    };
+    let m = ((significand << 2) as i64 + towards) as u64;
+
+    let round_up = |shift: u32| -> (u64, bool) {
+        let (kept, rem, half) = if shift > 56 {
+            (0, m, 1 << 56)
+        } else {
+            (m >> shift, m & ((1 << shift) - 1), 1 << (shift - 1))
+        };
+        let up = match rm {
+            Rne => rem > half || (rem == half && kept & 1 == 1),
+            Rtz => false,
+            Rdn => rem != 0 && sign != 0,
+            Rup => rem != 0 && sign == 0,
+            Rmm => rem >= half,
+            Dynamic => unreachable!("the dynamic rounding mode must be resolved first"),
+        };
+        (kept + up as u64, rem != 0)
+    };
+
+    // keep 24 bits, or fewer for subnormals
+    let (magnitude, inexact) = round_up(31 + (-126 - exp).max(0) as u32);
+    // the leading one carries into the exponent field, which is 0 for subnormals
+    let exponent = if exp >= -126 { (exp + 126) as u64 } else { 0 };
+    let magnitude = magnitude + (exponent << 23);
+
+    if magnitude >= 0x7f800000 {
+        let infinite = match rm {
+            Rtz => false,
+            Rdn => sign != 0,
+            Rup => sign == 0,
+            _ => true,
+        };
+        let magnitude = if infinite { 0x7f800000 } else { 0x7f7fffff };
+        return (sign | magnitude, OF | NX);
+    }
+
+    // tininess is detected after rounding, as if the exponent was unbounded
+    let tiny = exp < -127 || (exp == -127 && round_up(31).0 < 1 << 24);
+    let flags = match (inexact, tiny) {
+        (false, _) => 0,
+        (true, false) => NX,
+        (true, true) => NX | UF,
+    };
+
+    (sign | magnitude as u32, flags)
+}
+
+/// The canonical NaN and the flags raised by an operation on `a` and `b`
+/// whose result is a NaN
+fn nan(a: u32, b: u32) -> (u32, u32) {
+    let invalid = is_signaling(a) || is_signaling(b) || !(is_nan(a) || is_nan(b));
+    (CANONICAL_NAN, if invalid { NV } else { 0 })
+}
+
+pub fn add(a: u32, b: u32, rm: RoundingMode) -> (u32, u32) {
+    let (x, y) = (float(a), float(b));
+    let mut sum = x + y;
+    if sum.is_nan() {
+        return nan(a, b);
+    }
+
+    // the sum of values with opposite signs is -0 when rounding down
+    if sum == 0.0 && rm == RoundingMode::Rdn && (a | b) & SIGN != 0 {
+        sum = -0.0;
+    }
+
+    // the error of the sum is exact, by Knuth's two-sum
+    let y_part = sum - x;
+    let error = (x - (sum - y_part)) + (y - y_part);
+    round(sum, error, rm)
+}
+
+pub fn sub(a: u32, b: u32, rm: RoundingMode) -> (u32, u32) {
+    add(a, b ^ SIGN, rm)
+}
+
+pub fn mul(a: u32, b: u32, rm: RoundingMode) -> (u32, u32) {
+    // the product of two 24-bit significands fits in a double exactly
+    let product = float(a) * float(b);
+    if product.is_nan() {
+        return nan(a, b);
+    }
+
+    round(product, 0.0, rm)
+}
+
+pub fn div(a: u32, b: u32, rm: RoundingMode) -> (u32, u32) {
+    let (x, y) = (float(a), float(b));
+    let quotient = x / y;
+    if quotient.is_nan() {
+        return nan(a, b);
+    } else if y == 0.0 && x.is_finite() {
+        return (round(quotient, 0.0, rm).0, DZ);
+    }
+
+    // x = quotient * y + remainder, where the remainder is exact
+    let remainder = (-quotient).mul_add(y, x);
+    round(quotient, remainder * y.signum(), rm)
+}
+
+pub fn sqrt(a: u32, rm: RoundingMode) -> (u32, u32) {
+    let x = float(a);
+    let root = x.sqrt();
+    if root.is_nan() {
+        return nan(a, a);
+    }
+
+    let remainder = (-root).mul_add(root, x);
+    round(root, remainder, rm)
+}
+
+/// Replaces the sign of `a` by `op(a, b)` applied to the signs of `a` and `b`
+pub fn sign_inject(a: u32, b: u32, op: fn(u32, u32) -> u32) -> u32 {
+    a & !SIGN | op(a, b) & SIGN
+}
+
+/// The smaller of `a` and `b` if `min` is set and the larger otherwise, where
+/// -0 is smaller than +0 and NaNs are only returned if both are NaNs
+pub fn min_max(a: u32, b: u32, min: bool) -> (u32, u32) {
+    let flags = if is_signaling(a) || is_signaling(b) {
+        NV
+    } else {
+        0
+    };
+    let result = match (is_nan(a), is_nan(b)) {
+        (true, true) => CANONICAL_NAN,
+        (true, false) => b,
+        (false, true) => a,
+        _ => {
+            let (x, y) = (f32::from_bits(a), f32::from_bits(b));
+            let a_first = x < y || (x == y && a & SIGN != 0);
+            if a_first == min {
+                a
+            } else {
+                b
+            }
+        }
+    };
+
+    (result, flags)
+}
+
+/// Compares `a` and `b` with `op`, which is false if either is a NaN.
+///
+/// `feq.s` is a quiet comparison that only raises the invalid flag for
+/// signaling NaNs, while the others raise it for all NaNs.
+pub fn compare(a: u32, b: u32, quiet: bool, op: fn(&f32, &f32) -> bool) -> (u32, u32) {
+    let invalid = if quiet {
+        is_signaling(a) || is_signaling(b)
+    } else {
+        is_nan(a) || is_nan(b)
+    };
+
+    let result = op(&f32::from_bits(a), &f32::from_bits(b));
+    (result as u32, if invalid { NV } else { 0 })
+}
+
+/// The class of `a`, as the one-hot mask written by `fclass.s`
+pub fn classify(a: u32) -> u32 {
+    let negative = a & SIGN != 0;
+    let bit = match f32::from_bits(a).classify() {
+        _ if is_signaling(a) => 8,
+        std::num::FpCategory::Nan => 9,
+        std::num::FpCategory::Infinite => 0,
+        std::num::FpCategory::Normal => 1,
+        std::num::FpCategory::Subnormal => 2,
+        std::num::FpCategory::Zero => 3,
+    };
+
+    // positive classes mirror the negative ones
+    let bit = match (bit, negative) {
+        (8 | 9, _) | (_, true) => bit,
+        (_, false) => 7 - bit,
+    };
+    1 << bit
+}
+
+/// Rounds `a` to an integer in `rm` and converts it to a 32-bit integer,
+/// saturating if it is out of range
+pub fn to_int(a: u32, signed: bool, rm: RoundingMode) -> (u32, u32) {
+    use RoundingMode::*;
+
+    let x = float(a);
+    let (min, max) = if signed {
+        (i32::MIN as f64, i32::MAX as f64)
+    } else {
+        (0.0, u32::MAX as f64)
+    };
+
+    let rounded = match rm {
+        Rne => x.round_ties_even(),
+        Rtz => x.trunc(),
+        Rdn => x.floor(),
+        Rup => x.ceil(),
+        Rmm => x.round(),
+        Dynamic => unreachable!("the dynamic rounding mode must be resolved first"),
+    };
+
+    // NaNs saturate to the maximum
+    let saturated = |value: f64| if value < min { min } else { max };
+    match rounded {
+        _ if rounded.is_nan() || rounded < min || rounded > max => {
+            let value = saturated(rounded);
+            let bits = if signed {
+                value as i32 as u32
+            } else {
+                value as u32
+            };
+            (bits, NV)
+        }
+        _ => {
+            let bits = if signed {
+                rounded as i32 as u32
+            } else {
+                rounded as u32
+            };
+            (bits, if rounded != x { NX } else { 0 })
+        }
+    }
+}
+
+/// Converts the 32-bit integer `a` to single precision, rounding in `rm`
+pub fn from_int(a: u32, signed: bool, rm: RoundingMode) -> (u32, u32) {
+    let value = if signed { a as i32 as f64 } else { a as f64 };
+    round(value, 0.0, rm)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::hart::instruction::RoundingMode::*;
+
+    use super::{add, div, flags::*, from_int, min_max, sqrt, to_int, CANONICAL_NAN};
+
+    #[test]
+    fn rounding_modes() {
+        // 1 + 2^-24 is halfway between 1 and the next value up
+        let one = 1.0f32.to_bits();
+        let half_ulp = 2.0f32.powi(-24).to_bits();
+        assert_eq!(add(one, half_ulp, Rne), (one, NX));
+        assert_eq!(add(one, half_ulp, Rmm), (one + 1, NX));
+        assert_eq!(add(one, half_ulp, Rup), (one + 1, NX));
+
+        // 1/3 is just below 0x3eaaaaab
+        let three = 3.0f32.to_bits();
+        assert_eq!(div(one, three, Rne), (0x3eaaaaab, NX));
+        assert_eq!(div(one, three, Rtz), (0x3eaaaaaa, NX));
+        assert_eq!(div(one, three, Rup), (0x3eaaaaab, NX));
+        assert_eq!(div(one | 1 << 31, three, Rdn), (0xbeaaaaab, NX));
+
+        assert_eq!(sqrt(4.0f32.to_bits(), Rne), (2.0f32.to_bits(), 0));
+        assert_eq!(sqrt(2.0f32.to_bits(), Rtz), (0x3fb504f3, NX));
+    }
+
+    #[test]
+    fn exceptions() {
+        let max = f32::MAX.to_bits();
+        assert_eq!(add(max, max, Rne), (0x7f800000, OF | NX));
+        assert_eq!(add(max, max, Rtz), (max, OF | NX));
+
+        let zero = 0.0f32.to_bits();
+        assert_eq!(div(1.0f32.to_bits(), zero, Rne), (0x7f800000, DZ));
+        assert_eq!(div(zero, zero, Rne), (CANONICAL_NAN, NV));
+        assert_eq!(
+            add(0x7f800000, 0xff800000, Rne),
+            (CANONICAL_NAN, NV),
+            "inf - inf should be invalid"
+        );
+
+        // half the smallest subnormal rounds to 0
+        let tiny = f32::from_bits(1).to_bits();
+        assert_eq!(div(tiny, 2.0f32.to_bits(), Rne), (zero, NX | UF));
+
+        // signaling NaNs are invalid even where quiet ones are not
+        assert_eq!(min_max(0x7f800001, zero, true), (zero, NV));
+        assert_eq!(min_max(CANONICAL_NAN, zero, true), (zero, 0));
+        assert_eq!(min_max(0x80000000, zero, true), (0x80000000, 0));
+    }
+
+    #[test]
+    fn conversions() {
+        assert_eq!(to_int(2.5f32.to_bits(), true, Rne), (2, NX));
+        assert_eq!(to_int(2.5f32.to_bits(), true, Rmm), (3, NX));
+        assert_eq!(to_int((-2.5f32).to_bits(), true, Rdn), (-3i32 as u32, NX));
+        assert_eq!(to_int(3e9f32.to_bits(), true, Rne), (i32::MAX as u32, NV));
+        assert_eq!(to_int((-1.0f32).to_bits(), false, Rne), (0, NV));
+        assert_eq!(to_int(CANONICAL_NAN, false, Rne), (u32::MAX, NV));
+
+        assert_eq!(from_int(-7i32 as u32, true, Rne), ((-7.0f32).to_bits(), 0));
+        // 2^24 + 1 is not representable
+        assert_eq!(from_int(0x1000001, false, Rup), (0x4b800001, NX));
+    }
+}
@@ -1,14 +1,17 @@
 pub mod decode;
+mod disassemble;
+mod encode;
 mod types;
 
-pub use types::Conclusion;
+pub use types::{Conclusion, ExceptionKind, FenceMode, FenceSet, RoundingMode};
 
-use super::{csr::Csr, Reg};
+use super::{csr::Csr, FReg, Reg};
 use types::*;
 
 #[rustfmt::skip]
 #[allow(unused)]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Instruction {
     Lui   { rd: Reg, imm: Int32Trunc12 },
     Auipc { rd: Reg, imm: Int32Trunc12 },
@@ -60,6 +63,8 @@ pub enum Instruction {
 
     Ecall,
     Ebreak,
+    Mret,
+    Sret,
 
     Fencei { rd: Reg, rs1: Reg, imm: Int12 },
 
@@ -92,6 +97,34 @@ pub enum Instruction {
     AmoMinuw { rd: Reg, rs1: Reg, rs2: Reg, aq: bool, rl: bool },
     AmoMaxuw { rd: Reg, rs1: Reg, rs2: Reg, aq: bool, rl: bool },
 
+    Flw { rd: FReg, rs1: Reg, imm: Int12 },
+    Fsw { rs1: Reg, rs2: FReg, imm: Int12 },
+
+    Fadds { rd: FReg, rs1: FReg, rs2: FReg, rm: RoundingMode },
+    Fsubs { rd: FReg, rs1: FReg, rs2: FReg, rm: RoundingMode },
+    Fmuls { rd: FReg, rs1: FReg, rs2: FReg, rm: RoundingMode },
+    Fdivs { rd: FReg, rs1: FReg, rs2: FReg, rm: RoundingMode },
+    Fsqrts { rd: FReg, rs1: FReg, rm: RoundingMode },
+
+    Fsgnjs  { rd: FReg, rs1: FReg, rs2: FReg },
+    Fsgnjns { rd: FReg, rs1: FReg, rs2: FReg },
+    Fsgnjxs { rd: FReg, rs1: FReg, rs2: FReg },
+    Fmins   { rd: FReg, rs1: FReg, rs2: FReg },
+    Fmaxs   { rd: FReg, rs1: FReg, rs2: FReg },
+
+    Feqs { rd: Reg, rs1: FReg, rs2: FReg },
+    Flts { rd: Reg, rs1: FReg, rs2: FReg },
+    Fles { rd: Reg, rs1: FReg, rs2: FReg },
+
+    Fcvtws  { rd: Reg, rs1: FReg, rm: RoundingMode },
+    Fcvtwus { rd: Reg, rs1: FReg, rm: RoundingMode },
+    Fcvtsw  { rd: FReg, rs1: Reg, rm: RoundingMode },
+    Fcvtswu { rd: FReg, rs1: Reg, rm: RoundingMode },
+
+    Fmvxw   { rd: Reg, rs1: FReg },
+    Fmvwx   { rd: FReg, rs1: Reg },
+    Fclasss { rd: Reg, rs1: FReg },
+
     Invalid { raw: u32 }
 }
 
@@ -100,3 +133,274 @@ impl Default for Instruction {
         Self::Invalid { raw: 0 }
     }
 }
+impl Instruction {
+    /// The kind of this instruction, discarding its operands
+    pub fn kind(&self) -> InstructionKind {
+        use Instruction::*;
+        match self {
+            Lui { .. } => InstructionKind::Lui,
+            Auipc { .. } => InstructionKind::Auipc,
+            Jal { .. } => InstructionKind::Jal,
+            Jalr { .. } => InstructionKind::Jalr,
+            Beq { .. } => InstructionKind::Beq,
+            Bne { .. } => InstructionKind::Bne,
+            Blt { .. } => InstructionKind::Blt,
+            Bge { .. } => InstructionKind::Bge,
+            Bltu { .. } => InstructionKind::Bltu,
+            Bgeu { .. } => InstructionKind::Bgeu,
+            Lb { .. } => InstructionKind::Lb,
+            Lh { .. } => InstructionKind::Lh,
+            Lw { .. } => InstructionKind::Lw,
+            Lbu { .. } => InstructionKind::Lbu,
+            Lhu { .. } => InstructionKind::Lhu,
+            Sb { .. } => InstructionKind::Sb,
+            Sh { .. } => InstructionKind::Sh,
+            Sw { .. } => InstructionKind::Sw,
+            Addi { .. } => InstructionKind::Addi,
+            Slti { .. } => InstructionKind::Slti,
+            Sltiu { .. } => InstructionKind::Sltiu,
+            Xori { .. } => InstructionKind::Xori,
+            Ori { .. } => InstructionKind::Ori,
+            Andi { .. } => InstructionKind::Andi,
+            Slli { .. } => InstructionKind::Slli,
+            Srli { .. } => InstructionKind::Srli,
+            Srai { .. } => InstructionKind::Srai,
+            Add { .. } => InstructionKind::Add,
+            Sub { .. } => InstructionKind::Sub,
+            Sll { .. } => InstructionKind::Sll,
+            Slt { .. } => InstructionKind::Slt,
+            Sltu { .. } => InstructionKind::Sltu,
+            Xor { .. } => InstructionKind::Xor,
+            Srl { .. } => InstructionKind::Srl,
+            Sra { .. } => InstructionKind::Sra,
+            Or { .. } => InstructionKind::Or,
+            And { .. } => InstructionKind::And,
+            Fence { .. } => InstructionKind::Fence,
+            Ecall => InstructionKind::Ecall,
+            Ebreak => InstructionKind::Ebreak,
+            Mret => InstructionKind::Mret,
+            Sret => InstructionKind::Sret,
+            Fencei { .. } => InstructionKind::Fencei,
+            CsrRw { .. } => InstructionKind::CsrRw,
+            CsrRs { .. } => InstructionKind::CsrRs,
+            CsrRc { .. } => InstructionKind::CsrRc,
+            CsrRwi { .. } => InstructionKind::CsrRwi,
+            CsrRsi { .. } => InstructionKind::CsrRsi,
+            CsrRci { .. } => InstructionKind::CsrRci,
+            Mul { .. } => InstructionKind::Mul,
+            Mulh { .. } => InstructionKind::Mulh,
+            Mulhsu { .. } => InstructionKind::Mulhsu,
+            Mulhu { .. } => InstructionKind::Mulhu,
+            Div { .. } => InstructionKind::Div,
+            Divu { .. } => InstructionKind::Divu,
+            Rem { .. } => InstructionKind::Rem,
+            Remu { .. } => InstructionKind::Remu,
+            Lrw { .. } => InstructionKind::Lrw,
+            Scw { .. } => InstructionKind::Scw,
+            AmoSwapw { .. } => InstructionKind::AmoSwapw,
+            AmoAddw { .. } => InstructionKind::AmoAddw,
+            AmoXorw { .. } => InstructionKind::AmoXorw,
+            AmoAndw { .. } => InstructionKind::AmoAndw,
+            AmoOrw { .. } => InstructionKind::AmoOrw,
+            AmoMinw { .. } => InstructionKind::AmoMinw,
+            AmoMaxw { .. } => InstructionKind::AmoMaxw,
+            AmoMinuw { .. } => InstructionKind::AmoMinuw,
+            AmoMaxuw { .. } => InstructionKind::AmoMaxuw,
+            Flw { .. } => InstructionKind::Flw,
+            Fsw { .. } => InstructionKind::Fsw,
+            Fadds { .. } => InstructionKind::Fadds,
+            Fsubs { .. } => InstructionKind::Fsubs,
+            Fmuls { .. } => InstructionKind::Fmuls,
+            Fdivs { .. } => InstructionKind::Fdivs,
+            Fsqrts { .. } => InstructionKind::Fsqrts,
+            Fsgnjs { .. } => InstructionKind::Fsgnjs,
+            Fsgnjns { .. } => InstructionKind::Fsgnjns,
+            Fsgnjxs { .. } => InstructionKind::Fsgnjxs,
+            Fmins { .. } => InstructionKind::Fmins,
+            Fmaxs { .. } => InstructionKind::Fmaxs,
+            Feqs { .. } => InstructionKind::Feqs,
+            Flts { .. } => InstructionKind::Flts,
+            Fles { .. } => InstructionKind::Fles,
+            Fcvtws { .. } => InstructionKind::Fcvtws,
+            Fcvtwus { .. } => InstructionKind::Fcvtwus,
+            Fcvtsw { .. } => InstructionKind::Fcvtsw,
+            Fcvtswu { .. } => InstructionKind::Fcvtswu,
+            Fmvxw { .. } => InstructionKind::Fmvxw,
+            Fmvwx { .. } => InstructionKind::Fmvwx,
+            Fclasss { .. } => InstructionKind::Fclasss,
+            Invalid { .. } => InstructionKind::Invalid,
+        }
+    }
+}
+
+/// The kind of an [`Instruction`] without any of its operands
+#[allow(unused)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum InstructionKind {
+    Lui,
+    Auipc,
+
+    Jal,
+
+    Jalr,
+
+    Beq,
+    Bne,
+    Blt,
+    Bge,
+    Bltu,
+    Bgeu,
+
+    Lb,
+    Lh,
+    Lw,
+    Lbu,
+    Lhu,
+
+    Sb,
+    Sh,
+    Sw,
+
+    Addi,
+    Slti,
+    Sltiu,
+    Xori,
+    Ori,
+    Andi,
+
+    Slli,
+    Srli,
+    Srai,
+
+    Add,
+    Sub,
+    Sll,
+    Slt,
+    Sltu,
+    Xor,
+    Srl,
+    Sra,
+    Or,
+    And,
+
+    Fence,
+
+    Ecall,
+    Ebreak,
+    Mret,
+    Sret,
+
+    Fencei,
+
+    CsrRw,
+    CsrRs,
+    CsrRc,
+
+    CsrRwi,
+    CsrRsi,
+    CsrRci,
+
+    Mul,
+    Mulh,
+    Mulhsu,
+    Mulhu,
+    Div,
+    Divu,
+    Rem,
+    Remu,
+
+    Lrw,
+    Scw,
+    AmoSwapw,
+    AmoAddw,
+    AmoXorw,
+    AmoAndw,
+    AmoOrw,
+    AmoMinw,
+    AmoMaxw,
+    AmoMinuw,
+    AmoMaxuw,
+
+    Flw,
+    Fsw,
+
+    Fadds,
+    Fsubs,
+    Fmuls,
+    Fdivs,
+    Fsqrts,
+
+    Fsgnjs,
+    Fsgnjns,
+    Fsgnjxs,
+    Fmins,
+    Fmaxs,
+
+    Feqs,
+    Flts,
+    Fles,
+
+    Fcvtws,
+    Fcvtwus,
+    Fcvtsw,
+    Fcvtswu,
+
+    Fmvxw,
+    Fmvwx,
+    Fclasss,
+
+    Invalid,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::hart::Reg;
+
+    use super::{decode::Decode, Instruction, InstructionKind};
+
+    #[test]
+    fn kind() {
+        let addi = Instruction::Addi {
+            rd: Reg::A0,
+            rs1: Reg::A1,
+            imm: (-4).into(),
+        };
+        assert_eq!(addi.kind(), InstructionKind::Addi);
+        assert_eq!(Instruction::Ecall.kind(), InstructionKind::Ecall);
+        assert_eq!(0xffffffffu32.decode().kind(), InstructionKind::Invalid);
+        assert_eq!(0x00b52023u32.decode().kind(), InstructionKind::Sw);
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn serde_round_trip() {
+        #[rustfmt::skip]
+        let raw: [u32; 8] = [
+            0x12345537, // lui a0, 0x12345
+            0xfeb50ee3, // beq a0, a1, -4
+            0xffc58513, // addi a0, a1, -4
+            0x00351513, // slli a0, a0, 3
+            0x3402d573, // csrrwi a0, mscratch, 5
+            0x04b6252f, // amoadd.w.aq a0, a1, (a2)
+            0x0310000f, // fence rw, w
+            0xffffffff, // invalid
+        ];
+
+        for raw in raw {
+            let op = raw.decode();
+            let json = serde_json::to_string(&op).unwrap();
+            assert_eq!(
+                serde_json::from_str::<Instruction>(&json).unwrap(),
+                op,
+                "{json}"
+            );
+        }
+
+        let addi = serde_json::to_string(&0xffc58513u32.decode()).unwrap();
+        assert_eq!(addi, r#"{"Addi":{"rd":"X10","rs1":"X11","imm":-4}}"#);
+
+        let out_of_range = r#"{"Addi":{"rd":"X10","rs1":"X11","imm":4096}}"#;
+        assert!(serde_json::from_str::<Instruction>(out_of_range).is_err());
+    }
+}
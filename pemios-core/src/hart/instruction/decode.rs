@@ -7,10 +7,10 @@
 //
 // Copyright © 2022 mumblingdrunkard
 
-use crate::hart::{csr::Csr, instruction::Instruction, Reg};
+use crate::hart::{csr::Csr, instruction::Instruction, FReg, Reg};
 
 use super::types::{
-    FenceMode, FenceSet, Int12, Int13Trunc1, Int21Trunc1, Int32Trunc12, OpCode, UInt5,
+    FenceMode, FenceSet, Int12, Int13Trunc1, Int21Trunc1, Int32Trunc12, OpCode, RoundingMode, UInt5,
 };
 
 /// An adapter for u32 that lets us extract fields from a RISC-V instruction
@@ -59,7 +59,7 @@ impl Decoder<'_> {
     }
 
     fn shamt(&self) -> UInt5 {
-        ((self.0 >> 15) & 0x1f).into()
+        ((self.0 >> 20) & 0x1f).into()
     }
 
     fn imm_i(&self) -> Int12 {
@@ -130,7 +130,150 @@ impl Decoder<'_> {
     }
 
     fn mode(&self) -> FenceMode {
-        todo!()
+        FenceMode::new((self.0 >> 28) as u8)
+    }
+
+    // Floating-point fields
+    fn fd(&self) -> FReg {
+        ((self.0 >> 7) & 0x1f).into()
+    }
+
+    fn fs1(&self) -> FReg {
+        ((self.0 >> 15) & 0x1f).into()
+    }
+
+    fn fs2(&self) -> FReg {
+        ((self.0 >> 20) & 0x1f).into()
+    }
+
+    fn rm(&self) -> Option<RoundingMode> {
+        RoundingMode::new(self.funct3() as u8)
+    }
+}
+
+/// An adapter for u16 that lets us extract fields from a compressed (RVC)
+/// instruction
+///
+/// Registers in the 3-bit fields written `rd'`, `rs1'` and `rs2'` by the
+/// specification are the eight most used ones, `x8` to `x15`.
+pub struct CompressedDecoder<'a>(&'a u16);
+
+impl<'a> CompressedDecoder<'a> {
+    fn new(raw: &'a u16) -> Self {
+        Self(raw)
+    }
+}
+
+impl CompressedDecoder<'_> {
+    /// Bits `lo..=hi` of the instruction, shifted down to bit 0
+    fn bits(&self, hi: u32, lo: u32) -> u32 {
+        (*self.0 as u32 >> lo) & ((1 << (hi - lo + 1)) - 1)
+    }
+
+    /// Bit `at` of the instruction, moved to bit `to`
+    fn bit(&self, at: u32, to: u32) -> u32 {
+        self.bits(at, at) << to
+    }
+
+    fn quadrant(&self) -> u32 {
+        self.bits(1, 0)
+    }
+
+    fn funct3(&self) -> u32 {
+        self.bits(15, 13)
+    }
+
+    /// The full register field at bits 11..7, as a destination
+    fn rd(&self) -> Reg {
+        match self.rs1() {
+            Reg::X0 => Reg::Ignore,
+            rd => rd,
+        }
+    }
+
+    /// The full register field at bits 11..7, as a source
+    fn rs1(&self) -> Reg {
+        self.bits(11, 7).into()
+    }
+
+    /// The full register field at bits 6..2
+    fn rs2(&self) -> Reg {
+        self.bits(6, 2).into()
+    }
+
+    /// The 3-bit register field at bits 9..7
+    fn rs1_prime(&self) -> Reg {
+        (8 + self.bits(9, 7)).into()
+    }
+
+    /// The 3-bit register field at bits 4..2
+    fn rs2_prime(&self) -> Reg {
+        (8 + self.bits(4, 2)).into()
+    }
+
+    /// The 6-bit signed immediate split between bit 12 and bits 6..2, as a
+    /// raw i32
+    fn imm6(&self) -> i32 {
+        (((self.bit(12, 5) | self.bits(6, 2)) as i32) << 26) >> 26
+    }
+
+    fn imm_ci(&self) -> Int12 {
+        self.imm6().into()
+    }
+
+    fn shamt(&self) -> UInt5 {
+        self.bits(6, 2).into()
+    }
+
+    /// The scaled offset of `c.lw` and `c.sw`
+    fn uimm_w(&self) -> Int12 {
+        ((self.bits(12, 10) << 3 | self.bit(6, 2) | self.bit(5, 6)) as i32).into()
+    }
+
+    /// The scaled offset of `c.lwsp`
+    fn uimm_lwsp(&self) -> Int12 {
+        ((self.bits(6, 4) << 2 | self.bit(12, 5) | self.bits(3, 2) << 6) as i32).into()
+    }
+
+    /// The scaled offset of `c.swsp`
+    fn uimm_swsp(&self) -> Int12 {
+        ((self.bits(12, 9) << 2 | self.bits(8, 7) << 6) as i32).into()
+    }
+
+    /// The scaled, non-zero immediate of `c.addi4spn`
+    fn nzuimm_addi4spn(&self) -> i32 {
+        (self.bits(12, 11) << 4 | self.bits(10, 7) << 6 | self.bit(6, 2) | self.bit(5, 3)) as i32
+    }
+
+    /// The scaled, non-zero immediate of `c.addi16sp`
+    fn nzimm_addi16sp(&self) -> i32 {
+        let imm = self.bit(12, 9)
+            | self.bit(6, 4)
+            | self.bit(5, 6)
+            | self.bits(4, 3) << 7
+            | self.bit(2, 5);
+        ((imm as i32) << 22) >> 22
+    }
+
+    fn imm_cj(&self) -> Int21Trunc1 {
+        let imm = self.bit(12, 11)
+            | self.bit(11, 4)
+            | self.bits(10, 9) << 8
+            | self.bit(8, 10)
+            | self.bit(7, 6)
+            | self.bit(6, 7)
+            | self.bits(5, 3) << 1
+            | self.bit(2, 5);
+        (((imm as i32) << 20) >> 20).into()
+    }
+
+    fn imm_cb(&self) -> Int13Trunc1 {
+        let imm = self.bit(12, 8)
+            | self.bits(11, 10) << 3
+            | self.bits(6, 5) << 6
+            | self.bits(4, 3) << 1
+            | self.bit(2, 5);
+        (((imm as i32) << 23) >> 23).into()
     }
 }
 
@@ -141,6 +284,13 @@ pub trait Decode {
 impl Decode for u32 {
     fn decode(&self) -> Instruction {
         use Instruction::*;
+
+        // anything not ending in 0b11 is a 16-bit instruction, and the rest
+        // belongs to whatever follows it
+        if self & 3 != 3 {
+            return (*self as u16).decode();
+        }
+
         let decoder = Decoder::new(self);
 
         let raw = *self;
@@ -163,6 +313,12 @@ impl Decode for u32 {
                 }
             }
 
+            OpCode::LoadFp if cfg!(feature = "rv32f") && funct3 == 2 => Flw {
+                rd: decoder.fd(),
+                rs1,
+                imm: decoder.imm_i(),
+            },
+
             OpCode::MiscMem => match funct3 {
                 0 => Fence {
                     rd,
@@ -211,6 +367,12 @@ impl Decode for u32 {
                 }
             }
 
+            OpCode::StoreFp if cfg!(feature = "rv32f") && funct3 == 2 => Fsw {
+                rs1,
+                rs2: decoder.fs2(),
+                imm: decoder.imm_s(),
+            },
+
             #[rustfmt::skip]
             OpCode::Amo => {
                 let aq = decoder.aq();
@@ -239,7 +401,7 @@ impl Decode for u32 {
                 3 => Sltu { rd, rs1, rs2 },
                 4 => Xor { rd, rs1, rs2 },
                 5 if funct7 == 0 => Srl { rd, rs1, rs2 },
-                5 if funct7 == 0x20 => Srl { rd, rs1, rs2 },
+                5 if funct7 == 0x20 => Sra { rd, rs1, rs2 },
                 6 => Or { rd, rs1, rs2 },
                 7 => And { rd, rs1, rs2 },
                 _ => Invalid { raw },
@@ -250,11 +412,43 @@ impl Decode for u32 {
                 imm: decoder.imm_u(),
             },
 
+            // only single precision, with the format in the low bits of funct7
+            #[rustfmt::skip]
+            OpCode::OpFp if cfg!(feature = "rv32f") => {
+                let (fd, fs1, fs2) = (decoder.fd(), decoder.fs1(), decoder.fs2());
+                let rs2 = u32::from(fs2);
+                match (funct7, decoder.rm()) {
+                    (0x00, Some(rm)) => Fadds { rd: fd, rs1: fs1, rs2: fs2, rm },
+                    (0x04, Some(rm)) => Fsubs { rd: fd, rs1: fs1, rs2: fs2, rm },
+                    (0x08, Some(rm)) => Fmuls { rd: fd, rs1: fs1, rs2: fs2, rm },
+                    (0x0c, Some(rm)) => Fdivs { rd: fd, rs1: fs1, rs2: fs2, rm },
+                    (0x2c, Some(rm)) if rs2 == 0 => Fsqrts { rd: fd, rs1: fs1, rm },
+                    (0x60, Some(rm)) if rs2 == 0 => Fcvtws { rd, rs1: fs1, rm },
+                    (0x60, Some(rm)) if rs2 == 1 => Fcvtwus { rd, rs1: fs1, rm },
+                    (0x68, Some(rm)) if rs2 == 0 => Fcvtsw { rd: fd, rs1, rm },
+                    (0x68, Some(rm)) if rs2 == 1 => Fcvtswu { rd: fd, rs1, rm },
+                    _ => match (funct7, funct3) {
+                        (0x10, 0) => Fsgnjs { rd: fd, rs1: fs1, rs2: fs2 },
+                        (0x10, 1) => Fsgnjns { rd: fd, rs1: fs1, rs2: fs2 },
+                        (0x10, 2) => Fsgnjxs { rd: fd, rs1: fs1, rs2: fs2 },
+                        (0x14, 0) => Fmins { rd: fd, rs1: fs1, rs2: fs2 },
+                        (0x14, 1) => Fmaxs { rd: fd, rs1: fs1, rs2: fs2 },
+                        (0x50, 0) => Fles { rd, rs1: fs1, rs2: fs2 },
+                        (0x50, 1) => Flts { rd, rs1: fs1, rs2: fs2 },
+                        (0x50, 2) => Feqs { rd, rs1: fs1, rs2: fs2 },
+                        (0x70, 0) if rs2 == 0 => Fmvxw { rd, rs1: fs1 },
+                        (0x70, 1) if rs2 == 0 => Fclasss { rd, rs1: fs1 },
+                        (0x78, 0) if rs2 == 0 => Fmvwx { rd: fd, rs1 },
+                        _ => Invalid { raw },
+                    },
+                }
+            }
+
             OpCode::Branch => {
                 let imm = decoder.imm_b();
                 match funct3 {
-                    0 => Bne { rs1, rs2, imm },
-                    1 => Beq { rs1, rs2, imm },
+                    0 => Beq { rs1, rs2, imm },
+                    1 => Bne { rs1, rs2, imm },
                     4 => Blt { rs1, rs2, imm },
                     5 => Bge { rs1, rs2, imm },
                     6 => Bltu { rs1, rs2, imm },
@@ -277,9 +471,14 @@ impl Decode for u32 {
             OpCode::System if funct3 == 0 => match decoder.funct12() {
                 0 => Ecall,
                 1 => Ebreak,
+                0x102 => Sret,
+                0x302 => Mret,
                 _ => Invalid { raw },
             },
 
+            // accessing a CSR that is not implemented is illegal
+            OpCode::System if funct3 != 4 && decoder.csr() == Csr::Invalid => Invalid { raw },
+
             OpCode::System if funct3 != 4 => {
                 let csr = decoder.csr();
                 let uimm = decoder.uimm();
@@ -299,6 +498,182 @@ impl Decode for u32 {
     }
 }
 
+/// Expands a compressed instruction into the 32-bit instruction it stands
+/// for.
+///
+/// Encodings that are reserved, or that need an extension this hart does not
+/// implement, are invalid.
+impl Decode for u16 {
+    fn decode(&self) -> Instruction {
+        use Instruction::*;
+        let decoder = CompressedDecoder::new(self);
+
+        let raw = *self as u32;
+        let rd = decoder.rd();
+        let rs1 = decoder.rs1();
+        let rs2 = decoder.rs2();
+        let rs1_prime = decoder.rs1_prime();
+        let rs2_prime = decoder.rs2_prime();
+
+        match (decoder.quadrant(), decoder.funct3()) {
+            // c.addi4spn, where an immediate of 0 also covers the all-zero
+            // illegal instruction
+            (0b00, 0b000) => match decoder.nzuimm_addi4spn() {
+                0 => Invalid { raw },
+                imm => Addi {
+                    rd: rs2_prime,
+                    rs1: Reg::SP,
+                    imm: imm.into(),
+                },
+            },
+            (0b00, 0b010) => Lw {
+                rd: rs2_prime,
+                rs1: rs1_prime,
+                imm: decoder.uimm_w(),
+            },
+            (0b00, 0b110) => Sw {
+                rs1: rs1_prime,
+                rs2: rs2_prime,
+                imm: decoder.uimm_w(),
+            },
+
+            // c.addi, or c.nop when rd is x0
+            (0b01, 0b000) => Addi {
+                rd,
+                rs1,
+                imm: decoder.imm_ci(),
+            },
+            (0b01, 0b001) => Jal {
+                rd: Reg::RA,
+                imm: decoder.imm_cj(),
+            },
+            // c.li
+            (0b01, 0b010) => Addi {
+                rd,
+                rs1: Reg::X0,
+                imm: decoder.imm_ci(),
+            },
+            (0b01, 0b011) if rs1 == Reg::SP => match decoder.nzimm_addi16sp() {
+                0 => Invalid { raw },
+                imm => Addi {
+                    rd,
+                    rs1,
+                    imm: imm.into(),
+                },
+            },
+            (0b01, 0b011) => match decoder.imm6() {
+                0 => Invalid { raw },
+                imm => Lui {
+                    rd,
+                    imm: (imm << 12).into(),
+                },
+            },
+            (0b01, 0b100) => {
+                let rd = rs1_prime;
+                let rs1 = rs1_prime;
+                match (decoder.bits(12, 10), decoder.bits(6, 5)) {
+                    (0b000, _) => Srli {
+                        rd,
+                        rs1,
+                        shamt: decoder.shamt(),
+                    },
+                    (0b001, _) => Srai {
+                        rd,
+                        rs1,
+                        shamt: decoder.shamt(),
+                    },
+                    (0b010 | 0b110, _) => Andi {
+                        rd,
+                        rs1,
+                        imm: decoder.imm_ci(),
+                    },
+                    (0b011, 0b00) => Sub {
+                        rd,
+                        rs1,
+                        rs2: rs2_prime,
+                    },
+                    (0b011, 0b01) => Xor {
+                        rd,
+                        rs1,
+                        rs2: rs2_prime,
+                    },
+                    (0b011, 0b10) => Or {
+                        rd,
+                        rs1,
+                        rs2: rs2_prime,
+                    },
+                    (0b011, 0b11) => And {
+                        rd,
+                        rs1,
+                        rs2: rs2_prime,
+                    },
+                    // shifts by 32 or more, and the RV64 word operations
+                    _ => Invalid { raw },
+                }
+            }
+            // c.j
+            (0b01, 0b101) => Jal {
+                rd: Reg::Ignore,
+                imm: decoder.imm_cj(),
+            },
+            (0b01, 0b110) => Beq {
+                rs1: rs1_prime,
+                rs2: Reg::X0,
+                imm: decoder.imm_cb(),
+            },
+            (0b01, 0b111) => Bne {
+                rs1: rs1_prime,
+                rs2: Reg::X0,
+                imm: decoder.imm_cb(),
+            },
+
+            (0b10, 0b000) if decoder.bits(12, 12) == 0 => Slli {
+                rd,
+                rs1,
+                shamt: decoder.shamt(),
+            },
+            (0b10, 0b010) if rs1 != Reg::X0 => Lw {
+                rd,
+                rs1: Reg::SP,
+                imm: decoder.uimm_lwsp(),
+            },
+            (0b10, 0b100) => match (decoder.bits(12, 12), rs1, rs2) {
+                // c.jr
+                (0, Reg::X0, Reg::X0) => Invalid { raw },
+                (0, _, Reg::X0) => Jalr {
+                    rd: Reg::Ignore,
+                    rs1,
+                    imm: 0.into(),
+                },
+                // c.mv
+                (0, _, _) => Add {
+                    rd,
+                    rs1: Reg::X0,
+                    rs2,
+                },
+                (_, Reg::X0, Reg::X0) => Ebreak,
+                // c.jalr
+                (_, _, Reg::X0) => Jalr {
+                    rd: Reg::RA,
+                    rs1,
+                    imm: 0.into(),
+                },
+                // c.add
+                (_, _, _) => Add { rd, rs1, rs2 },
+            },
+            (0b10, 0b110) => Sw {
+                rs1: Reg::SP,
+                rs2,
+                imm: decoder.uimm_swsp(),
+            },
+
+            // reserved encodings and compressed floating-point loads and
+            // stores
+            _ => Invalid { raw },
+        }
+    }
+}
+
 impl From<u32> for Instruction {
     fn from(value: u32) -> Self {
         value.decode()
@@ -307,6 +682,106 @@ impl From<u32> for Instruction {
 
 #[cfg(test)]
 mod tests {
+    use crate::hart::{
+        instruction::{FenceMode, FenceSet, Instruction},
+        Reg,
+    };
+
     #[test]
     fn decode() {}
+
+    #[test]
+    fn shifts() {
+        // the shift amount is in the rs2 field, not rs1
+        let Instruction::Slli { rd, rs1, shamt } = Instruction::from(0x00359513) else {
+            panic!("slli a0, a1, 3 should decode to Slli");
+        };
+        assert_eq!((rd, rs1, u32::from(shamt)), (Reg::A0, Reg::A1, 3));
+
+        let Instruction::Srai { shamt, .. } = Instruction::from(0x41f5d513) else {
+            panic!("srai a0, a1, 31 should decode to Srai");
+        };
+        assert_eq!(u32::from(shamt), 31);
+
+        assert!(matches!(
+            Instruction::from(0x40c5d533),
+            Instruction::Sra {
+                rd: Reg::A0,
+                rs1: Reg::A1,
+                rs2: Reg::A2
+            }
+        ));
+    }
+
+    #[test]
+    fn fence_mode() {
+        let Instruction::Fence {
+            mode, pred, succ, ..
+        } = Instruction::from(0x0ff0000f)
+        else {
+            panic!("fence should decode to Fence");
+        };
+        assert_eq!(mode, FenceMode::None);
+        assert_eq!((pred, succ), (FenceSet::new(0b1111), FenceSet::new(0b1111)));
+
+        let Instruction::Fence {
+            mode, pred, succ, ..
+        } = Instruction::from(0x8330000f)
+        else {
+            panic!("fence.tso should decode to Fence");
+        };
+        assert_eq!(mode, FenceMode::Tso);
+        assert_eq!((pred, succ), (FenceSet::new(0b0011), FenceSet::new(0b0011)));
+    }
+
+    #[test]
+    fn compressed() {
+        #[rustfmt::skip]
+        let pairs = [
+            (0x0505, 0x00150513), // c.addi a0, 1        addi a0, a0, 1
+            (0x414c, 0x00452583), // c.lw a1, 4(a0)      lw a1, 4(a0)
+            (0x8082, 0x00008067), // c.jr ra             jalr zero, 0(ra)
+            (0xc501, 0x00050463), // c.beqz a0, 8        beq a0, zero, 8
+            (0x4515, 0x00500513), // c.li a0, 5          addi a0, zero, 5
+            (0x9582, 0x000580e7), // c.jalr a1           jalr ra, 0(a1)
+            (0x0028, 0x00810513), // c.addi4spn a0, 8    addi a0, sp, 8
+            (0x7505, 0xfffe1537), // c.lui a0, 0xfffe1   lui a0, 0xfffe1
+            (0x7139, 0xfc010113), // c.addi16sp -64      addi sp, sp, -64
+            (0xc62a, 0x00a12623), // c.swsp a0, 12(sp)   sw a0, 12(sp)
+            (0x4532, 0x00c12503), // c.lwsp a0, 12(sp)   lw a0, 12(sp)
+            (0x850d, 0x40355513), // c.srai a0, 3        srai a0, a0, 3
+            (0x9979, 0xffe57513), // c.andi a0, -2       andi a0, a0, -2
+            (0x8d0d, 0x40b50533), // c.sub a0, a1        sub a0, a0, a1
+            (0x852e, 0x00b00533), // c.mv a0, a1         add a0, zero, a1
+            (0x952e, 0x00b50533), // c.add a0, a1        add a0, a0, a1
+            (0x0512, 0x00451513), // c.slli a0, 4        slli a0, a0, 4
+            (0xc14c, 0x00b52223), // c.sw a1, 4(a0)      sw a1, 4(a0)
+            (0xfd65, 0xfe051ce3), // c.bnez a0, -8       bne a0, zero, -8
+            (0x9002, 0x00100073), // c.ebreak            ebreak
+        ];
+
+        for (compressed, expanded) in pairs {
+            assert_eq!(
+                Instruction::from(compressed),
+                Instruction::from(expanded),
+                "{compressed:#06x} should expand to {expanded:#010x}"
+            );
+            assert_eq!(
+                Instruction::from(0xabcd0000 | compressed),
+                Instruction::from(expanded),
+                "The parcel after a compressed instruction should be ignored"
+            );
+        }
+
+        // the all-zero parcel is defined to be illegal
+        assert_eq!(
+            Instruction::from(0xabcd0000),
+            Instruction::Invalid { raw: 0 }
+        );
+        // c.jr with rs1 = x0 is reserved
+        assert_eq!(
+            Instruction::from(0x8002),
+            Instruction::Invalid { raw: 0x8002 }
+        );
+    }
 }
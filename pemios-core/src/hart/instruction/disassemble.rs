@@ -0,0 +1,309 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// This Source Code Form is "Incompatible With Secondary Licenses", as
+// defined by the Mozilla Public License, v. 2.0.
+//
+// Copyright © 2022 mumblingdrunkard
+
+use std::fmt;
+
+use super::{FenceMode, FenceSet, Instruction, InstructionKind, RoundingMode};
+
+impl InstructionKind {
+    /// The assembly mnemonic of this kind of instruction
+    pub fn mnemonic(&self) -> &'static str {
+        use InstructionKind::*;
+        match self {
+            Lui => "lui",
+            Auipc => "auipc",
+            Jal => "jal",
+            Jalr => "jalr",
+            Beq => "beq",
+            Bne => "bne",
+            Blt => "blt",
+            Bge => "bge",
+            Bltu => "bltu",
+            Bgeu => "bgeu",
+            Lb => "lb",
+            Lh => "lh",
+            Lw => "lw",
+            Lbu => "lbu",
+            Lhu => "lhu",
+            Sb => "sb",
+            Sh => "sh",
+            Sw => "sw",
+            Addi => "addi",
+            Slti => "slti",
+            Sltiu => "sltiu",
+            Xori => "xori",
+            Ori => "ori",
+            Andi => "andi",
+            Slli => "slli",
+            Srli => "srli",
+            Srai => "srai",
+            Add => "add",
+            Sub => "sub",
+            Sll => "sll",
+            Slt => "slt",
+            Sltu => "sltu",
+            Xor => "xor",
+            Srl => "srl",
+            Sra => "sra",
+            Or => "or",
+            And => "and",
+            Fence => "fence",
+            Ecall => "ecall",
+            Ebreak => "ebreak",
+            Mret => "mret",
+            Sret => "sret",
+            Fencei => "fence.i",
+            CsrRw => "csrrw",
+            CsrRs => "csrrs",
+            CsrRc => "csrrc",
+            CsrRwi => "csrrwi",
+            CsrRsi => "csrrsi",
+            CsrRci => "csrrci",
+            Mul => "mul",
+            Mulh => "mulh",
+            Mulhsu => "mulhsu",
+            Mulhu => "mulhu",
+            Div => "div",
+            Divu => "divu",
+            Rem => "rem",
+            Remu => "remu",
+            Lrw => "lr.w",
+            Scw => "sc.w",
+            AmoSwapw => "amoswap.w",
+            AmoAddw => "amoadd.w",
+            AmoXorw => "amoxor.w",
+            AmoAndw => "amoand.w",
+            AmoOrw => "amoor.w",
+            AmoMinw => "amomin.w",
+            AmoMaxw => "amomax.w",
+            AmoMinuw => "amominu.w",
+            AmoMaxuw => "amomaxu.w",
+            Flw => "flw",
+            Fsw => "fsw",
+            Fadds => "fadd.s",
+            Fsubs => "fsub.s",
+            Fmuls => "fmul.s",
+            Fdivs => "fdiv.s",
+            Fsqrts => "fsqrt.s",
+            Fsgnjs => "fsgnj.s",
+            Fsgnjns => "fsgnjn.s",
+            Fsgnjxs => "fsgnjx.s",
+            Fmins => "fmin.s",
+            Fmaxs => "fmax.s",
+            Feqs => "feq.s",
+            Flts => "flt.s",
+            Fles => "fle.s",
+            Fcvtws => "fcvt.w.s",
+            Fcvtwus => "fcvt.wu.s",
+            Fcvtsw => "fcvt.s.w",
+            Fcvtswu => "fcvt.s.wu",
+            Fmvxw => "fmv.x.w",
+            Fmvwx => "fmv.w.x",
+            Fclasss => "fclass.s",
+            Invalid => ".word",
+        }
+    }
+}
+
+impl fmt::Display for FenceSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let flags = [
+            (self.input(), 'i'),
+            (self.output(), 'o'),
+            (self.read(), 'r'),
+            (self.write(), 'w'),
+        ];
+
+        if flags.iter().all(|&(set, _)| !set) {
+            return f.write_str("0");
+        }
+
+        flags
+            .iter()
+            .filter(|&&(set, _)| set)
+            .try_for_each(|&(_, c)| write!(f, "{c}"))
+    }
+}
+
+/// The ordering suffix of an atomic instruction
+fn ordering(aq: bool, rl: bool) -> &'static str {
+    match (aq, rl) {
+        (false, false) => "",
+        (true, false) => ".aq",
+        (false, true) => ".rl",
+        (true, true) => ".aqrl",
+    }
+}
+
+/// The rounding mode operand of a floating-point instruction, which is left
+/// out when it is dynamic
+fn rounding(rm: RoundingMode) -> &'static str {
+    match rm {
+        RoundingMode::Rne => ", rne",
+        RoundingMode::Rtz => ", rtz",
+        RoundingMode::Rdn => ", rdn",
+        RoundingMode::Rup => ", rup",
+        RoundingMode::Rmm => ", rmm",
+        RoundingMode::Dynamic => "",
+    }
+}
+
+impl Instruction {
+    /// Renders the instruction as assembly, as if it was located at `pc`.
+    ///
+    /// Registers are written with their ABI names, and branch and jump targets
+    /// are written as absolute addresses.
+    pub fn disassemble(&self, pc: u32) -> String {
+        use Instruction::*;
+
+        let m = self.kind().mnemonic();
+        let target = |offset: i32| pc.wrapping_add_signed(offset);
+
+        match *self {
+            Lui { rd, imm } | Auipc { rd, imm } => {
+                format!("{m} {rd}, {:#x}", i32::from(imm) as u32 >> 12)
+            }
+            Jal { rd, imm } => format!("{m} {rd}, {:#x}", target(imm.into())),
+            Jalr { rd, rs1, imm }
+            | Lb { rd, rs1, imm }
+            | Lh { rd, rs1, imm }
+            | Lw { rd, rs1, imm }
+            | Lbu { rd, rs1, imm }
+            | Lhu { rd, rs1, imm } => format!("{m} {rd}, {}({rs1})", i32::from(imm)),
+            Beq { rs1, rs2, imm }
+            | Bne { rs1, rs2, imm }
+            | Blt { rs1, rs2, imm }
+            | Bge { rs1, rs2, imm }
+            | Bltu { rs1, rs2, imm }
+            | Bgeu { rs1, rs2, imm } => format!("{m} {rs1}, {rs2}, {:#x}", target(imm.into())),
+            Sb { rs1, rs2, imm } | Sh { rs1, rs2, imm } | Sw { rs1, rs2, imm } => {
+                format!("{m} {rs2}, {}({rs1})", i32::from(imm))
+            }
+            Addi { rd, rs1, imm }
+            | Slti { rd, rs1, imm }
+            | Sltiu { rd, rs1, imm }
+            | Xori { rd, rs1, imm }
+            | Ori { rd, rs1, imm }
+            | Andi { rd, rs1, imm } => format!("{m} {rd}, {rs1}, {}", i32::from(imm)),
+            Slli { rd, rs1, shamt } | Srli { rd, rs1, shamt } | Srai { rd, rs1, shamt } => {
+                format!("{m} {rd}, {rs1}, {}", u32::from(shamt))
+            }
+            Add { rd, rs1, rs2 }
+            | Sub { rd, rs1, rs2 }
+            | Sll { rd, rs1, rs2 }
+            | Slt { rd, rs1, rs2 }
+            | Sltu { rd, rs1, rs2 }
+            | Xor { rd, rs1, rs2 }
+            | Srl { rd, rs1, rs2 }
+            | Sra { rd, rs1, rs2 }
+            | Or { rd, rs1, rs2 }
+            | And { rd, rs1, rs2 }
+            | Mul { rd, rs1, rs2 }
+            | Mulh { rd, rs1, rs2 }
+            | Mulhsu { rd, rs1, rs2 }
+            | Mulhu { rd, rs1, rs2 }
+            | Div { rd, rs1, rs2 }
+            | Divu { rd, rs1, rs2 }
+            | Rem { rd, rs1, rs2 }
+            | Remu { rd, rs1, rs2 } => format!("{m} {rd}, {rs1}, {rs2}"),
+            Fence {
+                mode: FenceMode::Tso,
+                ..
+            } => "fence.tso".to_string(),
+            Fence { pred, succ, .. } => format!("{m} {pred}, {succ}"),
+            Ecall | Ebreak | Mret | Sret | Fencei { .. } => m.to_string(),
+            CsrRw { rd, rs1, csr } | CsrRs { rd, rs1, csr } | CsrRc { rd, rs1, csr } => {
+                format!("{m} {rd}, {csr}, {rs1}")
+            }
+            CsrRwi { rd, uimm, csr } | CsrRsi { rd, uimm, csr } | CsrRci { rd, uimm, csr } => {
+                format!("{m} {rd}, {csr}, {}", u32::from(uimm))
+            }
+            Lrw { rd, rs1, aq, rl } => format!("{m}{} {rd}, ({rs1})", ordering(aq, rl)),
+            #[rustfmt::skip]
+            Scw { rd, rs1, rs2, aq, rl }
+            | AmoSwapw { rd, rs1, rs2, aq, rl }
+            | AmoAddw { rd, rs1, rs2, aq, rl }
+            | AmoXorw { rd, rs1, rs2, aq, rl }
+            | AmoAndw { rd, rs1, rs2, aq, rl }
+            | AmoOrw { rd, rs1, rs2, aq, rl }
+            | AmoMinw { rd, rs1, rs2, aq, rl }
+            | AmoMaxw { rd, rs1, rs2, aq, rl }
+            | AmoMinuw { rd, rs1, rs2, aq, rl }
+            | AmoMaxuw { rd, rs1, rs2, aq, rl } => {
+                format!("{m}{} {rd}, {rs2}, ({rs1})", ordering(aq, rl))
+            }
+            Flw { rd, rs1, imm } => format!("{m} {rd}, {}({rs1})", i32::from(imm)),
+            Fsw { rs1, rs2, imm } => format!("{m} {rs2}, {}({rs1})", i32::from(imm)),
+            Fadds { rd, rs1, rs2, rm }
+            | Fsubs { rd, rs1, rs2, rm }
+            | Fmuls { rd, rs1, rs2, rm }
+            | Fdivs { rd, rs1, rs2, rm } => format!("{m} {rd}, {rs1}, {rs2}{}", rounding(rm)),
+            Fsqrts { rd, rs1, rm } => format!("{m} {rd}, {rs1}{}", rounding(rm)),
+            Fsgnjs { rd, rs1, rs2 }
+            | Fsgnjns { rd, rs1, rs2 }
+            | Fsgnjxs { rd, rs1, rs2 }
+            | Fmins { rd, rs1, rs2 }
+            | Fmaxs { rd, rs1, rs2 } => format!("{m} {rd}, {rs1}, {rs2}"),
+            Feqs { rd, rs1, rs2 } | Flts { rd, rs1, rs2 } | Fles { rd, rs1, rs2 } => {
+                format!("{m} {rd}, {rs1}, {rs2}")
+            }
+            Fcvtws { rd, rs1, rm } | Fcvtwus { rd, rs1, rm } => {
+                format!("{m} {rd}, {rs1}{}", rounding(rm))
+            }
+            Fcvtsw { rd, rs1, rm } | Fcvtswu { rd, rs1, rm } => {
+                format!("{m} {rd}, {rs1}{}", rounding(rm))
+            }
+            Fmvxw { rd, rs1 } | Fclasss { rd, rs1 } => format!("{m} {rd}, {rs1}"),
+            Fmvwx { rd, rs1 } => format!("{m} {rd}, {rs1}"),
+            Invalid { raw } => format!("{m} {raw:#010x}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::hart::instruction::Instruction;
+
+    fn disassemble(raw: u32, pc: u32) -> String {
+        Instruction::from(raw).disassemble(pc)
+    }
+
+    #[test]
+    fn formats() {
+        let cases = [
+            (0x00c58533, "add a0, a1, a2"),
+            (0xffc58513, "addi a0, a1, -4"),
+            (0x00351513, "slli a0, a0, 3"),
+            (0x41f5d513, "srai a0, a1, 31"),
+            (0x40c5d533, "sra a0, a1, a2"),
+            (0x00812283, "lw t0, 8(sp)"),
+            (0xfeb42a23, "sw a1, -12(s0)"),
+            (0x12345537, "lui a0, 0x12345"),
+            (0x000500e7, "jalr ra, 0(a0)"),
+            (0x30059573, "csrrw a0, mstatus, a1"),
+            (0x30446073, "csrrsi zero, mie, 8"),
+            (0x06c5a52f, "amoadd.w.aqrl a0, a2, (a1)"),
+            (0x100522af, "lr.w t0, (a0)"),
+            (0x0310000f, "fence rw, w"),
+            (0x8330000f, "fence.tso"),
+            (0x00000073, "ecall"),
+            (0xffffffff, ".word 0xffffffff"),
+        ];
+
+        for (raw, asm) in cases {
+            assert_eq!(disassemble(raw, 0), asm);
+        }
+    }
+
+    #[test]
+    fn branch_targets_are_absolute() {
+        // beq ra, sp, -8
+        assert_eq!(disassemble(0xfe208ce3, 0x100), "beq ra, sp, 0xf8");
+    }
+}
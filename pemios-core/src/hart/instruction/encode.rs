@@ -0,0 +1,407 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// This Source Code Form is "Incompatible With Secondary Licenses", as
+// defined by the Mozilla Public License, v. 2.0.
+//
+// Copyright © 2022 mumblingdrunkard
+
+use super::{FenceMode, Instruction, InstructionKind};
+
+impl InstructionKind {
+    /// The encoding of this kind of instruction with all operands set to 0
+    #[rustfmt::skip]
+    pub fn template(&self) -> u32 {
+        use InstructionKind::*;
+        match self {
+            Lui      => 0x00000037,
+            Auipc    => 0x00000017,
+            Jal      => 0x0000006f,
+            Jalr     => 0x00000067,
+            Beq      => 0x00000063,
+            Bne      => 0x00001063,
+            Blt      => 0x00004063,
+            Bge      => 0x00005063,
+            Bltu     => 0x00006063,
+            Bgeu     => 0x00007063,
+            Lb       => 0x00000003,
+            Lh       => 0x00001003,
+            Lw       => 0x00002003,
+            Lbu      => 0x00004003,
+            Lhu      => 0x00005003,
+            Sb       => 0x00000023,
+            Sh       => 0x00001023,
+            Sw       => 0x00002023,
+            Addi     => 0x00000013,
+            Slti     => 0x00002013,
+            Sltiu    => 0x00003013,
+            Xori     => 0x00004013,
+            Ori      => 0x00006013,
+            Andi     => 0x00007013,
+            Slli     => 0x00001013,
+            Srli     => 0x00005013,
+            Srai     => 0x40005013,
+            Add      => 0x00000033,
+            Sub      => 0x40000033,
+            Sll      => 0x00001033,
+            Slt      => 0x00002033,
+            Sltu     => 0x00003033,
+            Xor      => 0x00004033,
+            Srl      => 0x00005033,
+            Sra      => 0x40005033,
+            Or       => 0x00006033,
+            And      => 0x00007033,
+            Fence    => 0x0000000f,
+            Ecall    => 0x00000073,
+            Ebreak   => 0x00100073,
+            Mret     => 0x30200073,
+            Sret     => 0x10200073,
+            Fencei   => 0x0000100f,
+            CsrRw    => 0x00001073,
+            CsrRs    => 0x00002073,
+            CsrRc    => 0x00003073,
+            CsrRwi   => 0x00005073,
+            CsrRsi   => 0x00006073,
+            CsrRci   => 0x00007073,
+            Mul      => 0x02000033,
+            Mulh     => 0x02001033,
+            Mulhsu   => 0x02002033,
+            Mulhu    => 0x02003033,
+            Div      => 0x02004033,
+            Divu     => 0x02005033,
+            Rem      => 0x02006033,
+            Remu     => 0x02007033,
+            Lrw      => 0x1000202f,
+            Scw      => 0x1800202f,
+            AmoSwapw => 0x0800202f,
+            AmoAddw  => 0x0000202f,
+            AmoXorw  => 0x2000202f,
+            AmoAndw  => 0x6000202f,
+            AmoOrw   => 0x4000202f,
+            AmoMinw  => 0x8000202f,
+            AmoMaxw  => 0xa000202f,
+            AmoMinuw => 0xc000202f,
+            AmoMaxuw => 0xe000202f,
+            Flw      => 0x00002007,
+            Fsw      => 0x00002027,
+            Fadds    => 0x00000053,
+            Fsubs    => 0x08000053,
+            Fmuls    => 0x10000053,
+            Fdivs    => 0x18000053,
+            Fsqrts   => 0x58000053,
+            Fsgnjs   => 0x20000053,
+            Fsgnjns  => 0x20001053,
+            Fsgnjxs  => 0x20002053,
+            Fmins    => 0x28000053,
+            Fmaxs    => 0x28001053,
+            Feqs     => 0xa0002053,
+            Flts     => 0xa0001053,
+            Fles     => 0xa0000053,
+            Fcvtws   => 0xc0000053,
+            Fcvtwus  => 0xc0100053,
+            Fcvtsw   => 0xd0000053,
+            Fcvtswu  => 0xd0100053,
+            Fmvxw    => 0xe0000053,
+            Fmvwx    => 0xf0000053,
+            Fclasss  => 0xe0001053,
+            Invalid  => 0x00000000,
+        }
+    }
+}
+
+/// Operands shifted into their positions in an encoded instruction
+mod field {
+    use crate::hart::{
+        instruction::{FenceSet, RoundingMode},
+        FReg, Reg,
+    };
+
+    // `Reg::Ignore` is encoded as x0, which is what it is decoded from
+    pub fn rd(rd: Reg) -> u32 {
+        (rd as u32 & 0x1f) << 7
+    }
+
+    pub fn rs1(rs1: Reg) -> u32 {
+        (rs1 as u32 & 0x1f) << 15
+    }
+
+    pub fn rs2(rs2: Reg) -> u32 {
+        (rs2 as u32 & 0x1f) << 20
+    }
+
+    pub fn fd(rd: FReg) -> u32 {
+        u32::from(rd) << 7
+    }
+
+    pub fn fs1(rs1: FReg) -> u32 {
+        u32::from(rs1) << 15
+    }
+
+    pub fn fs2(rs2: FReg) -> u32 {
+        u32::from(rs2) << 20
+    }
+
+    pub fn rm(rm: RoundingMode) -> u32 {
+        u32::from(rm) << 12
+    }
+
+    pub fn imm_i(imm: i32) -> u32 {
+        (imm as u32) << 20
+    }
+
+    pub fn imm_s(imm: i32) -> u32 {
+        let imm = imm as u32;
+        ((imm & 0xfe0) << 20) | ((imm & 0x1f) << 7)
+    }
+
+    pub fn imm_b(imm: i32) -> u32 {
+        let imm = imm as u32;
+        ((imm & 0x1000) << 19) | ((imm & 0x7e0) << 20) | ((imm & 0x1e) << 7) | ((imm & 0x800) >> 4)
+    }
+
+    pub fn imm_j(imm: i32) -> u32 {
+        let imm = imm as u32;
+        ((imm & 0x100000) << 11) | ((imm & 0x7fe) << 20) | ((imm & 0x800) << 9) | (imm & 0xff000)
+    }
+
+    pub fn aqrl(aq: bool, rl: bool) -> u32 {
+        (aq as u32) << 26 | (rl as u32) << 25
+    }
+
+    pub fn fence_set(set: FenceSet) -> u32 {
+        (set.input() as u32) << 3
+            | (set.output() as u32) << 2
+            | (set.read() as u32) << 1
+            | set.write() as u32
+    }
+}
+
+impl Instruction {
+    /// Encodes the instruction by filling its operands into the template of its kind.
+    ///
+    /// Instructions that were decoded from an invalid encoding are returned as they were.
+    /// Fences with a mode other than none or `.tso` are given the reserved mode `0b1111`.
+    pub fn encode(&self) -> u32 {
+        use Instruction::*;
+
+        let template = self.kind().template();
+        let operands = match *self {
+            Lui { rd, imm } | Auipc { rd, imm } => field::rd(rd) | i32::from(imm) as u32,
+            Jal { rd, imm } => field::rd(rd) | field::imm_j(imm.into()),
+            Jalr { rd, rs1, imm }
+            | Lb { rd, rs1, imm }
+            | Lh { rd, rs1, imm }
+            | Lw { rd, rs1, imm }
+            | Lbu { rd, rs1, imm }
+            | Lhu { rd, rs1, imm }
+            | Addi { rd, rs1, imm }
+            | Slti { rd, rs1, imm }
+            | Sltiu { rd, rs1, imm }
+            | Xori { rd, rs1, imm }
+            | Ori { rd, rs1, imm }
+            | Andi { rd, rs1, imm }
+            | Fencei { rd, rs1, imm } => field::rd(rd) | field::rs1(rs1) | field::imm_i(imm.into()),
+            Beq { rs1, rs2, imm }
+            | Bne { rs1, rs2, imm }
+            | Blt { rs1, rs2, imm }
+            | Bge { rs1, rs2, imm }
+            | Bltu { rs1, rs2, imm }
+            | Bgeu { rs1, rs2, imm } => {
+                field::rs1(rs1) | field::rs2(rs2) | field::imm_b(imm.into())
+            }
+            Sb { rs1, rs2, imm } | Sh { rs1, rs2, imm } | Sw { rs1, rs2, imm } => {
+                field::rs1(rs1) | field::rs2(rs2) | field::imm_s(imm.into())
+            }
+            Slli { rd, rs1, shamt } | Srli { rd, rs1, shamt } | Srai { rd, rs1, shamt } => {
+                field::rd(rd) | field::rs1(rs1) | u32::from(shamt) << 20
+            }
+            Add { rd, rs1, rs2 }
+            | Sub { rd, rs1, rs2 }
+            | Sll { rd, rs1, rs2 }
+            | Slt { rd, rs1, rs2 }
+            | Sltu { rd, rs1, rs2 }
+            | Xor { rd, rs1, rs2 }
+            | Srl { rd, rs1, rs2 }
+            | Sra { rd, rs1, rs2 }
+            | Or { rd, rs1, rs2 }
+            | And { rd, rs1, rs2 }
+            | Mul { rd, rs1, rs2 }
+            | Mulh { rd, rs1, rs2 }
+            | Mulhsu { rd, rs1, rs2 }
+            | Mulhu { rd, rs1, rs2 }
+            | Div { rd, rs1, rs2 }
+            | Divu { rd, rs1, rs2 }
+            | Rem { rd, rs1, rs2 }
+            | Remu { rd, rs1, rs2 } => field::rd(rd) | field::rs1(rs1) | field::rs2(rs2),
+            Fence {
+                rd,
+                rs1,
+                pred,
+                succ,
+                mode,
+            } => {
+                let mode = match mode {
+                    FenceMode::None => 0,
+                    FenceMode::Tso => 8,
+                    FenceMode::Other => 15,
+                };
+                field::rd(rd)
+                    | field::rs1(rs1)
+                    | mode << 28
+                    | field::fence_set(pred) << 24
+                    | field::fence_set(succ) << 20
+            }
+            Ecall | Ebreak | Mret | Sret => 0,
+            CsrRw { rd, rs1, csr } | CsrRs { rd, rs1, csr } | CsrRc { rd, rs1, csr } => {
+                field::rd(rd) | field::rs1(rs1) | u32::from(csr) << 20
+            }
+            CsrRwi { rd, uimm, csr } | CsrRsi { rd, uimm, csr } | CsrRci { rd, uimm, csr } => {
+                field::rd(rd) | u32::from(uimm) << 15 | u32::from(csr) << 20
+            }
+            Lrw { rd, rs1, aq, rl } => field::rd(rd) | field::rs1(rs1) | field::aqrl(aq, rl),
+            #[rustfmt::skip]
+            Scw { rd, rs1, rs2, aq, rl }
+            | AmoSwapw { rd, rs1, rs2, aq, rl }
+            | AmoAddw { rd, rs1, rs2, aq, rl }
+            | AmoXorw { rd, rs1, rs2, aq, rl }
+            | AmoAndw { rd, rs1, rs2, aq, rl }
+            | AmoOrw { rd, rs1, rs2, aq, rl }
+            | AmoMinw { rd, rs1, rs2, aq, rl }
+            | AmoMaxw { rd, rs1, rs2, aq, rl }
+            | AmoMinuw { rd, rs1, rs2, aq, rl }
+            | AmoMaxuw { rd, rs1, rs2, aq, rl } => {
+                field::rd(rd) | field::rs1(rs1) | field::rs2(rs2) | field::aqrl(aq, rl)
+            },
+            Flw { rd, rs1, imm } => field::fd(rd) | field::rs1(rs1) | field::imm_i(imm.into()),
+            Fsw { rs1, rs2, imm } => field::rs1(rs1) | field::fs2(rs2) | field::imm_s(imm.into()),
+            Fadds { rd, rs1, rs2, rm }
+            | Fsubs { rd, rs1, rs2, rm }
+            | Fmuls { rd, rs1, rs2, rm }
+            | Fdivs { rd, rs1, rs2, rm } => {
+                field::fd(rd) | field::fs1(rs1) | field::fs2(rs2) | field::rm(rm)
+            }
+            Fsqrts { rd, rs1, rm } => field::fd(rd) | field::fs1(rs1) | field::rm(rm),
+            Fsgnjs { rd, rs1, rs2 }
+            | Fsgnjns { rd, rs1, rs2 }
+            | Fsgnjxs { rd, rs1, rs2 }
+            | Fmins { rd, rs1, rs2 }
+            | Fmaxs { rd, rs1, rs2 } => field::fd(rd) | field::fs1(rs1) | field::fs2(rs2),
+            Feqs { rd, rs1, rs2 } | Flts { rd, rs1, rs2 } | Fles { rd, rs1, rs2 } => {
+                field::rd(rd) | field::fs1(rs1) | field::fs2(rs2)
+            }
+            Fcvtws { rd, rs1, rm } | Fcvtwus { rd, rs1, rm } => {
+                field::rd(rd) | field::fs1(rs1) | field::rm(rm)
+            }
+            Fcvtsw { rd, rs1, rm } | Fcvtswu { rd, rs1, rm } => {
+                field::fd(rd) | field::rs1(rs1) | field::rm(rm)
+            }
+            Fmvxw { rd, rs1 } | Fclasss { rd, rs1 } => field::rd(rd) | field::fs1(rs1),
+            Fmvwx { rd, rs1 } => field::fd(rd) | field::rs1(rs1),
+            Invalid { raw } => raw,
+        };
+
+        template | operands
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::hart::{csr::Csr, instruction::Instruction, Reg};
+
+    #[test]
+    fn encode() {
+        let addi = Instruction::Addi {
+            rd: Reg::A0,
+            rs1: Reg::A1,
+            imm: (-4).into(),
+        };
+        assert_eq!(addi.encode(), 0xffc58513);
+
+        let sw = Instruction::Sw {
+            rs1: Reg::S0,
+            rs2: Reg::A1,
+            imm: (-12).into(),
+        };
+        assert_eq!(sw.encode(), 0xfeb42a23);
+
+        let beq = Instruction::Beq {
+            rs1: Reg::RA,
+            rs2: Reg::SP,
+            imm: (-8).into(),
+        };
+        assert_eq!(beq.encode(), 0xfe208ce3);
+
+        let csrrw = Instruction::CsrRw {
+            rd: Reg::A0,
+            rs1: Reg::A1,
+            csr: Csr::MStatus,
+        };
+        assert_eq!(csrrw.encode(), 0x30059573);
+    }
+
+    #[test]
+    fn round_trip() {
+        // `jal` is left out, as its immediate doesn't survive decoding
+        let cases = [
+            0x12345537, // lui a0, 0x12345
+            0xfffff297, // auipc t0, 0xfffff
+            0x000500e7, // jalr ra, 0(a0)
+            0xfe208ce3, // beq ra, sp, -8
+            0x7eb51fe3, // bne a0, a1, 4094
+            0x80c5c063, // blt a1, a2, -4096
+            0x00812283, // lw t0, 8(sp)
+            0xfff54583, // lbu a1, -1(a0)
+            0xfeb42a23, // sw a1, -12(s0)
+            0x7eb50fa3, // sb a1, 2047(a0)
+            0xffc58513, // addi a0, a1, -4
+            0x80053513, // sltiu a0, a0, -2048
+            0x00351513, // slli a0, a0, 3
+            0x41f5d513, // srai a0, a1, 31
+            0x40c58533, // sub a0, a1, a2
+            0x40c5d533, // sra a0, a1, a2
+            0x0310000f, // fence rw, w
+            0x8330000f, // fence.tso
+            0x00000073, // ecall
+            0x00100073, // ebreak
+            0x30200073, // mret
+            0x10200073, // sret
+            0x30059573, // csrrw a0, mstatus, a1
+            0x30446073, // csrrsi zero, mie, 8
+            0x100522af, // lr.w t0, (a0)
+            0x1ac5a52f, // sc.w.rl a0, a2, (a1)
+            0x06c5a52f, // amoadd.w.aqrl a0, a2, (a1)
+            0xe0c5a52f, // amomaxu.w a0, a2, (a1)
+            0xffffffff, // invalid
+        ];
+
+        for raw in cases {
+            let instruction = Instruction::from(raw);
+            assert_eq!(instruction.encode(), raw, "{instruction:?}");
+            assert_eq!(Instruction::from(instruction.encode()), instruction);
+        }
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "rv32f"), ignore = "needs the F extension")]
+    fn float_round_trip() {
+        let cases = [
+            (0xffc12507, "flw fa0, -4(sp)"),
+            (0x00b52427, "fsw fa1, 8(a0)"),
+            (0x18209053, "fdiv.s ft0, ft1, ft2, rtz"),
+            (0x5804f453, "fsqrt.s fs0, fs1"),
+            (0x20c59553, "fsgnjn.s fa0, fa1, fa2"),
+            (0x28c59553, "fmax.s fa0, fa1, fa2"),
+            (0xa0c59553, "flt.s a0, fa1, fa2"),
+            (0xc015a553, "fcvt.wu.s a0, fa1, rdn"),
+            (0xd005f553, "fcvt.s.w fa0, a1"),
+            (0xe0059553, "fclass.s a0, fa1"),
+        ];
+
+        for (raw, asm) in cases {
+            let instruction = Instruction::from(raw);
+            assert_eq!(instruction.encode(), raw, "{instruction:?}");
+            assert_eq!(instruction.disassemble(0), asm);
+        }
+    }
+}
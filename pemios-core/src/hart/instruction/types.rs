@@ -7,6 +7,8 @@
 //
 // Copyright © 2022 mumblingdrunkard
 
+use crate::hart::mmu::Access;
+
 #[derive(Clone, Copy, Debug)]
 /// A Conclusion is used to indicate the status of the executed instruction.
 pub enum Conclusion {
@@ -16,10 +18,90 @@ pub enum Conclusion {
     /// we should not manually update it
     Jumped,
     /// Conclusion::Exception indicates an exception occured and we should raise this to the OS
-    Exception(u8),
+    Exception(ExceptionKind),
+    /// Conclusion::Watchpoint indicates that a watchpoint at `addr` was hit by an access of `kind`.
+    /// Data accesses have completed and the pc has been updated, while watched instructions have
+    /// not been executed yet
+    Watchpoint { addr: u32, kind: Access },
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// The kind of exception raised by an instruction, along with the faulting address where there
+/// is one
+pub enum ExceptionKind {
+    /// A jump or taken branch to a `target` that is not aligned
+    InstructionMisaligned { target: u32 },
+    /// An instruction fetch from `addr` that could not be performed
+    InstructionAccessFault { addr: u32 },
+    /// An invalid or unsupported instruction, whose encoding is `raw` if known and 0 otherwise
+    IllegalInstruction { raw: u32 },
+    /// A load from a misaligned `addr`
+    LoadMisaligned { addr: u32 },
+    /// A load from `addr` that could not be performed
+    LoadAccessFault { addr: u32 },
+    /// A store or AMO to a misaligned `addr`
+    StoreMisaligned { addr: u32 },
+    /// A store or AMO to `addr` that could not be performed
+    StoreAccessFault { addr: u32 },
+    /// An `ebreak` at `addr`
+    Breakpoint { addr: u32 },
+    /// An environment call from user mode
+    EcallFromU,
+    /// An environment call from supervisor mode
+    EcallFromS,
+    /// An environment call from machine mode
+    EcallFromM,
+    /// An instruction fetch from `addr` that was not permitted by the page table
+    InstructionPageFault { addr: u32 },
+    /// A load from `addr` that was not permitted by the page table
+    LoadPageFault { addr: u32 },
+    /// A store or AMO to `addr` that was not permitted by the page table
+    StorePageFault { addr: u32 },
+}
+
+impl ExceptionKind {
+    /// The value written to `mtval` for this exception
+    pub fn tval(&self) -> u32 {
+        use ExceptionKind::*;
+        match *self {
+            InstructionMisaligned { target } => target,
+            IllegalInstruction { raw } => raw,
+            InstructionAccessFault { addr }
+            | Breakpoint { addr }
+            | LoadMisaligned { addr }
+            | LoadAccessFault { addr }
+            | StoreMisaligned { addr }
+            | StoreAccessFault { addr }
+            | InstructionPageFault { addr }
+            | LoadPageFault { addr }
+            | StorePageFault { addr } => addr,
+            EcallFromU | EcallFromS | EcallFromM => 0,
+        }
+    }
+
+    /// The exception code written to `mcause` for this exception
+    pub fn cause(&self) -> u32 {
+        use ExceptionKind::*;
+        match self {
+            InstructionMisaligned { .. } => 0,
+            InstructionAccessFault { .. } => 1,
+            IllegalInstruction { .. } => 2,
+            Breakpoint { .. } => 3,
+            LoadMisaligned { .. } => 4,
+            LoadAccessFault { .. } => 5,
+            StoreMisaligned { .. } => 6,
+            StoreAccessFault { .. } => 7,
+            EcallFromU => 8,
+            EcallFromS => 9,
+            EcallFromM => 11,
+            InstructionPageFault { .. } => 12,
+            LoadPageFault { .. } => 13,
+            StorePageFault { .. } => 15,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 /// Unsigned, 5-bit integer
 /// Can be cast to a u32
 pub struct UInt5(u8);
@@ -37,7 +119,7 @@ impl From<UInt5> for u32 {
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 /// Signed, 12-bit integer
 /// Can be cast to an i32
 pub struct Int12(i16);
@@ -58,7 +140,7 @@ impl From<Int12> for i32 {
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 /// Signed, 32-bit integer with the 12 least significant bits set to 0
 /// Can be cast to an i32
 pub struct Int32Trunc12([u8; 3]);
@@ -85,7 +167,7 @@ impl From<Int32Trunc12> for i32 {
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 /// Signed, 21-bit integer with the least significant bit set to 0
 /// Can be cast to an i32
 pub struct Int21Trunc1([u8; 3]);
@@ -93,11 +175,9 @@ pub struct Int21Trunc1([u8; 3]);
 impl From<i32> for Int21Trunc1 {
     fn from(val: i32) -> Self {
         assert!((val << 11) >> 11 == val && val & 1 == 0, "");
-        Self(
-            (val & 0xfffff000u32 as i32).to_le_bytes()[..3]
-                .try_into()
-                .unwrap(),
-        )
+        // stored as the upper three bytes of `val << 11`, which an arithmetic
+        // shift turns back into `val`
+        Self((val << 11).to_le_bytes()[1..].try_into().unwrap())
     }
 }
 
@@ -109,7 +189,7 @@ impl From<Int21Trunc1> for i32 {
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 /// Signed, 13-bit integer with the least significant bit set to 0
 /// Can be cast to an i32
 pub struct Int13Trunc1(i16);
@@ -127,7 +207,50 @@ impl From<Int13Trunc1> for i32 {
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+/// Implements `Serialize` and `Deserialize` for an immediate as the value it represents, since
+/// the truncated form it is stored in is an implementation detail.
+/// Values the immediate cannot hold are rejected instead of panicking.
+#[cfg(feature = "serde")]
+macro_rules! serde_immediate {
+    ($imm:ident, $int:ty, $valid:expr) => {
+        impl serde::Serialize for $imm {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serde::Serialize::serialize(&<$int>::from(*self), serializer)
+            }
+        }
+
+        impl<'de> serde::Deserialize<'de> for $imm {
+            fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                let val = <$int as serde::Deserialize>::deserialize(deserializer)?;
+                let valid: fn($int) -> bool = $valid;
+                if valid(val) {
+                    Ok(Self::from(val))
+                } else {
+                    Err(serde::de::Error::custom(format!(
+                        "{val} is not a valid {}",
+                        stringify!($imm)
+                    )))
+                }
+            }
+        }
+    };
+}
+
+#[cfg(feature = "serde")]
+serde_immediate!(UInt5, u32, |val| val < 32);
+#[cfg(feature = "serde")]
+serde_immediate!(Int12, i32, |val| (-2048..2048).contains(&val));
+#[cfg(feature = "serde")]
+serde_immediate!(Int32Trunc12, i32, |val| val & 0xfff == 0);
+#[cfg(feature = "serde")]
+serde_immediate!(Int21Trunc1, i32, |val| (val << 11) >> 11 == val
+    && val & 1 == 0);
+#[cfg(feature = "serde")]
+serde_immediate!(Int13Trunc1, i32, |val| (val << 19) >> 19 == val
+    && val & 1 == 0);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FenceSet(u8);
 
 impl FenceSet {
@@ -157,11 +280,21 @@ impl FenceSet {
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 /// The fence mode.
 /// Fence will never raise an exception meaning we can store this in a lossy format
 pub enum FenceMode {
     None,
+    /// `fence.tso` orders prior loads before later loads and prior stores
+    /// before later stores, but allows later loads to be performed before
+    /// prior stores.
+    ///
+    /// A hart performs its own loads in program order, so the only reordering
+    /// visible to other agents comes from stores waiting in the write-back
+    /// data cache.
+    /// `fence.tso` therefore writes back all dirty lines, but leaves them
+    /// resident in the cache.
     Tso,
     Other,
 }
@@ -176,16 +309,66 @@ impl FenceMode {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+/// The rounding mode of a floating-point instruction
+pub enum RoundingMode {
+    /// Round to nearest, ties to even
+    Rne,
+    /// Round towards zero
+    Rtz,
+    /// Round down, towards negative infinity
+    Rdn,
+    /// Round up, towards positive infinity
+    Rup,
+    /// Round to nearest, ties to max magnitude
+    Rmm,
+    /// Use the rounding mode in `frm`
+    Dynamic,
+}
+
+impl RoundingMode {
+    /// The rounding mode encoded as `rm`, or `None` if it is reserved
+    pub fn new(rm: u8) -> Option<Self> {
+        match rm {
+            0 => Some(Self::Rne),
+            1 => Some(Self::Rtz),
+            2 => Some(Self::Rdn),
+            3 => Some(Self::Rup),
+            4 => Some(Self::Rmm),
+            7 => Some(Self::Dynamic),
+            _ => None,
+        }
+    }
+}
+
+impl From<RoundingMode> for u32 {
+    fn from(rm: RoundingMode) -> Self {
+        match rm {
+            RoundingMode::Rne => 0,
+            RoundingMode::Rtz => 1,
+            RoundingMode::Rdn => 2,
+            RoundingMode::Rup => 3,
+            RoundingMode::Rmm => 4,
+            RoundingMode::Dynamic => 7,
+        }
+    }
+}
+
 #[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum OpCode {
     Load,
+    LoadFp,
     MiscMem,
     OpImm,
     Auipc,
     Store,
+    StoreFp,
     Amo,
     Op,
     Lui,
+    OpFp,
     Branch,
     Jalr,
     Jal,
@@ -198,13 +381,16 @@ impl From<u32> for OpCode {
         use OpCode::*;
         match op {
             0b0000011 => Load,
+            0b0000111 => LoadFp,
             0b0001111 => MiscMem,
             0b0010011 => OpImm,
             0b0010111 => Auipc,
             0b0100011 => Store,
+            0b0100111 => StoreFp,
             0b0101111 => Amo,
             0b0110011 => Op,
             0b0110111 => Lui,
+            0b1010011 => OpFp,
             0b1100011 => Branch,
             0b1100111 => Jalr,
             0b1101111 => Jal,
@@ -213,3 +399,56 @@ impl From<u32> for OpCode {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{ExceptionKind, Int21Trunc1, Int32Trunc12};
+
+    #[test]
+    fn exception_causes() {
+        use ExceptionKind::*;
+
+        for (kind, cause, tval) in [
+            (InstructionMisaligned { target: 0x102 }, 0, 0x102),
+            (InstructionAccessFault { addr: 0x4 }, 1, 0x4),
+            (IllegalInstruction { raw: 0xffffffff }, 2, 0xffffffff),
+            (Breakpoint { addr: 0x8 }, 3, 0x8),
+            (LoadMisaligned { addr: 0x11 }, 4, 0x11),
+            (LoadAccessFault { addr: 0x12 }, 5, 0x12),
+            (StoreMisaligned { addr: 0x13 }, 6, 0x13),
+            (StoreAccessFault { addr: 0x14 }, 7, 0x14),
+            (EcallFromU, 8, 0),
+            (EcallFromS, 9, 0),
+            (EcallFromM, 11, 0),
+            (InstructionPageFault { addr: 0x1000 }, 12, 0x1000),
+            (LoadPageFault { addr: 0x2000 }, 13, 0x2000),
+            (StorePageFault { addr: 0x3000 }, 15, 0x3000),
+        ] {
+            assert_eq!((kind.cause(), kind.tval()), (cause, tval), "{kind:?}");
+        }
+    }
+
+    #[test]
+    fn int32_trunc12() {
+        for val in [0, 0x1000, 0x7ffff000, 0x80000000u32 as i32, -0x1000] {
+            assert_eq!(i32::from(Int32Trunc12::from(val)), val);
+        }
+    }
+
+    #[test]
+    fn int21_trunc1() {
+        for val in [
+            0,
+            2,
+            -2,
+            0x7fe,
+            0x800,
+            0x1002,
+            0xffffe,
+            0x100000 - 2,
+            -0x100000,
+        ] {
+            assert_eq!(i32::from(Int21Trunc1::from(val)), val);
+        }
+    }
+}
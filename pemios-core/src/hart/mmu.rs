@@ -7,27 +7,48 @@
 //
 // Copyright © 2022 mumblingdrunkard
 
-use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::atomic::{fence, AtomicU32, Ordering};
 
 use crate::{
     bus::{Bus, BusError},
     memory::{
-        self,
-        mapping::{Mapping, MemoryError, PmaPacked},
+        endian::MemoryOrder,
+        mapping::{
+            Cacheability, Idempotency, Mapping, MemoryError, MemoryResult, PmaPacked,
+            Reservability, SendSyncMapping,
+        },
     },
 };
 
 use self::cache::Cache;
 
-use super::{instruction::Instruction, sv32::Pte};
+use super::{
+    csr::MStatus,
+    instruction::Instruction,
+    pmp::Pmp,
+    step::{handler, Handler},
+    sv32::{Pte, PteKind, VirtualAddress},
+    PrivilegeMode,
+};
 
 mod cache;
 
+/// The kind of access an address is translated for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    Execute,
+}
+
 #[derive(Debug)]
 pub enum MmuError {
     LoadMisaligned { addr: u32, alignment: u32 },
     StoreMisaligned { addr: u32, alignment: u32 },
     OutOfBoundsAccess { addr: u32 },
+    PageFault { addr: u32, access: Access },
+    AccessFault { addr: u32, access: Access },
+    ReservationUnsupported { addr: u32 },
     BusError { e: BusError },
 }
 
@@ -49,6 +70,17 @@ pub fn addr_to_reservation_set(addr: u32) -> u32 {
     addr >> 6
 }
 
+/// The ordering of an atomic memory operation with the given `aq` and `rl`
+/// bits.
+pub fn aqrl_ordering(aq: bool, rl: bool) -> Ordering {
+    match (aq, rl) {
+        (false, false) => Ordering::Relaxed,
+        (true, false) => Ordering::Acquire,
+        (false, true) => Ordering::Release,
+        (true, true) => Ordering::AcqRel,
+    }
+}
+
 pub fn helper_invalidate_reservations(
     reservations: &[&std::sync::atomic::AtomicU32],
     should_be: u32,
@@ -66,17 +98,137 @@ pub fn helper_check_reservation(reservation: &AtomicU32, should_be: u32) -> u32
     }
 }
 
+/// An entry in the instruction cache.
+///
+/// The instruction cache has one entry per 16-bit parcel so that instructions
+/// can be fetched from any halfword-aligned address.
+#[derive(Clone, Copy)]
+enum Fetched {
+    /// The instruction starting at this parcel, the handler executing it, and
+    /// its length in bytes
+    Decoded(Instruction, Handler, u8),
+
+    /// The raw 32 bits starting at this parcel, decoded on first use since
+    /// most of a line is often never executed
+    Raw(u32),
+
+    /// The first parcel of a 32-bit instruction that continues into a line
+    /// that was not available when this one was filled
+    Partial(u16),
+}
+
+impl Default for Fetched {
+    fn default() -> Self {
+        Self::Raw(0)
+    }
+}
+
+/// Decodes the instruction starting with the parcel `lo`, followed by `hi`.
+#[inline(always)]
+fn decode_parcels(lo: u16, hi: u16) -> Instruction {
+    (lo as u32 | (hi as u32) << 16).into()
+}
+
+/// Whether the leaf entry `pte` permits an access of kind `access` performed
+/// in `mode`.
+///
+/// User mode may only access user pages, and supervisor mode may only load
+/// from and store to them while `mstatus.SUM` is set, but never execute them.
+/// With `mstatus.MXR` set, executable pages are also readable.
+#[inline(always)]
+fn permits(pte: Pte, access: Access, mode: PrivilegeMode, mstatus: MStatus) -> bool {
+    let privileged = match mode {
+        PrivilegeMode::User => pte.user(),
+        PrivilegeMode::Supervisor => !pte.user() || (access != Access::Execute && mstatus.sum()),
+        PrivilegeMode::Machine => true,
+    };
+
+    privileged
+        && match access {
+            Access::Read => pte.readable() || (mstatus.mxr() && pte.executable()),
+            Access::Write => pte.writable(),
+            Access::Execute => pte.executable(),
+        }
+}
+
+/// Whether an access of kind `access` needs to set the accessed or dirty bit
+/// of the leaf entry `pte`.
+#[inline(always)]
+fn needs_update(pte: Pte, access: Access) -> bool {
+    !pte.accessed() || (access == Access::Write && !pte.dirty())
+}
+
+/// Reads all of `dst` from `addr`, failing if any of it is not backed by
+/// memory.
+fn read_all(bus: &Bus, addr: u32, dst: &mut [u8]) -> MemoryResult<()> {
+    let read = bus.block_read(addr, dst)?;
+    if read < dst.len() {
+        return Err(MemoryError::OutOfBoundsAccess {
+            offset: addr + read as u32,
+        });
+    }
+
+    Ok(())
+}
+
 pub struct Mmu<'a> {
     reservation: &'a AtomicU32,
     d_cache: Box<cache::Cache<u32, u64, 8, 2, 4>>,
-    i_cache: Box<cache::Cache<Instruction, (), 8, 2, 4>>,
+    i_cache: Box<cache::Cache<Fetched, (), 8, 2, 5>>,
     // only one element per cache line as it makes little sense to block-fetch memory attributes
-    #[allow(unused)]
     attr: Box<cache::Cache<PmaPacked, (), 12, 3, 0>>,
     // only one element per cache line as block-fetching translations also makes no sense
-    #[allow(unused)]
-    tlb: Box<cache::Cache<Pte, (), 12, 3, 0>>,
+    tlb: Box<cache::Cache<Translation, (), 12, 3, 0>>,
+    satp: u32,
+    pmp: Pmp,
+    privilege: PrivilegeMode,
+    mstatus: MStatus,
     bus: &'a Bus<'a>,
+    watchpoints: Vec<(u32, Access)>,
+    watchpoint_hit: Option<(u32, Access)>,
+    // stores to streamable mappings that have not been sent to the bus yet,
+    // all within the frame `wc_frame`
+    wc_bufs: Vec<(u16, u8, u32)>,
+    wc_frame: u32,
+    // words read ahead from an idempotent streamable mapping, in memory
+    // order, covering the 64 bytes at `rc_addr`
+    rc_bufs: [u32; 16],
+    rc_addr: Option<u32>,
+    // whether stores invalidate the instruction cache lines they hit, for
+    // programs that modify their code without `fence.i`
+    coherent_instructions: bool,
+}
+
+/// The number of stores the write-combining buffers hold before they are
+/// flushed.
+const WC_CAPACITY: usize = 16;
+
+/// A cached translation: the leaf entry for a page, and the address space it
+/// was walked in.
+#[derive(Clone, Copy, Default)]
+struct Translation {
+    pte: Pte,
+    asid: u32,
+}
+
+impl Translation {
+    /// Whether the translation may be used in address space `asid`, which is
+    /// always the case for global pages.
+    #[inline(always)]
+    fn matches(&self, asid: u32) -> bool {
+        self.pte.global() || self.asid == asid
+    }
+}
+
+/// The bits of a line's dirty-byte tracker covered by a store of `W` bytes at
+/// `addr`.
+///
+/// The tracker has one bit per byte of the 64-byte line, so byte `n` of the
+/// line is bit `n`, which is the layout `block_write_masked` expects once the
+/// tracker is laid out in little-endian.
+#[inline(always)]
+fn dirty_bits<const W: u8>(addr: u32) -> u64 {
+    ((1 << W) - 1) << (addr & 0x3f)
 }
 
 trait AsU8Array<const W: usize> {
@@ -133,7 +285,18 @@ impl<'a> Mmu<'a> {
             i_cache: Box::new(Cache::new()),
             attr: Box::new(Cache::new()),
             tlb: Box::new(Cache::new()),
+            satp: 0,
+            pmp: Pmp::new(),
+            privilege: PrivilegeMode::Machine,
+            mstatus: MStatus::from(0),
             bus,
+            watchpoints: Vec::new(),
+            watchpoint_hit: None,
+            wc_bufs: Vec::with_capacity(WC_CAPACITY),
+            wc_frame: 0,
+            rc_bufs: [0; 16],
+            rc_addr: None,
+            coherent_instructions: false,
         }
     }
 
@@ -141,15 +304,520 @@ impl<'a> Mmu<'a> {
         self.reservation
     }
 
+    /// Writes the dirty bytes of an evicted d-cache line back to the bus.
+    ///
+    /// `addr` is the word address of the first word in the line and `mask`
+    /// has bit `i` set if byte `i` of the line has been written to.
+    #[inline(always)]
+    fn write_back(bus: &Bus, addr: u32, data: &[u32; 16], mask: u64) -> MmuResult<()> {
+        let mask = mask.to_le(); // ensures mask.as_u8_array()[0] & 1 is the first bit
+        let mask = mask.as_u8_array();
+        let (_, src, _) = unsafe { data.align_to::<u8>() };
+        bus.block_write_masked(addr << 2, src, &mask[..])?;
+        Ok(())
+    }
+
+    /// Writes back the data cache line holding the physical address `paddr` if
+    /// it is dirty, and invalidates it, so the next access fetches it from
+    /// the bus.
+    fn evict_line(&mut self, paddr: u32) -> MmuResult<()> {
+        if let Some((line, data, mask)) = self.d_cache.invalidate(paddr >> 2) {
+            Self::write_back(self.bus, line, &data, mask)?;
+        }
+        Ok(())
+    }
+
+    /// Writes back all dirty lines in the data cache, leaving them resident.
+    ///
+    /// After this returns, every store performed through this MMU is visible
+    /// on the bus.
+    pub fn write_back_all(&mut self) -> MmuResult<()> {
+        self.invalidate_read_combining();
+        self.flush_write_combining()?;
+        let bus = self.bus;
+        self.d_cache
+            .flush(|addr, data: &[u32; 16], mask| Self::write_back(bus, addr, data, mask))
+    }
+
+    /// Sends the stores held in the write-combining buffers to the bus as a
+    /// single `stream_write`.
+    ///
+    /// The buffers are emptied even if the write fails.
+    pub fn flush_write_combining(&mut self) -> MmuResult<()> {
+        if self.wc_bufs.is_empty() {
+            return Ok(());
+        }
+
+        let result = self.bus.stream_write(self.wc_frame, &self.wc_bufs);
+        self.wc_bufs.clear();
+        result?;
+        Ok(())
+    }
+
+    /// Buffers a store of `width` bytes to a streamable mapping.
+    ///
+    /// The buffers are flushed first if they are full or hold stores to a
+    /// different frame.
+    fn combine_write(&mut self, addr: u32, width: u8, val: u32) -> MmuResult<()> {
+        let frame = addr >> 12;
+        if frame != self.wc_frame || self.wc_bufs.len() == WC_CAPACITY {
+            self.flush_write_combining()?;
+            self.wc_frame = frame;
+        }
+
+        let val = val & (u32::MAX >> (32 - 8 * width as u32));
+        self.wc_bufs.push(((addr & 0xfff) as u16, width, val));
+        Ok(())
+    }
+
+    /// Discards the values held in the read-combining buffers, so the next
+    /// load from a streamable mapping reads from the bus.
+    pub fn invalidate_read_combining(&mut self) {
+        self.rc_addr = None;
+    }
+
+    /// Loads `W` bytes from a streamable mapping.
+    ///
+    /// Idempotent mappings are read ahead, a 64-byte line at a time, with a
+    /// single `stream_read`, and later loads from the line are served from
+    /// the read-combining buffers.
+    /// Non-idempotent mappings are never read ahead and go straight to the
+    /// bus.
+    fn combine_read<const W: u8>(&mut self, addr: u32, idempotent: bool) -> MmuResult<u32> {
+        // buffered stores to a device must reach it before it is read
+        self.flush_write_combining()?;
+
+        if !idempotent {
+            return Ok(match W {
+                4 => self.bus.load_word(addr)?,
+                2 => self.bus.load_half_word(addr)? as u32,
+                _ => self.bus.load_byte(addr)? as u32,
+            });
+        }
+
+        let line = addr & !0x3f;
+        if self.rc_addr != Some(line) {
+            self.rc_addr = None;
+            let reads: [(u16, u8); 16] =
+                std::array::from_fn(|i| ((line & 0xfff) as u16 + 4 * i as u16, 4));
+            self.bus
+                .stream_read(line >> 12, &reads, &mut self.rc_bufs)?;
+            self.rc_bufs.iter_mut().for_each(|w| *w = w.to_memory());
+            self.rc_addr = Some(line);
+        }
+
+        let w = self.rc_bufs[(addr as usize >> 2) & 0xf];
+        Ok(if W == 4 {
+            u32::from_memory(w)
+        } else if W == 2 {
+            let a = w.as_u16_array();
+            u16::from_memory(a[(addr as usize >> 1) & 1]) as u32
+        } else {
+            let a = w.as_u8_array();
+            (a[addr as usize & 3]) as u32
+        })
+    }
+
+    /// Makes all stores performed through this MMU visible to instruction
+    /// fetches.
+    ///
+    /// Dirty lines are written back and the instruction cache is emptied, so
+    /// instructions are fetched from the bus again.
+    pub fn synchronize_instructions(&mut self) -> MmuResult<()> {
+        self.write_back_all()?;
+        self.i_cache.invalidate_all();
+        Ok(())
+    }
+
+    /// Makes stores through this MMU visible to instruction fetches without a
+    /// `fence.i`, like hardware with coherent instruction caches.
+    ///
+    /// Every store then checks the instruction cache, and writes back its data
+    /// cache line if it hit an instruction, so this is off by default.
+    pub fn set_coherent_instructions(&mut self, coherent: bool) {
+        self.coherent_instructions = coherent;
+    }
+
+    /// Invalidates the instruction cache lines holding parcels at the physical
+    /// address `paddr`, which was just stored to.
+    ///
+    /// If any were cached, the data cache line is written back and
+    /// invalidated too, so the new instructions are fetched from the bus.
+    fn snoop(&mut self, paddr: u32) -> MmuResult<()> {
+        let mut hit = false;
+
+        // the last instruction of the previous line may continue into this one
+        let previous = (paddr & 0x3f < 2).then(|| paddr.wrapping_sub(2));
+        for addr in previous.into_iter().chain([paddr]) {
+            if self.i_cache.get(addr >> 1).is_some() {
+                self.i_cache.invalidate(addr >> 1);
+                hit = true;
+            }
+        }
+
+        if hit {
+            if let Some((line, data, mask)) = self.d_cache.invalidate(paddr >> 2) {
+                Self::write_back(self.bus, line, &data, mask)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the MMU to the state it was created in.
+    ///
+    /// Dirty lines are written back before the caches are emptied, so no
+    /// stores are lost.
+    /// Translation is disabled, every PMP entry is turned off, and the
+    /// reservation is cleared.
+    pub fn reset(&mut self) -> MmuResult<()> {
+        self.write_back_all()?;
+        self.invalidate_all();
+        self.satp = 0;
+        self.mstatus = MStatus::from(0);
+        self.pmp = Pmp::new();
+        self.reservation.store(u32::MAX, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Unmaps the mapping based at `base_frame` from the bus, returning it, or
+    /// `None` if no mapping is based there.
+    ///
+    /// Everything buffered for the bus is written back first, and the caches
+    /// are emptied so nothing of the mapping is kept.
+    pub fn remove_mapping(
+        &mut self,
+        base_frame: u32,
+    ) -> MmuResult<Option<&'a dyn SendSyncMapping<'a>>> {
+        self.write_back_all()?;
+        self.invalidate_all();
+        Ok(self.bus.remove_mapping(base_frame))
+    }
+
+    /// Empties every cache, discarding dirty lines and buffered stores instead
+    /// of writing them back.
+    pub fn invalidate_all(&mut self) {
+        self.wc_bufs.clear();
+        self.rc_addr = None;
+        self.d_cache.invalidate_all();
+        self.i_cache.invalidate_all();
+        self.attr.invalidate_all();
+        self.tlb.invalidate_all();
+    }
+
+    /// Watches the virtual address `addr` for accesses of kind `kind`.
+    ///
+    /// Loads and stores that touch the byte at `addr` complete as normal and
+    /// are reported by `take_watchpoint_hit` afterwards.
+    /// Fetching an instruction starting at `addr` is reported the same way,
+    /// but checked before the instruction cache, so it is caught every time.
+    pub fn add_watchpoint(&mut self, addr: u32, kind: Access) {
+        if !self.watchpoints.contains(&(addr, kind)) {
+            self.watchpoints.push((addr, kind));
+        }
+    }
+
+    /// Removes a watchpoint added with `add_watchpoint`, returning whether it
+    /// existed.
+    pub fn remove_watchpoint(&mut self, addr: u32, kind: Access) -> bool {
+        let len = self.watchpoints.len();
+        self.watchpoints.retain(|&w| w != (addr, kind));
+        self.watchpoints.len() != len
+    }
+
+    /// Takes the watchpoint hit by the accesses since the last call, if any.
+    pub fn take_watchpoint_hit(&mut self) -> Option<(u32, Access)> {
+        self.watchpoint_hit.take()
+    }
+
+    /// Records a hit if an access of kind `kind` to the `len` bytes at the
+    /// virtual address `addr` touches a watched byte.
+    #[inline(always)]
+    fn watch(&mut self, addr: u32, len: u32, kind: Access) {
+        if self.watchpoints.is_empty() {
+            return;
+        }
+
+        if let Some(&hit) = self
+            .watchpoints
+            .iter()
+            .find(|&&(w, k)| k == kind && w.wrapping_sub(addr) < len)
+        {
+            self.watchpoint_hit = Some(hit);
+        }
+    }
+
+    /// The attributes of the mapping `addr` belongs to, or `None` if nothing
+    /// is mapped there.
+    #[inline(always)]
+    fn attributes(&mut self, addr: u32) -> Option<PmaPacked> {
+        let frame = addr >> 12;
+        if let Some(&pma) = self.attr.get(frame) {
+            return Some(pma);
+        }
+
+        let pma = self.bus.attributes_at(addr)?.packed();
+        self.attr.insert(frame, [pma]);
+        Some(pma)
+    }
+
+    /// Whether `addr` may be cached, according to the attributes of the
+    /// mapping it belongs to.
+    ///
+    /// Non-idempotent mappings are never cached, even if they claim to be
+    /// cacheable, as every access to them may have side effects.
+    #[inline(always)]
+    fn cacheable(&mut self, addr: u32) -> bool {
+        // if nothing is mapped, the access fails on the bus
+        self.attributes(addr).is_some_and(|pma| {
+            pma.cacheability() == Cacheability::Cacheable
+                && pma.idempotency() == Idempotency::Idempotent
+        })
+    }
+
+    /// Whether stores to `addr` may be combined into a `stream_write`.
+    #[inline(always)]
+    fn write_streamable(&mut self, addr: u32) -> bool {
+        self.attributes(addr).is_some_and(|pma| {
+            matches!(
+                pma.cacheability(),
+                Cacheability::Stream | Cacheability::WriteStreamLoadCache
+            )
+        })
+    }
+
+    /// Whether loads from `addr` may be combined into a `stream_read`, and
+    /// whether they may also read ahead.
+    #[inline(always)]
+    fn read_streamable(&mut self, addr: u32) -> Option<bool> {
+        self.attributes(addr)
+            .filter(|pma| pma.cacheability() == Cacheability::Stream)
+            .map(|pma| pma.idempotency() == Idempotency::Idempotent)
+    }
+
+    /// Whether `addr` supports `lr` and `sc`.
+    ///
+    /// Mappings with `Reservability::NonEventual` are reservable, but `sc` may
+    /// fail there even when no other hart touched the reservation set.
+    #[inline(always)]
+    fn reservable(&mut self, addr: u32) -> bool {
+        self.attributes(addr)
+            .is_some_and(|pma| pma.reservability() != Reservability::None)
+    }
+
+    /// Sets the value of `satp` used for address translation.
+    pub fn set_satp(&mut self, satp: u32) {
+        self.satp = satp;
+    }
+
+    /// Sets the privilege mode accesses are performed in.
+    pub fn set_privilege(&mut self, mode: PrivilegeMode) {
+        self.privilege = mode;
+    }
+
+    /// Sets the value of `mstatus`, whose `MPRV` and `MPP` fields select the
+    /// privilege mode loads and stores are performed in.
+    pub fn set_mstatus(&mut self, mstatus: MStatus) {
+        self.mstatus = mstatus;
+    }
+
+    /// The privilege mode an access of kind `access` is performed in, which
+    /// for loads and stores is `mstatus.MPP` while `mstatus.MPRV` is set.
+    #[inline(always)]
+    fn access_privilege(&self, access: Access) -> PrivilegeMode {
+        if access != Access::Execute && self.mstatus.mprv() {
+            self.mstatus.mpp()
+        } else {
+            self.privilege
+        }
+    }
+
+    pub fn pmp(&self) -> &Pmp {
+        &self.pmp
+    }
+
+    /// Replaces every PMP entry at once.
+    pub fn set_pmp(&mut self, pmp: Pmp) {
+        self.pmp = pmp;
+    }
+
+    /// Writes `val` to `pmpcfg{n}`.
+    pub fn set_pmp_cfg(&mut self, n: usize, val: u32) {
+        self.pmp.set_cfg(n, val);
+    }
+
+    /// Writes `val` to `pmpaddr{i}`.
+    pub fn set_pmp_addr(&mut self, i: usize, val: u32) {
+        self.pmp.set_addr(i, val);
+    }
+
+    /// Checks an access of kind `access` to the `len` bytes at the physical
+    /// address `paddr` against the PMP entries, as performed in `mode`.
+    ///
+    /// PMP checks are not cached, so changes to the entries apply to the very
+    /// next access.
+    #[inline(always)]
+    pub fn pmp_check(
+        &self,
+        paddr: u32,
+        len: u32,
+        access: Access,
+        mode: PrivilegeMode,
+    ) -> MmuResult<()> {
+        if self.pmp.permits(paddr, len, access, mode) {
+            Ok(())
+        } else {
+            Err(MmuError::AccessFault {
+                addr: paddr,
+                access,
+            })
+        }
+    }
+
+    /// Translates the virtual address `addr` for an access of kind `access`,
+    /// then checks the physical address against the PMP entries.
+    #[inline(always)]
+    fn translate_checked(&mut self, addr: u32, len: u32, access: Access) -> MmuResult<u32> {
+        let paddr = self.translate(addr, access)?;
+        self.pmp_check(paddr, len, access, self.access_privilege(access))?;
+        Ok(paddr)
+    }
+
+    /// Translates the virtual address `addr` for an access of kind `access`.
+    ///
+    /// Addresses are only translated when `satp.MODE` selects sv32 and the
+    /// access is performed below machine mode; otherwise they are used as
+    /// physical addresses.
+    #[inline(always)]
+    fn translate(&mut self, addr: u32, access: Access) -> MmuResult<u32> {
+        if self.satp & 0x80000000 == 0 || self.access_privilege(access) == PrivilegeMode::Machine {
+            return Ok(addr);
+        }
+
+        // cached translations that still need their accessed or dirty bit set
+        // are walked again
+        let asid = self.asid();
+        let pte = match self.tlb.get(addr >> 12) {
+            Some(&t) if t.matches(asid) && !needs_update(t.pte, access) => t.pte,
+            _ => {
+                let pte = self.walk(addr, access)?;
+                self.tlb.insert(addr >> 12, [Translation { pte, asid }]);
+                pte
+            }
+        };
+
+        if !self.permits(pte, access) || needs_update(pte, access) {
+            return Err(MmuError::PageFault { addr, access });
+        }
+
+        Ok(pte.base() | (addr & 0xfff))
+    }
+
+    /// Whether the leaf entry `pte` permits an access of kind `access` in the
+    /// mode it is performed in.
     #[inline(always)]
-    fn cacheable(&self, addr: u32) -> bool {
-        addr & 0x80000000 == 0 || { todo!("Check attribute cache, or get attributes from bus") }
+    fn permits(&self, pte: Pte, access: Access) -> bool {
+        permits(pte, access, self.access_privilege(access), self.mstatus)
     }
 
-    #[allow(unused)]
+    /// The address space selected by `satp.ASID`
     #[inline(always)]
-    fn translate(&self, addr: u32) -> u32 {
-        todo!("Determine translation and protection, check tlb, walk page table")
+    fn asid(&self) -> u32 {
+        (self.satp >> 22) & 0x1ff
+    }
+
+    /// Invalidates cached translations, like `sfence.vma`.
+    ///
+    /// With `vaddr`, only translations for the page containing it are
+    /// invalidated, and with `asid`, only those of that address space.
+    /// Translations of global pages are kept when `asid` is given.
+    pub fn invalidate_translations(&mut self, vaddr: Option<u32>, asid: Option<u32>) {
+        match (vaddr, asid) {
+            (None, None) => self.tlb.invalidate_all(),
+            (Some(vaddr), None) => {
+                self.tlb.invalidate(vaddr >> 12);
+            }
+            (vaddr, Some(asid)) => self.tlb.invalidate_where(|vpn, &[t]: &[Translation; 1]| {
+                let page = vaddr.is_none_or(|vaddr| vaddr >> 12 == vpn);
+                page && !t.pte.global() && t.asid == asid & 0x1ff
+            }),
+        }
+    }
+
+    /// Walks the sv32 page table rooted at `satp.PPN` to find the leaf entry
+    /// for `addr`.
+    ///
+    /// Superpage entries are returned as the entry for the 4 KiB page within
+    /// them that contains `addr`, so that they can be cached like any other
+    /// page.
+    /// Page table entries are read through the data cache so that the walk
+    /// sees page tables written by this hart.
+    ///
+    /// If the access is permitted, the accessed bit of the leaf entry is set,
+    /// along with the dirty bit for writes, with an atomic update on the bus.
+    /// Should the entry have changed since it was read, the walk starts over.
+    fn walk(&mut self, addr: u32, access: Access) -> MmuResult<Pte> {
+        loop {
+            let (pte, pte_addr, raw) = self.find_leaf(addr, access)?;
+            if !self.permits(pte, access) || !needs_update(pte, access) {
+                return Ok(pte);
+            }
+
+            let bits = match access {
+                Access::Write => Pte::ACCESSED | Pte::DIRTY,
+                _ => Pte::ACCESSED,
+            };
+            if self.update_pte(pte_addr, bits)? == raw {
+                return Ok(Pte::from(pte.raw() | bits));
+            }
+        }
+    }
+
+    /// Finds the leaf entry for `addr` in the sv32 page table, along with the
+    /// physical address of the entry and its value in memory.
+    fn find_leaf(&mut self, addr: u32, access: Access) -> MmuResult<(Pte, u32, u32)> {
+        let fault = MmuError::PageFault { addr, access };
+        let va = VirtualAddress::from(addr);
+
+        // physical addresses are only 32 bits wide, so the upper bits of the
+        // 22-bit ppn are ignored
+        let mut table = self.satp << 12;
+        for level in [1, 0] {
+            let vpn = if level == 1 { va.vpn1() } else { va.vpn0() };
+            let pte_addr = table + vpn * 4;
+            let pte = Pte::from(self.load_physical::<4>(pte_addr)?);
+
+            if !pte.valid() {
+                return Err(fault);
+            }
+
+            match pte.kind() {
+                PteKind::Reserved => return Err(fault),
+                PteKind::Pointer => table = pte.base(),
+                // superpages must be aligned to 4 MiB
+                _ if level == 1 && pte.ppn0() != 0 => return Err(fault),
+                _ if level == 1 => {
+                    let page = Pte::from(pte.raw() | (va.vpn0() << 10));
+                    return Ok((page, pte_addr, pte.raw()));
+                }
+                _ => return Ok((pte, pte_addr, pte.raw())),
+            }
+        }
+
+        // pointer in a leaf table
+        Err(fault)
+    }
+
+    /// Atomically sets `bits` in the page table entry at the physical address
+    /// `pte_addr`, returning the entry as it was before.
+    ///
+    /// The line holding the entry is written back and invalidated first, so
+    /// later walks read the updated entry.
+    fn update_pte(&mut self, pte_addr: u32, bits: u32) -> MmuResult<u32> {
+        if let Some((line, data, mask)) = self.d_cache.invalidate(pte_addr >> 2) {
+            Self::write_back(self.bus, line, &data, mask)?;
+        }
+
+        Ok(self.bus.amoor_w(pte_addr, bits)?)
     }
 
     #[inline(always)]
@@ -165,10 +833,10 @@ impl<'a> Mmu<'a> {
         // fast path, if the value is in cache, it's cacheable
         if let Some(&w) = self.d_cache.get(addr >> 2) {
             if W == 4 {
-                return Ok(u32::from_le(w));
+                return Ok(u32::from_memory(w));
             } else if W == 2 {
                 let a = w.as_u16_array();
-                return Ok(u16::from_le(a[(addr as usize >> 1) & 1]) as u32);
+                return Ok(u16::from_memory(a[(addr as usize >> 1) & 1]) as u32);
             } else {
                 let a = w.as_u8_array();
                 return Ok((a[addr as usize & 3]) as u32);
@@ -181,30 +849,34 @@ impl<'a> Mmu<'a> {
             // closure to be executed when cache line is missing
             let missing = |x: &mut [u32; 16]| {
                 let (_, dst, _) = unsafe { x.align_to_mut::<u8>() };
-                self.bus.block_read(addr & 0xffffffc0, dst)
+                read_all(self.bus, addr & 0xffffffc0, dst)
             };
 
             let (&w, evicted) = self.d_cache.get_or_insert_with(addr >> 2, missing)?;
 
             if let Some((addr, data, mask)) = evicted {
-                let mask = mask.to_le(); // ensures mask.as_u8_array()[0] & 1 is the first bit
-                let mask = mask.as_u8_array();
-                let (_, src, _) = unsafe { data.align_to::<u8>() };
-                self.bus.block_write_masked(addr << 2, src, &mask[..])?;
+                Self::write_back(self.bus, addr, &data, mask)?;
             }
 
             if W == 4 {
-                Ok(u32::from_le(w))
+                Ok(u32::from_memory(w))
             } else if W == 2 {
                 let a = w.as_u16_array();
-                Ok(u16::from_le(a[(addr as usize >> 1) & 1]) as u32)
+                Ok(u16::from_memory(a[(addr as usize >> 1) & 1]) as u32)
             } else {
                 let a = w.as_u8_array();
                 Ok((a[addr as usize & 3]) as u32)
             }
+        } else if let Some(idempotent) = self.read_streamable(addr) {
+            self.combine_read::<W>(addr, idempotent)
         } else {
-            // check if address supports streamed operations
-            todo!()
+            // buffered stores to a device must reach it before it is read
+            self.flush_write_combining()?;
+            Ok(match W {
+                4 => self.bus.load_word(addr)?,
+                2 => self.bus.load_half_word(addr)? as u32,
+                _ => self.bus.load_byte(addr)? as u32,
+            })
         }
     }
 
@@ -212,11 +884,10 @@ impl<'a> Mmu<'a> {
     fn load<const W: u8>(&mut self, addr: u32) -> MmuResult<u32> {
         assert!(matches!(W, 1 | 2 | 4), "Load width must be 1, 2, or 4!");
 
-        // TODO Address translation
-        // TODO Check user mode
-        // TODO Check read permissions
-
-        self.load_physical::<W>(addr)
+        let paddr = self.translate_checked(addr, W as u32, Access::Read)?;
+        let val = self.load_physical::<W>(paddr)?;
+        self.watch(addr, W as u32, Access::Read);
+        Ok(val)
     }
 
     #[inline(always)]
@@ -236,36 +907,155 @@ impl<'a> Mmu<'a> {
 
     #[inline(always)]
     pub fn load_instruction(&mut self, addr: u32) -> MmuResult<Instruction> {
-        // TODO Address translation
-        // TODO Check user mode
-        // TODO Check read permissions
+        self.fetch(addr).map(|(op, ..)| op)
+    }
 
-        if addr & 3 != 0 {
-            return Err(MmuError::LoadMisaligned { addr, alignment: 4 });
+    /// Decodes the instruction at `addr` without reporting watchpoints, or
+    /// returns `None` if it can not be fetched.
+    ///
+    /// Memory that is not idempotent is not read, as the read could have side
+    /// effects.
+    pub fn peek_instruction(&mut self, addr: u32) -> Option<Instruction> {
+        let paddr = self.translate(addr, Access::Execute).ok()?;
+        let idempotent = self
+            .attributes(paddr)
+            .is_some_and(|pma| pma.idempotency() == Idempotency::Idempotent);
+        if !idempotent {
+            return None;
+        }
+
+        let inst = self.load_instruction(addr).ok();
+        self.take_watchpoint_hit();
+        inst
+    }
+
+    /// Loads the instruction at `addr` along with the handler executing it and
+    /// its length, 2 bytes if it is compressed and 4 otherwise.
+    #[inline(always)]
+    pub(crate) fn fetch(&mut self, addr: u32) -> MmuResult<(Instruction, Handler, u8)> {
+        if addr & 1 != 0 {
+            return Err(MmuError::LoadMisaligned { addr, alignment: 2 });
         }
 
-        if let Some(&op) = self.i_cache.get(addr >> 2) {
-            return Ok(op);
+        self.watch(addr, 1, Access::Execute);
+
+        // the instruction cache is physically tagged, and PMP is checked
+        // before it as entries may change while instructions are cached
+        let paddr = self.translate_checked(addr, 2, Access::Execute)?;
+
+        let fetched = match self.i_cache.get(paddr >> 1) {
+            Some(&fetched @ Fetched::Decoded(..)) => fetched,
+            _ => self.fill_instruction(paddr)?,
+        };
+
+        let (op, handler, len) = match fetched {
+            Fetched::Decoded(op, handler, len) => (op, handler, len),
+            Fetched::Partial(lo) => return self.complete_instruction(addr, lo),
+            Fetched::Raw(_) => unreachable!("Filled entries are decoded"),
+        };
+
+        // PMP regions are 4-byte aligned, so only the first parcel of an
+        // instruction at an odd halfword can be in a different region
+        if len == 4 && paddr & 2 != 0 {
+            self.pmp_check(paddr + 2, 2, Access::Execute, self.privilege)?;
         }
 
-        let missing = |x: &mut [Instruction; 16]| -> memory::mapping::MemoryResult<()> {
-            let mut raw = [0u32; 16];
+        Ok((op, handler, len))
+    }
+
+    /// Looks up the instruction at the physical address `paddr` in the
+    /// instruction cache, filling its line on a miss, and decodes it.
+    ///
+    /// The entry is `Partial` if the instruction continues into a line that
+    /// could not be read along with this one, and `Decoded` otherwise.
+    ///
+    /// Instructions in mappings that may not be cached are read from the bus
+    /// on every fetch instead.
+    #[inline(always)]
+    fn fill_instruction(&mut self, paddr: u32) -> MmuResult<Fetched> {
+        if !self.cacheable(paddr) {
+            return self.fetch_uncached(paddr);
+        }
+
+        let line = paddr & 0xffffffc0;
+        let missing = |x: &mut [Fetched; 32]| -> MemoryResult<()> {
+            // one parcel more than the line holds, for an instruction in the
+            // last slot that continues into the next line
+            let mut raw = [0u16; 33];
             let (_, dst, _) = unsafe { raw.align_to_mut::<u8>() };
-            match self.bus.block_read(addr & 0xffffffc0, dst) {
-                Ok(_) => {}
-                Err(e) => return Err(e),
-            };
+            read_all(self.bus, line, &mut dst[..64])?;
 
-            x.iter_mut()
-                .zip(raw.into_iter())
-                .for_each(|(d, s)| *d = u32::from_le(s).into());
+            // the next line can only be read ahead if it is in the same frame;
+            // otherwise it may not even be mapped
+            let tail =
+                (line + 64) & 0xfff != 0 && read_all(self.bus, line + 64, &mut dst[64..]).is_ok();
+
+            let raw = raw.map(u16::from_le);
+            x.iter_mut().enumerate().for_each(|(i, d)| {
+                *d = match (i, tail) {
+                    (31, false) if raw[i] & 3 == 3 => Fetched::Partial(raw[i]),
+                    _ => Fetched::Raw(raw[i] as u32 | (raw[i + 1] as u32) << 16),
+                }
+            });
 
             Ok(())
         };
 
-        let (&op, _) = self.i_cache.get_or_insert_with(addr >> 2, missing)?;
+        let ((entry, _), _) = self.i_cache.get_mut_or_insert_with(paddr >> 1, missing)?;
+        match *entry {
+            Fetched::Raw(raw) => {
+                let op = Instruction::from(raw);
+                let len = if raw & 3 == 3 { 4 } else { 2 };
+                *entry = Fetched::Decoded(op, handler(op.kind()), len);
+                Ok(*entry)
+            }
+            fetched => Ok(fetched),
+        }
+    }
+
+    /// Reads the instruction at the physical address `paddr` straight from the
+    /// bus and decodes it, without caching it.
+    ///
+    /// The entry is `Partial` if the instruction continues into the next page,
+    /// which may be mapped elsewhere.
+    #[cold]
+    fn fetch_uncached(&mut self, paddr: u32) -> MmuResult<Fetched> {
+        // buffered stores to a device must reach it before it is read
+        self.flush_write_combining()?;
+
+        let mut lo = [0u8; 2];
+        read_all(self.bus, paddr, &mut lo)?;
+        let lo = u16::from_le_bytes(lo);
+        if lo & 3 != 3 {
+            let op = decode_parcels(lo, 0);
+            return Ok(Fetched::Decoded(op, handler(op.kind()), 2));
+        } else if paddr & 0xfff == 0xffe {
+            return Ok(Fetched::Partial(lo));
+        }
 
-        Ok(op)
+        let mut hi = [0u8; 2];
+        read_all(self.bus, paddr + 2, &mut hi)?;
+        let op = decode_parcels(lo, u16::from_le_bytes(hi));
+        Ok(Fetched::Decoded(op, handler(op.kind()), 4))
+    }
+
+    /// Fetches the second parcel of a 32-bit instruction at `addr` whose first
+    /// parcel `lo` is the last one in its cache line.
+    ///
+    /// These instructions are not cached in decoded form because the next line
+    /// could not be read when the first one was filled.
+    /// The second parcel may be on another page, so `addr` is virtual.
+    #[cold]
+    fn complete_instruction(
+        &mut self,
+        addr: u32,
+        lo: u16,
+    ) -> MmuResult<(Instruction, Handler, u8)> {
+        let mut hi = [0u8; 2];
+        let addr = self.translate_checked(addr.wrapping_add(2), 2, Access::Execute)?;
+        read_all(self.bus, addr, &mut hi)?;
+        let op = decode_parcels(lo, u16::from_le_bytes(hi));
+        Ok((op, handler(op.kind()), 4))
     }
 
     #[inline(always)]
@@ -273,25 +1063,23 @@ impl<'a> Mmu<'a> {
         assert!(matches!(W, 1 | 2 | 4), "Load width must be 1, 2, or 4");
 
         if W == 4 && addr & 3 != 0 {
-            return Err(MmuError::LoadMisaligned { addr, alignment: 4 });
+            return Err(MmuError::StoreMisaligned { addr, alignment: 4 });
         } else if W == 2 && addr & 1 != 0 {
-            return Err(MmuError::LoadMisaligned { addr, alignment: 2 });
+            return Err(MmuError::StoreMisaligned { addr, alignment: 2 });
         }
 
         // fast path, if it is in cache, it's cacheable
         if let Some((target, tracker)) = self.d_cache.get_mut(addr >> 2) {
             if W == 4 {
-                *target = val.to_le();
-                *tracker |= 15 << (addr & 0x3f);
+                *target = val.to_memory();
             } else if W == 2 {
                 let a = target.as_u16_array_mut();
-                a[(addr as usize >> 1) & 1] = (val as u16).to_le();
-                *tracker |= 3 << (addr & 0x3f);
+                a[(addr as usize >> 1) & 1] = (val as u16).to_memory();
             } else {
                 let a = target.as_u8_array_mut();
                 a[addr as usize & 3] = val as u8;
-                *tracker |= 1 << (addr & 0x3f);
             }
+            *tracker |= dirty_bits::<W>(addr);
             return Ok(());
         }
 
@@ -300,34 +1088,39 @@ impl<'a> Mmu<'a> {
             // closure to be executed when cache line is missing
             let missing = |x: &mut [u32; 16]| {
                 let (_, dst, _) = unsafe { x.align_to_mut::<u8>() };
-                self.bus.block_read(addr & 0xffffffc0, dst)
+                read_all(self.bus, addr & 0xffffffc0, dst)
             };
 
             let ((target, tracker), evicted) =
                 self.d_cache.get_mut_or_insert_with(addr >> 2, missing)?;
 
             if let Some((addr, data, mask)) = evicted {
-                let mask = mask.to_le(); // ensures mask.as_u8_array()[0] & 1 is the first bit
-                let mask = mask.as_u8_array();
-                let (_, src, _) = unsafe { data.align_to::<u8>() };
-                self.bus.block_write_masked(addr << 2, src, &mask[..])?;
+                Self::write_back(self.bus, addr, &data, mask)?;
             }
 
             if W == 4 {
-                *target = val.to_le();
-                *tracker |= 15 << (addr & 0x3f);
+                *target = val.to_memory();
             } else if W == 2 {
                 let a = target.as_u16_array_mut();
-                a[(addr as usize >> 1) & 1] = (val as u16).to_le();
-                *tracker |= 3 << (addr & 0x3f);
+                a[(addr as usize >> 1) & 1] = (val as u16).to_memory();
             } else {
                 let a = target.as_u8_array_mut();
                 a[addr as usize & 3] = val as u8;
-                *tracker |= 1 << (addr & 0x3f);
             }
+            *tracker |= dirty_bits::<W>(addr);
             Ok(())
+        } else if self.write_streamable(addr) {
+            self.invalidate_read_combining();
+            self.combine_write(addr, W, val)
         } else {
-            todo!()
+            self.invalidate_read_combining();
+            self.flush_write_combining()?;
+            match W {
+                4 => self.bus.store_word(addr, val)?,
+                2 => self.bus.store_half_word(addr, val as u16)?,
+                _ => self.bus.store_byte(addr, val as u8)?,
+            }
+            Ok(())
         }
     }
 
@@ -335,11 +1128,13 @@ impl<'a> Mmu<'a> {
     fn store<const W: u8>(&mut self, addr: u32, val: u32) -> MmuResult<()> {
         assert!(matches!(W, 1 | 2 | 4), "Load width must be 1, 2, or 4");
 
-        if false {
-            todo!("Address translation");
+        let paddr = self.translate_checked(addr, W as u32, Access::Write)?;
+        self.store_physical::<W>(paddr, val)?;
+        if self.coherent_instructions {
+            self.snoop(paddr)?;
         }
-
-        self.store_physical::<W>(addr, val)
+        self.watch(addr, W as u32, Access::Write);
+        Ok(())
     }
 
     #[inline(always)]
@@ -357,72 +1152,1095 @@ impl<'a> Mmu<'a> {
         self.store::<4>(addr, w)
     }
 
+    /// Prepares for an atomic operation with `ordering`.
+    ///
+    /// If it releases, dirty lines are written back so that every earlier
+    /// store is visible on the bus before the operation is.
+    fn release(&mut self, ordering: Ordering) -> MmuResult<()> {
+        if matches!(ordering, Ordering::Release | Ordering::AcqRel) {
+            self.write_back_all()?;
+            fence(Ordering::Release);
+        }
+        Ok(())
+    }
+
+    /// Completes an atomic operation with `ordering`.
+    ///
+    /// If it acquires, the data cache is emptied so that later loads fetch
+    /// from the bus and observe what was released by other harts.
+    fn acquire(&mut self, ordering: Ordering) -> MmuResult<()> {
+        if matches!(ordering, Ordering::Acquire | Ordering::AcqRel) {
+            fence(Ordering::Acquire);
+            self.write_back_all()?;
+            self.d_cache.invalidate_all();
+        }
+        Ok(())
+    }
+
+    /// Loads the word at `addr` and registers a reservation on its
+    /// reservation set.
+    ///
+    /// Fails with `ReservationUnsupported` if the mapping does not support
+    /// reservations.
     #[inline(always)]
-    pub fn load_reserved(&mut self, _addr: u32) -> MmuResult<u32> {
-        // TODO address translation
-        // TODO check physical address attributes about reservability
+    pub fn load_reserved(&mut self, vaddr: u32, ordering: Ordering) -> MmuResult<u32> {
+        if vaddr & 3 != 0 {
+            return Err(MmuError::LoadMisaligned {
+                addr: vaddr,
+                alignment: 4,
+            });
+        }
+        let addr = self.translate_checked(vaddr, 4, Access::Read)?;
+        if !self.reservable(addr) {
+            return Err(MmuError::ReservationUnsupported { addr });
+        }
 
-        let reservation_set = addr_to_reservation_set(_addr);
+        let reservation_set = addr_to_reservation_set(addr);
 
+        // written back before the reservation is registered, as the write
+        // would invalidate it
+        self.evict_line(addr)?;
+
+        self.release(ordering)?;
         // register reservation
         self.reservation.store(reservation_set, Ordering::Relaxed);
-        Ok(self.bus.load_word(_addr)?) // load directly from bus
+        let val = self.bus.load_word(addr)?; // load directly from bus
+        self.acquire(ordering)?;
+        self.watch(vaddr, 4, Access::Read);
+        Ok(val)
     }
 
+    /// Stores `val` to `addr` if the reservation registered by `load_reserved`
+    /// is still held, returning 0 on success and 1 on failure.
+    ///
+    /// Always fails on mappings that do not support reservations.
     #[inline(always)]
-    pub fn store_conditional(&mut self, _addr: u32, _val: u32) -> MmuResult<u32> {
-        let reservation_set = addr_to_reservation_set(_addr);
-        if self.reservation.load(Ordering::Relaxed) != addr_to_reservation_set(_addr) {
+    pub fn store_conditional(
+        &mut self,
+        vaddr: u32,
+        val: u32,
+        ordering: Ordering,
+    ) -> MmuResult<u32> {
+        if vaddr & 3 != 0 {
+            return Err(MmuError::StoreMisaligned {
+                addr: vaddr,
+                alignment: 4,
+            });
+        }
+        let addr = self.translate_checked(vaddr, 4, Access::Write)?;
+        let reservation_set = addr_to_reservation_set(addr);
+
+        // stores to the line since the load are written back first, and the
+        // line is fetched again after the store
+        self.evict_line(addr)?;
+
+        if self.reservation.load(Ordering::Relaxed) != reservation_set || !self.reservable(addr) {
             Ok(1) // indicates failure
         } else {
-            Ok(self
-                .bus
-                .store_conditional(_addr, _val, self.reservation, reservation_set)?)
+            self.release(ordering)?;
+            let result =
+                self.bus
+                    .store_conditional(addr, val, self.reservation, reservation_set)?;
+            self.acquire(ordering)?;
+            if result == 0 {
+                if self.coherent_instructions {
+                    self.snoop(addr)?;
+                }
+                self.watch(vaddr, 4, Access::Write);
+            }
+            Ok(result)
+        }
+    }
+
+    /// Performs an atomic memory operation directly on the bus.
+    ///
+    /// The data cache is not coherent with the bus, so the line holding the
+    /// word is written back if it is dirty and invalidated before the
+    /// operation.
+    /// The next access to the line fetches it again, including the result.
+    ///
+    /// Accesses are ordered around the operation as described by `ordering`,
+    /// which comes from its `aq` and `rl` bits.
+    #[inline(always)]
+    fn atomic<F>(&mut self, addr: u32, ordering: Ordering, op: F) -> MmuResult<u32>
+    where
+        F: FnOnce(&Bus, u32) -> Result<u32, MemoryError>,
+    {
+        if addr & 3 != 0 {
+            return Err(MmuError::StoreMisaligned { addr, alignment: 4 });
+        }
+        let paddr = self.translate_checked(addr, 4, Access::Write)?;
+        self.pmp_check(paddr, 4, Access::Read, self.access_privilege(Access::Read))?;
+
+        self.evict_line(paddr)?;
+        self.invalidate_read_combining();
+        self.flush_write_combining()?;
+
+        self.release(ordering)?;
+        let val = op(self.bus, paddr)?;
+        self.acquire(ordering)?;
+        if self.coherent_instructions {
+            self.snoop(paddr)?;
         }
+        self.watch(addr, 4, Access::Read);
+        self.watch(addr, 4, Access::Write);
+        Ok(val)
     }
 
     #[inline(always)]
-    pub fn swap_word_atomic(&mut self, _addr: u32) -> MmuResult<u32> {
-        todo!()
+    pub fn swap_word_atomic(&mut self, addr: u32, val: u32, ordering: Ordering) -> MmuResult<u32> {
+        self.atomic(addr, ordering, |bus, addr| bus.amoswap_w(addr, val))
     }
 
     #[inline(always)]
-    pub fn add_word_atomic(&mut self, _addr: u32) -> MmuResult<u32> {
-        todo!()
+    pub fn add_word_atomic(&mut self, addr: u32, val: u32, ordering: Ordering) -> MmuResult<u32> {
+        self.atomic(addr, ordering, |bus, addr| bus.amoadd_w(addr, val))
     }
 
     #[inline(always)]
-    pub fn and_word_atomic(&mut self, _addr: u32) -> MmuResult<u32> {
-        todo!()
+    pub fn and_word_atomic(&mut self, addr: u32, val: u32, ordering: Ordering) -> MmuResult<u32> {
+        self.atomic(addr, ordering, |bus, addr| bus.amoand_w(addr, val))
     }
 
     #[inline(always)]
-    pub fn or_word_atomic(&mut self, _addr: u32) -> MmuResult<u32> {
-        todo!()
+    pub fn or_word_atomic(&mut self, addr: u32, val: u32, ordering: Ordering) -> MmuResult<u32> {
+        self.atomic(addr, ordering, |bus, addr| bus.amoor_w(addr, val))
     }
 
     #[inline(always)]
-    pub fn xor_word_atomic(&mut self, _addr: u32) -> MmuResult<u32> {
-        todo!()
+    pub fn xor_word_atomic(&mut self, addr: u32, val: u32, ordering: Ordering) -> MmuResult<u32> {
+        self.atomic(addr, ordering, |bus, addr| bus.amoxor_w(addr, val))
     }
 
     #[inline(always)]
-    pub fn max_word_atomic(&mut self, _addr: u32) -> MmuResult<u32> {
-        todo!()
+    pub fn max_word_atomic(&mut self, addr: u32, val: u32, ordering: Ordering) -> MmuResult<u32> {
+        self.atomic(addr, ordering, |bus, addr| bus.amomax_w(addr, val))
     }
 
     #[inline(always)]
-    pub fn min_word_atomic(&mut self, _addr: u32) -> MmuResult<u32> {
-        todo!()
+    pub fn min_word_atomic(&mut self, addr: u32, val: u32, ordering: Ordering) -> MmuResult<u32> {
+        self.atomic(addr, ordering, |bus, addr| bus.amomin_w(addr, val))
     }
 
     #[inline(always)]
-    pub fn maxu_word_atomic(&mut self, _addr: u32) -> MmuResult<u32> {
-        todo!()
+    pub fn maxu_word_atomic(&mut self, addr: u32, val: u32, ordering: Ordering) -> MmuResult<u32> {
+        self.atomic(addr, ordering, |bus, addr| bus.amomaxu_w(addr, val))
     }
 
     #[inline(always)]
-    pub fn minu_word_atomic(&mut self, _addr: u32) -> MmuResult<u32> {
-        todo!()
+    pub fn minu_word_atomic(&mut self, addr: u32, val: u32, ordering: Ordering) -> MmuResult<u32> {
+        self.atomic(addr, ordering, |bus, addr| bus.amominu_w(addr, val))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use crate::{
+        bus::Bus,
+        hart::{csr::MStatus, instruction::Instruction},
+        memory::{
+            endian::{MemoryOrder, BIG_ENDIAN},
+            mapping::{Cacheability, Idempotency, Mapping, Pma, Reservability},
+            test_device::{self, read_word, TestDevice},
+        },
+    };
+
+    use super::{Access, Mmu, MmuError, MmuResult, PrivilegeMode, WC_CAPACITY};
+
+    // lines that map to the same d-cache set are 256 lines of 64 bytes apart
+    const SET_STRIDE: u32 = 0x4000;
+
+    /// Switches `mmu` to `mode`, with a PMP entry granting it all of memory.
+    fn drop_to(mmu: &mut Mmu, mode: PrivilegeMode) {
+        mmu.set_pmp_addr(0, u32::MAX);
+        mmu.set_pmp_cfg(0, 0x1f);
+        mmu.set_privilege(mode);
+    }
+
+    #[test]
+    fn store_miss_is_written_back_on_eviction() -> MmuResult<()> {
+        let bus = Bus::builder().with_main_memory(12).build();
+        let reservation = AtomicU32::new(u32::MAX);
+        let mut mmu = Mmu::new(&bus, &reservation);
+
+        mmu.store_word(0x44, 0xdeadbeef)?;
+        assert_eq!(read_word(&bus, 0x44), 0, "Store should still be cached");
+
+        // two more lines in the same set evict the first one
+        mmu.load_word(0x44 + SET_STRIDE)?;
+        mmu.load_word(0x44 + 2 * SET_STRIDE)?;
+
+        assert_eq!(
+            read_word(&bus, 0x44),
+            0xdeadbeef,
+            "Store was not written back"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn byte_order() -> MmuResult<()> {
+        let bus = Bus::builder().with_main_memory(1).build();
+        let reservation = AtomicU32::new(u32::MAX);
+        let mut mmu = Mmu::new(&bus, &reservation);
+
+        let bytes = if BIG_ENDIAN {
+            [0x11, 0x22, 0x33, 0x44]
+        } else {
+            [0x44, 0x33, 0x22, 0x11]
+        };
+
+        // converted when the line is filled ...
+        bus.block_write(0x40, &bytes)?;
+        assert_eq!(mmu.load_word(0x40)?, 0x11223344);
+        assert_eq!(
+            mmu.load_half_word(0x42)?,
+            if BIG_ENDIAN { 0x3344 } else { 0x1122 }
+        );
+
+        // ... and when it is written back
+        mmu.store_word(0x80, 0x11223344)?;
+        mmu.write_back_all()?;
+        let mut written = [0; 4];
+        bus.block_read(0x80, &mut written)?;
+        assert_eq!(written, bytes);
+
+        Ok(())
+    }
+
+    #[test]
+    fn store_hit_on_clean_line_is_written_back_on_eviction() -> MmuResult<()> {
+        let bus = Bus::builder().with_main_memory(12).build();
+        let reservation = AtomicU32::new(u32::MAX);
+        let mut mmu = Mmu::new(&bus, &reservation);
+
+        // bring the line in clean, then dirty it through the fast path
+        mmu.load_word(0x80)?;
+        mmu.store_half_word(0x86, 0xbeef)?;
+
+        mmu.load_word(0x80 + SET_STRIDE)?;
+        mmu.load_word(0x80 + 2 * SET_STRIDE)?;
+
+        assert_eq!(
+            read_word(&bus, 0x84),
+            if BIG_ENDIAN { 0x0000beef } else { 0xbeef0000 },
+            "Store was not written back"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn write_back_only_touches_stored_bytes() -> MmuResult<()> {
+        let bus = Bus::builder().with_main_memory(12).build();
+        let reservation = AtomicU32::new(u32::MAX);
+        let mut mmu = Mmu::new(&bus, &reservation);
+
+        mmu.store_byte(0x105, 0xab)?;
+        mmu.store_byte(0x13f, 0xcd)?;
+
+        // change the rest of the line behind the cache's back
+        bus.block_write(0x100, &[0x11; 64]).unwrap();
+
+        mmu.load_word(0x100 + SET_STRIDE)?;
+        mmu.load_word(0x100 + 2 * SET_STRIDE)?;
+
+        let mut line = [0u8; 64];
+        bus.block_read(0x100, &mut line).unwrap();
+        let mut expected = [0x11; 64];
+        expected[0x05] = 0xab;
+        expected[0x3f] = 0xcd;
+        assert_eq!(line, expected, "Clean bytes were written back");
+        Ok(())
+    }
+
+    #[test]
+    fn partial_line_written_back_to_mapping() -> MmuResult<()> {
+        let device = TestDevice::new(0x80000, 1);
+        let bus = Bus::builder()
+            .with_main_memory(1)
+            .with_mapping(&device)
+            .build();
+        let reservation = AtomicU32::new(u32::MAX);
+        let mut mmu = Mmu::new(&bus, &reservation);
+        device.mem().block_write(0x40, &[0x11; 64])?;
+
+        mmu.store_byte(0x80000045, 0xab)?;
+        // changed behind the cache, but clean in it
+        device.mem().store_byte(0x46, 0x22)?;
+
+        mmu.write_back_all()?;
+        let mut line = [0; 64];
+        device.mem().block_read(0x40, &mut line)?;
+        let mut expected = [0x11; 64];
+        expected[0x05] = 0xab;
+        expected[0x06] = 0x22;
+        assert_eq!(line, expected, "Clean bytes were written back");
+        Ok(())
+    }
+
+    #[test]
+    fn reservations_on_unreservable_mappings() -> MmuResult<()> {
+        let device = TestDevice::with_attributes(
+            0x80000,
+            1,
+            Pma::main().with_reservability(Reservability::None),
+        );
+        let bus = Bus::builder()
+            .with_main_memory(1)
+            .with_mapping(&device)
+            .build();
+        let reservation = AtomicU32::new(u32::MAX);
+        let mut mmu = Mmu::new(&bus, &reservation);
+
+        assert!(matches!(
+            mmu.load_reserved(0x80000040, Ordering::Relaxed),
+            Err(MmuError::ReservationUnsupported { addr: 0x80000040 })
+        ));
+        assert_eq!(reservation.load(Ordering::Relaxed), u32::MAX);
+
+        // even with a matching reservation, the store is never performed
+        reservation.store(0x80000040 >> 6, Ordering::Relaxed);
+        assert_eq!(mmu.store_conditional(0x80000040, 1, Ordering::Relaxed)?, 1);
+        assert!(device.take_log().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn load_reserved_sees_cached_stores() -> MmuResult<()> {
+        let bus = Bus::builder().with_main_memory(1).build();
+        let reservation = AtomicU32::new(u32::MAX);
+        let mut mmu = Mmu::new(&bus, &reservation);
+
+        mmu.store_word(0x100, 42)?;
+        assert_eq!(
+            mmu.load_reserved(0x100, Ordering::Relaxed)?,
+            42,
+            "lr saw stale memory"
+        );
+        assert_eq!(reservation.load(Ordering::Relaxed), 0x100 >> 6);
+        Ok(())
+    }
+
+    #[test]
+    fn loads_see_conditional_stores() -> MmuResult<()> {
+        let bus = Bus::builder().with_main_memory(1).build();
+        let reservation = AtomicU32::new(u32::MAX);
+        let mut mmu = Mmu::new(&bus, &reservation);
+
+        // the line is cached before the reservation is taken
+        assert_eq!(mmu.load_word(0x100)?, 0);
+        mmu.load_reserved(0x100, Ordering::Relaxed)?;
+        assert_eq!(mmu.load_word(0x104)?, 0);
+
+        assert_eq!(mmu.store_conditional(0x100, 7, Ordering::Relaxed)?, 0);
+        assert_eq!(mmu.load_word(0x100)?, 7, "lw after sc saw stale cache");
+        Ok(())
+    }
+
+    #[test]
+    fn misaligned_stores() {
+        let bus = Bus::builder().with_main_memory(1).build();
+        let reservation = AtomicU32::new(u32::MAX);
+        let mut mmu = Mmu::new(&bus, &reservation);
+
+        assert!(matches!(
+            mmu.store_word(0x42, 0),
+            Err(MmuError::StoreMisaligned {
+                addr: 0x42,
+                alignment: 4
+            })
+        ));
+        assert!(matches!(
+            mmu.store_half_word(0x43, 0),
+            Err(MmuError::StoreMisaligned {
+                addr: 0x43,
+                alignment: 2
+            })
+        ));
+    }
+
+    #[test]
+    fn fetch_across_lines() -> MmuResult<()> {
+        let bus = Bus::builder().with_main_memory(2).build();
+        let reservation = AtomicU32::new(u32::MAX);
+        let mut mmu = Mmu::new(&bus, &reservation);
+
+        let addi = 0x02a00593u32.to_le_bytes(); // addi a1, zero, 42
+
+        // second parcel in the next line of the same frame
+        bus.block_write(0x3e, &addi)?;
+        // second parcel in the next frame
+        bus.block_write(0xffe, &addi)?;
+
+        for addr in [0x3e, 0xffe] {
+            // first from a cold cache, then from a warm one
+            for _ in 0..2 {
+                assert!(
+                    matches!(mmu.load_instruction(addr)?, Instruction::Addi { .. }),
+                    "Failed to fetch instruction at {addr:#x}"
+                );
+            }
+        }
+
+        // the second parcel is out of bounds
+        bus.block_write(0x1ffe, &addi[..2])?;
+        assert!(mmu.load_instruction(0x1ffe).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn clean_lines_are_not_written_back() -> MmuResult<()> {
+        let bus = Bus::builder().with_main_memory(12).build();
+        let reservation = AtomicU32::new(u32::MAX);
+        let mut mmu = Mmu::new(&bus, &reservation);
+
+        mmu.load_word(0x100)?;
+        // memory changes behind the cache's back; evicting the clean line must
+        // not overwrite it with the stale copy
+        bus.block_write(0x100, &0x12345678u32.to_memory().to_ne_bytes())?;
+        mmu.load_word(0x100 + SET_STRIDE)?;
+        mmu.load_word(0x100 + 2 * SET_STRIDE)?;
+
+        assert_eq!(read_word(&bus, 0x100), 0x12345678);
+        Ok(())
+    }
+
+    #[test]
+    fn sv32_translation() -> MmuResult<()> {
+        let bus = Bus::builder().with_main_memory(8).build();
+        let reservation = AtomicU32::new(u32::MAX);
+        let mut mmu = Mmu::new(&bus, &reservation);
+
+        // root table at 0x1000, leaf table at 0x2000
+        // 0x40003000 -> 0x5000 is readable, 0x40004000 -> 0x6000 is writable
+        // 0x00400000 -> 0x00000000 is a misaligned superpage
+        bus.store_word(0x1000 + 0x100 * 4, (2 << 10) | 0x01)?;
+        bus.store_word(0x1000 + 4, (1 << 10) | 0x43)?;
+        bus.store_word(0x2000 + 3 * 4, (5 << 10) | 0x43)?;
+        bus.store_word(0x2000 + 4 * 4, (6 << 10) | 0xc7)?;
+        bus.store_word(0x5008, 0xdeadbeef)?;
+
+        mmu.set_satp(0x80000001);
+        drop_to(&mut mmu, PrivilegeMode::Supervisor);
+        assert_eq!(mmu.load_word(0x40003008)?, 0xdeadbeef);
+
+        mmu.store_word(0x40004010, 42)?;
+        mmu.write_back_all()?;
+        assert_eq!(read_word(&bus, 0x6010), 42);
+
+        assert!(matches!(
+            mmu.store_word(0x40003008, 0),
+            Err(MmuError::PageFault {
+                addr: 0x40003008,
+                access: Access::Write
+            })
+        ));
+        assert!(matches!(
+            mmu.load_instruction(0x40003000),
+            Err(MmuError::PageFault { .. })
+        ));
+        assert!(matches!(
+            mmu.load_word(0x40005000),
+            Err(MmuError::PageFault { .. })
+        ));
+        assert!(matches!(
+            mmu.load_word(0x00400000),
+            Err(MmuError::PageFault { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn machine_mode_bypasses_translation() -> MmuResult<()> {
+        let bus = Bus::builder().with_main_memory(8).build();
+        let reservation = AtomicU32::new(u32::MAX);
+        let mut mmu = Mmu::new(&bus, &reservation);
+
+        // 0x5000 -> 0x6000
+        bus.store_word(0x1000, (2 << 10) | 0x01)?;
+        bus.store_word(0x2000 + 5 * 4, (6 << 10) | 0xcf)?;
+        bus.store_word(0x5000, 1)?;
+        bus.store_word(0x6000, 2)?;
+
+        mmu.set_satp(0x80000001);
+        mmu.set_pmp_addr(0, u32::MAX);
+        mmu.set_pmp_cfg(0, 0x1f);
+        assert_eq!(mmu.load_word(0x5000)?, 1);
+
+        // loads and stores use the mode in MPP while MPRV is set
+        let mut mstatus = MStatus::from(0);
+        mstatus.set_mprv(true);
+        mstatus.set_mpp(PrivilegeMode::Supervisor);
+        mmu.set_mstatus(mstatus);
+        assert_eq!(mmu.load_word(0x5000)?, 2);
+
+        mstatus.set_mpp(PrivilegeMode::Machine);
+        mmu.set_mstatus(mstatus);
+        assert_eq!(mmu.load_word(0x5000)?, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn user_pages() -> MmuResult<()> {
+        let bus = Bus::builder().with_main_memory(8).build();
+        let reservation = AtomicU32::new(u32::MAX);
+        let mut mmu = Mmu::new(&bus, &reservation);
+
+        // 0x40003000 -> 0x5000 is a user page, 0x40004000 -> 0x6000 is a
+        // supervisor page, and 0x40005000 -> 0x7000 is execute-only
+        bus.store_word(0x1000 + 0x100 * 4, (2 << 10) | 0x01)?;
+        bus.store_word(0x2000 + 3 * 4, (5 << 10) | 0xdf)?;
+        bus.store_word(0x2000 + 4 * 4, (6 << 10) | 0xcf)?;
+        bus.store_word(0x2000 + 5 * 4, (7 << 10) | 0xc9)?;
+        mmu.set_satp(0x80000001);
+
+        let fault_at = |result: MmuResult<u32>, access: Access| matches!(result, Err(MmuError::PageFault { access: a, .. }) if a == access);
+        let fetch = |mmu: &mut Mmu, addr| mmu.fetch(addr).map(|_| 0);
+
+        // user mode cannot touch supervisor pages
+        drop_to(&mut mmu, PrivilegeMode::User);
+        mmu.load_word(0x40003000)?;
+        fetch(&mut mmu, 0x40003000)?;
+        assert!(fault_at(mmu.load_word(0x40004000), Access::Read));
+        assert!(fault_at(fetch(&mut mmu, 0x40004000), Access::Execute));
+
+        // supervisor mode cannot touch user pages without SUM
+        drop_to(&mut mmu, PrivilegeMode::Supervisor);
+        mmu.load_word(0x40004000)?;
+        assert!(fault_at(mmu.load_word(0x40003000), Access::Read));
+        assert!(fault_at(
+            mmu.store_word(0x40003000, 1).map(|_| 0),
+            Access::Write
+        ));
+
+        // and can never execute them
+        let mut mstatus = MStatus::from(0);
+        mstatus.set_sum(true);
+        mmu.set_mstatus(mstatus);
+        mmu.load_word(0x40003000)?;
+        mmu.store_word(0x40003000, 1)?;
+        assert!(fault_at(fetch(&mut mmu, 0x40003000), Access::Execute));
+
+        // executable pages are only readable with MXR
+        assert!(fault_at(mmu.load_word(0x40005000), Access::Read));
+        mstatus.set_mxr(true);
+        mmu.set_mstatus(mstatus);
+        mmu.load_word(0x40005000)?;
+        Ok(())
+    }
+
+    #[test]
+    fn tlb_asids() -> MmuResult<()> {
+        let bus = Bus::builder().with_main_memory(8).build();
+        let reservation = AtomicU32::new(u32::MAX);
+        let mut mmu = Mmu::new(&bus, &reservation);
+
+        // 0x40003000 -> 0x5000 is private, 0x40004000 -> 0x5000 is global,
+        // both are later remapped to 0x6000
+        let (private, global) = (0x2000 + 3 * 4, 0x2000 + 4 * 4);
+        bus.store_word(0x1000 + 0x100 * 4, (2 << 10) | 0x01)?;
+        bus.store_word(private, (5 << 10) | 0xc3)?;
+        bus.store_word(global, (5 << 10) | 0xe3)?;
+        bus.store_word(0x5000, 1)?;
+        bus.store_word(0x6000, 2)?;
+
+        let satp = |asid: u32| 0x80000001 | asid << 22;
+        let remap = |mmu: &mut Mmu, ppn: u32| -> MmuResult<()> {
+            mmu.set_satp(0);
+            mmu.store_word(private, (ppn << 10) | 0xc3)?;
+            mmu.store_word(global, (ppn << 10) | 0xe3)
+        };
+
+        drop_to(&mut mmu, PrivilegeMode::Supervisor);
+        mmu.set_satp(satp(1));
+        assert_eq!(mmu.load_word(0x40003000)?, 1);
+        assert_eq!(mmu.load_word(0x40004000)?, 1);
+
+        // stale translations are used until they are invalidated
+        remap(&mut mmu, 6)?;
+        mmu.set_satp(satp(1));
+        assert_eq!(mmu.load_word(0x40003000)?, 1);
+        assert_eq!(mmu.load_word(0x40004000)?, 1);
+
+        // other address spaces do not see private translations
+        mmu.set_satp(satp(2));
+        assert_eq!(mmu.load_word(0x40003000)?, 2);
+        assert_eq!(mmu.load_word(0x40004000)?, 1);
+
+        // flushing an address space keeps global translations
+        remap(&mut mmu, 5)?;
+        mmu.set_satp(satp(2));
+        mmu.invalidate_translations(None, Some(1));
+        assert_eq!(mmu.load_word(0x40003000)?, 2, "Belongs to ASID 2");
+        mmu.invalidate_translations(Some(0x40003000), Some(2));
+        assert_eq!(mmu.load_word(0x40003000)?, 1);
+        assert_eq!(mmu.load_word(0x40004000)?, 1);
+
+        remap(&mut mmu, 6)?;
+        mmu.set_satp(satp(2));
+        mmu.invalidate_translations(None, Some(2));
+        assert_eq!(
+            mmu.load_word(0x40004000)?,
+            1,
+            "Global translations are kept"
+        );
+        mmu.invalidate_translations(None, None);
+        assert_eq!(mmu.load_word(0x40004000)?, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn superpages() -> MmuResult<()> {
+        let bus = Bus::builder().with_main_memory(8).build();
+        let reservation = AtomicU32::new(u32::MAX);
+        let mut mmu = Mmu::new(&bus, &reservation);
+
+        // 0x00c00000 -> 0x00000000 is a 4 MiB superpage
+        // 0x01000000 -> 0x00001000 is a misaligned one
+        bus.store_word(0x1000 + 3 * 4, 0xcf)?;
+        bus.store_word(0x1000 + 4 * 4, (1 << 10) | 0xcf)?;
+        bus.store_word(0x5008, 0xdeadbeef)?;
+        mmu.set_satp(0x80000001);
+        drop_to(&mut mmu, PrivilegeMode::Supervisor);
+
+        // the low 22 bits of the virtual address are the offset
+        assert_eq!(mmu.load_word(0x00c05008)?, 0xdeadbeef);
+        mmu.store_word(0x00c06010, 42)?;
+        mmu.write_back_all()?;
+        assert_eq!(read_word(&bus, 0x6010), 42);
+
+        assert!(matches!(
+            mmu.load_word(0x01005008),
+            Err(MmuError::PageFault {
+                addr: 0x01005008,
+                access: Access::Read
+            })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn accessed_and_dirty_bits() -> MmuResult<()> {
+        let bus = Bus::builder().with_main_memory(8).build();
+        let reservation = AtomicU32::new(u32::MAX);
+        let mut mmu = Mmu::new(&bus, &reservation);
+
+        // 0x40003000 -> 0x5000 is a clean page that has not been accessed
+        let pte = 0x2000 + 3 * 4;
+        bus.store_word(0x1000 + 0x100 * 4, (2 << 10) | 0x01)?;
+        bus.store_word(pte, (5 << 10) | 0x07)?;
+        mmu.set_satp(0x80000001);
+        drop_to(&mut mmu, PrivilegeMode::Supervisor);
+
+        mmu.load_word(0x40003000)?;
+        assert_eq!(read_word(&bus, pte), (5 << 10) | 0x47, "A should be set");
+
+        // the cached translation is not dirty, so the write walks again
+        mmu.store_word(0x40003000, 1)?;
+        assert_eq!(read_word(&bus, pte), (5 << 10) | 0xc7, "D should be set");
+
+        // pages that do not permit the access are left alone
+        bus.store_word(pte, (5 << 10) | 0x03)?;
+        mmu.invalidate_translations(None, None);
+        assert!(matches!(
+            mmu.store_word(0x40003000, 1),
+            Err(MmuError::PageFault { .. })
+        ));
+        assert_eq!(read_word(&bus, pte), (5 << 10) | 0x03);
+        Ok(())
+    }
+
+    #[test]
+    fn uncached_stores_reach_devices() -> MmuResult<()> {
+        let device = TestDevice::with_attributes(0x80000, 1, Pma::io());
+        let bus = Bus::builder()
+            .with_main_memory(1)
+            .with_mapping(&device)
+            .build();
+        let reservation = AtomicU32::new(u32::MAX);
+        let mut mmu = Mmu::new(&bus, &reservation);
+
+        mmu.store_word(0x80000010, 0x12345678)?;
+        mmu.store_byte(0x80000017, 0xab)?;
+
+        assert_eq!(
+            device.take_log(),
+            [
+                test_device::Access::Store {
+                    offset: 0x10,
+                    width: 4,
+                    value: 0x12345678
+                },
+                test_device::Access::Store {
+                    offset: 0x17,
+                    width: 1,
+                    value: 0xab
+                },
+            ]
+        );
+        assert!(mmu.d_cache.get(0x80000010 >> 2).is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn uncached_loads_reach_devices() -> MmuResult<()> {
+        let device = TestDevice::with_attributes(0x80000, 1, Pma::io());
+        let bus = Bus::builder()
+            .with_main_memory(1)
+            .with_mapping(&device)
+            .build();
+        let reservation = AtomicU32::new(u32::MAX);
+        let mut mmu = Mmu::new(&bus, &reservation);
+
+        device.mem().store_word(0x20, 0x87654321)?;
+        assert_eq!(mmu.load_word(0x80000020)?, 0x87654321);
+        assert_eq!(
+            mmu.load_half_word(0x80000022)?,
+            if BIG_ENDIAN { 0x4321 } else { 0x8765 }
+        );
+
+        // every load goes to the device, so changes are seen immediately
+        device.mem().store_word(0x20, 0x11111111)?;
+        assert_eq!(mmu.load_byte(0x80000020)?, 0x11);
+
+        assert_eq!(
+            device.take_log(),
+            [
+                test_device::Access::Load {
+                    offset: 0x20,
+                    width: 4
+                },
+                test_device::Access::Load {
+                    offset: 0x22,
+                    width: 2
+                },
+                test_device::Access::Load {
+                    offset: 0x20,
+                    width: 1
+                },
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn uncached_fetches_reach_devices() -> MmuResult<()> {
+        let device = TestDevice::with_attributes(0x80000, 1, Pma::io());
+        let bus = Bus::builder()
+            .with_main_memory(1)
+            .with_mapping(&device)
+            .build();
+        let reservation = AtomicU32::new(u32::MAX);
+        let mut mmu = Mmu::new(&bus, &reservation);
+
+        let (one, two) = (0x00100513u32, 0x00200513u32); // li a0, 1 and li a0, 2
+        device.mem().block_write(0x20, &one.to_le_bytes())?;
+        assert_eq!(mmu.load_instruction(0x80000020)?, Instruction::from(one));
+
+        // nothing is cached, so changes are seen immediately
+        device.mem().block_write(0x20, &two.to_le_bytes())?;
+        assert_eq!(mmu.load_instruction(0x80000020)?, Instruction::from(two));
+
+        let parcels = [
+            test_device::Access::BlockRead {
+                offset: 0x20,
+                len: 2,
+            },
+            test_device::Access::BlockRead {
+                offset: 0x22,
+                len: 2,
+            },
+        ];
+        assert_eq!(device.take_log(), [parcels, parcels].concat());
+        Ok(())
+    }
+
+    #[test]
+    fn cacheability_follows_attributes() -> MmuResult<()> {
+        let ram = TestDevice::new(0x80000, 1);
+        let io = TestDevice::with_attributes(0x80001, 1, Pma::io());
+        let bus = Bus::builder()
+            .with_main_memory(1)
+            .with_mapping(&ram)
+            .with_mapping(&io)
+            .build();
+        let reservation = AtomicU32::new(u32::MAX);
+        let mut mmu = Mmu::new(&bus, &reservation);
+
+        for _ in 0..2 {
+            mmu.load_word(0x80000040)?;
+            mmu.load_word(0x80001040)?;
+        }
+
+        assert_eq!(
+            ram.take_log(),
+            [test_device::Access::BlockRead {
+                offset: 0x40,
+                len: 64
+            }],
+            "Only the first load should miss"
+        );
+        assert_eq!(io.take_log().len(), 2, "Both loads should reach the device");
+        assert!(mmu.attr.get(0x80001).is_some());
+
+        Ok(())
+    }
+
+    #[test]
+    fn non_idempotent_mappings_are_not_cached() -> MmuResult<()> {
+        // cacheable by its attributes, but reads have side effects
+        let pma = Pma::main().with_idempotency(Idempotency::NonIdempotent);
+        let device = TestDevice::with_attributes(0x80000, 1, pma);
+        let bus = Bus::builder()
+            .with_main_memory(1)
+            .with_mapping(&device)
+            .build();
+        let reservation = AtomicU32::new(u32::MAX);
+        let mut mmu = Mmu::new(&bus, &reservation);
+
+        mmu.load_word(0x80000040)?;
+        mmu.load_word(0x80000040)?;
+        mmu.store_word(0x80000044, 1)?;
+
+        let load = test_device::Access::Load {
+            offset: 0x40,
+            width: 4,
+        };
+        let store = test_device::Access::Store {
+            offset: 0x44,
+            width: 4,
+            value: 1,
+        };
+        assert_eq!(device.take_log(), [load, load, store]);
+        Ok(())
+    }
+
+    #[test]
+    fn stores_to_stream_regions_are_combined() -> MmuResult<()> {
+        let pma = Pma::io().with_cacheability(Cacheability::Stream);
+        let device = TestDevice::with_attributes(0x80000, 2, pma);
+        let bus = Bus::builder()
+            .with_main_memory(1)
+            .with_mapping(&device)
+            .build();
+        let reservation = AtomicU32::new(u32::MAX);
+        let mut mmu = Mmu::new(&bus, &reservation);
+
+        for (i, b) in b"burst".iter().enumerate() {
+            mmu.store_byte(0x80000100 + i as u32, *b)?;
+        }
+        assert_eq!(device.take_log(), []);
+
+        // crossing into the next frame flushes the burst as one batch
+        mmu.store_half_word(0x80001000, 0x5678)?;
+        let batch = test_device::Access::StreamWrite { frame: 0, count: 5 };
+        assert_eq!(device.take_log(), [batch]);
+        let mut buf = [0; 5];
+        device.mem().block_read(0x100, &mut buf)?;
+        assert_eq!(&buf, b"burst");
+
+        // as does filling the buffers
+        for i in 1..=WC_CAPACITY as u32 {
+            mmu.store_half_word(0x80001000 + 2 * i, i as u16)?;
+        }
+        let batch = test_device::Access::StreamWrite {
+            frame: 1,
+            count: WC_CAPACITY,
+        };
+        assert_eq!(device.take_log(), [batch]);
+
+        mmu.write_back_all()?;
+        let batch = test_device::Access::StreamWrite { frame: 1, count: 1 };
+        assert_eq!(device.take_log(), [batch]);
+        assert_eq!(device.mem().load_half_word(0x1000)?, 0x5678);
+        assert_eq!(device.mem().load_half_word(0x1002)?, 1);
+        assert_eq!(device.mem().load_half_word(0x1020)?, WC_CAPACITY as u16);
+        Ok(())
+    }
+
+    #[test]
+    fn loads_from_stream_regions_are_combined() -> MmuResult<()> {
+        let pma = Pma::main().with_cacheability(Cacheability::Stream);
+        let device = TestDevice::with_attributes(0x80000, 1, pma);
+        let bus = Bus::builder()
+            .with_main_memory(1)
+            .with_mapping(&device)
+            .build();
+        let reservation = AtomicU32::new(u32::MAX);
+        let mut mmu = Mmu::new(&bus, &reservation);
+        device.mem().store_word(0x44, 0xdeadbeef)?;
+
+        // one read brings in the whole line
+        assert_eq!(mmu.load_word(0x80000044)?, 0xdeadbeef);
+        assert_eq!(
+            mmu.load_half_word(0x80000046)?,
+            if BIG_ENDIAN { 0xbeef } else { 0xdead }
+        );
+        assert_eq!(
+            mmu.load_byte(0x80000044)?,
+            if BIG_ENDIAN { 0xde } else { 0xef }
+        );
+        assert_eq!(mmu.load_word(0x8000007c)?, 0);
+        let batch = test_device::Access::StreamRead {
+            frame: 0,
+            count: 16,
+        };
+        assert_eq!(device.take_log(), [batch]);
+
+        // stores invalidate the buffers
+        mmu.store_word(0x80000048, 1)?;
+        assert_eq!(mmu.load_word(0x80000048)?, 1);
+        let store = test_device::Access::StreamWrite { frame: 0, count: 1 };
+        assert_eq!(device.take_log(), [store, batch]);
+        Ok(())
+    }
+
+    #[test]
+    fn non_idempotent_stream_regions_are_not_read_ahead() -> MmuResult<()> {
+        let pma = Pma::io().with_cacheability(Cacheability::Stream);
+        let device = TestDevice::with_attributes(0x80000, 1, pma);
+        let bus = Bus::builder()
+            .with_main_memory(1)
+            .with_mapping(&device)
+            .build();
+        let reservation = AtomicU32::new(u32::MAX);
+        let mut mmu = Mmu::new(&bus, &reservation);
+
+        mmu.load_byte(0x80000000)?;
+        mmu.load_byte(0x80000000)?;
+        let load = test_device::Access::Load {
+            offset: 0,
+            width: 1,
+        };
+        assert_eq!(device.take_log(), [load, load]);
+        Ok(())
+    }
+
+    #[test]
+    fn peek_instruction() -> MmuResult<()> {
+        let device = TestDevice::with_attributes(0x80000, 1, Pma::io());
+        let bus = Bus::builder()
+            .with_main_memory(1)
+            .with_mapping(&device)
+            .build();
+        let reservation = AtomicU32::new(u32::MAX);
+        let mut mmu = Mmu::new(&bus, &reservation);
+
+        // ebreak
+        bus.store_word(0x100, u32::from_memory(0x00100073u32.to_le()))?;
+        device
+            .mem()
+            .store_word(0, u32::from_memory(0x00100073u32.to_le()))?;
+
+        mmu.add_watchpoint(0x100, Access::Execute);
+        assert_eq!(mmu.peek_instruction(0x100), Some(Instruction::Ebreak));
+        assert_eq!(mmu.take_watchpoint_hit(), None);
+
+        assert_eq!(mmu.peek_instruction(0x80000000), None);
+        assert_eq!(device.take_log(), [], "Device memory was read");
+        Ok(())
+    }
+
+    #[test]
+    fn removed_mappings_are_written_back() -> MmuResult<()> {
+        let pma = Pma::io().with_cacheability(Cacheability::Stream);
+        let device = TestDevice::with_attributes(0x80000, 1, pma);
+        let bus = Bus::builder().with_main_memory(1).build();
+        let reservation = AtomicU32::new(u32::MAX);
+        let mut mmu = Mmu::new(&bus, &reservation);
+
+        bus.add_mapping(0x80000, &device);
+        mmu.store_byte(0x80000000, 0x42)?;
+        assert_eq!(device.take_log(), []);
+
+        assert!(mmu.remove_mapping(0x80000)?.is_some());
+        let batch = test_device::Access::StreamWrite { frame: 0, count: 1 };
+        assert_eq!(device.take_log(), [batch]);
+        assert_eq!(device.mem().load_byte(0)?, 0x42);
+        assert!(matches!(
+            mmu.load_byte(0x80000000),
+            Err(MmuError::BusError { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn pmp_tor_region_denies_writes() -> MmuResult<()> {
+        let bus = Bus::builder().with_main_memory(4).build();
+        let reservation = AtomicU32::new(u32::MAX);
+        let mut mmu = Mmu::new(&bus, &reservation);
+
+        // entry 1: TOR [0, 0x1000) R, entry 2: TOR [0x1000, 0x4000) RWX
+        mmu.set_pmp_addr(0, 0);
+        mmu.set_pmp_addr(1, 0x1000 >> 2);
+        mmu.set_pmp_addr(2, 0x4000 >> 2);
+        mmu.set_pmp_cfg(0, 0x0f_09_00);
+        mmu.set_privilege(PrivilegeMode::User);
+
+        mmu.load_word(0x100)?;
+        mmu.store_word(0x1100, 1)?;
+        assert!(matches!(
+            mmu.store_word(0x100, 1),
+            Err(MmuError::AccessFault {
+                addr: 0x100,
+                access: Access::Write
+            })
+        ));
+        assert!(matches!(
+            mmu.swap_word_atomic(0x100, 1, Ordering::Relaxed),
+            Err(MmuError::AccessFault { .. })
+        ));
+        assert!(
+            matches!(mmu.load_word(0x4000), Err(MmuError::AccessFault { .. })),
+            "No entry matches"
+        );
+
+        // entries that are not locked do not apply to machine mode
+        mmu.set_privilege(PrivilegeMode::Machine);
+        mmu.store_word(0x100, 1)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn pmp_locked_rule_applies_in_machine_mode() -> MmuResult<()> {
+        let bus = Bus::builder().with_main_memory(4).build();
+        let reservation = AtomicU32::new(u32::MAX);
+        let mut mmu = Mmu::new(&bus, &reservation);
+
+        // jal x0, 0 at 0x2000
+        bus.store_word(0x2000, 0x0000006f)?;
+        mmu.load_instruction(0x2000)?;
+
+        // a locked NAPOT entry over [0x2000, 0x3000) with only R set
+        mmu.set_pmp_addr(0, (0x2000 >> 2) | 0x1ff);
+        mmu.set_pmp_cfg(0, 0x99);
+
+        mmu.load_word(0x2000)?;
+        assert!(matches!(
+            mmu.store_word(0x2000, 0),
+            Err(MmuError::AccessFault { .. })
+        ));
+        assert!(
+            matches!(
+                mmu.load_instruction(0x2000),
+                Err(MmuError::AccessFault { .. })
+            ),
+            "Cached instructions are checked as well"
+        );
+
+        // the entry can no longer be changed
+        mmu.set_pmp_cfg(0, 0x1f);
+        mmu.set_pmp_addr(0, 0);
+        assert!(mmu.store_word(0x2000, 0).is_err());
+
+        // accesses outside it are unaffected
+        mmu.store_word(0x3000, 0)?;
+
+        Ok(())
     }
 }
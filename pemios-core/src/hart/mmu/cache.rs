@@ -10,9 +10,13 @@
 use self::{
     block::Block,
     set::Set,
-    types::{Addr, SetIndex, TagSet},
+    types::{Addr, SetIndex, Tag, TagSet},
 };
 
+/// An evicted block: the address of its first element, its data, and its
+/// tracker
+pub type Evicted<T, U, const B: usize> = (u32, [T; 1 << B], U);
+
 mod block;
 mod set;
 mod types;
@@ -22,7 +26,7 @@ where
     [(); 1 << B]:,
     [(); 1 << S]:,
     T: Copy,
-    U: Copy + Default,
+    U: Copy + Default + PartialEq,
 {
     sets: [Set<T, U, S, A, B>; 1 << S],
 }
@@ -32,9 +36,16 @@ where
     [(); 1 << B]:,
     [(); 1 << S]:,
     T: Copy + Default,
-    U: Copy + Default,
+    U: Copy + Default + PartialEq,
 {
     pub fn new() -> Self {
+        // the tag must be shiftable out of an address, and a valid tag must
+        // never equal the invalid one
+        assert!(
+            S + B > 0 && S + B < 32,
+            "A cache must have between 1 and 31 index bits"
+        );
+
         Self {
             sets: [Set::<T, U, S, A, B>::new(); 1 << S],
         }
@@ -58,7 +69,7 @@ where
         &mut self,
         addr: u32,
         f: F,
-    ) -> Result<(&T, Option<(u32, [T; 1 << B], U)>), E>
+    ) -> Result<(&T, Option<Evicted<T, U, B>>), E>
     where
         F: Fn(&mut [T; 1 << B]) -> Result<O, E>,
     {
@@ -70,15 +81,7 @@ where
 
         Ok((
             block.get(addr.offset()),
-            victim.map(|(tag, block)| {
-                let set_offset = (addr.raw() << (32 - S - B)) >> (32 - S - B);
-                let tag = (tag.raw() >> (S + B)) << (S + B);
-                let block_addr = tag | set_offset;
-
-                let (data, tracker) = block.internal();
-
-                (block_addr, *data, *tracker)
-            }),
+            victim.map(|(tag, block)| Self::evicted(tag, addr.set(), block)),
         ))
     }
 
@@ -87,7 +90,7 @@ where
         &mut self,
         addr: u32,
         f: F,
-    ) -> Result<((&mut T, &mut U), Option<(u32, [T; 1 << B], U)>), E>
+    ) -> Result<((&mut T, &mut U), Option<Evicted<T, U, B>>), E>
     where
         F: Fn(&mut [T; 1 << B]) -> Result<O, E>,
     {
@@ -98,15 +101,7 @@ where
 
         Ok((
             block.get_mut(addr.offset()),
-            victim.map(|(tag, block)| {
-                let set_offset = (addr.raw() << (32 - S - B)) >> (32 - S - B);
-                let tag = (tag.raw() >> (S + B)) << (S + B);
-                let block_addr = tag | set_offset;
-
-                let (data, tracker) = block.internal();
-
-                (block_addr, *data, *tracker)
-            }),
+            victim.map(|(tag, block)| Self::evicted(tag, addr.set(), block)),
         ))
     }
 
@@ -135,47 +130,205 @@ where
         addr.into()
     }
 
-    #[allow(unused)]
+    /// Reconstructs the address of the first element of a block from its tag
+    /// and the index of the set it is stored in.
     #[inline(always)]
-    pub fn insert(&mut self, addr: u32, block: [T; 1 << B]) -> Option<(u32, [T; 1 << B], U)> {
+    fn block_addr(tag: Tag<S, B>, set: u32) -> u32 {
+        (tag.raw() << (S + B)) | (set << B)
+    }
+
+    #[inline(always)]
+    fn evicted(tag: Tag<S, B>, set: SetIndex<S, B>, block: Block<T, U, B>) -> Evicted<T, U, B> {
+        let (data, tracker) = block.internal();
+
+        (Self::block_addr(tag, set.raw()), *data, *tracker)
+    }
+
+    /// Writes back every dirty block without evicting it.
+    ///
+    /// `write_back` is called with the address of the first element, the data,
+    /// and the tracker of each dirty block.
+    /// Blocks are marked clean as soon as `write_back` succeeds for them, so a
+    /// failed flush can be retried.
+    pub fn flush<F, E>(&mut self, mut write_back: F) -> Result<(), E>
+    where
+        F: FnMut(u32, &[T; 1 << B], U) -> Result<(), E>,
+    {
+        for (i, set) in self.sets.iter_mut().enumerate() {
+            set.clean_with(|tag, block| {
+                let (data, tracker) = block.internal();
+                write_back(Self::block_addr(tag, i as u32), data, *tracker)
+            })?;
+        }
+
+        Ok(())
+    }
+
+    /// Removes the block containing `addr`, if any.
+    ///
+    /// The block is returned if it was dirty, so that it can be written back.
+    pub fn invalidate(&mut self, addr: u32) -> Option<Evicted<T, U, B>> {
         let addr = Self::addr_from_u32(addr);
-        if let Some((tag, block)) = self
-            .get_set_mut(addr.set())
-            .insert(addr.tag(), block.into())
-            .1
-        {
-            let set_offset = (addr.raw() << (32 - S - B)) >> (32 - S - B);
-            let tag = (tag.raw() >> (S + B)) << (S + B);
-            let block_addr = tag | set_offset;
+        self.get_set_mut(addr.set())
+            .invalidate(addr.tag())
+            .map(|(tag, block)| Self::evicted(tag, addr.set(), block))
+    }
 
-            let (data, tracker) = block.internal();
+    /// Removes every block, discarding dirty data.
+    ///
+    /// Use `flush` first if dirty blocks must be written back.
+    pub fn invalidate_all(&mut self) {
+        self.sets.iter_mut().for_each(Set::invalidate_all);
+    }
 
-            Some((block_addr, *data, *tracker))
-        } else {
-            None
+    /// Removes every block for which `f` returns `true`, discarding dirty
+    /// data.
+    ///
+    /// `f` is called with the address of the first element and the data of
+    /// each block.
+    pub fn invalidate_where<F>(&mut self, mut f: F)
+    where
+        F: FnMut(u32, &[T; 1 << B]) -> bool,
+    {
+        for (i, set) in self.sets.iter_mut().enumerate() {
+            set.invalidate_where(|tag, block| {
+                f(Self::block_addr(tag, i as u32), block.internal().0)
+            });
         }
     }
 
+    #[inline(always)]
+    pub fn insert(&mut self, addr: u32, block: [T; 1 << B]) -> Option<Evicted<T, U, B>> {
+        let addr = Self::addr_from_u32(addr);
+        self.get_set_mut(addr.set())
+            .insert(addr.tag(), block.into())
+            .1
+            .map(|(tag, block)| Self::evicted(tag, addr.set(), block))
+    }
+
     #[allow(unused)]
     #[inline(always)]
-    pub fn insert_with<F, O, E>(
-        &mut self,
-        addr: u32,
-        f: F,
-    ) -> Result<Option<(u32, [T; 1 << B], U)>, E>
+    pub fn insert_with<F, O, E>(&mut self, addr: u32, f: F) -> Result<Option<Evicted<T, U, B>>, E>
     where
         F: Fn(&mut [T; 1 << B]) -> Result<O, E>,
     {
         let addr = Self::addr_from_u32(addr);
-        if let Some((tag, block)) = self.get_set_mut(addr.set()).insert_with(addr.tag(), f)?.1 {
-            let set_offset = (addr.raw() << (32 - S - B)) >> (32 - S - B);
-            let tag = (tag.raw() >> (S + B)) << (S + B);
-            let block_addr = tag | set_offset;
-            let (data, tracker) = block.internal();
-
-            Ok(Some((block_addr, *data, *tracker)))
-        } else {
-            Ok(None)
+        Ok(self
+            .get_set_mut(addr.set())
+            .insert_with(addr.tag(), f)?
+            .1
+            .map(|(tag, block)| Self::evicted(tag, addr.set(), block)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Cache;
+
+    /// 4 sets of 2 blocks of 4 elements
+    type TestCache = Cache<u32, u8, 2, 2, 2>;
+
+    #[test]
+    fn flush() {
+        let mut cache = Box::new(TestCache::new());
+        for addr in [0x100, 0x104, 0x208, 0x30c, 0x40c] {
+            cache.insert(addr, [addr, addr + 1, addr + 2, addr + 3]);
+        }
+
+        for (addr, val, mask) in [(0x105, 5, 0b0010), (0x30c, 12, 0b0001), (0x40f, 15, 0b1000)] {
+            let (data, tracker) = cache.get_mut(addr).unwrap();
+            *data = val;
+            *tracker |= mask;
+        }
+
+        let mut written = Vec::new();
+        cache
+            .flush(|addr, data: &[u32; 4], mask| {
+                written.push((addr, *data, mask));
+                Ok::<_, ()>(())
+            })
+            .unwrap();
+
+        assert_eq!(
+            written,
+            [
+                (0x104, [0x104, 5, 0x106, 0x107], 0b0010),
+                (0x30c, [12, 0x30d, 0x30e, 0x30f], 0b0001),
+                (0x40c, [0x40c, 0x40d, 0x40e, 15], 0b1000),
+            ]
+        );
+
+        // flushed blocks are clean but still resident
+        assert_eq!(cache.get(0x105), Some(&5));
+        cache
+            .flush(|addr, _: &[u32; 4], _| Err(addr))
+            .expect("no block should be dirty after a flush");
+    }
+
+    #[test]
+    fn invalidate() {
+        let mut cache = Box::new(TestCache::new());
+        cache.insert(0x100, [1, 2, 3, 4]);
+        cache.insert(0x204, [5, 6, 7, 8]);
+
+        assert_eq!(cache.invalidate(0x102), None, "clean blocks are dropped");
+        assert_eq!(cache.get(0x100), None);
+
+        let (data, tracker) = cache.get_mut(0x206).unwrap();
+        *data = 0;
+        *tracker = 0b0100;
+        assert_eq!(cache.invalidate(0x207), Some((0x204, [5, 6, 0, 8], 0b0100)));
+        assert_eq!(cache.get(0x204), None);
+
+        assert_eq!(cache.invalidate(0x300), None);
+    }
+
+    /// 4 sets of 2 blocks of a single element, like the attribute cache and
+    /// the TLB
+    type ElementCache = Cache<u32, u8, 2, 2, 0>;
+
+    #[test]
+    fn single_element_blocks() {
+        let mut cache = Box::new(ElementCache::new());
+        // 0x1, 0x5, and 0x9 all map to set 1
+        for addr in [0x1, 0x5, 0x2] {
+            assert_eq!(cache.insert(addr, [addr]), None);
         }
+        assert_eq!(cache.get(0x1), Some(&0x1));
+        assert_eq!(cache.get(0x5), Some(&0x5));
+        assert_eq!(cache.get(0x2), Some(&0x2));
+        assert_eq!(cache.get(0x9), None);
+        assert_eq!(cache.get(0x3), None);
+
+        // dirty blocks are evicted with the address they were inserted at
+        *cache.get_mut(0x1).unwrap().1 = 1;
+        assert_eq!(cache.insert(0x9, [0x9]), Some((0x1, [0x1], 1)));
+        assert_eq!(cache.get(0x1), None);
+        assert_eq!(cache.get(0x9), Some(&0x9));
+        assert_eq!(cache.get(0x5), Some(&0x5));
+
+        *cache.get_mut(0x5).unwrap().1 = 2;
+        assert_eq!(cache.invalidate(0x5), Some((0x5, [0x5], 2)));
+    }
+
+    #[test]
+    fn single_element_addresses() {
+        // the dimensions of the attribute cache, with one block per set
+        let mut cache = Box::new(Cache::<u32, u8, 12, 1, 0>::new());
+
+        // page numbers that only differ in their tags
+        cache.insert(0xfffff, [1]);
+        *cache.get_mut(0xfffff).unwrap().1 = 1;
+        assert_eq!(cache.insert(0x00fff, [2]), Some((0xfffff, [1], 1)));
+
+        *cache.get_mut(0x00fff).unwrap().1 = 1;
+        let mut written = Vec::new();
+        cache
+            .flush(|addr, data: &[u32; 1], _| {
+                written.push((addr, data[0]));
+                Ok::<_, ()>(())
+            })
+            .unwrap();
+        assert_eq!(written, [(0x00fff, 2)]);
     }
 }
@@ -9,16 +9,18 @@
 
 use super::{block::Block, types::Tag};
 
+/// A block removed from a set together with the tag it was stored under
+pub type Victim<T, U, const S: usize, const B: usize> = (Tag<S, B>, Block<T, U, B>);
+
 #[derive(Clone, Copy)]
 pub struct Set<T, U, const S: usize, const A: usize, const B: usize>
 where
     [(); 1 << B]:,
     T: Copy,
-    U: Copy + Default,
+    U: Copy + Default + PartialEq,
 {
     blocks: [Block<T, U, B>; A],
     tags: [Tag<S, B>; A],
-    dirty: [bool; A],
     victim: usize,
 }
 
@@ -26,13 +28,12 @@ impl<T, U, const S: usize, const A: usize, const B: usize> Set<T, U, S, A, B>
 where
     [(); 1 << B]:,
     T: Copy + Default,
-    U: Copy + Default,
+    U: Copy + Default + PartialEq,
 {
     pub fn new() -> Self {
         Self {
             blocks: [Block::new(); A],
             tags: [Tag::INV; A],
-            dirty: [false; A],
             victim: 0,
         }
     }
@@ -47,10 +48,10 @@ where
 
     #[inline(always)]
     pub fn get_block_mut(&mut self, tag: Tag<S, B>) -> Option<&mut Block<T, U, B>> {
-        self.tags.iter().position(|&t| t == tag).and_then(|i| {
-            self.dirty[i] = true;
-            self.blocks.get_mut(i)
-        })
+        self.tags
+            .iter()
+            .position(|&t| t == tag)
+            .and_then(|i| self.blocks.get_mut(i))
     }
 
     #[inline(always)]
@@ -58,7 +59,7 @@ where
         &mut self,
         tag: Tag<S, B>,
         f: F,
-    ) -> Result<(&Block<T, U, B>, Option<(Tag<S, B>, Block<T, U, B>)>), E>
+    ) -> Result<(&Block<T, U, B>, Option<Victim<T, U, S, B>>), E>
     where
         F: Fn(&mut [T; 1 << B]) -> Result<O, E>,
     {
@@ -75,12 +76,12 @@ where
         &mut self,
         tag: Tag<S, B>,
         f: F,
-    ) -> Result<(&mut Block<T, U, B>, Option<(Tag<S, B>, Block<T, U, B>)>), E>
+    ) -> Result<(&mut Block<T, U, B>, Option<Victim<T, U, S, B>>), E>
     where
         F: Fn(&mut [T; 1 << B]) -> Result<O, E>,
     {
         if let Some(i) = self.tags.iter().position(|&t| t == tag) {
-            return Ok((&mut self.blocks[i], None));
+            Ok((&mut self.blocks[i], None))
         } else {
             let (inserted, victim) = self.insert_with(tag, f)?;
             Ok((inserted, victim))
@@ -93,29 +94,20 @@ where
         &mut self,
         tag: Tag<S, B>,
         block: Block<T, U, B>,
-    ) -> (&mut Block<T, U, B>, Option<(Tag<S, B>, Block<T, U, B>)>) {
+    ) -> (&mut Block<T, U, B>, Option<Victim<T, U, S, B>>) {
         // search for empty slot
         let idx = self
             .tags
             .iter()
             .position(|&t| t.is_invalid())
             // or select victim and increment
-            .unwrap_or_else(|| {
-                let res = self.victim;
-                self.victim += 1;
-                self.victim %= A;
-                res
-            });
-
-        let victim_tag = self.tags[idx];
-        let victim_block = self.blocks[idx];
+            .unwrap_or_else(|| self.next_victim());
+
+        let victim = self.take(idx);
         self.tags[idx] = tag;
         self.blocks[idx] = block;
 
-        (
-            &mut self.blocks[idx],
-            (victim_tag.is_valid() && self.dirty[idx]).then_some((victim_tag, victim_block)),
-        )
+        (&mut self.blocks[idx], victim)
     }
 
     #[inline(always)]
@@ -123,31 +115,92 @@ where
         &mut self,
         tag: Tag<S, B>,
         f: F,
-    ) -> Result<(&mut Block<T, U, B>, Option<(Tag<S, B>, Block<T, U, B>)>), E>
+    ) -> Result<(&mut Block<T, U, B>, Option<Victim<T, U, S, B>>), E>
     where
         F: Fn(&mut [T; 1 << B]) -> Result<O, E>,
     {
+        // fill a fresh block first so a failing `f` leaves the set untouched
+        let mut data = [T::default(); 1 << B];
+        f(&mut data)?;
+
         let idx = self
             .tags
             .iter()
             .position(|&t| t.is_invalid() || t == tag)
             // or select victim and increment
-            .unwrap_or_else(|| {
-                let res = self.victim;
-                self.victim += 1;
-                self.victim %= A;
-                res
-            });
-
-        let victim_tag = self.tags[idx];
-        let victim_block = self.blocks[idx];
+            .unwrap_or_else(|| self.next_victim());
 
+        let victim = self.take(idx);
         self.tags[idx] = tag;
-        f(&mut self.blocks[idx].internal_mut().0)?;
+        self.blocks[idx] = data.into();
+
+        Ok((&mut self.blocks[idx], victim))
+    }
+
+    /// Calls `f` with every valid and dirty block in the set, marking the
+    /// block clean once `f` succeeds.
+    pub fn clean_with<F, E>(&mut self, mut f: F) -> Result<(), E>
+    where
+        F: FnMut(Tag<S, B>, &Block<T, U, B>) -> Result<(), E>,
+    {
+        for (tag, block) in self.tags.iter().zip(self.blocks.iter_mut()) {
+            if tag.is_valid() && *block.internal().1 != U::default() {
+                f(*tag, block)?;
+                *block.internal_mut().1 = U::default();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Removes the block stored under `tag`, if any.
+    ///
+    /// The block is returned if it was dirty and has to be written back.
+    pub fn invalidate(&mut self, tag: Tag<S, B>) -> Option<Victim<T, U, S, B>> {
+        self.tags
+            .iter()
+            .position(|&t| t == tag)
+            .and_then(|idx| self.take(idx))
+    }
+
+    /// Removes every block in the set, discarding dirty data.
+    pub fn invalidate_all(&mut self) {
+        self.tags = [Tag::INV; A];
+    }
+
+    /// Removes every valid block for which `f` returns `true`, discarding
+    /// dirty data.
+    pub fn invalidate_where<F>(&mut self, mut f: F)
+    where
+        F: FnMut(Tag<S, B>, &Block<T, U, B>) -> bool,
+    {
+        for (tag, block) in self.tags.iter_mut().zip(self.blocks.iter()) {
+            if tag.is_valid() && f(*tag, block) {
+                *tag = Tag::INV;
+            }
+        }
+    }
+
+    #[inline(always)]
+    fn next_victim(&mut self) -> usize {
+        let res = self.victim;
+        self.victim += 1;
+        self.victim %= A;
+        res
+    }
+
+    /// Removes the block at `idx` from the set.
+    ///
+    /// The block's tracker doubles as its dirty state: a block is only dirty
+    /// if its tracker differs from `U::default()`.
+    /// The removed block is returned only if it was valid and dirty, meaning
+    /// it has to be written back.
+    #[inline(always)]
+    fn take(&mut self, idx: usize) -> Option<Victim<T, U, S, B>> {
+        let tag = std::mem::replace(&mut self.tags[idx], Tag::INV);
+        let block = self.blocks[idx];
+        let (_, tracker) = block.internal();
 
-        Ok((
-            &mut self.blocks[idx],
-            (victim_tag.is_valid() && self.dirty[idx]).then_some((victim_tag, victim_block)),
-        ))
+        (tag.is_valid() && *tracker != U::default()).then_some((tag, block))
     }
 }
@@ -44,7 +44,7 @@ impl<const S: usize, const B: usize> TagSet<S, B> {
     }
 
     pub const fn set(&self) -> SetIndex<S, B> {
-        SetIndex((self.0 << (32 - S - B)) >> (32 - S))
+        SetIndex((self.0 >> B) & ((1 << S) - 1))
     }
 }
 
@@ -67,6 +67,7 @@ impl<const S: usize, const B: usize> SetIndex<S, B> {
 pub struct Addr<const S: usize, const B: usize>(u32);
 
 impl<const S: usize, const B: usize> Addr<S, B> {
+    #[allow(unused)]
     pub const fn raw(&self) -> u32 {
         self.0
     }
@@ -76,11 +77,11 @@ impl<const S: usize, const B: usize> Addr<S, B> {
     }
 
     pub const fn set(&self) -> SetIndex<S, B> {
-        SetIndex((self.0 << (32 - S - B)) >> (32 - S))
+        SetIndex((self.0 >> B) & ((1 << S) - 1))
     }
 
     pub const fn offset(&self) -> BlockOffset<B> {
-        BlockOffset((self.0 << (32 - B)) >> (32 - B))
+        BlockOffset(self.0 & ((1 << B) - 1))
     }
 
     pub const fn tag_set(&self) -> TagSet<S, B> {
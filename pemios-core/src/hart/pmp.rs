@@ -0,0 +1,223 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// This Source Code Form is "Incompatible With Secondary Licenses", as
+// defined by the Mozilla Public License, v. 2.0.
+//
+// Copyright © 2022 mumblingdrunkard
+
+use super::{mmu::Access, PrivilegeMode};
+
+/// The number of implemented PMP entries, packed into `pmpcfg0`-`pmpcfg3`
+pub const ENTRIES: usize = 16;
+
+const R: u8 = 1 << 0;
+const W: u8 = 1 << 1;
+const X: u8 = 1 << 2;
+const A: u8 = 3 << 3;
+const L: u8 = 1 << 7;
+
+/// How an entry's address register selects the region it covers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Matching {
+    Off,
+    /// From the previous entry's address up to this one's
+    Tor,
+    /// The naturally aligned 4-byte region at the address
+    Na4,
+    /// The naturally aligned power-of-two region encoded in the address
+    Napot,
+}
+
+impl Matching {
+    fn of(cfg: u8) -> Self {
+        match (cfg & A) >> 3 {
+            0 => Self::Off,
+            1 => Self::Tor,
+            2 => Self::Na4,
+            _ => Self::Napot,
+        }
+    }
+}
+
+/// The physical memory protection entries of a hart
+#[derive(Clone, Default)]
+pub struct Pmp {
+    cfg: [u8; ENTRIES],
+    addr: [u32; ENTRIES],
+    /// Whether any entry is locked, in which case machine-mode accesses have
+    /// to be checked as well
+    locked: bool,
+}
+
+impl Pmp {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn is_locked(&self, i: usize) -> bool {
+        self.cfg[i] & L != 0
+    }
+
+    /// The value of `pmpcfg{n}`, which holds the configuration of entries
+    /// `4n` to `4n + 3`.
+    pub fn cfg(&self, n: usize) -> u32 {
+        u32::from_le_bytes(self.cfg[n * 4..n * 4 + 4].try_into().unwrap())
+    }
+
+    /// Writes `val` to `pmpcfg{n}`.
+    ///
+    /// Locked entries are left as they are, and the reserved combination of
+    /// write permission without read permission is cleared.
+    pub fn set_cfg(&mut self, n: usize, val: u32) {
+        for (i, cfg) in (n * 4..).zip(val.to_le_bytes()) {
+            if self.is_locked(i) {
+                continue;
+            }
+
+            let cfg = cfg & (L | A | X | W | R);
+            self.cfg[i] = if cfg & (R | W) == W { cfg & !W } else { cfg };
+        }
+
+        self.locked = (0..ENTRIES).any(|i| self.is_locked(i));
+    }
+
+    /// The value of `pmpaddr{i}`, which holds bits 33 to 2 of an address.
+    pub fn addr(&self, i: usize) -> u32 {
+        self.addr[i]
+    }
+
+    /// Writes `val` to `pmpaddr{i}`.
+    ///
+    /// The write is ignored if entry `i` is locked, or if the next entry is a
+    /// locked TOR entry that uses this address as its bottom.
+    pub fn set_addr(&mut self, i: usize, val: u32) {
+        let top_of_locked = i + 1 < ENTRIES
+            && self.is_locked(i + 1)
+            && Matching::of(self.cfg[i + 1]) == Matching::Tor;
+
+        if !self.is_locked(i) && !top_of_locked {
+            self.addr[i] = val;
+        }
+    }
+
+    /// The range of physical addresses covered by entry `i`, as a half-open
+    /// range of 34-bit addresses, or `None` if the entry is off.
+    fn range(&self, i: usize) -> Option<(u64, u64)> {
+        let addr = self.addr[i] as u64;
+        match Matching::of(self.cfg[i]) {
+            Matching::Off => None,
+            Matching::Tor => {
+                let bottom = if i == 0 { 0 } else { self.addr[i - 1] as u64 };
+                Some((bottom << 2, addr << 2))
+            }
+            Matching::Na4 => Some((addr << 2, (addr << 2) + 4)),
+            Matching::Napot => {
+                let ones = addr.trailing_ones();
+                let base = (addr & !((1 << ones) - 1)) << 2;
+                Some((base, base + (8 << ones)))
+            }
+        }
+    }
+
+    /// Whether an access of kind `access` to the `len` bytes at the physical
+    /// address `addr` is permitted in `mode`.
+    ///
+    /// The lowest-numbered entry that covers any of the bytes decides, and the
+    /// access fails if it does not cover all of them.
+    /// Machine-mode accesses are only restricted by locked entries and are
+    /// permitted when no entry matches, but accesses from other modes fail.
+    #[inline(always)]
+    pub fn permits(&self, addr: u32, len: u32, access: Access, mode: PrivilegeMode) -> bool {
+        if mode == PrivilegeMode::Machine && !self.locked {
+            return true;
+        }
+
+        let (start, end) = (addr as u64, addr as u64 + len as u64);
+        for i in 0..ENTRIES {
+            let Some((bottom, top)) = self.range(i) else {
+                continue;
+            };
+
+            if end <= bottom || top <= start {
+                continue;
+            }
+
+            if start < bottom || top < end {
+                return false;
+            }
+
+            if mode == PrivilegeMode::Machine && !self.is_locked(i) {
+                return true;
+            }
+
+            let required = match access {
+                Access::Read => R,
+                Access::Write => W,
+                Access::Execute => X,
+            };
+            return self.cfg[i] & required != 0;
+        }
+
+        mode == PrivilegeMode::Machine
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matching_modes() {
+        let mut pmp = Pmp::new();
+        let user = PrivilegeMode::User;
+
+        // TOR [0x1000, 0x2000), NA4 at 0x3000, NAPOT [0x8000, 0x9000)
+        pmp.set_addr(0, 0x1000 >> 2);
+        pmp.set_addr(1, 0x2000 >> 2);
+        pmp.set_addr(2, 0x3000 >> 2);
+        pmp.set_addr(3, (0x8000 >> 2) | 0x1ff);
+        pmp.set_cfg(0, 0x1f_11_0f_00);
+
+        assert!(!pmp.permits(0x0ffc, 4, Access::Read, user));
+        assert!(pmp.permits(0x1000, 4, Access::Execute, user));
+        assert!(pmp.permits(0x1ffc, 4, Access::Read, user));
+        assert!(
+            !pmp.permits(0x1ffe, 4, Access::Read, user),
+            "Straddles the top of the region"
+        );
+        assert!(pmp.permits(0x3000, 4, Access::Read, user));
+        assert!(!pmp.permits(0x3000, 4, Access::Execute, user));
+        assert!(!pmp.permits(0x3004, 1, Access::Read, user));
+        assert!(pmp.permits(0x8000, 4, Access::Write, user));
+        assert!(pmp.permits(0x8ffc, 4, Access::Execute, user));
+        assert!(!pmp.permits(0x9000, 4, Access::Read, user));
+
+        // nothing is locked, so machine mode can access everything
+        assert!(pmp.permits(0x3000, 4, Access::Write, PrivilegeMode::Machine));
+    }
+
+    #[test]
+    fn locked_entries_ignore_writes() {
+        let mut pmp = Pmp::new();
+        pmp.set_addr(0, 0x100);
+        pmp.set_addr(1, 0x200);
+        pmp.set_cfg(0, 0x89 << 8); // locked TOR, R
+
+        pmp.set_cfg(0, 0x0f0f);
+        pmp.set_addr(0, 0);
+        pmp.set_addr(1, 0);
+
+        assert_eq!(pmp.cfg(0), 0x890f);
+        assert_eq!(pmp.addr(0), 0x100, "Bottom of a locked TOR entry");
+        assert_eq!(pmp.addr(1), 0x200);
+    }
+
+    #[test]
+    fn write_without_read_is_reserved() {
+        let mut pmp = Pmp::new();
+        pmp.set_cfg(0, 0x0e);
+        assert_eq!(pmp.cfg(0), 0x0c);
+    }
+}
@@ -9,6 +9,7 @@
 
 #[allow(unused)]
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Reg {
     /// Description: "Hard-wired zero"
     /// ABI Name: `zero`
@@ -202,6 +203,11 @@ pub enum Reg {
     /// Saver: Caller
     X31,
 
+    /// A destination that discards what is written to it.
+    ///
+    /// The decoder produces it in place of `x0` as a destination register, so
+    /// that instructions can write their result unconditionally.
+    /// It is never produced by `From<u32>` and has no encoding of its own.
     Ignore,
 }
 
@@ -363,7 +369,42 @@ impl From<u32> for Reg {
     }
 }
 
-#[derive(Debug)]
+/// The ABI names of `x0` to `x31`
+#[rustfmt::skip]
+const ABI_NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3",
+    "a4", "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11",
+    "t3", "t4", "t5", "t6",
+];
+
+impl Reg {
+    /// Parses a register from its number, as in `x8`, or from its ABI name,
+    /// as in `s0` or `fp`.
+    pub fn from_abi_name(name: &str) -> Option<Self> {
+        let number = name
+            .strip_prefix('x')
+            .filter(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+            .and_then(|n| n.parse().ok());
+
+        let r = match (number, name) {
+            (Some(r @ 0..=31), _) => r,
+            (Some(_), _) => return None,
+            (None, "fp") => 8,
+            (None, _) => ABI_NAMES.iter().position(|&n| n == name)? as u32,
+        };
+        Some(Self::from(r))
+    }
+}
+
+impl std::fmt::Display for Reg {
+    /// Writes the ABI name of the register
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // writes to x0 are decoded as writes to `Ignore`
+        f.write_str(ABI_NAMES[*self as usize & 31])
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct RegisterFile {
     reg: [u32; 33],
 }
@@ -378,6 +419,27 @@ impl RegisterFile {
     pub fn new() -> Self {
         Self { reg: [0; 33] }
     }
+
+    /// The values of `x0` to `x31`
+    pub fn snapshot(&self) -> [u32; 32] {
+        self.reg[..32].try_into().unwrap()
+    }
+
+    /// The registers `x0` to `x31` along with their values
+    pub fn iter(&self) -> impl Iterator<Item = (Reg, u32)> + '_ {
+        (0..32).map(|r| (Reg::from(r), self.reg[r as usize]))
+    }
+}
+
+impl std::fmt::Display for RegisterFile {
+    /// Writes the registers by their ABI names, four to a line
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (reg, val) in self.iter() {
+            let end = if reg as usize % 4 == 3 { "\n" } else { "  " };
+            write!(f, "{:>4}: {val:08x}{end}", reg.to_string())?;
+        }
+        Ok(())
+    }
 }
 
 impl std::ops::Index<Reg> for RegisterFile {
@@ -389,7 +451,150 @@ impl std::ops::Index<Reg> for RegisterFile {
 }
 
 impl std::ops::IndexMut<Reg> for RegisterFile {
+    /// Writes to `x0` go to the same throwaway slot as `Reg::Ignore`, so `x0`
+    /// always reads as 0.
     fn index_mut(&mut self, index: Reg) -> &mut Self::Output {
+        let index = match index {
+            Reg::X0 => Reg::Ignore,
+            r => r,
+        };
         unsafe { self.reg.get_unchecked_mut(index as usize) }
     }
 }
+
+/// A floating-point register, `f0` to `f31`
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FReg(u8);
+
+impl From<u32> for FReg {
+    fn from(r: u32) -> Self {
+        debug_assert!(
+            (0..32).contains(&r),
+            "Register value must be in the range 0..32"
+        );
+        Self(r as u8 & 31)
+    }
+}
+
+impl From<FReg> for u32 {
+    fn from(r: FReg) -> Self {
+        r.0 as u32
+    }
+}
+
+impl std::fmt::Display for FReg {
+    /// Writes the ABI name of the register
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        #[rustfmt::skip]
+        const NAMES: [&str; 32] = [
+            "ft0", "ft1", "ft2", "ft3", "ft4", "ft5", "ft6", "ft7", "fs0", "fs1", "fa0", "fa1",
+            "fa2", "fa3", "fa4", "fa5", "fa6", "fa7", "fs2", "fs3", "fs4", "fs5", "fs6", "fs7",
+            "fs8", "fs9", "fs10", "fs11", "ft8", "ft9", "ft10", "ft11",
+        ];
+
+        f.write_str(NAMES[self.0 as usize])
+    }
+}
+
+/// The floating-point registers, holding the bits of single-precision values
+#[derive(Debug, Clone)]
+pub struct FRegisterFile {
+    reg: [u32; 32],
+}
+
+impl Default for FRegisterFile {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FRegisterFile {
+    pub fn new() -> Self {
+        Self { reg: [0; 32] }
+    }
+}
+
+impl std::ops::Index<FReg> for FRegisterFile {
+    type Output = u32;
+
+    fn index(&self, index: FReg) -> &Self::Output {
+        &self.reg[index.0 as usize]
+    }
+}
+
+impl std::ops::IndexMut<FReg> for FRegisterFile {
+    fn index_mut(&mut self, index: FReg) -> &mut Self::Output {
+        &mut self.reg[index.0 as usize]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::hart::instruction::Instruction;
+
+    use super::{Reg, RegisterFile};
+
+    #[test]
+    fn x0_is_hardwired() {
+        let mut reg = RegisterFile::new();
+        reg[Reg::X0] = 0xdeadbeef;
+        reg[Reg::X1] = 0xdeadbeef;
+
+        assert_eq!(reg[Reg::X0], 0);
+        assert_eq!(reg[Reg::X1], 0xdeadbeef);
+    }
+
+    #[test]
+    fn abi_names() {
+        for name in ["fp", "s0", "x8"] {
+            assert_eq!(Reg::from_abi_name(name), Some(Reg::X8), "{name}");
+        }
+        assert_eq!(Reg::from_abi_name("zero"), Some(Reg::X0));
+        assert_eq!(Reg::from_abi_name("x31"), Some(Reg::X31));
+        assert_eq!(Reg::from_abi_name("s11"), Some(Reg::X27));
+        assert!(
+            (0..32).all(|r| Reg::from_abi_name(&Reg::from(r).to_string()) == Some(Reg::from(r)))
+        );
+
+        for name in ["x32", "x", "x+1", "a8", "X8", ""] {
+            assert_eq!(Reg::from_abi_name(name), None, "{name}");
+        }
+    }
+
+    #[test]
+    fn x0_destination_is_ignored() {
+        // addi zero, ra, 5
+        let Instruction::Addi { rd, rs1, .. } = Instruction::from(0x00508013) else {
+            panic!("Expected addi");
+        };
+        assert_eq!(rd, Reg::Ignore);
+        assert_eq!(rs1, Reg::X1);
+        assert!((0..32).all(|r| Reg::from(r) != Reg::Ignore));
+
+        let mut reg = RegisterFile::new();
+        reg[rd] = 5;
+        assert_eq!(reg[Reg::X0], 0);
+    }
+
+    #[test]
+    fn snapshot() {
+        let mut reg = RegisterFile::new();
+        reg[Reg::X0] = 1;
+        reg[Reg::Ignore] = 2;
+        reg[Reg::A0] = 3;
+        reg[Reg::T6] = 4;
+
+        let snapshot = reg.snapshot();
+        assert_eq!(snapshot[0], 0);
+        assert_eq!(snapshot[10], 3);
+        assert_eq!(snapshot[31], 4);
+        assert_eq!(reg.iter().count(), 32);
+        assert!(reg.iter().all(|(r, val)| snapshot[r as usize] == val));
+
+        let grid = reg.to_string();
+        assert_eq!(grid.lines().count(), 8);
+        assert!(grid.starts_with("zero: 00000000    ra: 00000000"));
+        assert!(grid.contains("  a0: 00000003"));
+    }
+}
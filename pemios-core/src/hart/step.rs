@@ -7,296 +7,1391 @@
 //
 // Copyright © 2022 mumblingdrunkard
 
-use std::ops::{BitAnd, BitOr, BitXor};
+mod execute;
 
-use crate::hart::{instruction::Instruction, Hart};
+pub(crate) use execute::{handler, Handler};
 
-use super::instruction::Conclusion;
+use crate::{
+    bus::BusError,
+    hart::{csr::Csr, Hart, Reg},
+    memory::mapping::MemoryError,
+};
 
-pub trait Step {
-    fn step(&mut self) -> Conclusion;
+use super::{
+    instruction::{Conclusion, ExceptionKind, Instruction, RoundingMode},
+    mmu::{Access, MmuError},
+};
+
+/// Converts the error from an access of kind `access` to the virtual address
+/// `addr` into the exception it raises.
+fn exception(e: MmuError, access: Access, addr: u32) -> ExceptionKind {
+    use ExceptionKind::*;
+
+    let misaligned = matches!(
+        e,
+        MmuError::LoadMisaligned { .. }
+            | MmuError::StoreMisaligned { .. }
+            | MmuError::BusError {
+                e: BusError::MemoryError {
+                    e: MemoryError::LoadMisaligned { .. }
+                        | MemoryError::StoreMisaligned { .. }
+                        | MemoryError::AmoMisaligned { .. }
+                }
+            }
+    );
+
+    match (e, access) {
+        (MmuError::PageFault { .. }, Access::Read) => LoadPageFault { addr },
+        (MmuError::PageFault { .. }, Access::Write) => StorePageFault { addr },
+        (MmuError::PageFault { .. }, Access::Execute) => InstructionPageFault { addr },
+        (_, Access::Read) if misaligned => LoadMisaligned { addr },
+        (_, Access::Write) if misaligned => StoreMisaligned { addr },
+        (_, Access::Execute) if misaligned => InstructionMisaligned { target: addr },
+        (_, Access::Read) => LoadAccessFault { addr },
+        (_, Access::Write) => StoreAccessFault { addr },
+        (_, Access::Execute) => InstructionAccessFault { addr },
+    }
 }
 
-impl Step for Hart<'_> {
-    fn step(&mut self) -> Conclusion {
-        use Instruction::*;
+impl Hart<'_> {
+    /// Reads `csr` into `rd` and, if there is a `src` operand, writes
+    /// `op(old, src)` back to it.
+    ///
+    /// Raises illegal-instruction with the encoding of `inst` if the access is
+    /// not allowed.
+    fn csr_op<F>(
+        &mut self,
+        inst: Instruction,
+        rd: Reg,
+        csr: Csr,
+        src: Option<u32>,
+        op: F,
+    ) -> Conclusion
+    where
+        F: FnOnce(u32, u32) -> u32,
+    {
+        // the floating-point CSRs only exist along with the F extension
+        let missing = match csr {
+            Csr::Invalid => true,
+            Csr::FFlags | Csr::Frm | Csr::FCsr => !cfg!(feature = "rv32f"),
+            _ => false,
+        };
+        let privileged = csr.privilege() > self.privilege as u32;
+        if missing || privileged || (csr.is_read_only() && src.is_some()) {
+            let raw = inst.encode();
+            return Conclusion::Exception(ExceptionKind::IllegalInstruction { raw });
+        }
+
+        let old = self.get_csr(csr);
+        if let Some(src) = src {
+            self.set_csr(csr, op(old, src));
+        }
+        self.reg[rd] = old;
+        Conclusion::None
+    }
 
-        let inst = match self.mmu.load_instruction(self.pc) {
-            Ok(op) => op,
-            Err(_) => todo!(),
+    /// Runs the floating-point operation `op` in the rounding mode `rm`,
+    /// reading it from `frm` if it is dynamic, and accrues the exception flags
+    /// it returns in `fflags`.
+    ///
+    /// Raises illegal-instruction with the encoding of `inst` instead if the
+    /// rounding mode is reserved.
+    fn fp_op<F>(&mut self, inst: Instruction, rm: RoundingMode, op: F) -> Conclusion
+    where
+        F: FnOnce(&mut Self, RoundingMode) -> u32,
+    {
+        let rm = match rm {
+            RoundingMode::Dynamic => RoundingMode::new(self.get_csr(Csr::Frm) as u8),
+            rm => Some(rm),
         };
 
-        let conclusion = match inst {
-            Lui { rd, imm } => {
-                self.reg[rd] = i32::from(imm) as u32;
-                Conclusion::None
+        match rm {
+            Some(RoundingMode::Dynamic) | None => {
+                Conclusion::Exception(ExceptionKind::IllegalInstruction { raw: inst.encode() })
             }
-            Auipc { rd, imm } => {
-                self.reg[rd] = self.pc.wrapping_add_signed(imm.into());
+            Some(rm) => {
+                let flags = op(self, rm);
+                self.accrue(flags);
                 Conclusion::None
             }
-            Jal { rd, imm } => {
-                let target = self.pc.wrapping_add_signed(imm.into());
-                if target & 3 != 0 {
-                    todo!("Add misaligned jump exception");
-                }
-                self.reg[rd] = self.pc.wrapping_add(4);
-                self.pc = target;
-                Conclusion::Jumped
-            }
-            Jalr { rd, rs1, imm } => {
-                let target = self.reg[rs1].wrapping_add_signed(imm.into()) & 0xfffffffe;
-                if target & 3 != 0 {
-                    todo!("Add misaligned jump exception");
-                }
-                self.reg[rd] = self.pc.wrapping_add(4);
-                self.pc = target;
-                Conclusion::Jumped
-            }
-            Beq { rs1, rs2, imm } => {
-                if self.reg[rs1] != self.reg[rs2] {
-                    Conclusion::None
-                } else {
-                    let target = self.pc.wrapping_add_signed(imm.into());
-                    if target & 3 != 0 {
-                        todo!("Add misaligned jump exception");
-                    }
+        }
+    }
 
-                    self.pc = target;
-                    Conclusion::Jumped
-                }
-            }
-            Bne { rs1, rs2, imm } => {
-                if self.reg[rs1] == self.reg[rs2] {
-                    Conclusion::None
-                } else {
-                    let target = self.pc.wrapping_add_signed(imm.into());
-                    if target & 3 != 0 {
-                        todo!("Add misaligned jump exception");
-                    }
+    /// Sets the exception `flags` in `fflags`, leaving those already set.
+    fn accrue(&mut self, flags: u32) {
+        self.csr[Csr::FCsr] |= flags;
+    }
+}
 
-                    self.pc = target;
-                    Conclusion::Jumped
-                }
-            }
-            Blt { rs1, rs2, imm } => {
-                if (self.reg[rs1] as i32) >= (self.reg[rs2] as i32) {
-                    Conclusion::None
-                } else {
-                    let target = self.pc.wrapping_add_signed(imm.into());
-                    if target & 3 != 0 {
-                        todo!("Add misaligned jump exception");
-                    }
+/// How [`Hart::run`] stopped
+#[derive(Clone, Copy, Debug)]
+pub enum RunResult {
+    /// An instruction raised `exception` after `retired` steps that did not
+    Trapped {
+        exception: ExceptionKind,
+        retired: usize,
+    },
 
-                    self.pc = target;
-                    Conclusion::Jumped
-                }
-            }
-            Bge { rs1, rs2, imm } => {
-                if (self.reg[rs1] as i32) < (self.reg[rs2] as i32) {
-                    Conclusion::None
-                } else {
-                    let target = self.pc.wrapping_add_signed(imm.into());
-                    if target & 3 != 0 {
-                        todo!("Add misaligned jump exception");
-                    }
+    /// A watchpoint at `addr` was hit by an access of `kind` after `retired`
+    /// steps, including the one that hit it if it was a data access
+    Watchpoint {
+        addr: u32,
+        kind: Access,
+        retired: usize,
+    },
 
-                    self.pc = target;
-                    Conclusion::Jumped
-                }
-            }
-            Bltu { rs1, rs2, imm } => {
-                if self.reg[rs1] >= self.reg[rs2] {
-                    Conclusion::None
-                } else {
-                    let target = self.pc.wrapping_add_signed(imm.into());
-                    if target & 3 != 0 {
-                        todo!("Add misaligned jump exception");
-                    }
+    /// The hart took the maximum number of steps without raising an exception
+    BudgetExhausted { retired: usize },
+}
 
-                    self.pc = target;
-                    Conclusion::Jumped
-                }
-            }
-            Bgeu { rs1, rs2, imm } => {
-                if self.reg[rs1] < self.reg[rs2] {
-                    Conclusion::None
-                } else {
-                    let target = self.pc.wrapping_add_signed(imm.into());
-                    if target & 3 != 0 {
-                        todo!("Add misaligned jump exception");
-                    }
+impl RunResult {
+    /// The number of steps taken that did not raise an exception
+    pub fn retired(&self) -> usize {
+        match *self {
+            Self::Trapped { retired, .. }
+            | Self::Watchpoint { retired, .. }
+            | Self::BudgetExhausted { retired } => retired,
+        }
+    }
+}
 
-                    self.pc = target;
-                    Conclusion::Jumped
-                }
-            }
+impl Hart<'_> {
+    /// Steps until an instruction raises an exception or a watchpoint is hit,
+    /// or until `max_instructions` steps have been taken if it is given.
+    pub fn run(&mut self, max_instructions: Option<usize>) -> RunResult {
+        // without a budget, steps are taken in batches of this size
+        const BATCH: usize = 0x10000;
 
-            Lb { rd, rs1, imm } => todo!("{rd:?}, {rs1:?}, {imm:?}"),
-            Lh { rd, rs1, imm } => todo!("{rd:?}, {rs1:?}, {imm:?}"),
-            Lw { rd, rs1, imm } => {
-                let addr = self.reg[rs1].wrapping_add_signed(imm.into());
-                match self.mmu.load_word(addr) {
-                    Ok(val) => {
-                        self.reg[rd] = val;
-                        Conclusion::None
+        let mut retired = 0;
+        loop {
+            let n = match max_instructions {
+                Some(max) if retired == max => return RunResult::BudgetExhausted { retired },
+                Some(max) => max - retired,
+                None => BATCH,
+            };
+
+            match self.step_many(n) {
+                (steps, Conclusion::Exception(exception)) => {
+                    return RunResult::Trapped {
+                        exception,
+                        retired: retired + steps - 1,
                     }
-                    Err(e) => todo!("{:?}", e),
                 }
-            }
-            Lbu { rd, rs1, imm } => todo!(),
-            Lhu { rd, rs1, imm } => todo!(),
-
-            Sb { rs1, rs2, imm } => {
-                let addr = self.reg[rs1].wrapping_add_signed(imm.into());
-                match self.mmu.store_byte(addr, self.reg[rs2] as u8) {
-                    Ok(_) => Conclusion::None,
-                    Err(e) => todo!("{:?}", e),
-                }
-            }
-            Sh { rs1, rs2, imm } => {
-                let addr = self.reg[rs1].wrapping_add_signed(imm.into());
-                match self.mmu.store_half_word(addr, self.reg[rs2] as u16) {
-                    Ok(_) => Conclusion::None,
-                    Err(e) => todo!("{:?}", e),
-                }
-            }
-            Sw { rs1, rs2, imm } => {
-                let addr = self.reg[rs1].wrapping_add_signed(imm.into());
-                match self.mmu.store_word(addr, self.reg[rs2]) {
-                    Ok(_) => Conclusion::None,
-                    Err(e) => todo!("{:?}", e),
+                (steps, Conclusion::Watchpoint { addr, kind }) => {
+                    // a watched instruction is stopped before it executes
+                    let retired = match kind {
+                        Access::Execute => retired + steps - 1,
+                        _ => retired + steps,
+                    };
+                    return RunResult::Watchpoint {
+                        addr,
+                        kind,
+                        retired,
+                    };
                 }
+                (steps, _) => retired += steps,
             }
+        }
+    }
+}
 
-            Addi { rd, rs1, imm } => {
-                self.reg[rd] = self.reg[rs1].wrapping_add_signed(imm.into());
-                Conclusion::None
-            }
-            Slti { rd, rs1, imm } => {
-                self.reg[rd] = ((self.reg[rs1] as i32) < imm.into()) as u32;
-                Conclusion::None
-            }
-            Sltiu { rd, rs1, imm } => {
-                self.reg[rd] = (self.reg[rs1] < i32::from(imm) as u32) as u32;
-                Conclusion::None
-            }
-            Xori { rd, rs1, imm } => {
-                self.reg[rd] = self.reg[rs1].bitxor(i32::from(imm) as u32);
-                Conclusion::None
-            }
-            Ori { rd, rs1, imm } => {
-                self.reg[rd] = self.reg[rs1].bitor(i32::from(imm) as u32);
-                Conclusion::None
-            }
-            Andi { rd, rs1, imm } => {
-                self.reg[rd] = self.reg[rs1].bitand(i32::from(imm) as u32);
-                Conclusion::None
-            }
-            Slli { rd, rs1, shamt } => {
-                self.reg[rd] = self.reg[rs1] << u32::from(shamt);
-                Conclusion::None
-            }
-            Srli { rd, rs1, shamt } => {
-                self.reg[rd] = self.reg[rs1] >> u32::from(shamt);
-                Conclusion::None
-            }
-            Srai { rd, rs1, shamt } => {
-                self.reg[rd] = (self.reg[rs1] as i32 >> u32::from(shamt)) as u32;
-                Conclusion::None
-            }
+/// The pc and integer registers of a hart after a step, e.g. as parsed from
+/// the commit log of a reference simulator
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RegisterSnapshot {
+    pub pc: u32,
+    pub reg: [u32; 32],
+}
 
-            Add { rd, rs1, rs2 } => {
-                self.reg[rd] = self.reg[rs1].wrapping_add(self.reg[rs2]);
-                Conclusion::None
-            }
-            Sub { rd, rs1, rs2 } => {
-                self.reg[rd] = self.reg[rs1].wrapping_sub(self.reg[rs2]);
-                Conclusion::None
-            }
-            Sll { rd, rs1, rs2 } => {
-                self.reg[rd] = self.reg[rs1].wrapping_shl(self.reg[rs2]);
-                Conclusion::None
-            }
-            Slt { rd, rs1, rs2 } => {
-                self.reg[rd] = ((self.reg[rs1] as i32) < (self.reg[rs2] as i32)) as u32;
-                Conclusion::None
-            }
-            Sltu { rd, rs1, rs2 } => {
-                self.reg[rd] = (self.reg[rs1] < self.reg[rs2]) as u32;
-                Conclusion::None
-            }
-            Xor { rd, rs1, rs2 } => {
-                self.reg[rd] = self.reg[rs1].bitxor(self.reg[rs2]);
-                Conclusion::None
+/// The first difference [`Hart::compare_step`] found between the hart and the
+/// expected snapshot
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Divergence {
+    Pc {
+        expected: u32,
+        actual: u32,
+    },
+    Reg {
+        reg: Reg,
+        expected: u32,
+        actual: u32,
+    },
+}
+
+impl Hart<'_> {
+    /// Takes a step and compares the pc and then `x1` to `x31` against
+    /// `expected`, for running alongside a reference simulator.
+    ///
+    /// Steps that raise an exception are compared like any other, as the
+    /// trap handler is entered in the same step.
+    pub fn compare_step(&mut self, expected: &RegisterSnapshot) -> Result<(), Divergence> {
+        self.step();
+
+        if self.pc != expected.pc {
+            return Err(Divergence::Pc {
+                expected: expected.pc,
+                actual: self.pc,
+            });
+        }
+
+        self.reg
+            .iter()
+            .zip(expected.reg)
+            .find(|&((_, actual), expected)| actual != expected)
+            .map_or(Ok(()), |((reg, actual), expected)| {
+                Err(Divergence::Reg {
+                    reg,
+                    expected,
+                    actual,
+                })
+            })
+    }
+}
+
+pub trait Step {
+    fn step(&mut self) -> Conclusion;
+
+    /// Steps up to `n` times, stopping early at the first step that raises an
+    /// exception or hits a watchpoint.
+    ///
+    /// Returns the number of steps taken, including the one that stopped, and
+    /// the conclusion of the last step.
+    #[inline]
+    fn step_many(&mut self, n: usize) -> (usize, Conclusion) {
+        let mut conclusion = Conclusion::None;
+        for i in 0..n {
+            conclusion = self.step();
+            if !matches!(conclusion, Conclusion::None | Conclusion::Jumped) {
+                return (i + 1, conclusion);
             }
-            Srl { rd, rs1, rs2 } => {
-                self.reg[rd] = self.reg[rs1].wrapping_shr(self.reg[rs2]);
-                Conclusion::None
+        }
+        (n, conclusion)
+    }
+}
+
+impl Step for Hart<'_> {
+    fn step(&mut self) -> Conclusion {
+        // every step takes a cycle, but only instructions that complete are retired
+        self.increment_counter(Csr::MCycle, Csr::MCycleh);
+
+        // taking an interrupt uses up the step, leaving the pc at the handler
+        if self.check_interrupts() {
+            return Conclusion::Jumped;
+        }
+
+        let (inst, handler, len) = match self.mmu.fetch(self.pc) {
+            Ok(fetched) => fetched,
+            Err(e) => {
+                let e = exception(e, Access::Execute, self.pc);
+                self.take_trap(e.cause(), e.tval());
+                return Conclusion::Exception(e);
             }
-            Sra { rd, rs1, rs2 } => {
-                self.reg[rd] = (self.reg[rs1] as i32).wrapping_shr(self.reg[rs2]) as u32;
-                Conclusion::None
+        };
+
+        if let Some((addr, kind)) = self.mmu.take_watchpoint_hit() {
+            return Conclusion::Watchpoint { addr, kind };
+        }
+
+        if let Some(trace) = &mut self.trace {
+            trace(self.pc, &inst);
+        }
+
+        self.next_pc = self.pc.wrapping_add(len as u32);
+        let conclusion = if cfg!(feature = "threaded") {
+            handler(self, inst)
+        } else {
+            self.execute(inst)
+        };
+
+        match conclusion {
+            Conclusion::None => self.pc = self.next_pc,
+            Conclusion::Jumped | Conclusion::Watchpoint { .. } => {}
+            Conclusion::Exception(e) => {
+                // the access that hit a watchpoint did not complete
+                self.mmu.take_watchpoint_hit();
+                self.take_trap(e.cause(), e.tval());
+                return conclusion;
             }
-            Or { rd, rs1, rs2 } => {
-                self.reg[rd] = self.reg[rs1].bitor(self.reg[rs2]);
-                Conclusion::None
+        }
+
+        self.increment_counter(Csr::MInstRet, Csr::MInstReth);
+
+        match self.mmu.take_watchpoint_hit() {
+            Some((addr, kind)) => Conclusion::Watchpoint { addr, kind },
+            None => conclusion,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicU32, AtomicU64},
+        Arc, Mutex,
+    };
+
+    use crate::{
+        asm::Assembler,
+        bus::{AccessEvent, AccessKind, Bus},
+        hart::{
+            csr::Csr,
+            instruction::{Conclusion, ExceptionKind, Instruction},
+            register::Reg,
+            Hart, PrivilegeMode,
+        },
+        memory::{
+            endian::MemoryOrder,
+            mapping::{Mapping, MemoryKind, Pma},
+            test_device::{read_word, TestDevice},
+        },
+    };
+
+    use super::{Divergence, RegisterSnapshot, Step};
+
+    fn program(code: &[u32]) -> Vec<u8> {
+        code.iter().flat_map(|i| i.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn fence_tso_makes_stores_visible() {
+        let bus = Bus::builder().with_main_memory(2).build();
+        let reservation = AtomicU32::new(u32::MAX);
+        bus.set_mm(&program(&[
+            0x00001537, // lui a0, 1
+            0x02a00593, // li a1, 42
+            0x00b52023, // sw a1, 0(a0)
+            0x8330000f, // fence.tso
+        ]))
+        .unwrap();
+
+        let mut hart = Hart::new(&bus, &reservation);
+        for _ in 0..3 {
+            assert!(matches!(hart.step(), Conclusion::None));
+        }
+        assert_eq!(read_word(&bus, 0x1000), 0, "Store should still be cached");
+
+        assert!(matches!(hart.step(), Conclusion::None));
+        assert_eq!(read_word(&bus, 0x1000), 42, "fence.tso did not write back");
+    }
+
+    #[test]
+    fn upper_immediates() {
+        let bus = Bus::builder().with_main_memory(1).build();
+        let reservation = AtomicU32::new(u32::MAX);
+        bus.set_mm(&program(&[
+            0xfffff0b7, // lui ra, 0xfffff
+            0x80000137, // lui sp, 0x80000
+            0x80000197, // auipc gp, 0x80000
+            0xfffff217, // auipc tp, 0xfffff
+        ]))
+        .unwrap();
+
+        let mut hart = Hart::new(&bus, &reservation);
+        for _ in 0..4 {
+            assert!(matches!(hart.step(), Conclusion::None));
+        }
+        assert_eq!(hart.reg[Reg::RA], 0xfffff000);
+        assert_eq!(hart.reg[Reg::SP], 0x80000000);
+        assert_eq!(hart.reg[Reg::GP], 0x80000008);
+        assert_eq!(hart.reg[Reg::TP], 0xfffff00c, "auipc should wrap around");
+    }
+
+    #[test]
+    fn set_less_than_immediate() {
+        let mut asm = Assembler::new();
+        asm.li(Reg::A1, 1)
+            .li(Reg::A2, -1)
+            .li(Reg::A3, -5)
+            // seqz
+            .sltiu(Reg::T0, Reg::ZERO, 1)
+            .sltiu(Reg::T1, Reg::A1, 1)
+            // the immediate is sign-extended, then compared as 0xffffffff
+            .sltiu(Reg::T2, Reg::A1, -1)
+            .sltiu(Reg::T3, Reg::A2, -1)
+            .slti(Reg::T4, Reg::A3, -4)
+            .slti(Reg::T5, Reg::A3, -5)
+            .slti(Reg::T6, Reg::A2, 0)
+            .slti(Reg::S1, Reg::A1, -1);
+        let len = asm.assemble().len();
+
+        let bus = Bus::builder().with_main_memory(1).build();
+        let reservation = AtomicU32::new(u32::MAX);
+        bus.set_mm(&asm.assemble_bytes()).unwrap();
+
+        let mut hart = Hart::new(&bus, &reservation);
+        hart.step_many(len);
+        assert_eq!(hart.reg[Reg::T0], 1, "0 should be below 1");
+        assert_eq!(hart.reg[Reg::T1], 0);
+        assert_eq!(hart.reg[Reg::T2], 1, "1 should be below 0xffffffff");
+        assert_eq!(hart.reg[Reg::T3], 0);
+        assert_eq!(hart.reg[Reg::T4], 1, "-5 should be below -4");
+        assert_eq!(hart.reg[Reg::T5], 0);
+        assert_eq!(hart.reg[Reg::T6], 1, "-1 should be below 0");
+        assert_eq!(hart.reg[Reg::S1], 0);
+    }
+
+    #[test]
+    fn fence_writes_back_to_devices() {
+        let device = TestDevice::new(0x80000, 1);
+        let bus = Bus::builder()
+            .with_main_memory(1)
+            .with_mapping(&device)
+            .build();
+        let reservation = AtomicU32::new(u32::MAX);
+        bus.set_mm(&program(&[
+            0x80000537, // lui a0, 0x80000
+            0x02a00593, // li a1, 42
+            0x00b52023, // sw a1, 0(a0)
+            0x0220000f, // fence r, r
+            0x0110000f, // fence w, w
+        ]))
+        .unwrap();
+
+        let mut hart = Hart::new(&bus, &reservation);
+        for _ in 0..4 {
+            assert!(matches!(hart.step(), Conclusion::None));
+        }
+        assert_eq!(
+            device.mem().load_word(0).unwrap(),
+            0,
+            "Only fences ordering writes write back"
+        );
+
+        assert!(matches!(hart.step(), Conclusion::None));
+        assert_eq!(device.mem().load_word(0).unwrap(), 42);
+    }
+
+    #[test]
+    fn branches() {
+        let bus = Bus::builder().with_main_memory(1).build();
+        let reservation = AtomicU32::new(u32::MAX);
+        bus.set_mm(&program(&[
+            0x00100513, // li a0, 1
+            0x00050463, // beq a0, zero, 8
+            0x00051463, // bne a0, zero, 8
+            0x00000013, // nop
+            0x00a50463, // beq a0, a0, 8
+            0x00000013, // nop
+            0x00001463, // bne zero, zero, 8
+        ]))
+        .unwrap();
+
+        let mut hart = Hart::new(&bus, &reservation);
+        assert!(matches!(hart.step(), Conclusion::None));
+
+        assert!(matches!(hart.step(), Conclusion::None));
+        assert_eq!(hart.pc, 8, "beq should not be taken");
+        assert!(matches!(hart.step(), Conclusion::Jumped));
+        assert_eq!(hart.pc, 16, "bne should be taken");
+        assert!(matches!(hart.step(), Conclusion::Jumped));
+        assert_eq!(hart.pc, 24, "beq should be taken");
+        assert!(matches!(hart.step(), Conclusion::None));
+        assert_eq!(hart.pc, 28, "bne should not be taken");
+    }
+
+    #[test]
+    fn lr_sc_retry() {
+        let bus = Bus::builder().with_main_memory(2).build();
+        let reservations = [AtomicU32::new(u32::MAX), AtomicU32::new(u32::MAX)];
+        reservations
+            .iter()
+            .for_each(|r| bus.register_reservation_set(r));
+        bus.set_mm(&program(&[
+            0x00001537, // lui a0, 1
+            0x100525af, // retry: lr.w a1, (a0)
+            0x00158593, // addi a1, a1, 1
+            0x18b5262f, // sc.w a2, a1, (a0)
+            0xfe061ae3, // bnez a2, retry
+        ]))
+        .unwrap();
+
+        let mut a = Hart::new(&bus, &reservations[0]);
+        let mut b = Hart::new(&bus, &reservations[1]);
+        for _ in 0..2 {
+            assert!(matches!(a.step(), Conclusion::None));
+        }
+        // b increments the word between a's lr.w and sc.w
+        for _ in 0..5 {
+            assert!(matches!(b.step(), Conclusion::None));
+        }
+        assert_eq!(b.reg[Reg::A2], 0, "Uncontended sc.w failed");
+
+        for _ in 0..2 {
+            assert!(matches!(a.step(), Conclusion::None));
+        }
+        assert_eq!(
+            a.reg[Reg::A2],
+            1,
+            "sc.w succeeded after a conflicting store"
+        );
+        assert!(matches!(a.step(), Conclusion::Jumped));
+        assert_eq!(a.pc, 4, "The loop did not retry");
+
+        for _ in 0..4 {
+            assert!(matches!(a.step(), Conclusion::None));
+        }
+        assert_eq!(a.reg[Reg::A2], 0, "Retried sc.w failed");
+        assert_eq!(a.pc, 20);
+        assert_eq!(read_word(&bus, 0x1000), 2, "An increment was lost");
+    }
+
+    #[test]
+    fn amoadd_w() {
+        let bus = Bus::builder().with_main_memory(2).build();
+        let reservation = AtomicU32::new(u32::MAX);
+        bus.set_mm(&program(&[
+            0x00001537, // lui a0, 1
+            0x00500593, // li a1, 5
+            0x00b52023, // sw a1, 0(a0)
+            0x02500613, // li a2, 37
+            0x00c526af, // amoadd.w a3, a2, (a0)
+            0x00052703, // lw a4, 0(a0)
+        ]))
+        .unwrap();
+
+        let mut hart = Hart::new(&bus, &reservation);
+        for _ in 0..6 {
+            assert!(matches!(hart.step(), Conclusion::None));
+        }
+
+        assert_eq!(
+            hart.reg[Reg::A3],
+            5,
+            "amoadd.w did not return the old value"
+        );
+        assert_eq!(
+            read_word(&bus, 0x1000),
+            42,
+            "amoadd.w did not update memory"
+        );
+        assert_eq!(hart.reg[Reg::A4], 42, "Cached copy went stale");
+    }
+
+    #[test]
+    fn halfword_aligned_jumps() {
+        let bus = Bus::builder().with_main_memory(1).build();
+        let reservation = AtomicU32::new(u32::MAX);
+        bus.set_mm(&program(&[
+            0x10300513, // li a0, 0x103
+            0x000500e7, // jalr ra, 0(a0)
+        ]))
+        .unwrap();
+
+        let mut hart = Hart::new(&bus, &reservation);
+        assert!(matches!(hart.step(), Conclusion::None));
+        assert!(
+            matches!(hart.step(), Conclusion::Jumped),
+            "Halfword-aligned targets should not trap with compressed instructions"
+        );
+        assert_eq!(hart.pc, 0x102);
+        assert_eq!(hart.reg[Reg::RA], 8);
+    }
+
+    #[test]
+    fn compressed_instructions() {
+        let bus = Bus::builder().with_main_memory(1).build();
+        let reservation = AtomicU32::new(u32::MAX);
+        #[rustfmt::skip]
+        let parcels: [u16; 8] = [
+            0x4515,         // c.li a0, 5
+            0x0513, 0x0015, // addi a0, a0, 1
+            0x45b9,         // c.li a1, 14
+            0x9582,         // c.jalr a1
+            0x9002,         // c.ebreak
+            0x9002,         // c.ebreak
+            0x0505,         // c.addi a0, 1
+        ];
+        let code: Vec<u8> = parcels.iter().flat_map(|p| p.to_le_bytes()).collect();
+        bus.set_mm(&code).unwrap();
+
+        let mut hart = Hart::new(&bus, &reservation);
+        assert!(matches!(hart.step(), Conclusion::None));
+        assert_eq!(hart.pc, 2, "Compressed instructions should be 2 bytes long");
+        assert!(matches!(hart.step(), Conclusion::None));
+        assert_eq!(hart.pc, 6, "A 32-bit instruction may be halfword-aligned");
+        assert!(matches!(hart.step(), Conclusion::None));
+        assert!(matches!(hart.step(), Conclusion::Jumped));
+        assert_eq!(
+            hart.reg[Reg::RA],
+            10,
+            "c.jalr should link the address of the next parcel"
+        );
+        assert!(matches!(hart.step(), Conclusion::None));
+        assert_eq!(hart.pc, 16);
+        assert_eq!(hart.reg[Reg::A0], 7);
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "rv32f"), ignore = "needs the F extension")]
+    fn fadd_s() {
+        let bus = Bus::builder().with_main_memory(1).build();
+        let reservation = AtomicU32::new(u32::MAX);
+        bus.set_mm(&program(&[
+            0x3dccd537, // lui a0, 0x3dccd
+            0xccd50513, // addi a0, a0, -819
+            0x3e4cd5b7, // lui a1, 0x3e4cd
+            0xccd58593, // addi a1, a1, -819
+            0xf0050553, // fmv.w.x fa0, a0
+            0xf00585d3, // fmv.w.x fa1, a1
+            0x00b57653, // fadd.s fa2, fa0, fa1
+            0xe0060653, // fmv.x.w a2, fa2
+            0x001026f3, // csrr a3, fflags
+            0x04c02027, // fsw fa2, 0x40(zero)
+            0x04002687, // flw fa3, 0x40(zero)
+            0xa0d62753, // feq.s a4, fa2, fa3
+        ]))
+        .unwrap();
+
+        let mut hart = Hart::new(&bus, &reservation);
+        for _ in 0..12 {
+            assert!(matches!(hart.step(), Conclusion::None));
+        }
+
+        // 0.1 + 0.2 in single precision lies a quarter ulp below 0x3e99999a
+        assert_eq!(hart.reg[Reg::A2], 0x3e99999a);
+        assert_eq!(hart.reg[Reg::A3], 1, "The inexact flag should be set");
+        assert_eq!(hart.reg[Reg::A4], 1, "fsw and flw should round-trip");
+    }
+
+    #[test]
+    fn sub_word_loads() {
+        let bus = Bus::builder().with_main_memory(1).build();
+        let reservation = AtomicU32::new(u32::MAX);
+        bus.set_mm(&program(&[
+            0x04400583, // lb a1, 0x44(zero)
+            0x04404603, // lbu a2, 0x44(zero)
+            0x04001683, // lh a3, 0x40(zero)
+            0x04005703, // lhu a4, 0x40(zero)
+        ]))
+        .unwrap();
+        bus.store_half_word(0x40, 0xff80).unwrap();
+        bus.store_byte(0x44, 0x80).unwrap();
+
+        let mut hart = Hart::new(&bus, &reservation);
+        for _ in 0..4 {
+            assert!(matches!(hart.step(), Conclusion::None));
+        }
+
+        assert_eq!(hart.reg[Reg::A1], 0xffffff80);
+        assert_eq!(hart.reg[Reg::A2], 0x80);
+        assert_eq!(hart.reg[Reg::A3], 0xffffff80);
+        assert_eq!(hart.reg[Reg::A4], 0xff80);
+    }
+
+    #[test]
+    fn memory_faults() {
+        let bus = Bus::builder().with_main_memory(1).build();
+        let reservation = AtomicU32::new(u32::MAX);
+        bus.set_mm(&program(&[
+            0x00010537, // lui a0, 0x10
+            0x00052583, // lw a1, 0(a0)
+            0x00200513, // li a0, 2
+            0x00b52023, // sw a1, 0(a0)
+        ]))
+        .unwrap();
+
+        let mut hart = Hart::new(&bus, &reservation);
+        assert!(matches!(hart.step(), Conclusion::None));
+        assert!(matches!(
+            hart.step(),
+            Conclusion::Exception(ExceptionKind::LoadAccessFault { addr: 0x10000 })
+        ));
+        assert_eq!(hart.read_csr(0x341), 4);
+
+        hart.pc = 8;
+        assert!(matches!(hart.step(), Conclusion::None));
+        assert!(matches!(
+            hart.step(),
+            Conclusion::Exception(ExceptionKind::StoreMisaligned { addr: 2 })
+        ));
+
+        hart.pc = 0x1000;
+        assert!(matches!(
+            hart.step(),
+            Conclusion::Exception(ExceptionKind::InstructionAccessFault { addr: 0x1000 })
+        ));
+    }
+
+    #[test]
+    fn fence_i() {
+        // instructions are little-endian whatever the byte order of data
+        let patch = Assembler::new().addi(Reg::A0, Reg::ZERO, 2).assemble()[0];
+        let patch = u32::from_memory(patch.to_le());
+
+        // overwrites the instruction at 16, which is already in the
+        // instruction cache
+        let mut asm = Assembler::new();
+        asm.lui(Reg::T0, patch.wrapping_add(0x800) >> 12)
+            .addi(Reg::T0, Reg::T0, (patch << 20) as i32 >> 20)
+            .sw(Reg::T0, Reg::ZERO, 16)
+            .instruction(Instruction::Fencei {
+                rd: Reg::ZERO,
+                rs1: Reg::ZERO,
+                imm: 0.into(),
+            })
+            .addi(Reg::A0, Reg::ZERO, 1)
+            .ebreak();
+        assert_eq!(
+            asm.assemble().len(),
+            6,
+            "the patched instruction should be at 16"
+        );
+
+        let bus = Bus::builder().with_main_memory(1).build();
+        let reservation = AtomicU32::new(u32::MAX);
+        bus.set_mm(&asm.assemble_bytes()).unwrap();
+
+        let mut hart = Hart::new(&bus, &reservation);
+        let (steps, _) = hart.step_many(6);
+        assert_eq!(steps, 6);
+        assert_eq!(
+            hart.reg[Reg::A0],
+            2,
+            "the patched instruction should be executed"
+        );
+    }
+
+    #[test]
+    fn coherent_instructions() {
+        let patch = Assembler::new().addi(Reg::A0, Reg::ZERO, 2).assemble()[0];
+        let patch = u32::from_memory(patch.to_le());
+
+        // overwrites the instruction at 12 without a fence.i
+        let mut asm = Assembler::new();
+        asm.lui(Reg::T0, (patch + 0x800) >> 12)
+            .addi(Reg::T0, Reg::T0, (patch << 20) as i32 >> 20)
+            .sw(Reg::T0, Reg::ZERO, 12)
+            .addi(Reg::A0, Reg::ZERO, 1)
+            .ebreak();
+        assert_eq!(
+            asm.assemble().len(),
+            5,
+            "the patched instruction should be at 12"
+        );
+
+        let run = |coherent| {
+            let bus = Bus::builder().with_main_memory(1).build();
+            let reservation = AtomicU32::new(u32::MAX);
+            bus.set_mm(&asm.assemble_bytes()).unwrap();
+
+            let mut hart = Hart::new(&bus, &reservation);
+            hart.set_coherent_instructions(coherent);
+            while !matches!(hart.step(), Conclusion::Exception(_)) {}
+            hart.reg[Reg::A0]
+        };
+
+        assert_eq!(run(false), 1, "the stale instruction should be executed");
+        assert_eq!(run(true), 2);
+    }
+
+    #[test]
+    fn wild_jumps() {
+        let mut asm = Assembler::new();
+        asm.lui(Reg::T0, 0x90000).jalr(Reg::ZERO, Reg::T0, 0);
+
+        let bus = Bus::builder().with_main_memory(1).build();
+        let reservation = AtomicU32::new(u32::MAX);
+        bus.set_mm(&asm.assemble_bytes()).unwrap();
+
+        let mut hart = Hart::new(&bus, &reservation);
+        hart.write_csr(0x305, 0x800); // mtvec
+        assert!(matches!(hart.step(), Conclusion::None));
+        assert!(matches!(hart.step(), Conclusion::Jumped));
+
+        // nothing is mapped at 0x90000000, so the fetch traps
+        assert!(matches!(
+            hart.step(),
+            Conclusion::Exception(ExceptionKind::InstructionAccessFault { addr: 0x90000000 })
+        ));
+        assert_eq!(hart.read_csr(0x341), 0x90000000, "mepc");
+        assert_eq!(hart.read_csr(0x342), 1, "mcause");
+        assert_eq!(hart.read_csr(0x343), 0x90000000, "mtval");
+        assert_eq!(hart.pc, 0x800);
+    }
+
+    #[test]
+    fn illegal_instruction() {
+        let bus = Bus::builder().with_main_memory(1).build();
+        let reservation = AtomicU32::new(u32::MAX);
+        bus.set_mm(&program(&[0xffffffff])).unwrap();
+
+        let mut hart = Hart::new(&bus, &reservation);
+        assert!(matches!(
+            hart.step(),
+            Conclusion::Exception(ExceptionKind::IllegalInstruction { raw: 0xffffffff })
+        ));
+        assert_eq!(hart.read_csr(0x343), 0xffffffff);
+    }
+
+    #[test]
+    fn ebreak_and_ecall() {
+        let bus = Bus::builder().with_main_memory(1).build();
+        let reservation = AtomicU32::new(u32::MAX);
+        bus.set_mm(&program(&[
+            0x00000013, // nop
+            0x00100073, // ebreak
+            0x00000073, // ecall
+        ]))
+        .unwrap();
+
+        let mut hart = Hart::new(&bus, &reservation);
+        assert!(matches!(hart.step(), Conclusion::None));
+        match hart.step() {
+            Conclusion::Exception(e) => {
+                assert_eq!(e, ExceptionKind::Breakpoint { addr: 4 });
+                assert_eq!(e.cause(), 3);
             }
-            And { rd, rs1, rs2 } => {
-                self.reg[rd] = self.reg[rs1].bitand(self.reg[rs2]);
-                Conclusion::None
+            c => panic!("Expected a breakpoint, got {c:?}"),
+        }
+
+        hart.pc = 8;
+        match hart.step() {
+            Conclusion::Exception(e) => {
+                assert_eq!(e, ExceptionKind::EcallFromM);
+                assert_eq!(e.cause(), 11);
             }
+            c => panic!("Expected an environment call, got {c:?}"),
+        }
+    }
+
+    #[test]
+    fn privilege_modes() {
+        let bus = Bus::builder().with_main_memory(1).build();
+        let reservation = AtomicU32::new(u32::MAX);
+        bus.set_mm(&program(&[
+            0x00000073, // ecall
+            0x34002373, // csrr t1, mscratch
+            0x30200073, // mret
+            0x5c002373, // csrr t1, 0x5c0
+        ]))
+        .unwrap();
+
+        let mut hart = Hart::new(&bus, &reservation);
+        // modes below machine mode need a PMP entry to access anything
+        hart.write_csr(0x3b0, u32::MAX); // pmpaddr0, all of memory
+        hart.write_csr(0x3a0, 0x1f); // pmpcfg0, NAPOT and RWX
+        assert_eq!(hart.privilege(), PrivilegeMode::Machine);
+        assert!(matches!(
+            hart.step(),
+            Conclusion::Exception(ExceptionKind::EcallFromM)
+        ));
+
+        hart.set_privilege(PrivilegeMode::User);
+        hart.pc = 0;
+        assert!(matches!(
+            hart.step(),
+            Conclusion::Exception(ExceptionKind::EcallFromU)
+        ));
+        assert_eq!(hart.read_csr(0x342), 8);
+        assert_eq!(hart.privilege(), PrivilegeMode::Machine);
+        assert_eq!(hart.read_csr(0x300) >> 11 & 3, 0); // mstatus.MPP
+
+        hart.set_privilege(PrivilegeMode::Supervisor);
+        hart.pc = 0;
+        assert!(matches!(
+            hart.step(),
+            Conclusion::Exception(ExceptionKind::EcallFromS)
+        ));
+
+        // machine-mode CSRs and mret are off limits below machine mode, and
+        // their encoding is written to mtval
+        for (pc, raw) in [(4, 0x34002373), (8, 0x30200073)] {
+            hart.set_privilege(PrivilegeMode::User);
+            hart.pc = pc;
+            assert!(matches!(
+                hart.step(),
+                Conclusion::Exception(ExceptionKind::IllegalInstruction { raw: r }) if r == raw
+            ));
+            assert_eq!(hart.read_csr(0x343), raw);
+        }
+
+        // mret returns to the mode saved in mstatus.MPP
+        hart.write_csr(0x341, 0x40); // mepc
+        hart.pc = 8;
+        assert!(matches!(hart.step(), Conclusion::Jumped | Conclusion::None));
+        assert_eq!((hart.pc, hart.privilege()), (0x40, PrivilegeMode::User));
+
+        // CSRs that are not implemented are off limits in every mode
+        hart.set_privilege(PrivilegeMode::Machine);
+        hart.pc = 12;
+        assert!(matches!(
+            hart.step(),
+            Conclusion::Exception(ExceptionKind::IllegalInstruction { raw: 0x5c002373 })
+        ));
+    }
+
+    #[test]
+    fn missing_extensions_report_the_encoding() {
+        let bus = Bus::builder().with_main_memory(1).build();
+        let reservation = AtomicU32::new(u32::MAX);
+        bus.set_mm(&program(&[
+            0x02b50533, // mul a0, a0, a1
+        ]))
+        .unwrap();
+
+        let mut hart = Hart::new(&bus, &reservation);
+        assert!(matches!(
+            hart.step(),
+            Conclusion::Exception(ExceptionKind::IllegalInstruction { raw: 0x02b50533 })
+        ));
+        assert_eq!(hart.read_csr(0x343), 0x02b50533);
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "rv32f"), ignore = "needs the F extension")]
+    fn reserved_rounding_mode() {
+        let bus = Bus::builder().with_main_memory(1).build();
+        let reservation = AtomicU32::new(u32::MAX);
+        bus.set_mm(&program(&[
+            0x00b55653, // fadd.s fa2, fa0, fa1 with rm = 5
+        ]))
+        .unwrap();
+
+        let mut hart = Hart::new(&bus, &reservation);
+        assert!(matches!(
+            hart.step(),
+            Conclusion::Exception(ExceptionKind::IllegalInstruction { raw: 0x00b55653 })
+        ));
+    }
+
+    #[test]
+    fn delegated_page_fault() {
+        let bus = Bus::builder().with_main_memory(4).build();
+        let reservation = AtomicU32::new(u32::MAX);
+        let mut code = program(&[
+            0x00052283, // lw t0, 0(a0)
+            0x00000013, // nop
+        ]);
+        code.resize(0x100, 0);
+        code.extend(program(&[
+            // handler: skip the faulting instruction
+            0x14102373, // csrr t1, sepc
+            0x00430313, // addi t1, t1, 4
+            0x14131073, // csrw sepc, t1
+            0x10200073, // sret
+        ]));
+        bus.set_mm(&code).unwrap();
+
+        // the first page is mapped to itself, 0x3000 is not mapped
+        bus.store_word(0x1000, (2 << 10) | 1).unwrap();
+        bus.store_word(0x2000, 0xcf).unwrap();
+
+        let mut hart = Hart::new(&bus, &reservation);
+        hart.write_csr(0x3b0, u32::MAX); // pmpaddr0, all of memory
+        hart.write_csr(0x3a0, 0x1f); // pmpcfg0, NAPOT and RWX
+        hart.write_csr(0x302, 1 << 13); // medeleg, load page faults
+        hart.write_csr(0x105, 0x100); // stvec
+        hart.set_satp(0x80000001);
+        hart.set_privilege(PrivilegeMode::Supervisor);
+        hart.reg[Reg::A0] = 0x3000;
 
-            #[rustfmt::skip]
-            Fence { rd, rs1, pred, succ, mode } => todo!(),
-            Ecall => {
-                println!("Executed ebreak which is unimplemented!");
-                Conclusion::Exception(2)
+        assert!(matches!(
+            hart.step(),
+            Conclusion::Exception(ExceptionKind::LoadPageFault { addr: 0x3000 })
+        ));
+        assert_eq!(hart.pc, 0x100);
+        assert_eq!(hart.privilege(), PrivilegeMode::Supervisor);
+        assert_eq!(hart.read_csr(0x142), 13); // scause
+        assert_eq!(hart.read_csr(0x143), 0x3000); // stval
+        assert_eq!(hart.read_csr(0x342), 0, "The trap should skip machine mode");
+        assert_eq!(hart.read_csr(0x100) & 1 << 8, 1 << 8, "sstatus.SPP");
+
+        for _ in 0..4 {
+            assert!(!matches!(hart.step(), Conclusion::Exception(_)));
+        }
+        assert_eq!(hart.pc, 4);
+        assert_eq!(hart.privilege(), PrivilegeMode::Supervisor);
+
+        // without delegation, the same fault goes to machine mode
+        hart.write_csr(0x302, 0);
+        hart.pc = 0;
+        hart.step();
+        assert_eq!(hart.privilege(), PrivilegeMode::Machine);
+        assert_eq!(hart.read_csr(0x342), 13);
+    }
+
+    #[test]
+    fn delegated_ecall() {
+        let bus = Bus::builder().with_main_memory(1).build();
+        let reservation = AtomicU32::new(u32::MAX);
+        bus.set_mm(&program(&[0x00000073])).unwrap(); // ecall
+
+        let mut hart = Hart::new(&bus, &reservation);
+        hart.write_csr(0x3b0, u32::MAX); // pmpaddr0, all of memory
+        hart.write_csr(0x3a0, 0x1f); // pmpcfg0, NAPOT and RWX
+        hart.write_csr(0x302, 1 << 8); // medeleg, ecall from U
+        hart.write_csr(0x105, 0x100); // stvec
+        hart.write_csr(0x305, 0x200); // mtvec
+
+        hart.set_privilege(PrivilegeMode::User);
+        assert!(matches!(
+            hart.step(),
+            Conclusion::Exception(ExceptionKind::EcallFromU)
+        ));
+        assert_eq!(hart.pc, 0x100);
+        assert_eq!(hart.privilege(), PrivilegeMode::Supervisor);
+        assert_eq!((hart.read_csr(0x141), hart.read_csr(0x142)), (0, 8));
+
+        // ecall from S is not delegated
+        hart.pc = 0;
+        hart.step();
+        assert_eq!(hart.pc, 0x200);
+        assert_eq!(hart.privilege(), PrivilegeMode::Machine);
+        assert_eq!(hart.read_csr(0x342), 9);
+    }
+
+    #[test]
+    fn supervisor_csrs() {
+        let bus = Bus::builder().with_main_memory(1).build();
+        let reservation = AtomicU32::new(u32::MAX);
+        let mut hart = Hart::new(&bus, &reservation);
+
+        hart.write_csr(0x100, u32::MAX); // sstatus
+        assert_eq!(hart.read_csr(0x300), 0x800de762, "mstatus");
+        hart.write_csr(0x303, 0x222); // mideleg, the supervisor interrupts
+        hart.write_csr(0x104, u32::MAX); // sie
+        assert_eq!(hart.read_csr(0x304), 0x222, "mie");
+        hart.write_csr(0x144, u32::MAX); // sip
+        assert_eq!(hart.read_csr(0x344), 0x2, "mip");
+        assert_eq!(hart.read_csr(0x144), 0x2, "sip");
+    }
+
+    #[test]
+    fn trap_handler() {
+        let bus = Bus::builder().with_main_memory(1).build();
+        let reservation = AtomicU32::new(u32::MAX);
+        let mut code = program(&[
+            0x10000293, // li t0, 0x100
+            0x30529073, // csrw mtvec, t0
+            0x00000073, // ecall
+            0x00000073, // ecall
+        ]);
+        code.resize(0x100, 0);
+        code.extend(program(&[
+            // handler: count traps and return past the trapping instruction
+            0x00140413, // addi s0, s0, 1
+            0x34102373, // csrr t1, mepc
+            0x00430313, // addi t1, t1, 4
+            0x34131073, // csrw mepc, t1
+            0x30200073, // mret
+        ]));
+        bus.set_mm(&code).unwrap();
+
+        let mut hart = Hart::new(&bus, &reservation);
+        hart.step();
+        hart.step();
+
+        for ecall in [8, 12] {
+            assert!(matches!(
+                hart.step(),
+                Conclusion::Exception(ExceptionKind::EcallFromM)
+            ));
+            assert_eq!(hart.pc, 0x100);
+            assert_eq!(hart.read_csr(0x341), ecall);
+            assert_eq!(hart.read_csr(0x342), 11);
+
+            for _ in 0..5 {
+                assert!(!matches!(hart.step(), Conclusion::Exception(_)));
             }
-            Ebreak => todo!("Implement ebreak"),
-            Fencei { rd, rs1, imm } => todo!("Implement fencei"),
-            CsrRw { rd, rs1, csr } => todo!(),
-            CsrRs { rd, rs1, csr } => todo!(),
-            CsrRc { rd, rs1, csr } => todo!(),
-            CsrRwi { rd, uimm, csr } => todo!(),
-            CsrRsi { rd, uimm, csr } => todo!(),
-            CsrRci { rd, uimm, csr } => todo!(),
-            Mul { rd, rs1, rs2 } => todo!(),
-            Mulh { rd, rs1, rs2 } => todo!(),
-            Mulhsu { rd, rs1, rs2 } => todo!(),
-            Mulhu { rd, rs1, rs2 } => todo!(),
-            Div { rd, rs1, rs2 } => todo!(),
-            Divu { rd, rs1, rs2 } => todo!(),
-            Rem { rd, rs1, rs2 } => todo!(),
-            Remu { rd, rs1, rs2 } => todo!(),
-            Lrw { rd, rs1, aq, rl } => todo!(),
-            #[rustfmt::skip]
-            Scw { rd, rs1, rs2, aq, rl, } => todo!(),
-            #[rustfmt::skip]
-            AmoSwapw { rd, rs1, rs2, aq, rl, } => todo!(),
-            #[rustfmt::skip]
-            AmoAddw { rd, rs1, rs2, aq, rl, } => todo!(),
-            #[rustfmt::skip]
-            AmoXorw { rd, rs1, rs2, aq, rl, } => todo!(),
-            #[rustfmt::skip]
-            AmoAndw { rd, rs1, rs2, aq, rl, } => todo!(),
-            #[rustfmt::skip]
-            AmoOrw { rd, rs1, rs2, aq, rl, } => todo!(),
-            #[rustfmt::skip]
-            AmoMinw { rd, rs1, rs2, aq, rl, } => todo!(),
-            #[rustfmt::skip]
-            AmoMaxw { rd, rs1, rs2, aq, rl, } => todo!(),
-            #[rustfmt::skip]
-            AmoMinuw { rd, rs1, rs2, aq, rl, } => todo!(),
-            #[rustfmt::skip]
-            AmoMaxuw { rd, rs1, rs2, aq, rl, } => todo!(),
-            Invalid { raw } => todo!("Invalid: {raw:b}"),
+            assert_eq!(hart.pc, ecall + 4);
+        }
+
+        assert_eq!(hart.reg[Reg::S0], 2);
+    }
+
+    #[test]
+    fn timer_interrupt() {
+        let bus = Bus::builder().with_main_memory(1).build();
+        let reservation = AtomicU32::new(u32::MAX);
+        bus.set_mm(&program(&[
+            0x00000013, // nop
+            0x00000013, // nop
+        ]))
+        .unwrap();
+
+        let mut hart = Hart::new(&bus, &reservation);
+        hart.write_csr(0x305, 0x200); // mtvec
+        hart.write_csr(0x304, 1 << 7); // mie.MTIE
+        hart.write_csr(0x344, 1 << 7); // mip.MTIP
+
+        assert!(matches!(hart.step(), Conclusion::None));
+        assert_eq!(hart.pc, 4, "Interrupts are disabled by mstatus.MIE");
+
+        hart.write_csr(0x300, 1 << 3); // mstatus.MIE
+        assert!(matches!(hart.step(), Conclusion::Jumped));
+        assert_eq!(hart.pc, 0x200);
+        assert_eq!(hart.read_csr(0x341), 4);
+        assert_eq!(hart.read_csr(0x342), 0x80000007);
+    }
+
+    #[test]
+    fn trace_hook() {
+        let mut asm = Assembler::new();
+        let top = asm.label();
+        asm.li(Reg::A0, 0)
+            .li(Reg::A1, 1)
+            .li(Reg::A2, 10)
+            .bind(top)
+            .add(Reg::A3, Reg::A0, Reg::A1)
+            .addi(Reg::A0, Reg::A1, 0)
+            .addi(Reg::A1, Reg::A3, 0)
+            .addi(Reg::A2, Reg::A2, -1)
+            .bne(Reg::A2, Reg::ZERO, top)
+            .ebreak();
+
+        let bus = Bus::builder().with_main_memory(1).build();
+        let reservation = AtomicU32::new(u32::MAX);
+        bus.set_mm(&asm.assemble_bytes()).unwrap();
+
+        let mut trace = Vec::new();
+        {
+            let mut hart = Hart::new(&bus, &reservation);
+            hart.set_trace_hook(Box::new(|pc, inst| trace.push((pc, *inst))));
+            while !matches!(hart.step(), Conclusion::Exception(_)) {}
+            assert_eq!(hart.reg[Reg::A0], 55);
+        }
+
+        // 3 setup instructions, 10 iterations of 5, and the trapping ebreak
+        assert_eq!(trace.len(), 3 + 10 * 5 + 1);
+        assert_eq!(
+            trace[..4].iter().map(|&(pc, _)| pc).collect::<Vec<_>>(),
+            [0, 4, 8, 12]
+        );
+        assert_eq!(trace[8].0, 12, "the loop should be taken");
+        assert_eq!(trace.last(), Some(&(32, Instruction::Ebreak)));
+    }
+
+    #[test]
+    fn access_log() {
+        let mut asm = Assembler::new();
+        asm.lui(Reg::T0, 0x80000)
+            .li(Reg::T1, 42)
+            .sw(Reg::T1, Reg::T0, 4)
+            .sb(Reg::T1, Reg::T0, 9)
+            .lw(Reg::T2, Reg::T0, 4)
+            .ebreak();
+
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let device = TestDevice::with_attributes(0x80000, 1, Pma::io());
+        let bus = Bus::builder()
+            .with_main_memory(1)
+            .with_mapping(&device)
+            .build();
+        let reservation = AtomicU32::new(u32::MAX);
+        bus.set_mm(&asm.assemble_bytes()).unwrap();
+        let logger = Arc::clone(&log);
+        bus.set_access_logger(Some(Box::new(move |event| {
+            logger.lock().unwrap().push(event)
+        })));
+
+        let mut hart = Hart::new(&bus, &reservation);
+        while !matches!(hart.step(), Conclusion::Exception(_)) {}
+        assert_eq!(hart.reg[Reg::T2], 42);
+
+        let event = |addr, width, value, kind| AccessEvent {
+            addr,
+            width,
+            value,
+            kind,
+            memory: MemoryKind::Io,
+        };
+        let events = log.lock().unwrap().clone();
+        assert_eq!(
+            events
+                .iter()
+                .filter(|e| e.memory == MemoryKind::Io)
+                .copied()
+                .collect::<Vec<_>>(),
+            [
+                event(0x80000004, 4, 42, AccessKind::Write),
+                event(0x80000009, 1, 42, AccessKind::Write),
+                event(0x80000004, 4, 42, AccessKind::Read),
+            ]
+        );
+
+        // the instructions are fetched from main memory a line at a time
+        assert!(events
+            .iter()
+            .any(|e| e.memory == MemoryKind::Main && e.kind == AccessKind::Read && e.addr == 0));
+
+        // nothing is logged once the logger is removed
+        bus.set_access_logger(None);
+        bus.load_word(0x80000004).unwrap();
+        assert_eq!(log.lock().unwrap().len(), events.len());
+    }
+
+    #[test]
+    fn compare_step() {
+        let mut asm = Assembler::new();
+        asm.li(Reg::A0, 5)
+            .addi(Reg::A1, Reg::A0, 2)
+            .add(Reg::A2, Reg::A0, Reg::A1);
+
+        let bus = Bus::builder().with_main_memory(1).build();
+        let reservation = AtomicU32::new(u32::MAX);
+        bus.set_mm(&asm.assemble_bytes()).unwrap();
+        let mut hart = Hart::new(&bus, &reservation);
+
+        let mut expected = RegisterSnapshot {
+            pc: 4,
+            reg: [0; 32],
+        };
+        expected.reg[Reg::A0 as usize] = 5;
+        assert_eq!(hart.compare_step(&expected), Ok(()));
+
+        // the reference computed 7 instead
+        expected.pc = 8;
+        expected.reg[Reg::A1 as usize] = 8;
+        assert_eq!(
+            hart.compare_step(&expected),
+            Err(Divergence::Reg {
+                reg: Reg::A1,
+                expected: 8,
+                actual: 7
+            })
+        );
+
+        expected.pc = 16;
+        assert_eq!(
+            hart.compare_step(&expected),
+            Err(Divergence::Pc {
+                expected: 16,
+                actual: 12
+            })
+        );
+    }
+
+    #[test]
+    fn snapshot_and_restore() {
+        // keeps a running sum of the fibonacci numbers in memory at 0x100
+        let mut asm = Assembler::new();
+        let top = asm.label();
+        asm.li(Reg::A0, 0)
+            .li(Reg::A1, 1)
+            .li(Reg::A2, 10)
+            .bind(top)
+            .add(Reg::A3, Reg::A0, Reg::A1)
+            .addi(Reg::A0, Reg::A1, 0)
+            .addi(Reg::A1, Reg::A3, 0)
+            .lw(Reg::A4, Reg::ZERO, 0x100)
+            .add(Reg::A4, Reg::A4, Reg::A3)
+            .sw(Reg::A4, Reg::ZERO, 0x100)
+            .addi(Reg::A2, Reg::A2, -1)
+            .bne(Reg::A2, Reg::ZERO, top)
+            .ebreak();
+
+        let bus = Bus::builder().with_main_memory(1).build();
+        let reservation = AtomicU32::new(u32::MAX);
+        bus.set_mm(&asm.assemble_bytes()).unwrap();
+        let mut hart = Hart::new(&bus, &reservation);
+
+        let run = |hart: &mut Hart| {
+            while !matches!(hart.step(), Conclusion::Exception(_)) {}
+            let mut sum = [0; 4];
+            hart.read_memory(0x100, &mut sum).unwrap();
+            (
+                hart.pc,
+                [Reg::A0, Reg::A1, Reg::A2, Reg::A3, Reg::A4].map(|r| hart.reg[r]),
+                u32::from_le_bytes(sum),
+            )
         };
 
-        if let Conclusion::None = conclusion {
-            self.pc = self.pc.wrapping_add(4);
+        for _ in 0..40 {
+            hart.step();
         }
+        let state = hart.snapshot().unwrap();
+        let memory = bus.main_memory().snapshot();
+
+        let first = run(&mut hart);
+        assert_eq!(first.1[0], 55);
+
+        bus.main_memory().restore(&memory);
+        hart.restore(&state);
+        assert_eq!(run(&mut hart), first);
+    }
+
+    #[test]
+    fn step_many() {
+        let mut asm = Assembler::new();
+        asm.nop().nop().nop().ebreak().nop();
+
+        let bus = Bus::builder().with_main_memory(1).build();
+        let reservation = AtomicU32::new(u32::MAX);
+        bus.set_mm(&asm.assemble_bytes()).unwrap();
+        let mut hart = Hart::new(&bus, &reservation);
+        hart.write_csr(0x305, 0x100); // mtvec
+
+        assert!(matches!(hart.step_many(2), (2, Conclusion::None)));
+        assert_eq!(hart.pc, 8);
+        assert!(matches!(
+            hart.step_many(10),
+            (
+                2,
+                Conclusion::Exception(ExceptionKind::Breakpoint { addr: 12 })
+            )
+        ));
+        assert_eq!(hart.pc, 0x100);
+    }
+
+    #[test]
+    fn counters() {
+        let mut asm = Assembler::new();
+        asm.csrrs(Reg::A0, Csr::MInstRet, Reg::ZERO)
+            .nop()
+            .nop()
+            .nop()
+            .csrrs(Reg::A1, Csr::InstRet, Reg::ZERO)
+            .csrrs(Reg::A2, Csr::Cycle, Reg::ZERO)
+            .instruction(Instruction::Invalid { raw: 0 })
+            .nop();
+
+        let bus = Bus::builder().with_main_memory(1).build();
+        let reservation = AtomicU32::new(u32::MAX);
+        bus.set_mm(&asm.assemble_bytes()).unwrap();
+        let mut hart = Hart::new(&bus, &reservation);
+        hart.write_csr(0x305, 0x1c); // mtvec, the last nop
+
+        hart.write_csr(0xb02, u32::MAX); // minstret
+        assert!(matches!(hart.step_many(7), (7, Conclusion::Exception(_))));
+        assert_eq!(hart.reg[Reg::A0], u32::MAX);
+        assert_eq!(hart.reg[Reg::A1], 3, "minstret should carry into minstreth");
+        assert_eq!(hart.read_csr(0xb82), 1);
+        assert_eq!(hart.reg[Reg::A2], 6);
+
+        // the illegal instruction takes a cycle but does not retire
+        assert_eq!(hart.read_csr(0xb02), 5);
+        assert_eq!(hart.read_csr(0xb00), 7);
+    }
+
+    #[test]
+    fn unprivileged_counters() {
+        let mut asm = Assembler::new();
+        asm.csrrs(Reg::A0, Csr::Cycle, Reg::ZERO)
+            .nop()
+            .nop()
+            .csrrs(Reg::A1, Csr::Cycle, Reg::ZERO)
+            .csrrs(Reg::A2, Csr::Time, Reg::ZERO)
+            .csrrs(Reg::A3, Csr::Timeh, Reg::ZERO)
+            .csrrw(Reg::ZERO, Csr::Cycle, Reg::ZERO);
+
+        let bus = Bus::builder().with_main_memory(1).build();
+        let reservation = AtomicU32::new(u32::MAX);
+        let mtime = AtomicU64::new(0x1_0000_0005);
+        bus.set_mm(&asm.assemble_bytes()).unwrap();
+        let mut hart = Hart::new(&bus, &reservation);
+        hart.set_mtime(&mtime);
+
+        assert!(matches!(hart.step_many(6), (6, Conclusion::None)));
+        assert!(hart.reg[Reg::A1] > hart.reg[Reg::A0]);
+        assert_eq!(hart.reg[Reg::A2], 5, "time should read mtime");
+        assert_eq!(hart.reg[Reg::A3], 1);
 
-        conclusion
+        assert!(
+            matches!(
+                hart.step(),
+                Conclusion::Exception(ExceptionKind::IllegalInstruction { .. })
+            ),
+            "Writes to read-only counters should be illegal"
+        );
     }
 }
@@ -0,0 +1,695 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// This Source Code Form is "Incompatible With Secondary Licenses", as
+// defined by the Mozilla Public License, v. 2.0.
+//
+// Copyright © 2022 mumblingdrunkard
+
+//! The semantics of every instruction, one handler per kind.
+//!
+//! `step` either matches on the decoded instruction to find its handler, or,
+//! with the `threaded` feature, calls the handler cached alongside it in the
+//! instruction cache.
+
+use std::ops::{BitAnd, BitOr, BitXor};
+
+use crate::hart::{
+    fpu,
+    instruction::{
+        Conclusion, ExceptionKind, FenceMode,
+        Instruction::{self, *},
+        InstructionKind,
+    },
+    mmu::{aqrl_ordering, Access},
+    Hart, PrivilegeMode, Reg,
+};
+
+use super::exception;
+
+/// Executes an instruction of the kind it was looked up for.
+///
+/// The pc is left for `step` to advance, unless the instruction jumps.
+pub(crate) type Handler = fn(&mut Hart, Instruction) -> Conclusion;
+
+/// Defines a handler for each instruction kind, along with [`handler`] to
+/// look them up and `Hart::execute` to dispatch on a decoded instruction.
+///
+/// Each handler binds the hart to the given name and destructures the
+/// instruction with the given pattern.
+macro_rules! instructions {
+    ($($($kind:ident)|+ => fn $name:ident($h:ident, $pat:pat) $body:block)*) => {
+        $(
+            #[inline(always)]
+            #[allow(unused_variables, irrefutable_let_patterns)]
+            fn $name($h: &mut Hart, inst: Instruction) -> Conclusion {
+                let $pat = inst else {
+                    unreachable!("{:?} dispatched to {}", inst, stringify!($name))
+                };
+                $body
+            }
+        )*
+
+        /// The handler for instructions of `kind`
+        pub(crate) fn handler(kind: InstructionKind) -> Handler {
+            match kind {
+                $($(InstructionKind::$kind)|+ => $name,)*
+            }
+        }
+
+        impl Hart<'_> {
+            /// Executes `inst` by matching on it
+            #[inline(always)]
+            pub(super) fn execute(&mut self, inst: Instruction) -> Conclusion {
+                match inst {
+                    $($(Instruction::$kind { .. })|+ => $name(self, inst),)*
+                }
+            }
+        }
+    };
+}
+
+instructions! {
+    Lui => fn lui(h, Lui { rd, imm }) {
+        h.reg[rd] = i32::from(imm) as u32;
+        Conclusion::None
+    }
+
+    Auipc => fn auipc(h, Auipc { rd, imm }) {
+        h.reg[rd] = h.pc.wrapping_add_signed(imm.into());
+        Conclusion::None
+    }
+
+    // with compressed instructions, every even target is aligned, so jumps
+    // and branches can no longer be misaligned
+    Jal => fn jal(h, Jal { rd, imm }) {
+        h.reg[rd] = h.next_pc;
+        h.pc = h.pc.wrapping_add_signed(imm.into());
+        Conclusion::Jumped
+    }
+
+    Jalr => fn jalr(h, Jalr { rd, rs1, imm }) {
+        let target = h.reg[rs1].wrapping_add_signed(imm.into()) & 0xfffffffe;
+        h.reg[rd] = h.next_pc;
+        h.pc = target;
+        Conclusion::Jumped
+    }
+
+    Beq => fn beq(h, Beq { rs1, rs2, imm }) {
+        if h.reg[rs1] != h.reg[rs2] {
+            Conclusion::None
+        } else {
+            h.pc = h.pc.wrapping_add_signed(imm.into());
+            Conclusion::Jumped
+        }
+    }
+
+    Bne => fn bne(h, Bne { rs1, rs2, imm }) {
+        if h.reg[rs1] == h.reg[rs2] {
+            Conclusion::None
+        } else {
+            h.pc = h.pc.wrapping_add_signed(imm.into());
+            Conclusion::Jumped
+        }
+    }
+
+    Blt => fn blt(h, Blt { rs1, rs2, imm }) {
+        if (h.reg[rs1] as i32) >= (h.reg[rs2] as i32) {
+            Conclusion::None
+        } else {
+            h.pc = h.pc.wrapping_add_signed(imm.into());
+            Conclusion::Jumped
+        }
+    }
+
+    Bge => fn bge(h, Bge { rs1, rs2, imm }) {
+        if (h.reg[rs1] as i32) < (h.reg[rs2] as i32) {
+            Conclusion::None
+        } else {
+            h.pc = h.pc.wrapping_add_signed(imm.into());
+            Conclusion::Jumped
+        }
+    }
+
+    Bltu => fn bltu(h, Bltu { rs1, rs2, imm }) {
+        if h.reg[rs1] >= h.reg[rs2] {
+            Conclusion::None
+        } else {
+            h.pc = h.pc.wrapping_add_signed(imm.into());
+            Conclusion::Jumped
+        }
+    }
+
+    Bgeu => fn bgeu(h, Bgeu { rs1, rs2, imm }) {
+        if h.reg[rs1] < h.reg[rs2] {
+            Conclusion::None
+        } else {
+            h.pc = h.pc.wrapping_add_signed(imm.into());
+            Conclusion::Jumped
+        }
+    }
+
+    Lb => fn lb(h, Lb { rd, rs1, imm }) {
+        let addr = h.reg[rs1].wrapping_add_signed(imm.into());
+        match h.mmu.load_byte(addr) {
+            Ok(val) => {
+                h.reg[rd] = val as u8 as i8 as u32;
+                Conclusion::None
+            }
+            Err(e) => Conclusion::Exception(exception(e, Access::Read, addr)),
+        }
+    }
+
+    Lh => fn lh(h, Lh { rd, rs1, imm }) {
+        let addr = h.reg[rs1].wrapping_add_signed(imm.into());
+        match h.mmu.load_half_word(addr) {
+            Ok(val) => {
+                h.reg[rd] = val as u16 as i16 as u32;
+                Conclusion::None
+            }
+            Err(e) => Conclusion::Exception(exception(e, Access::Read, addr)),
+        }
+    }
+
+    Lw => fn lw(h, Lw { rd, rs1, imm }) {
+        let addr = h.reg[rs1].wrapping_add_signed(imm.into());
+        match h.mmu.load_word(addr) {
+            Ok(val) => {
+                h.reg[rd] = val;
+                Conclusion::None
+            }
+            Err(e) => Conclusion::Exception(exception(e, Access::Read, addr)),
+        }
+    }
+
+    Lbu => fn lbu(h, Lbu { rd, rs1, imm }) {
+        let addr = h.reg[rs1].wrapping_add_signed(imm.into());
+        match h.mmu.load_byte(addr) {
+            Ok(val) => {
+                h.reg[rd] = val;
+                Conclusion::None
+            }
+            Err(e) => Conclusion::Exception(exception(e, Access::Read, addr)),
+        }
+    }
+
+    Lhu => fn lhu(h, Lhu { rd, rs1, imm }) {
+        let addr = h.reg[rs1].wrapping_add_signed(imm.into());
+        match h.mmu.load_half_word(addr) {
+            Ok(val) => {
+                h.reg[rd] = val;
+                Conclusion::None
+            }
+            Err(e) => Conclusion::Exception(exception(e, Access::Read, addr)),
+        }
+    }
+
+    Sb => fn sb(h, Sb { rs1, rs2, imm }) {
+        let addr = h.reg[rs1].wrapping_add_signed(imm.into());
+        match h.mmu.store_byte(addr, h.reg[rs2] as u8) {
+            Ok(_) => Conclusion::None,
+            Err(e) => Conclusion::Exception(exception(e, Access::Write, addr)),
+        }
+    }
+
+    Sh => fn sh(h, Sh { rs1, rs2, imm }) {
+        let addr = h.reg[rs1].wrapping_add_signed(imm.into());
+        match h.mmu.store_half_word(addr, h.reg[rs2] as u16) {
+            Ok(_) => Conclusion::None,
+            Err(e) => Conclusion::Exception(exception(e, Access::Write, addr)),
+        }
+    }
+
+    Sw => fn sw(h, Sw { rs1, rs2, imm }) {
+        let addr = h.reg[rs1].wrapping_add_signed(imm.into());
+        match h.mmu.store_word(addr, h.reg[rs2]) {
+            Ok(_) => Conclusion::None,
+            Err(e) => Conclusion::Exception(exception(e, Access::Write, addr)),
+        }
+    }
+
+    Addi => fn addi(h, Addi { rd, rs1, imm }) {
+        h.reg[rd] = h.reg[rs1].wrapping_add_signed(imm.into());
+        Conclusion::None
+    }
+
+    Slti => fn slti(h, Slti { rd, rs1, imm }) {
+        h.reg[rd] = ((h.reg[rs1] as i32) < imm.into()) as u32;
+        Conclusion::None
+    }
+
+    Sltiu => fn sltiu(h, Sltiu { rd, rs1, imm }) {
+        h.reg[rd] = (h.reg[rs1] < i32::from(imm) as u32) as u32;
+        Conclusion::None
+    }
+
+    Xori => fn xori(h, Xori { rd, rs1, imm }) {
+        h.reg[rd] = h.reg[rs1].bitxor(i32::from(imm) as u32);
+        Conclusion::None
+    }
+
+    Ori => fn ori(h, Ori { rd, rs1, imm }) {
+        h.reg[rd] = h.reg[rs1].bitor(i32::from(imm) as u32);
+        Conclusion::None
+    }
+
+    Andi => fn andi(h, Andi { rd, rs1, imm }) {
+        h.reg[rd] = h.reg[rs1].bitand(i32::from(imm) as u32);
+        Conclusion::None
+    }
+
+    Slli => fn slli(h, Slli { rd, rs1, shamt }) {
+        h.reg[rd] = h.reg[rs1] << u32::from(shamt);
+        Conclusion::None
+    }
+
+    Srli => fn srli(h, Srli { rd, rs1, shamt }) {
+        h.reg[rd] = h.reg[rs1] >> u32::from(shamt);
+        Conclusion::None
+    }
+
+    Srai => fn srai(h, Srai { rd, rs1, shamt }) {
+        h.reg[rd] = (h.reg[rs1] as i32 >> u32::from(shamt)) as u32;
+        Conclusion::None
+    }
+
+    Add => fn add(h, Add { rd, rs1, rs2 }) {
+        h.reg[rd] = h.reg[rs1].wrapping_add(h.reg[rs2]);
+        Conclusion::None
+    }
+
+    Sub => fn sub(h, Sub { rd, rs1, rs2 }) {
+        h.reg[rd] = h.reg[rs1].wrapping_sub(h.reg[rs2]);
+        Conclusion::None
+    }
+
+    Sll => fn sll(h, Sll { rd, rs1, rs2 }) {
+        h.reg[rd] = h.reg[rs1].wrapping_shl(h.reg[rs2]);
+        Conclusion::None
+    }
+
+    Slt => fn slt(h, Slt { rd, rs1, rs2 }) {
+        h.reg[rd] = ((h.reg[rs1] as i32) < (h.reg[rs2] as i32)) as u32;
+        Conclusion::None
+    }
+
+    Sltu => fn sltu(h, Sltu { rd, rs1, rs2 }) {
+        h.reg[rd] = (h.reg[rs1] < h.reg[rs2]) as u32;
+        Conclusion::None
+    }
+
+    Xor => fn xor(h, Xor { rd, rs1, rs2 }) {
+        h.reg[rd] = h.reg[rs1].bitxor(h.reg[rs2]);
+        Conclusion::None
+    }
+
+    Srl => fn srl(h, Srl { rd, rs1, rs2 }) {
+        h.reg[rd] = h.reg[rs1].wrapping_shr(h.reg[rs2]);
+        Conclusion::None
+    }
+
+    Sra => fn sra(h, Sra { rd, rs1, rs2 }) {
+        h.reg[rd] = (h.reg[rs1] as i32).wrapping_shr(h.reg[rs2]) as u32;
+        Conclusion::None
+    }
+
+    Or => fn or(h, Or { rd, rs1, rs2 }) {
+        h.reg[rd] = h.reg[rs1].bitor(h.reg[rs2]);
+        Conclusion::None
+    }
+
+    And => fn and(h, And { rd, rs1, rs2 }) {
+        h.reg[rd] = h.reg[rs1].bitand(h.reg[rs2]);
+        Conclusion::None
+    }
+
+    Fence => fn fence(h, Fence { rd, rs1, pred, succ, mode }) {
+        // harts execute in order, so only stores held in the data cache or
+        // the write-combining buffers can be observed out of order, and they
+        // are written back before any later store or device output
+        // values read ahead from devices are discarded by every fence
+        let result = if mode == FenceMode::Tso || succ.write() || succ.output() {
+            h.mmu.write_back_all()
+        } else {
+            h.mmu.invalidate_read_combining();
+            h.mmu.flush_write_combining()
+        };
+
+        match result {
+            Ok(()) => Conclusion::None,
+            // the line that failed is not known here, and mtval may be 0
+            Err(e) => Conclusion::Exception(exception(e, Access::Write, 0)),
+        }
+    }
+
+    Ecall => fn ecall(h, Ecall) {
+        Conclusion::Exception(match h.privilege {
+            PrivilegeMode::User => ExceptionKind::EcallFromU,
+            PrivilegeMode::Supervisor => ExceptionKind::EcallFromS,
+            PrivilegeMode::Machine => ExceptionKind::EcallFromM,
+        })
+    }
+
+    Ebreak => fn ebreak(h, Ebreak) {
+        Conclusion::Exception(ExceptionKind::Breakpoint { addr: h.pc })
+    }
+
+    Fencei => fn fencei(h, Fencei { rd, rs1, imm }) {
+        // later fetches see every store this hart has performed
+        match h.mmu.synchronize_instructions() {
+            Ok(()) => Conclusion::None,
+            // the line that failed is not known here, and mtval may be 0
+            Err(e) => Conclusion::Exception(exception(e, Access::Write, 0)),
+        }
+    }
+
+    Mret => fn mret(h, inst @ Mret) {
+        if h.privilege != PrivilegeMode::Machine {
+            let raw = inst.encode();
+            return Conclusion::Exception(ExceptionKind::IllegalInstruction { raw });
+        }
+
+        h.mret();
+        Conclusion::Jumped
+    }
+
+    Sret => fn sret(h, inst @ Sret) {
+        // mstatus.TSR traps sret in supervisor mode
+        let tsr = h.mstatus().tsr();
+        match h.privilege {
+            PrivilegeMode::Machine => {}
+            PrivilegeMode::Supervisor if !tsr => {}
+            _ => {
+                let raw = inst.encode();
+                return Conclusion::Exception(ExceptionKind::IllegalInstruction { raw });
+            }
+        }
+
+        h.sret();
+        Conclusion::Jumped
+    }
+
+    CsrRw => fn csr_rw(h, inst @ CsrRw { rd, rs1, csr }) {
+        h.csr_op(inst, rd, csr, Some(h.reg[rs1]), |_, src| src)
+    }
+
+    CsrRs => fn csr_rs(h, inst @ CsrRs { rd, rs1, csr }) {
+        let src = (rs1 != Reg::X0).then(|| h.reg[rs1]);
+        h.csr_op(inst, rd, csr, src, |old, src| old | src)
+    }
+
+    CsrRc => fn csr_rc(h, inst @ CsrRc { rd, rs1, csr }) {
+        let src = (rs1 != Reg::X0).then(|| h.reg[rs1]);
+        h.csr_op(inst, rd, csr, src, |old, src| old & !src)
+    }
+
+    CsrRwi => fn csr_rwi(h, inst @ CsrRwi { rd, uimm, csr }) {
+        h.csr_op(inst, rd, csr, Some(uimm.into()), |_, src| src)
+    }
+
+    CsrRsi => fn csr_rsi(h, inst @ CsrRsi { rd, uimm, csr }) {
+        let src = Some(u32::from(uimm)).filter(|&src| src != 0);
+        h.csr_op(inst, rd, csr, src, |old, src| old | src)
+    }
+
+    CsrRci => fn csr_rci(h, inst @ CsrRci { rd, uimm, csr }) {
+        let src = Some(u32::from(uimm)).filter(|&src| src != 0);
+        h.csr_op(inst, rd, csr, src, |old, src| old & !src)
+    }
+
+    // not implemented yet, so these behave as if their extension is missing
+    Mul | Mulh | Mulhsu | Mulhu | Div | Divu | Rem | Remu => fn unsupported(h, inst) {
+        Conclusion::Exception(ExceptionKind::IllegalInstruction { raw: inst.encode() })
+    }
+
+    Lrw => fn lrw(h, Lrw { rd, rs1, aq, rl }) {
+        match h.mmu.load_reserved(h.reg[rs1], aqrl_ordering(aq, rl)) {
+            Ok(val) => {
+                h.reg[rd] = val;
+                Conclusion::None
+            }
+            Err(e) => Conclusion::Exception(exception(e, Access::Read, h.reg[rs1])),
+        }
+    }
+
+    Scw => fn scw(h, Scw { rd, rs1, rs2, aq, rl }) {
+        match h.mmu.store_conditional(h.reg[rs1], h.reg[rs2], aqrl_ordering(aq, rl)) {
+            Ok(result) => {
+                h.reg[rd] = result;
+                Conclusion::None
+            }
+            Err(e) => Conclusion::Exception(exception(e, Access::Write, h.reg[rs1])),
+        }
+    }
+
+    AmoSwapw => fn amo_swapw(h, AmoSwapw { rd, rs1, rs2, aq, rl }) {
+        match h.mmu.swap_word_atomic(h.reg[rs1], h.reg[rs2], aqrl_ordering(aq, rl)) {
+            Ok(val) => {
+                h.reg[rd] = val;
+                Conclusion::None
+            }
+            Err(e) => Conclusion::Exception(exception(e, Access::Write, h.reg[rs1])),
+        }
+    }
+
+    AmoAddw => fn amo_addw(h, AmoAddw { rd, rs1, rs2, aq, rl }) {
+        match h.mmu.add_word_atomic(h.reg[rs1], h.reg[rs2], aqrl_ordering(aq, rl)) {
+            Ok(val) => {
+                h.reg[rd] = val;
+                Conclusion::None
+            }
+            Err(e) => Conclusion::Exception(exception(e, Access::Write, h.reg[rs1])),
+        }
+    }
+
+    AmoXorw => fn amo_xorw(h, AmoXorw { rd, rs1, rs2, aq, rl }) {
+        match h.mmu.xor_word_atomic(h.reg[rs1], h.reg[rs2], aqrl_ordering(aq, rl)) {
+            Ok(val) => {
+                h.reg[rd] = val;
+                Conclusion::None
+            }
+            Err(e) => Conclusion::Exception(exception(e, Access::Write, h.reg[rs1])),
+        }
+    }
+
+    AmoAndw => fn amo_andw(h, AmoAndw { rd, rs1, rs2, aq, rl }) {
+        match h.mmu.and_word_atomic(h.reg[rs1], h.reg[rs2], aqrl_ordering(aq, rl)) {
+            Ok(val) => {
+                h.reg[rd] = val;
+                Conclusion::None
+            }
+            Err(e) => Conclusion::Exception(exception(e, Access::Write, h.reg[rs1])),
+        }
+    }
+
+    AmoOrw => fn amo_orw(h, AmoOrw { rd, rs1, rs2, aq, rl }) {
+        match h.mmu.or_word_atomic(h.reg[rs1], h.reg[rs2], aqrl_ordering(aq, rl)) {
+            Ok(val) => {
+                h.reg[rd] = val;
+                Conclusion::None
+            }
+            Err(e) => Conclusion::Exception(exception(e, Access::Write, h.reg[rs1])),
+        }
+    }
+
+    AmoMinw => fn amo_minw(h, AmoMinw { rd, rs1, rs2, aq, rl }) {
+        match h.mmu.min_word_atomic(h.reg[rs1], h.reg[rs2], aqrl_ordering(aq, rl)) {
+            Ok(val) => {
+                h.reg[rd] = val;
+                Conclusion::None
+            }
+            Err(e) => Conclusion::Exception(exception(e, Access::Write, h.reg[rs1])),
+        }
+    }
+
+    AmoMaxw => fn amo_maxw(h, AmoMaxw { rd, rs1, rs2, aq, rl }) {
+        match h.mmu.max_word_atomic(h.reg[rs1], h.reg[rs2], aqrl_ordering(aq, rl)) {
+            Ok(val) => {
+                h.reg[rd] = val;
+                Conclusion::None
+            }
+            Err(e) => Conclusion::Exception(exception(e, Access::Write, h.reg[rs1])),
+        }
+    }
+
+    AmoMinuw => fn amo_minuw(h, AmoMinuw { rd, rs1, rs2, aq, rl }) {
+        match h.mmu.minu_word_atomic(h.reg[rs1], h.reg[rs2], aqrl_ordering(aq, rl)) {
+            Ok(val) => {
+                h.reg[rd] = val;
+                Conclusion::None
+            }
+            Err(e) => Conclusion::Exception(exception(e, Access::Write, h.reg[rs1])),
+        }
+    }
+
+    AmoMaxuw => fn amo_maxuw(h, AmoMaxuw { rd, rs1, rs2, aq, rl }) {
+        match h.mmu.maxu_word_atomic(h.reg[rs1], h.reg[rs2], aqrl_ordering(aq, rl)) {
+            Ok(val) => {
+                h.reg[rd] = val;
+                Conclusion::None
+            }
+            Err(e) => Conclusion::Exception(exception(e, Access::Write, h.reg[rs1])),
+        }
+    }
+
+    Flw => fn flw(h, Flw { rd, rs1, imm }) {
+        let addr = h.reg[rs1].wrapping_add_signed(imm.into());
+        match h.mmu.load_word(addr) {
+            Ok(val) => {
+                h.freg[rd] = val;
+                Conclusion::None
+            }
+            Err(e) => Conclusion::Exception(exception(e, Access::Read, addr)),
+        }
+    }
+
+    Fsw => fn fsw(h, Fsw { rs1, rs2, imm }) {
+        let addr = h.reg[rs1].wrapping_add_signed(imm.into());
+        match h.mmu.store_word(addr, h.freg[rs2]) {
+            Ok(_) => Conclusion::None,
+            Err(e) => Conclusion::Exception(exception(e, Access::Write, addr)),
+        }
+    }
+
+    Fadds => fn fadds(h, inst @ Fadds { rd, rs1, rs2, rm }) {
+        h.fp_op(inst, rm, |h, rm| {
+            let (result, flags) = fpu::add(h.freg[rs1], h.freg[rs2], rm);
+            h.freg[rd] = result;
+            flags
+        })
+    }
+
+    Fsubs => fn fsubs(h, inst @ Fsubs { rd, rs1, rs2, rm }) {
+        h.fp_op(inst, rm, |h, rm| {
+            let (result, flags) = fpu::sub(h.freg[rs1], h.freg[rs2], rm);
+            h.freg[rd] = result;
+            flags
+        })
+    }
+
+    Fmuls => fn fmuls(h, inst @ Fmuls { rd, rs1, rs2, rm }) {
+        h.fp_op(inst, rm, |h, rm| {
+            let (result, flags) = fpu::mul(h.freg[rs1], h.freg[rs2], rm);
+            h.freg[rd] = result;
+            flags
+        })
+    }
+
+    Fdivs => fn fdivs(h, inst @ Fdivs { rd, rs1, rs2, rm }) {
+        h.fp_op(inst, rm, |h, rm| {
+            let (result, flags) = fpu::div(h.freg[rs1], h.freg[rs2], rm);
+            h.freg[rd] = result;
+            flags
+        })
+    }
+
+    Fsqrts => fn fsqrts(h, inst @ Fsqrts { rd, rs1, rm }) {
+        h.fp_op(inst, rm, |h, rm| {
+            let (result, flags) = fpu::sqrt(h.freg[rs1], rm);
+            h.freg[rd] = result;
+            flags
+        })
+    }
+
+    Fsgnjs => fn fsgnjs(h, Fsgnjs { rd, rs1, rs2 }) {
+        h.freg[rd] = fpu::sign_inject(h.freg[rs1], h.freg[rs2], |_, b| b);
+        Conclusion::None
+    }
+
+    Fsgnjns => fn fsgnjns(h, Fsgnjns { rd, rs1, rs2 }) {
+        h.freg[rd] = fpu::sign_inject(h.freg[rs1], h.freg[rs2], |_, b| !b);
+        Conclusion::None
+    }
+
+    Fsgnjxs => fn fsgnjxs(h, Fsgnjxs { rd, rs1, rs2 }) {
+        h.freg[rd] = fpu::sign_inject(h.freg[rs1], h.freg[rs2], u32::bitxor);
+        Conclusion::None
+    }
+
+    Fmins => fn fmins(h, Fmins { rd, rs1, rs2 }) {
+        let (result, flags) = fpu::min_max(h.freg[rs1], h.freg[rs2], true);
+        h.freg[rd] = result;
+        h.accrue(flags);
+        Conclusion::None
+    }
+
+    Fmaxs => fn fmaxs(h, Fmaxs { rd, rs1, rs2 }) {
+        let (result, flags) = fpu::min_max(h.freg[rs1], h.freg[rs2], false);
+        h.freg[rd] = result;
+        h.accrue(flags);
+        Conclusion::None
+    }
+
+    Feqs => fn feqs(h, Feqs { rd, rs1, rs2 }) {
+        let (result, flags) = fpu::compare(h.freg[rs1], h.freg[rs2], true, f32::eq);
+        h.reg[rd] = result;
+        h.accrue(flags);
+        Conclusion::None
+    }
+
+    Flts => fn flts(h, Flts { rd, rs1, rs2 }) {
+        let (result, flags) = fpu::compare(h.freg[rs1], h.freg[rs2], false, f32::lt);
+        h.reg[rd] = result;
+        h.accrue(flags);
+        Conclusion::None
+    }
+
+    Fles => fn fles(h, Fles { rd, rs1, rs2 }) {
+        let (result, flags) = fpu::compare(h.freg[rs1], h.freg[rs2], false, f32::le);
+        h.reg[rd] = result;
+        h.accrue(flags);
+        Conclusion::None
+    }
+
+    Fcvtws => fn fcvtws(h, inst @ Fcvtws { rd, rs1, rm }) {
+        h.fp_op(inst, rm, |h, rm| {
+            let (result, flags) = fpu::to_int(h.freg[rs1], true, rm);
+            h.reg[rd] = result;
+            flags
+        })
+    }
+
+    Fcvtwus => fn fcvtwus(h, inst @ Fcvtwus { rd, rs1, rm }) {
+        h.fp_op(inst, rm, |h, rm| {
+            let (result, flags) = fpu::to_int(h.freg[rs1], false, rm);
+            h.reg[rd] = result;
+            flags
+        })
+    }
+
+    Fcvtsw => fn fcvtsw(h, inst @ Fcvtsw { rd, rs1, rm }) {
+        h.fp_op(inst, rm, |h, rm| {
+            let (result, flags) = fpu::from_int(h.reg[rs1], true, rm);
+            h.freg[rd] = result;
+            flags
+        })
+    }
+
+    Fcvtswu => fn fcvtswu(h, inst @ Fcvtswu { rd, rs1, rm }) {
+        h.fp_op(inst, rm, |h, rm| {
+            let (result, flags) = fpu::from_int(h.reg[rs1], false, rm);
+            h.freg[rd] = result;
+            flags
+        })
+    }
+
+    Fmvxw => fn fmvxw(h, Fmvxw { rd, rs1 }) {
+        h.reg[rd] = h.freg[rs1];
+        Conclusion::None
+    }
+
+    Fmvwx => fn fmvwx(h, Fmvwx { rd, rs1 }) {
+        h.freg[rd] = h.reg[rs1];
+        Conclusion::None
+    }
+
+    Fclasss => fn fclasss(h, Fclasss { rd, rs1 }) {
+        h.reg[rd] = fpu::classify(h.freg[rs1]);
+        Conclusion::None
+    }
+
+    Invalid => fn invalid(h, Invalid { raw }) {
+        Conclusion::Exception(ExceptionKind::IllegalInstruction { raw })
+    }
+}
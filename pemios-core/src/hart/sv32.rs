@@ -10,6 +10,12 @@
 #[allow(unused)]
 pub struct VirtualAddress(u32);
 
+impl From<u32> for VirtualAddress {
+    fn from(addr: u32) -> Self {
+        Self(addr)
+    }
+}
+
 #[allow(unused)]
 impl VirtualAddress {
     #[inline]
@@ -59,6 +65,12 @@ impl PhysicalAddress {
 #[derive(Copy, Clone, Default)]
 pub struct Pte(u32);
 
+impl From<u32> for Pte {
+    fn from(raw: u32) -> Self {
+        Self(raw)
+    }
+}
+
 #[allow(unused)]
 #[repr(u8)]
 pub enum PteRsw {
@@ -80,6 +92,12 @@ pub enum PteKind {
 
 #[allow(unused)]
 impl Pte {
+    /// The accessed bit, set when the page is accessed
+    pub const ACCESSED: u32 = 0b01000000;
+
+    /// The dirty bit, set when the page is written to
+    pub const DIRTY: u32 = 0b10000000;
+
     pub fn raw(&self) -> u32 {
         self.0
     }
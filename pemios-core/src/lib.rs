@@ -10,6 +10,11 @@
 #![allow(incomplete_features)]
 #![feature(generic_const_exprs)]
 
+pub mod asm;
 pub mod bus;
+#[cfg(feature = "gdb")]
+pub mod gdb;
 pub mod hart;
+pub mod loader;
+pub mod machine;
 pub mod memory;
@@ -0,0 +1,260 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// This Source Code Form is "Incompatible With Secondary Licenses", as
+// defined by the Mozilla Public License, v. 2.0.
+//
+// Copyright © 2022 mumblingdrunkard
+
+use crate::{
+    bus::Bus,
+    memory::mapping::{Mapping, MemoryError},
+};
+
+const ELF_MAGIC: [u8; 4] = [0x7f, b'E', b'L', b'F'];
+const ELFCLASS32: u8 = 1;
+const ELFDATA2LSB: u8 = 1;
+const EM_RISCV: u16 = 243;
+const PT_LOAD: u32 = 1;
+
+const EHDR_SIZE: usize = 52;
+const PHDR_SIZE: usize = 32;
+
+/// `.bss` is zero-filled this many bytes at a time
+const ZEROS: [u8; 0x1000] = [0; 0x1000];
+
+#[derive(Debug)]
+pub enum LoaderError {
+    /// The file ends before a header or segment it describes
+    Truncated,
+    /// The file does not start with the ELF magic number
+    NotElf,
+    /// The file is not a 32-bit ELF file
+    WrongClass { class: u8 },
+    /// The file is not little-endian
+    WrongEndianness { data: u8 },
+    /// The file is not for RISC-V
+    WrongMachine { machine: u16 },
+    /// The segment at `paddr` does not fit in the address space, or is
+    /// smaller in memory than in the file
+    BadSegment { paddr: u32 },
+    /// A segment could not be written to the bus
+    MemoryError { e: MemoryError },
+}
+
+impl From<MemoryError> for LoaderError {
+    fn from(e: MemoryError) -> Self {
+        Self::MemoryError { e }
+    }
+}
+
+pub type LoaderResult<T> = std::result::Result<T, LoaderError>;
+
+fn u16_at(bytes: &[u8], offset: usize) -> LoaderResult<u16> {
+    bytes
+        .get(offset..offset + 2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .ok_or(LoaderError::Truncated)
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> LoaderResult<u32> {
+    bytes
+        .get(offset..offset + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or(LoaderError::Truncated)
+}
+
+/// Writes all of `data` to `addr`, failing if any of it is not backed by
+/// memory.
+fn write_all(bus: &Bus, addr: u32, data: &[u8]) -> LoaderResult<()> {
+    let written = bus.block_write(addr, data)?;
+    if written < data.len() {
+        let offset = addr
+            .checked_add(written as u32)
+            .ok_or(LoaderError::BadSegment { paddr: addr })?;
+        return Err(MemoryError::OutOfBoundsAccess { offset }.into());
+    }
+
+    Ok(())
+}
+
+/// Loads the ELF32 little-endian RISC-V executable in `bytes` onto `bus`.
+///
+/// Every `PT_LOAD` segment is copied to its physical address, and the part of
+/// it that is not backed by the file (`.bss`) is zero-filled.
+/// Returns the entry point, which should be assigned to `hart.pc`.
+pub fn load_elf(bus: &Bus, bytes: &[u8]) -> LoaderResult<u32> {
+    if bytes.len() < EHDR_SIZE {
+        return Err(LoaderError::Truncated);
+    }
+
+    if bytes[..4] != ELF_MAGIC {
+        return Err(LoaderError::NotElf);
+    }
+
+    match (bytes[4], bytes[5]) {
+        (ELFCLASS32, ELFDATA2LSB) => {}
+        (ELFCLASS32, data) => return Err(LoaderError::WrongEndianness { data }),
+        (class, _) => return Err(LoaderError::WrongClass { class }),
+    }
+
+    let machine = u16_at(bytes, 18)?;
+    if machine != EM_RISCV {
+        return Err(LoaderError::WrongMachine { machine });
+    }
+
+    let entry = u32_at(bytes, 24)?;
+    let phoff = u32_at(bytes, 28)? as usize;
+    let phentsize = u16_at(bytes, 42)? as usize;
+    let phnum = u16_at(bytes, 44)? as usize;
+
+    if phnum > 0 && phentsize < PHDR_SIZE {
+        return Err(LoaderError::Truncated);
+    }
+
+    for i in 0..phnum {
+        let ph = phoff + i * phentsize;
+        if u32_at(bytes, ph)? != PT_LOAD {
+            continue;
+        }
+
+        let offset = u32_at(bytes, ph + 4)? as usize;
+        let paddr = u32_at(bytes, ph + 12)?;
+        let filesz = u32_at(bytes, ph + 16)? as usize;
+        let memsz = u32_at(bytes, ph + 20)? as usize;
+
+        let fits = memsz == 0 || paddr.checked_add(memsz as u32 - 1).is_some();
+        if !fits || filesz > memsz {
+            return Err(LoaderError::BadSegment { paddr });
+        }
+
+        let data = offset
+            .checked_add(filesz)
+            .and_then(|end| bytes.get(offset..end))
+            .ok_or(LoaderError::Truncated)?;
+        write_all(bus, paddr, data)?;
+
+        // the segment fits, so none of these addresses overflow
+        for start in (filesz..memsz).step_by(ZEROS.len()) {
+            let len = ZEROS.len().min(memsz - start);
+            write_all(bus, paddr + start as u32, &ZEROS[..len])?;
+        }
+    }
+
+    Ok(entry)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        bus::Bus,
+        memory::{endian::read_bytes, mapping::Mapping},
+    };
+
+    use super::{load_elf, LoaderError};
+
+    /// An executable with a single segment of 8 bytes of data and 8 bytes of
+    /// `.bss`, loaded at 0x1000
+    fn elf() -> Vec<u8> {
+        let mut elf = vec![0; 52];
+        elf[..4].copy_from_slice(b"\x7fELF");
+        elf[4] = 1; // ELFCLASS32
+        elf[5] = 1; // ELFDATA2LSB
+        elf[6] = 1; // EV_CURRENT
+        elf[16..18].copy_from_slice(&2u16.to_le_bytes()); // ET_EXEC
+        elf[18..20].copy_from_slice(&243u16.to_le_bytes()); // EM_RISCV
+        elf[20..24].copy_from_slice(&1u32.to_le_bytes());
+        elf[24..28].copy_from_slice(&0x1004u32.to_le_bytes()); // e_entry
+        elf[28..32].copy_from_slice(&52u32.to_le_bytes()); // e_phoff
+        elf[40..42].copy_from_slice(&52u16.to_le_bytes()); // e_ehsize
+        elf[42..44].copy_from_slice(&32u16.to_le_bytes()); // e_phentsize
+        elf[44..46].copy_from_slice(&1u16.to_le_bytes()); // e_phnum
+
+        for field in [1, 84, 0x1000, 0x1000, 8, 16, 7, 4] {
+            elf.extend(u32::to_le_bytes(field));
+        }
+        elf.extend([1, 2, 3, 4, 5, 6, 7, 8]);
+
+        elf
+    }
+
+    #[test]
+    fn load() {
+        let bus = Bus::builder().with_main_memory(2).build();
+        bus.store_word(0x100c, 0xffffffff).unwrap();
+
+        assert_eq!(load_elf(&bus, &elf()).unwrap(), 0x1004);
+        assert_eq!(bus.load_byte(0x1000).unwrap(), 1);
+        assert_eq!(bus.load_word(0x1004).unwrap(), read_bytes(&[5, 6, 7, 8]));
+        assert_eq!(bus.load_word(0x100c).unwrap(), 0, ".bss was not zeroed");
+    }
+
+    #[test]
+    fn reject() {
+        let bus = Bus::builder().with_main_memory(2).build();
+
+        let mut elf64 = elf();
+        elf64[4] = 2;
+        assert!(matches!(
+            load_elf(&bus, &elf64),
+            Err(LoaderError::WrongClass { class: 2 })
+        ));
+
+        let mut big_endian = elf();
+        big_endian[5] = 2;
+        assert!(matches!(
+            load_elf(&bus, &big_endian),
+            Err(LoaderError::WrongEndianness { data: 2 })
+        ));
+
+        let mut x86 = elf();
+        x86[18] = 62;
+        assert!(matches!(
+            load_elf(&bus, &x86),
+            Err(LoaderError::WrongMachine { machine: 62 })
+        ));
+
+        assert!(matches!(
+            load_elf(&bus, &elf()[..90]),
+            Err(LoaderError::Truncated)
+        ));
+        assert!(matches!(
+            load_elf(&bus, b"#!/bin/sh"),
+            Err(LoaderError::Truncated)
+        ));
+
+        // a segment that wraps around the end of the address space
+        let mut wrapping = elf();
+        wrapping[64..68].copy_from_slice(&0xfffffff8u32.to_le_bytes()); // p_paddr
+        assert!(matches!(
+            load_elf(&bus, &wrapping),
+            Err(LoaderError::BadSegment { paddr: 0xfffffff8 })
+        ));
+
+        let mut short = elf();
+        short[72..76].copy_from_slice(&4u32.to_le_bytes()); // p_memsz
+        assert!(matches!(
+            load_elf(&bus, &short),
+            Err(LoaderError::BadSegment { paddr: 0x1000 })
+        ));
+    }
+
+    #[test]
+    fn large_bss() {
+        let bus = Bus::builder().with_main_memory(4).build();
+        bus.store_word(0x3ffc, 0xffffffff).unwrap();
+
+        let mut elf = elf();
+        elf[72..76].copy_from_slice(&0x3000u32.to_le_bytes()); // p_memsz
+        assert_eq!(load_elf(&bus, &elf).unwrap(), 0x1004);
+        assert_eq!(bus.load_word(0x3ffc).unwrap(), 0, ".bss was not zeroed");
+
+        // the end of .bss is not backed by memory
+        elf[72..76].copy_from_slice(&0x4000u32.to_le_bytes());
+        assert!(matches!(
+            load_elf(&bus, &elf),
+            Err(LoaderError::MemoryError { .. })
+        ));
+    }
+}
@@ -0,0 +1,72 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// This Source Code Form is "Incompatible With Secondary Licenses", as
+// defined by the Mozilla Public License, v. 2.0.
+//
+// Copyright © 2022 mumblingdrunkard
+
+//! Helpers for running several harts on one bus.
+
+use std::sync::atomic::AtomicU32;
+
+use crate::{
+    bus::Bus,
+    hart::{Hart, HartConfig},
+    memory::mapping::Mapping,
+};
+
+/// Owns the reservation sets of a group of harts that share a bus.
+///
+/// ```
+/// use std::thread;
+///
+/// use pemios_core::{bus::Bus, hart::step::Step, machine::Machine};
+///
+/// let bus = Bus::builder().with_main_memory(1).build();
+/// let machine = Machine::new(2);
+///
+/// thread::scope(|s| {
+///     for mut hart in machine.harts(&bus) {
+///         s.spawn(move || hart.step());
+///     }
+/// });
+/// ```
+pub struct Machine {
+    reservations: Box<[AtomicU32]>,
+}
+
+impl Machine {
+    /// Creates the reservation sets for `hart_count` harts.
+    pub fn new(hart_count: usize) -> Self {
+        Self {
+            reservations: (0..hart_count).map(|_| AtomicU32::new(u32::MAX)).collect(),
+        }
+    }
+
+    pub fn hart_count(&self) -> usize {
+        self.reservations.len()
+    }
+
+    /// Creates one hart per reservation set and registers every set on `bus`.
+    ///
+    /// Each hart gets its index as its `mhartid`.
+    ///
+    /// This should only be called once per bus, as the sets would otherwise
+    /// be registered more than once.
+    pub fn harts<'a>(&'a self, bus: &'a Bus<'a>) -> Vec<Hart<'a>> {
+        self.reservations
+            .iter()
+            .zip(0..)
+            .map(|(reservation, hart_id)| {
+                bus.register_reservation_set(reservation);
+                let config = HartConfig {
+                    hart_id,
+                    ..HartConfig::default()
+                };
+                Hart::with_config(bus, reservation, config)
+            })
+            .collect()
+    }
+}
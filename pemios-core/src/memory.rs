@@ -7,5 +7,12 @@
 //
 // Copyright © 2022 mumblingdrunkard
 
+pub mod atomic_main;
+pub mod endian;
 pub mod main;
 pub mod mapping;
+pub mod rom;
+#[cfg(test)]
+pub(crate) mod test_device;
+pub mod timer;
+pub mod uart;
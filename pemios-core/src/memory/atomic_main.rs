@@ -0,0 +1,589 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// This Source Code Form is "Incompatible With Secondary Licenses", as
+// defined by the Mozilla Public License, v. 2.0.
+//
+// Copyright © 2022 mumblingdrunkard
+
+use std::{
+    ops::RangeInclusive,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Mutex, MutexGuard, RwLock,
+    },
+};
+
+use crate::hart::mmu::{
+    addr_to_reservation_set, helper_check_reservation, helper_invalidate_reservations,
+};
+
+use super::{
+    endian::{read_bytes, write_bytes},
+    main::Main,
+    mapping::{Mapping, MemoryError, MemoryResult, Pma, Properties, StoreCallback},
+};
+
+/// A main memory region backed by atomic words instead of locked frames.
+///
+/// Loads and stores never take a lock.
+/// Every naturally aligned access is a single relaxed operation on the word
+/// containing it, which gives the per-address coherence RVWMO requires.
+/// Like with [`Main`], ordering between different words is left to fences.
+///
+/// AMOs and store-conditionals still take a per-frame lock, so an AMO can not
+/// slip in between a store-conditional checking its reservation and
+/// performing its store.
+///
+/// Block and stream operations are performed one word at a time and, unlike
+/// with [`Main`], are not atomic as a whole.
+pub struct AtomicMain<'a> {
+    // the frame the bus maps the memory at, see `Mapping::relocate`
+    base_frame: AtomicU32,
+    // each word holds its four bytes in little-endian order, whatever the
+    // byte order of the data stored in them
+    words: Box<[AtomicU32]>,
+    locks: Box<[Mutex<()>]>,
+    reservations: Mutex<Vec<&'a AtomicU32>>,
+    callbacks: RwLock<Vec<StoreCallback<'a>>>,
+}
+
+/// The mask selecting the `width` bytes at `offset` in their word
+fn lane(offset: u32, width: u32) -> u32 {
+    (u32::MAX >> (32 - width * 8)) << ((offset & 3) * 8)
+}
+
+/// The word holding the bytes of `value` as stored in memory
+fn encode(value: u32) -> u32 {
+    let mut bytes = [0; 4];
+    write_bytes(value, &mut bytes);
+    u32::from_le_bytes(bytes)
+}
+
+/// The value stored in the bytes of `word`
+fn decode(word: u32) -> u32 {
+    read_bytes(&word.to_le_bytes())
+}
+
+fn mask_bit(mask: &[u8], i: usize) -> bool {
+    (mask[i >> 3] >> (i & 7)) & 1 == 1
+}
+
+impl<'a> AtomicMain<'a> {
+    /// Create a new main memory with `frame_count` frames of 4096 bytes each.
+    pub fn new(base_frame: u32, frame_count: u32) -> Self {
+        Self {
+            base_frame: AtomicU32::new(base_frame),
+            words: (0..frame_count << 10).map(|_| AtomicU32::new(0)).collect(),
+            locks: (0..frame_count).map(|_| Mutex::new(())).collect(),
+            reservations: Mutex::new(Vec::new()),
+            callbacks: RwLock::new(Vec::new()),
+        }
+    }
+
+    /// Finds the index of the word containing the access of width `W` at
+    /// `offset`, which is a store if `STORE` is set.
+    fn check_offset<const W: u32, const STORE: bool>(&self, offset: u32) -> MemoryResult<usize> {
+        if offset & (W - 1) != 0 {
+            let alignment = W;
+            return Err(if STORE {
+                MemoryError::StoreMisaligned { offset, alignment }
+            } else {
+                MemoryError::LoadMisaligned { offset, alignment }
+            });
+        }
+
+        let index = offset as usize >> 2;
+        if index >= self.words.len() {
+            return Err(MemoryError::OutOfBoundsAccess { offset });
+        }
+
+        Ok(index)
+    }
+
+    /// How many of the `len` bytes starting at `offset` are backed by a frame
+    fn backed_len(&self, offset: u32, len: usize) -> usize {
+        let size = self.words.len() << 2;
+        std::cmp::min(len, size.saturating_sub(offset as usize))
+    }
+
+    fn lock_frame(&self, frame_number: usize) -> MutexGuard<'_, ()> {
+        self.locks[frame_number]
+            .lock()
+            .expect("Tried to acquire frame, but the lock was poisoned.")
+    }
+
+    /// The reservation set holding the byte at `offset`, as named by harts.
+    fn reservation_set(&self, offset: u32) -> u32 {
+        addr_to_reservation_set((self.base_frame.load(Ordering::Relaxed) << 12) + offset)
+    }
+
+    fn invalidate_reservation_range(&self, should_be: RangeInclusive<u32>) {
+        let g = self
+            .reservations
+            .lock()
+            .expect("Failed to lock reservation sets for invalidation!");
+        should_be.for_each(|set| helper_invalidate_reservations(g.as_ref(), set));
+    }
+
+    /// Calls every registered store callback with `offset`.
+    fn notify_store(&self, offset: u32) {
+        self.callbacks
+            .read()
+            .expect("Failed to lock store callbacks")
+            .iter()
+            .for_each(|callback| callback(offset));
+    }
+
+    /// Replaces the bytes of word `index` selected by `mask` with those of
+    /// `value`.
+    fn merge(&self, index: usize, mask: u32, value: u32) {
+        let word = &self.words[index];
+        if mask == u32::MAX {
+            word.store(value, Ordering::Relaxed);
+        } else if mask != 0 {
+            // the update always succeeds, as the closure never returns `None`
+            let _ = word.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |old| {
+                Some(old & !mask | value & mask)
+            });
+        }
+    }
+
+    /// Stores `value` in the `width` bytes at `offset`, which must be aligned
+    /// and backed.
+    fn store_bytes(&self, offset: u32, width: u32, value: u32) {
+        let start = offset as usize & 3;
+        let mut bytes = [0; 4];
+        write_bytes(value, &mut bytes[start..start + width as usize]);
+        self.merge(
+            offset as usize >> 2,
+            lane(offset, width),
+            u32::from_le_bytes(bytes),
+        );
+    }
+
+    /// Loads the value stored in the `width` bytes at `offset`, which must be
+    /// aligned and backed.
+    fn load_bytes(&self, offset: u32, width: u32) -> u32 {
+        let start = offset as usize & 3;
+        let bytes = self.words[offset as usize >> 2]
+            .load(Ordering::Relaxed)
+            .to_le_bytes();
+        read_bytes(&bytes[start..start + width as usize])
+    }
+
+    fn store<const W: u32>(&self, offset: u32, val: u32) -> MemoryResult<()> {
+        self.check_offset::<W, true>(offset)?;
+        self.store_bytes(offset, W, val);
+        self.notify_store(offset);
+        Ok(())
+    }
+
+    fn load<const W: u32>(&self, offset: u32) -> MemoryResult<u32> {
+        self.check_offset::<W, false>(offset)?;
+        Ok(self.load_bytes(offset, W))
+    }
+
+    /// Atomically replaces the word at `offset` with `op(old)`, returning
+    /// `old`.
+    fn amo<F: Fn(u32) -> u32>(&self, offset: u32, op: F) -> MemoryResult<u32> {
+        let index = self.check_offset::<4, true>(offset)?;
+        let set = self.reservation_set(offset);
+
+        let _guard = self.lock_frame(index >> 10);
+        // plain stores do not take the lock, so the update must still be atomic
+        let old =
+            match self.words[index].fetch_update(Ordering::Relaxed, Ordering::Relaxed, |old| {
+                Some(encode(op(decode(old))))
+            }) {
+                Ok(old) | Err(old) => old,
+            };
+        self.invalidate_reservation_range(set..=set);
+        self.notify_store(offset);
+
+        Ok(decode(old))
+    }
+
+    fn block_write_internal<const M: bool>(
+        &self,
+        offset: u32,
+        src: &[u8],
+        mask: &[u8],
+    ) -> MemoryResult<usize> {
+        if M && mask.len() * 8 < src.len() {
+            panic!("Mask must contain enough bits to mask src!");
+        }
+
+        // bytes past the last frame are not backed and are skipped
+        let backed = self.backed_len(offset, src.len());
+
+        let mut written = 0;
+        let mut i = 0;
+        while i < backed {
+            let addr = offset as usize + i;
+            let n = std::cmp::min(4 - (addr & 3), backed - i);
+
+            let mut bytes = [0; 4];
+            let mut word_mask = [0; 4];
+            for j in 0..n {
+                if !M || mask_bit(mask, i + j) {
+                    bytes[(addr & 3) + j] = src[i + j];
+                    word_mask[(addr & 3) + j] = 0xff;
+                    written += 1;
+                }
+            }
+            self.merge(
+                addr >> 2,
+                u32::from_le_bytes(word_mask),
+                u32::from_le_bytes(bytes),
+            );
+
+            i += n;
+        }
+
+        if written > 0 {
+            let (first, last) = (offset, offset + backed as u32 - 1);
+            self.invalidate_reservation_range(
+                self.reservation_set(first)..=self.reservation_set(last),
+            );
+            self.notify_store(offset);
+        }
+        Ok(written)
+    }
+
+    fn block_read_internal<const M: bool>(
+        &self,
+        offset: u32,
+        dst: &mut [u8],
+        mask: &[u8],
+    ) -> MemoryResult<usize> {
+        if M && mask.len() * 8 < dst.len() {
+            panic!("Mask must contain enough bits to mask dst!");
+        }
+
+        // bytes past the last frame are not backed and are left untouched
+        let backed = self.backed_len(offset, dst.len());
+
+        let mut read = 0;
+        let mut i = 0;
+        while i < backed {
+            let addr = offset as usize + i;
+            let n = std::cmp::min(4 - (addr & 3), backed - i);
+
+            let bytes = self.words[addr >> 2].load(Ordering::Relaxed).to_le_bytes();
+            for j in 0..n {
+                if !M || mask_bit(mask, i + j) {
+                    dst[i + j] = bytes[(addr & 3) + j];
+                    read += 1;
+                }
+            }
+
+            i += n;
+        }
+
+        Ok(read)
+    }
+
+    /// Checks that `frame` is backed and returns the offset of its first byte
+    fn check_frame(&self, frame: u32) -> MemoryResult<u32> {
+        let base = frame << 12;
+        if frame as usize >= self.locks.len() {
+            return Err(MemoryError::OutOfBoundsAccess { offset: base });
+        }
+        Ok(base)
+    }
+}
+
+impl<'a> Mapping<'a> for AtomicMain<'a> {
+    fn block_write(&self, offset: u32, src: &[u8]) -> MemoryResult<usize> {
+        self.block_write_internal::<false>(offset, src, &[])
+    }
+
+    fn block_write_masked(&self, offset: u32, src: &[u8], mask: &[u8]) -> MemoryResult<usize> {
+        self.block_write_internal::<true>(offset, src, mask)
+    }
+
+    fn block_read(&self, offset: u32, dst: &mut [u8]) -> MemoryResult<usize> {
+        self.block_read_internal::<false>(offset, dst, &[])
+    }
+
+    fn block_read_masked(&self, offset: u32, dst: &mut [u8], mask: &[u8]) -> MemoryResult<usize> {
+        self.block_read_internal::<true>(offset, dst, mask)
+    }
+
+    fn stream_write(&self, frame: u32, writes: &[(u16, u8, u32)]) -> MemoryResult<usize> {
+        if writes.is_empty() {
+            return Ok(0);
+        }
+
+        let base = self.check_frame(frame)?;
+
+        // validate everything up front so the batch is applied fully
+        for &(offset, width, _) in writes {
+            Main::check_stream_access(base, offset, width, true)?;
+        }
+
+        for &(offset, width, value) in writes {
+            self.store_bytes(base + offset as u32, width as u32, value);
+            self.notify_store(base + offset as u32);
+        }
+
+        Ok(writes.len())
+    }
+
+    fn stream_read(&self, frame: u32, reads: &[(u16, u8)], dst: &mut [u32]) -> MemoryResult<usize> {
+        assert_eq!(
+            reads.len(),
+            dst.len(),
+            "dst must have room for exactly one value per read"
+        );
+
+        if reads.is_empty() {
+            return Ok(0);
+        }
+
+        let base = self.check_frame(frame)?;
+
+        for &(offset, width) in reads {
+            Main::check_stream_access(base, offset, width, false)?;
+        }
+
+        for (&(offset, width), d) in reads.iter().zip(dst.iter_mut()) {
+            *d = self.load_bytes(base + offset as u32, width as u32);
+        }
+
+        Ok(reads.len())
+    }
+
+    fn store_byte(&self, offset: u32, byte: u8) -> MemoryResult<()> {
+        self.store::<1>(offset, byte as u32)
+    }
+
+    fn store_half_word(&self, offset: u32, half_word: u16) -> MemoryResult<()> {
+        self.store::<2>(offset, half_word as u32)
+    }
+
+    fn store_word(&self, offset: u32, word: u32) -> MemoryResult<()> {
+        self.store::<4>(offset, word)
+    }
+
+    fn load_byte(&self, offset: u32) -> MemoryResult<u8> {
+        self.load::<1>(offset).map(|x| x as u8)
+    }
+
+    fn load_half_word(&self, offset: u32) -> MemoryResult<u16> {
+        self.load::<2>(offset).map(|x| x as u16)
+    }
+
+    fn load_word(&self, offset: u32) -> MemoryResult<u32> {
+        self.load::<4>(offset)
+    }
+
+    fn store_conditional(
+        &self,
+        offset: u32,
+        src: u32,
+        reservation: &AtomicU32,
+        should_be: u32,
+    ) -> MemoryResult<u32> {
+        let index = self.check_offset::<4, true>(offset)?;
+
+        let _guard = self.lock_frame(index >> 10);
+        let result = helper_check_reservation(reservation, should_be);
+        if result == 0 {
+            self.words[index].store(encode(src), Ordering::Relaxed);
+            self.invalidate_reservation_range(should_be..=should_be);
+            self.notify_store(offset);
+        }
+
+        Ok(result)
+    }
+
+    fn amoswap_w(&self, offset: u32, src: u32) -> MemoryResult<u32> {
+        self.amo(offset, |_| src)
+    }
+
+    fn amoadd_w(&self, offset: u32, src: u32) -> MemoryResult<u32> {
+        self.amo(offset, |old| old.wrapping_add(src))
+    }
+
+    fn amoand_w(&self, offset: u32, src: u32) -> MemoryResult<u32> {
+        self.amo(offset, |old| old & src)
+    }
+
+    fn amoor_w(&self, offset: u32, src: u32) -> MemoryResult<u32> {
+        self.amo(offset, |old| old | src)
+    }
+
+    fn amoxor_w(&self, offset: u32, src: u32) -> MemoryResult<u32> {
+        self.amo(offset, |old| old ^ src)
+    }
+
+    fn amomax_w(&self, offset: u32, src: u32) -> MemoryResult<u32> {
+        self.amo(offset, |old| (old as i32).max(src as i32) as u32)
+    }
+
+    fn amomaxu_w(&self, offset: u32, src: u32) -> MemoryResult<u32> {
+        self.amo(offset, |old| old.max(src))
+    }
+
+    fn amomin_w(&self, offset: u32, src: u32) -> MemoryResult<u32> {
+        self.amo(offset, |old| (old as i32).min(src as i32) as u32)
+    }
+
+    fn amominu_w(&self, offset: u32, src: u32) -> MemoryResult<u32> {
+        self.amo(offset, |old| old.min(src))
+    }
+
+    fn attributes(&self) -> Pma {
+        Pma::main()
+    }
+
+    fn properties(&self) -> Properties {
+        Properties::new(
+            self.base_frame.load(Ordering::Relaxed),
+            self.locks.len() as u32,
+        )
+    }
+
+    fn register_reservation_set(&'a self, reservation: &'a AtomicU32) {
+        self.reservations
+            .lock()
+            .expect("Failed to grab lock to register reservation set")
+            .push(reservation);
+    }
+
+    fn register_store_callback(&'a self, callback: StoreCallback<'a>) {
+        self.callbacks
+            .write()
+            .expect("Failed to lock store callbacks for registration")
+            .push(callback);
+    }
+
+    fn relocate(&self, base_frame: u32) {
+        self.base_frame.store(base_frame, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicU32;
+
+    use crate::{
+        hart::mmu::addr_to_reservation_set,
+        memory::{
+            atomic_main::AtomicMain,
+            endian::read_bytes,
+            main::Main,
+            mapping::{Mapping, MemoryError, MemoryResult},
+        },
+    };
+
+    #[test]
+    fn empty_operations() -> MemoryResult<()> {
+        let m = AtomicMain::new(0, 2);
+
+        // at the start, the end, and past the end of the memory
+        for offset in [0, 0x2000, 0x10000] {
+            assert_eq!(m.block_write(offset, &[])?, 0);
+            assert_eq!(m.block_write_masked(offset, &[], &[])?, 0);
+            assert_eq!(m.block_read(offset, &mut [])?, 0);
+            assert_eq!(m.block_read_masked(offset, &mut [], &[])?, 0);
+        }
+        for frame in [0, 2] {
+            assert_eq!(m.stream_write(frame, &[])?, 0);
+            assert_eq!(m.stream_read(frame, &[], &mut [])?, 0);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn matches_main() -> MemoryResult<()> {
+        let a = AtomicMain::new(0, 2);
+        let m = Main::new(0, 2);
+
+        for mem in [&a as &dyn Mapping, &m] {
+            mem.store_word(0x10, 0x11223344)?;
+            mem.store_byte(0x11, 0xaa)?;
+            mem.store_half_word(0x12, 0xbbcc)?;
+            mem.block_write(0xffe, &[1, 2, 3, 4, 5, 6])?;
+            mem.stream_write(1, &[(0x8, 2, 0xdead), (0xb, 1, 0x7f)])?;
+            assert_eq!(mem.amoadd_w(0x20, 5)?, 0);
+            assert_eq!(mem.amomax_w(0x20, -1i32 as u32)?, 5);
+        }
+
+        let (mut x, mut y) = ([0; 0x2000], [0; 0x2000]);
+        a.block_read(0, &mut x)?;
+        m.block_read(0, &mut y)?;
+        assert!(x == y, "Memories diverged");
+
+        for offset in [0x10, 0x20, 0xffc, 0x1000, 0x1008] {
+            assert_eq!(a.load_word(offset)?, m.load_word(offset)?);
+            assert_eq!(a.load_half_word(offset + 2)?, m.load_half_word(offset + 2)?);
+            assert_eq!(a.load_byte(offset + 1)?, m.load_byte(offset + 1)?);
+        }
+        assert_eq!(a.load_word(0x20)?, 5);
+        assert!(matches!(
+            a.load_half_word(0x11),
+            Err(MemoryError::LoadMisaligned { alignment: 2, .. })
+        ));
+        assert!(matches!(
+            a.store_word(0x2000, 0),
+            Err(MemoryError::OutOfBoundsAccess { offset: 0x2000 })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn store_conditional() -> MemoryResult<()> {
+        let a = AtomicMain::new(0, 1);
+        let set = addr_to_reservation_set(0x40);
+        let reservation = AtomicU32::new(set);
+        a.register_reservation_set(&reservation);
+
+        assert_eq!(a.store_conditional(0x40, 1, &reservation, set)?, 0);
+        assert_eq!(a.load_word(0x40)?, 1);
+        assert_eq!(
+            a.store_conditional(0x40, 2, &reservation, set)?,
+            1,
+            "Reservation should be consumed by the first store-conditional"
+        );
+
+        reservation.store(set, std::sync::atomic::Ordering::Relaxed);
+        a.amoswap_w(0x44, 3)?;
+        assert_eq!(
+            a.store_conditional(0x40, 2, &reservation, set)?,
+            1,
+            "An AMO in the reservation set should invalidate it"
+        );
+        assert_eq!(a.load_word(0x40)?, 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn concurrent_byte_stores() -> MemoryResult<()> {
+        let a = AtomicMain::new(0, 1);
+
+        std::thread::scope(|s| {
+            for t in 0..4 {
+                let a = &a;
+                s.spawn(move || {
+                    for i in 0..0x3ff {
+                        a.store_byte(i * 4 + t, t as u8 + 1).unwrap();
+                        a.amoadd_w(0xffc, 1).unwrap();
+                    }
+                });
+            }
+        });
+
+        for i in 0..0x3ff {
+            assert_eq!(a.load_word(i * 4)?, read_bytes(&[1, 2, 3, 4]));
+        }
+        assert_eq!(a.load_word(0xffc)?, 4 * 0x3ff);
+
+        Ok(())
+    }
+}
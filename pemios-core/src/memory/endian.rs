@@ -0,0 +1,109 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// This Source Code Form is "Incompatible With Secondary Licenses", as
+// defined by the Mozilla Public License, v. 2.0.
+//
+// Copyright © 2022 mumblingdrunkard
+
+//! The byte order of data in memory.
+//!
+//! Data is little-endian unless the `big-endian` feature is enabled.
+//! Instructions are always little-endian, as RISC-V requires regardless of
+//! the data byte order.
+
+/// Whether multi-byte data is stored most significant byte first
+pub const BIG_ENDIAN: bool = cfg!(feature = "big-endian");
+
+/// Conversion of integers between the byte order of memory and that of the
+/// host, like `from_le`/`to_le` but following [`BIG_ENDIAN`].
+pub trait MemoryOrder: Sized {
+    /// Converts a value whose bytes were copied from memory
+    fn from_memory(value: Self) -> Self;
+
+    /// Converts a value so its bytes can be copied to memory
+    fn to_memory(self) -> Self;
+}
+
+macro_rules! impl_memory_order {
+    ($($t:ty)*) => {
+        $(
+            impl MemoryOrder for $t {
+                #[inline(always)]
+                fn from_memory(value: Self) -> Self {
+                    if BIG_ENDIAN {
+                        <$t>::from_be(value)
+                    } else {
+                        <$t>::from_le(value)
+                    }
+                }
+
+                #[inline(always)]
+                fn to_memory(self) -> Self {
+                    if BIG_ENDIAN {
+                        self.to_be()
+                    } else {
+                        self.to_le()
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_memory_order!(u16 u32);
+
+/// Reads the value stored in the 1, 2, or 4 bytes of `src`.
+#[inline(always)]
+pub fn read_bytes(src: &[u8]) -> u32 {
+    let mut value = [0; 4];
+    if BIG_ENDIAN {
+        value[4 - src.len()..].copy_from_slice(src);
+        u32::from_be_bytes(value)
+    } else {
+        value[..src.len()].copy_from_slice(src);
+        u32::from_le_bytes(value)
+    }
+}
+
+/// Stores the `dst.len()` least significant bytes of `value` in `dst`.
+#[inline(always)]
+pub fn write_bytes(value: u32, dst: &mut [u8]) {
+    if BIG_ENDIAN {
+        dst.copy_from_slice(&value.to_be_bytes()[4 - dst.len()..]);
+    } else {
+        dst.copy_from_slice(&value.to_le_bytes()[..dst.len()]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_bytes, write_bytes, MemoryOrder, BIG_ENDIAN};
+
+    #[test]
+    fn byte_order() {
+        let mut bytes = [0; 4];
+        write_bytes(0x11223344, &mut bytes);
+        let expected = if BIG_ENDIAN {
+            [0x11, 0x22, 0x33, 0x44]
+        } else {
+            [0x44, 0x33, 0x22, 0x11]
+        };
+        assert_eq!(bytes, expected);
+        assert_eq!(read_bytes(&bytes), 0x11223344);
+        assert_eq!(
+            u32::from_memory(u32::from_ne_bytes(bytes)),
+            0x11223344,
+            "Conversions should agree with the byte helpers"
+        );
+        assert_eq!(0x11223344u32.to_memory().to_ne_bytes(), expected);
+
+        write_bytes(0xabcd, &mut bytes[2..]);
+        assert_eq!(read_bytes(&bytes[2..]), 0xabcd);
+        assert_eq!(
+            u16::from_memory(u16::from_ne_bytes([bytes[2], bytes[3]])),
+            0xabcd
+        );
+    }
+}
@@ -9,30 +9,47 @@
 
 use std::{
     ops::RangeInclusive,
-    sync::{atomic::AtomicU32, Mutex},
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Mutex, RwLock,
+    },
 };
 
-use crate::hart::mmu::{helper_check_reservation, helper_invalidate_reservations};
+use crate::hart::mmu::{
+    addr_to_reservation_set, helper_check_reservation, helper_invalidate_reservations,
+};
 
-use super::mapping::{Mapping, MemoryError, MemoryResult, Pma, Properties};
+use super::{
+    endian::{read_bytes, write_bytes, MemoryOrder},
+    mapping::{Mapping, MemoryError, MemoryResult, Pma, Properties, StoreCallback},
+};
 
-type Frame = [u32; 1024];
+pub type Frame = [u32; 1024];
 
 /// A main memory region that supports all memory operations
 pub struct Main<'a> {
-    base_frame: u32,
-    frames: Vec<Mutex<Frame>>,
+    // the frame the bus maps the memory at, see `Mapping::relocate`
+    base_frame: AtomicU32,
+    frames: Vec<RwLock<Frame>>,
     reservations: Mutex<Vec<&'a AtomicU32>>,
+    callbacks: RwLock<Vec<StoreCallback<'a>>>,
 }
 
 impl<'a> Main<'a> {
-    fn check_offset<const W: usize>(&self, offset: u32) -> MemoryResult<(usize, usize)> {
+    /// Finds the frame and the index of the element of width `W` in it for an
+    /// access at `offset`, which is a store if `STORE` is set.
+    fn check_offset<const W: usize, const STORE: bool>(
+        &self,
+        offset: u32,
+    ) -> MemoryResult<(usize, usize)> {
         assert!(matches!(W, 1 | 2 | 4), "Width must be 1, 2, or 4");
 
         if offset & (W as u32 - 1) != 0 {
-            return Err(MemoryError::StoreMisaligned {
-                offset,
-                alignment: W as u32,
+            let alignment = W as u32;
+            return Err(if STORE {
+                MemoryError::StoreMisaligned { offset, alignment }
+            } else {
+                MemoryError::LoadMisaligned { offset, alignment }
             });
         }
 
@@ -46,6 +63,54 @@ impl<'a> Main<'a> {
         Ok((frame_number, index))
     }
 
+    /// How many of the `len` bytes starting at `offset` are backed by a frame
+    fn backed_len(&self, offset: u32, len: usize) -> usize {
+        let size = self.frames.len() << 12;
+        std::cmp::min(len, size.saturating_sub(offset as usize))
+    }
+
+    /// Checks a single access of a stream operation on the frame starting at
+    /// `base`.
+    ///
+    /// Main memory does not support misaligned accesses, so these panic as
+    /// required by the `Mapping` contract for stream operations.
+    pub(super) fn check_stream_access(
+        base: u32,
+        offset: u16,
+        width: u8,
+        is_store: bool,
+    ) -> MemoryResult<()> {
+        let offset = offset as u32;
+        let width = width as u32;
+
+        if !matches!(width, 1 | 2 | 4) {
+            return Err(MemoryError::SizeUnsupported {
+                offset: base | offset,
+                size: width,
+            });
+        }
+
+        if offset + width > 0x1000 {
+            return Err(MemoryError::OutOfBoundsAccess {
+                offset: base + offset,
+            });
+        }
+
+        assert!(
+            offset & (width - 1) == 0,
+            "Misaligned stream {} of width {width} at offset {:#x}",
+            if is_store { "write" } else { "read" },
+            base | offset,
+        );
+
+        Ok(())
+    }
+
+    /// The reservation set holding the byte at `offset`, as named by harts.
+    fn reservation_set(&self, offset: u32) -> u32 {
+        addr_to_reservation_set((self.base_frame.load(Ordering::Relaxed) << 12) + offset)
+    }
+
     fn invalidate_reservation_range(&self, should_be: RangeInclusive<u32>) {
         self.reservations
             .lock()
@@ -57,13 +122,22 @@ impl<'a> Main<'a> {
             .expect("Failed to lock reservation sets for invalidation!");
     }
 
+    /// Calls every registered store callback with `offset`.
+    fn notify_store(&self, offset: u32) {
+        self.callbacks
+            .read()
+            .expect("Failed to lock store callbacks")
+            .iter()
+            .for_each(|callback| callback(offset));
+    }
+
     fn store<const W: usize>(&self, offset: u32, val: u32) -> MemoryResult<()> {
         assert!(matches!(W, 1 | 2 | 4), "Store width must be 1, 2, or 4");
-        let (frame_number, index) = self.check_offset::<W>(offset)?;
+        let (frame_number, index) = self.check_offset::<W, true>(offset)?;
         self.frames
             .get(frame_number)
             .and_then(|m| {
-                m.lock()
+                m.write()
                     .and_then(|mut g| {
                         match W {
                             1 => unsafe {
@@ -72,47 +146,79 @@ impl<'a> Main<'a> {
                             },
                             2 => unsafe {
                                 let (_, half_words, _) = g.align_to_mut::<u16>();
-                                *half_words.get_unchecked_mut(index) = val as u16
+                                *half_words.get_unchecked_mut(index) = (val as u16).to_memory()
                             },
-                            4 => unsafe { *g.get_unchecked_mut(index) = val },
+                            4 => unsafe { *g.get_unchecked_mut(index) = val.to_memory() },
                             _ => unsafe { std::hint::unreachable_unchecked() },
                         }
 
+                        // invalidated while the frame is still locked, so an sc
+                        // can not succeed between the store and the invalidation
+                        let set = self.reservation_set(offset);
+                        self.invalidate_reservation_range(set..=set);
+
                         Ok(())
                     })
                     .expect(
-                        "Tried to acquire frame, but .lock() returned an error.\
-Did a thread exit unexpectedly while holding this Mutex?",
+                        "Tried to acquire frame, but the lock was poisoned.\
+Did a thread exit unexpectedly while holding this RwLock?",
                     );
 
                 Some(())
             })
-            .ok_or(MemoryError::OutOfBoundsAccess { offset })
+            .ok_or(MemoryError::OutOfBoundsAccess { offset })?;
+
+        self.notify_store(offset);
+        Ok(())
+    }
+
+    /// Atomically replaces the word at `offset` with `op(old)`, returning
+    /// `old`.
+    fn amo<F: FnOnce(u32) -> u32>(&self, offset: u32, op: F) -> MemoryResult<u32> {
+        let (frame_number, index) = self.check_offset::<4, true>(offset)?;
+        let set = self.reservation_set(offset);
+
+        let old = self.frames[frame_number]
+            .write()
+            .and_then(|mut g| {
+                let old = u32::from_memory(g[index]);
+                g[index] = op(old).to_memory();
+
+                self.invalidate_reservation_range(set..=set);
+                Ok(old)
+            })
+            .expect(
+                "Tried to acquire frame, but the lock was poisoned.\
+Did a thread exit unexpectedly while holding this RwLock?",
+            );
+
+        self.notify_store(offset);
+        Ok(old)
     }
 
     fn load<const W: usize>(&self, offset: u32) -> Result<u32, MemoryError> {
         assert!(matches!(W, 1 | 2 | 4), "Load width must be 1, 2, or 4");
-        let (frame_number, index) = self.check_offset::<W>(offset)?;
+        let (frame_number, index) = self.check_offset::<W, false>(offset)?;
         self.frames
             .get(frame_number)
             .and_then(|m| {
                 let value = m
-                    .lock()
-                    .and_then(|mut g| match W {
+                    .read()
+                    .and_then(|g| match W {
                         1 => unsafe {
-                            let (_, bytes, _) = g.align_to_mut::<u8>();
+                            let (_, bytes, _) = g.align_to::<u8>();
                             Ok(*bytes.get_unchecked(index) as u32)
                         },
                         2 => unsafe {
-                            let (_, half_words, _) = g.align_to_mut::<u16>();
-                            Ok(*half_words.get_unchecked(index) as u32)
+                            let (_, half_words, _) = g.align_to::<u16>();
+                            Ok(u16::from_memory(*half_words.get_unchecked(index)) as u32)
                         },
-                        4 => unsafe { Ok(*g.get_unchecked(index)) },
+                        4 => unsafe { Ok(u32::from_memory(*g.get_unchecked(index))) },
                         _ => unsafe { std::hint::unreachable_unchecked() },
                     })
                     .expect(
-                        "Tried to acquire frame, but .lock() returned an error.\
-Did a thread exit unexpectedly while holding this Mutex?",
+                        "Tried to acquire frame, but the lock was poisoned.\
+Did a thread exit unexpectedly while holding this RwLock?",
                     );
 
                 Some(value)
@@ -130,49 +236,70 @@ Did a thread exit unexpectedly while holding this Mutex?",
             panic!("Mask must contain enough bits to mask src!");
         }
 
-        let start = offset as usize >> 12;
-        let end = (offset as usize + src.len() - 1) >> 12;
-
-        if end >= self.frames.len() {
-            return Err(MemoryError::OutOfBoundsAccess { offset });
+        // bytes past the last frame are not backed and are skipped
+        let backed = self.backed_len(offset, src.len());
+        if backed == 0 {
+            return Ok(0);
         }
 
+        let start = offset as usize >> 12;
+        let end = (offset as usize + backed - 1) >> 12;
+        let src = &src[..backed];
+
         let mut frame_offs = offset as usize & 0xfff; // frame offset
         let mut src_offs = 0; // data offset
         let mut written = 0;
 
-        self.frames[start..=end].iter().for_each(|frame| {
-            frame
-                .lock()
-                .and_then(|mut g| {
-                    let (_, dst, _) = unsafe { g.align_to_mut::<u8>() };
-                    let n = std::cmp::min(dst.len() - frame_offs, src.len() - src_offs);
-                    for i in 0..n {
-                        let mask_index = src_offs + i;
-                        let mask_byte = mask_index >> 3;
-                        let mask_bit = mask_index & 7;
+        self.frames[start..=end]
+            .iter()
+            .zip(start..)
+            .for_each(|(frame, number)| {
+                frame
+                    .write()
+                    .and_then(|mut g| {
+                        let (_, dst, _) = unsafe { g.align_to_mut::<u8>() };
+                        let n = std::cmp::min(dst.len() - frame_offs, src.len() - src_offs);
+                        let written_before = written;
                         if !M {
                             dst[frame_offs..frame_offs + n]
                                 .clone_from_slice(&src[src_offs..src_offs + n]);
-                        } else if (unsafe { mask.get_unchecked(mask_byte) } >> mask_bit) & 1 == 1 {
-                            // if Masked and mask bit is set
-                            written += 1;
-                            dst[frame_offs + i] = src[src_offs + i];
+                            written += n;
+                        } else {
+                            for i in 0..n {
+                                let mask_index = src_offs + i;
+                                let mask_byte = mask_index >> 3;
+                                let mask_bit = mask_index & 7;
+                                if (unsafe { mask.get_unchecked(mask_byte) } >> mask_bit) & 1 == 1 {
+                                    written += 1;
+                                    dst[frame_offs + i] = src[src_offs + i];
+                                }
+                            }
+                        }
+                        // the reservation sets touched in this frame are
+                        // invalidated while the frame is still locked, like for
+                        // single stores
+                        if written > written_before {
+                            let first = ((number << 12) + frame_offs) as u32;
+                            let last = first + n as u32 - 1;
+                            self.invalidate_reservation_range(
+                                self.reservation_set(first)..=self.reservation_set(last),
+                            );
                         }
-                    }
-                    src_offs += n;
-                    frame_offs = 0;
 
-                    // TODO invalidate reservations
+                        src_offs += n;
+                        frame_offs = 0;
 
-                    Ok(())
-                })
-                .expect(
-                    "Tried to acquire frame, but .lock() returned an error.\
-Did a thread exit unexpectedly while holding this Mutex?",
-                )
-        });
+                        Ok(())
+                    })
+                    .expect(
+                        "Tried to acquire frame, but the lock was poisoned.\
+Did a thread exit unexpectedly while holding this RwLock?",
+                    )
+            });
 
+        if written > 0 {
+            self.notify_store(offset);
+        }
         Ok(written)
     }
 }
@@ -187,19 +314,22 @@ impl<'a> Mapping<'a> for Main<'a> {
     }
 
     fn block_read(&self, offset: u32, dst: &mut [u8]) -> Result<usize, MemoryError> {
-        let start = offset as usize >> 12;
-        let end = (offset as usize + dst.len() - 1) >> 12;
-
-        if end >= self.frames.len() {
-            return Err(MemoryError::OutOfBoundsAccess { offset });
+        // bytes past the last frame are not backed and are left untouched
+        let backed = self.backed_len(offset, dst.len());
+        if backed == 0 {
+            return Ok(0);
         }
 
+        let start = offset as usize >> 12;
+        let end = (offset as usize + backed - 1) >> 12;
+        let dst = &mut dst[..backed];
+
         let mut frame_offs = offset as usize & 0xfff; // frame offset
         let mut dst_offs = 0; // data offset
 
         self.frames[start..=end].iter().for_each(|frame| {
             frame
-                .lock()
+                .read()
                 .and_then(|g| {
                     // calculate number of elements
                     let (_, src, _) = unsafe { g.align_to::<u8>() };
@@ -215,8 +345,8 @@ impl<'a> Mapping<'a> for Main<'a> {
                     Ok(())
                 })
                 .expect(
-                    "Tried to acquire frame, but .lock() returned an error.\
-Did a thread exit unexpectedly while holding this Mutex?",
+                    "Tried to acquire frame, but the lock was poisoned.\
+Did a thread exit unexpectedly while holding this RwLock?",
                 )
         });
 
@@ -229,29 +359,95 @@ Did a thread exit unexpectedly while holding this Mutex?",
         Ok(dst_offs)
     }
 
-    fn block_read_masked(
-        &self,
-        _offset: u32,
-        _dst: &mut [u8],
-        _mask: &[u8],
-    ) -> MemoryResult<usize> {
-        if _mask.len() * 8 < _dst.len() {
+    fn block_read_masked(&self, offset: u32, dst: &mut [u8], mask: &[u8]) -> MemoryResult<usize> {
+        if mask.len() * 8 < dst.len() {
             panic!("Mask must contain enough bits to mask src!");
         }
-        todo!()
+
+        let mut src = vec![0; dst.len()];
+        let read = self.block_read(offset, &mut src)?;
+        src[..read]
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| (mask[i >> 3] >> (i & 7)) & 1 == 1)
+            .for_each(|(i, &b)| dst[i] = b);
+
+        Ok(read)
     }
 
-    fn stream_write(&self, _offset: u32, _writes: &[(u16, u8, u32)]) -> MemoryResult<usize> {
-        todo!()
+    fn stream_write(&self, frame: u32, writes: &[(u16, u8, u32)]) -> MemoryResult<usize> {
+        if writes.is_empty() {
+            return Ok(0);
+        }
+
+        let base = frame << 12;
+        let f = self
+            .frames
+            .get(frame as usize)
+            .ok_or(MemoryError::OutOfBoundsAccess { offset: base })?;
+
+        // validate everything up front so the batch is applied fully or not at all
+        for &(offset, width, _) in writes {
+            Self::check_stream_access(base, offset, width, true)?;
+        }
+
+        f.write()
+            .map(|mut g| {
+                let (_, bytes, _) = unsafe { g.align_to_mut::<u8>() };
+                for &(offset, width, value) in writes {
+                    let offset = offset as usize;
+                    let width = width as usize;
+                    write_bytes(value, &mut bytes[offset..offset + width]);
+                }
+            })
+            .expect(
+                "Tried to acquire frame, but the lock was poisoned.\
+Did a thread exit unexpectedly while holding this RwLock?",
+            );
+
+        writes
+            .iter()
+            .for_each(|&(offset, ..)| self.notify_store(base + offset as u32));
+        Ok(writes.len())
     }
 
-    fn stream_read(
-        &self,
-        _offset: u32,
-        _reads: &[(u16, u8)],
-        _dst: &mut [u32],
-    ) -> MemoryResult<usize> {
-        todo!()
+    fn stream_read(&self, frame: u32, reads: &[(u16, u8)], dst: &mut [u32]) -> MemoryResult<usize> {
+        assert_eq!(
+            reads.len(),
+            dst.len(),
+            "dst must have room for exactly one value per read"
+        );
+
+        if reads.is_empty() {
+            return Ok(0);
+        }
+
+        let base = frame << 12;
+        let f = self
+            .frames
+            .get(frame as usize)
+            .ok_or(MemoryError::OutOfBoundsAccess { offset: base })?;
+
+        for &(offset, width) in reads {
+            Self::check_stream_access(base, offset, width, false)?;
+        }
+
+        // one lock for the whole batch makes the reads a consistent snapshot
+        f.read()
+            .map(|g| {
+                let (_, bytes, _) = unsafe { g.align_to::<u8>() };
+                for (&(offset, width), d) in reads.iter().zip(dst.iter_mut()) {
+                    let offset = offset as usize;
+                    let width = width as usize;
+                    *d = read_bytes(&bytes[offset..offset + width]);
+                }
+            })
+            .expect(
+                "Tried to acquire frame, but the lock was poisoned.\
+Did a thread exit unexpectedly while holding this RwLock?",
+            );
+
+        Ok(reads.len())
     }
 
     fn store_byte(&self, offset: u32, byte: u8) -> MemoryResult<()> {
@@ -278,40 +474,40 @@ Did a thread exit unexpectedly while holding this Mutex?",
         self.load::<4>(offset)
     }
 
-    fn amoswap_w(&self, _offset: u32, _src: u32) -> Result<u32, MemoryError> {
-        todo!()
+    fn amoswap_w(&self, offset: u32, src: u32) -> Result<u32, MemoryError> {
+        self.amo(offset, |_| src)
     }
 
-    fn amoadd_w(&self, _offset: u32, _src: u32) -> Result<u32, MemoryError> {
-        todo!()
+    fn amoadd_w(&self, offset: u32, src: u32) -> Result<u32, MemoryError> {
+        self.amo(offset, |old| old.wrapping_add(src))
     }
 
-    fn amoand_w(&self, _offset: u32, _src: u32) -> Result<u32, MemoryError> {
-        todo!()
+    fn amoand_w(&self, offset: u32, src: u32) -> Result<u32, MemoryError> {
+        self.amo(offset, |old| old & src)
     }
 
-    fn amoor_w(&self, _offset: u32, _src: u32) -> Result<u32, MemoryError> {
-        todo!()
+    fn amoor_w(&self, offset: u32, src: u32) -> Result<u32, MemoryError> {
+        self.amo(offset, |old| old | src)
     }
 
-    fn amoxor_w(&self, _offset: u32, _src: u32) -> Result<u32, MemoryError> {
-        todo!()
+    fn amoxor_w(&self, offset: u32, src: u32) -> Result<u32, MemoryError> {
+        self.amo(offset, |old| old ^ src)
     }
 
-    fn amomax_w(&self, _offset: u32, _src: u32) -> Result<u32, MemoryError> {
-        todo!()
+    fn amomax_w(&self, offset: u32, src: u32) -> Result<u32, MemoryError> {
+        self.amo(offset, |old| (old as i32).max(src as i32) as u32)
     }
 
-    fn amomaxu_w(&self, _offset: u32, _src: u32) -> Result<u32, MemoryError> {
-        todo!()
+    fn amomaxu_w(&self, offset: u32, src: u32) -> Result<u32, MemoryError> {
+        self.amo(offset, |old| old.max(src))
     }
 
-    fn amomin_w(&self, _offset: u32, _src: u32) -> Result<u32, MemoryError> {
-        todo!()
+    fn amomin_w(&self, offset: u32, src: u32) -> Result<u32, MemoryError> {
+        self.amo(offset, |old| (old as i32).min(src as i32) as u32)
     }
 
-    fn amominu_w(&self, _offset: u32, _src: u32) -> Result<u32, MemoryError> {
-        todo!()
+    fn amominu_w(&self, offset: u32, src: u32) -> Result<u32, MemoryError> {
+        self.amo(offset, |old| old.min(src))
     }
 
     fn attributes(&self) -> Pma {
@@ -319,7 +515,10 @@ Did a thread exit unexpectedly while holding this Mutex?",
     }
 
     fn properties(&self) -> Properties {
-        Properties::new(self.base_frame, self.frames.len() as u32)
+        Properties::new(
+            self.base_frame.load(Ordering::Relaxed),
+            self.frames.len() as u32,
+        )
     }
 
     fn register_reservation_set(&'a self, reservation: &'a AtomicU32) {
@@ -332,6 +531,17 @@ Did a thread exit unexpectedly while holding this Mutex?",
             .expect("Failed to grab lock to invalidate reservations");
     }
 
+    fn register_store_callback(&'a self, callback: StoreCallback<'a>) {
+        self.callbacks
+            .write()
+            .expect("Failed to lock store callbacks for registration")
+            .push(callback);
+    }
+
+    fn relocate(&self, base_frame: u32) {
+        self.base_frame.store(base_frame, Ordering::Relaxed);
+    }
+
     fn store_conditional(
         &self,
         offset: u32,
@@ -339,15 +549,15 @@ Did a thread exit unexpectedly while holding this Mutex?",
         reservation: &AtomicU32,
         should_be: u32,
     ) -> MemoryResult<u32> {
-        let (pfn, b) = self.check_offset::<4>(offset)?;
+        let (pfn, b) = self.check_offset::<4, true>(offset)?;
 
         let success = self.frames[pfn]
-            .lock()
+            .write()
             .and_then(|mut g| {
                 let success = helper_check_reservation(reservation, should_be);
-                if success == 1 {
+                if success == 0 {
                     // perform the store
-                    g[b] = src;
+                    g[b] = src.to_memory();
 
                     // ... and invalidate reservations
                     self.reservations
@@ -361,10 +571,13 @@ Did a thread exit unexpectedly while holding this Mutex?",
                 Ok(success)
             })
             .expect(
-                "Tried to acquire frame, but .lock() returned an error.\
-Did a thread exit unexpectedly while holding this Mutex?",
+                "Tried to acquire frame, but the lock was poisoned.\
+Did a thread exit unexpectedly while holding this RwLock?",
             );
 
+        if success == 0 {
+            self.notify_store(offset);
+        }
         Ok(success)
     }
 }
@@ -372,20 +585,142 @@ Did a thread exit unexpectedly while holding this Mutex?",
 impl<'a> Main<'a> {
     /// Create a new main memory with `pages` pages of 4096 bytes each.
     pub fn new(base_frame: u32, frame_count: u32) -> Self {
-        let frames = (0..frame_count).map(|_| Mutex::new([0; 1024])).collect();
+        Self::poisoned(base_frame, frame_count, 0)
+    }
+
+    /// Create a new main memory where every word reads as `pattern` until it
+    /// is written, so that reads of uninitialized memory stand out.
+    pub fn poisoned(base_frame: u32, frame_count: u32, pattern: u32) -> Self {
+        let frame = [pattern.to_memory(); 1024];
+        let frames = (0..frame_count).map(|_| RwLock::new(frame)).collect();
         Self {
-            base_frame,
+            base_frame: AtomicU32::new(base_frame),
             frames,
             reservations: Mutex::new(Vec::new()),
+            callbacks: RwLock::new(Vec::new()),
         }
     }
+
+    /// Create a new main memory just large enough to hold `data`, which is
+    /// copied to its start.
+    pub fn from_bytes(base_frame: u32, data: &[u8]) -> Self {
+        let frame_count = data.len().div_ceil(4096) as u32;
+        Self::with_data(base_frame, frame_count, data)
+    }
+
+    /// Create a new main memory with `frame_count` frames, with `data` copied
+    /// to its start and the rest zeroed.
+    ///
+    /// Panics if `data` does not fit.
+    pub fn with_data(base_frame: u32, frame_count: u32, data: &[u8]) -> Self {
+        assert!(
+            data.len() <= (frame_count as usize) << 12,
+            "Data does not fit in {frame_count} frames"
+        );
+
+        let main = Self::new(base_frame, frame_count);
+        main.block_write(0, data)
+            .expect("Writing to fresh memory should not fail");
+        main
+    }
+
+    /// Sets the `len` bytes starting at `offset` to `byte` without going
+    /// through a source buffer, e.g. to zero `.bss`.
+    ///
+    /// Bytes past the last frame are skipped, like with `block_write`, and
+    /// reservations are invalidated like for any other store.
+    pub fn fill(&self, offset: u32, len: usize, byte: u8) {
+        let backed = self.backed_len(offset, len);
+        if backed == 0 {
+            return;
+        }
+
+        let start = offset as usize >> 12;
+        let end = (offset as usize + backed - 1) >> 12;
+        let mut frame_offs = offset as usize & 0xfff;
+        let mut left = backed;
+
+        self.frames[start..=end]
+            .iter()
+            .zip(start..)
+            .for_each(|(frame, number)| {
+                let mut g = frame.write().expect(
+                    "Tried to acquire frame, but the lock was poisoned.\
+Did a thread exit unexpectedly while holding this RwLock?",
+                );
+                let (_, dst, _) = unsafe { g.align_to_mut::<u8>() };
+                let n = std::cmp::min(dst.len() - frame_offs, left);
+                dst[frame_offs..frame_offs + n].fill(byte);
+
+                let first = ((number << 12) + frame_offs) as u32;
+                let last = first + n as u32 - 1;
+                self.invalidate_reservation_range(
+                    self.reservation_set(first)..=self.reservation_set(last),
+                );
+
+                left -= n;
+                frame_offs = 0;
+            });
+
+        self.notify_store(offset);
+    }
+
+    /// Zeroes every frame.
+    pub fn clear(&self) {
+        self.fill(0, self.frames.len() << 12, 0);
+    }
+
+    /// Copies the contents of every frame.
+    ///
+    /// Stores still held in a hart's data cache are not included, so harts
+    /// should write them back first.
+    pub fn snapshot(&self) -> Vec<Frame> {
+        self.frames
+            .iter()
+            .map(|frame| *frame.read().expect("Failed to lock frame for snapshot"))
+            .collect()
+    }
+
+    /// Replaces the contents of every frame with `snapshot`, which must have
+    /// been taken from a memory of the same size.
+    ///
+    /// Like any other store, this invalidates all registered reservations.
+    pub fn restore(&self, snapshot: &[Frame]) {
+        assert_eq!(
+            snapshot.len(),
+            self.frames.len(),
+            "Snapshot does not match the size of the memory"
+        );
+
+        self.frames.iter().zip(snapshot).for_each(|(frame, src)| {
+            *frame.write().expect("Failed to lock frame for restore") = *src;
+        });
+
+        self.reservations
+            .lock()
+            .expect("Failed to grab lock to invalidate reservations")
+            .iter()
+            .for_each(|r| r.store(u32::MAX, Ordering::Relaxed));
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::memory::{
-        main::Main,
-        mapping::{Mapping, MemoryResult},
+    use std::{
+        sync::{
+            atomic::{AtomicU32, Ordering},
+            Mutex,
+        },
+        thread,
+    };
+
+    use crate::{
+        hart::mmu::addr_to_reservation_set,
+        memory::{
+            endian::{read_bytes, BIG_ENDIAN},
+            main::Main,
+            mapping::{Mapping, MemoryError, MemoryResult},
+        },
     };
 
     #[test]
@@ -398,6 +733,207 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn store_callbacks() -> MemoryResult<()> {
+        let stores = Mutex::new(Vec::new());
+        let callback = |offset| stores.lock().unwrap().push(offset);
+        let m = Main::new(0, 2);
+        m.register_store_callback(&callback);
+
+        m.store_word(0x60, 69)?;
+        m.store_byte(0x1001, 1)?;
+        m.amoadd_w(0x60, 1)?;
+        m.block_write(0xffe, &[0; 4])?;
+        m.load_word(0x60)?;
+        assert!(m.store_word(0x2000, 0).is_err());
+
+        assert_eq!(*stores.lock().unwrap(), [0x60, 0x1001, 0x60, 0xffe]);
+        Ok(())
+    }
+
+    #[test]
+    fn stores_invalidate_reservations() -> MemoryResult<()> {
+        let sets = [0x40, 0x80].map(addr_to_reservation_set);
+        let reservations = sets.map(AtomicU32::new);
+        let m = Main::new(0, 1);
+        reservations
+            .iter()
+            .for_each(|r| m.register_reservation_set(r));
+
+        m.store_byte(0x7f, 1)?;
+        assert_eq!(reservations[0].load(Ordering::Relaxed), u32::MAX);
+        assert_eq!(reservations[1].load(Ordering::Relaxed), sets[1]);
+        assert_eq!(
+            m.store_conditional(0x40, 5, &reservations[0], sets[0])?,
+            1,
+            "sc should fail after a store to the reservation set"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn block_write_invalidates_reservations() -> MemoryResult<()> {
+        let sets = [0x1000, 0x40, 0x1040].map(addr_to_reservation_set);
+        let reservations = sets.map(AtomicU32::new);
+        let m = Main::new(0, 2);
+        reservations
+            .iter()
+            .for_each(|r| m.register_reservation_set(r));
+
+        // crosses into the second frame, ending in the reservation set at 0x1000
+        thread::scope(|s| {
+            s.spawn(|| m.block_write(0xff8, &[1; 16]).unwrap());
+        });
+
+        assert_eq!(
+            m.store_conditional(0x1000, 5, &reservations[0], sets[0])?,
+            1,
+            "sc should fail after a block write over the reservation"
+        );
+        assert_eq!(reservations[1].load(Ordering::Relaxed), sets[1]);
+        assert_eq!(reservations[2].load(Ordering::Relaxed), sets[2]);
+
+        // masked out bytes are not written, so they do not invalidate
+        m.block_write_masked(0x1040, &[2; 8], &[0])?;
+        assert_eq!(reservations[2].load(Ordering::Relaxed), sets[2]);
+        m.block_write_masked(0x1040, &[2; 8], &[0x80])?;
+        assert_eq!(reservations[2].load(Ordering::Relaxed), u32::MAX);
+
+        Ok(())
+    }
+
+    #[test]
+    fn from_bytes() -> MemoryResult<()> {
+        let data = (0..=0xffu8).cycle().take(4100).collect::<Vec<_>>();
+        let m = Main::from_bytes(0, &data);
+        assert_eq!(m.properties().frame_count(), 2);
+        assert_eq!(m.load_byte(0x1003)?, 0x03);
+        assert_eq!(m.load_word(0x1004)?, 0);
+
+        let mut word = [0; 4];
+        m.block_read(0x20, &mut word)?;
+        assert_eq!(word, [0x20, 0x21, 0x22, 0x23]);
+
+        let m = Main::with_data(0, 4, &data[..8]);
+        assert_eq!(m.properties().frame_count(), 4);
+        m.block_read(4, &mut word)?;
+        assert_eq!(word, [4, 5, 6, 7]);
+        assert_eq!(m.load_word(0x3ffc)?, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    #[should_panic(expected = "Data does not fit in 1 frames")]
+    fn with_data_too_large() {
+        Main::with_data(0, 1, &[0; 4097]);
+    }
+
+    #[test]
+    fn byte_order() -> MemoryResult<()> {
+        let m = Main::new(0, 1);
+        m.store_word(0x10, 0x11223344)?;
+
+        let mut bytes = [0; 4];
+        m.block_read(0x10, &mut bytes)?;
+        if BIG_ENDIAN {
+            assert_eq!(bytes, [0x11, 0x22, 0x33, 0x44]);
+            assert_eq!(m.load_half_word(0x10)?, 0x1122);
+        } else {
+            assert_eq!(bytes, [0x44, 0x33, 0x22, 0x11]);
+            assert_eq!(m.load_half_word(0x10)?, 0x3344);
+        }
+        assert_eq!(m.load_byte(0x10)?, bytes[0]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn misaligned() {
+        let m = Main::new(0, 1);
+        assert!(matches!(
+            m.load_word(0x62),
+            Err(MemoryError::LoadMisaligned {
+                offset: 0x62,
+                alignment: 4
+            })
+        ));
+        assert!(matches!(
+            m.store_word(0x62, 0),
+            Err(MemoryError::StoreMisaligned {
+                offset: 0x62,
+                alignment: 4
+            })
+        ));
+        assert!(matches!(
+            m.load_half_word(0x61),
+            Err(MemoryError::LoadMisaligned { alignment: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn stream_write() -> MemoryResult<()> {
+        let m = Main::new(0, 2);
+        let written = m.stream_write(
+            1,
+            &[(0x10, 4, 0xdeadbeef), (0x14, 2, 0xcafe), (0x17, 1, 0x42)],
+        )?;
+        assert_eq!(written, 3);
+        assert_eq!(m.load_word(0x1010)?, 0xdeadbeef);
+        assert_eq!(m.load_half_word(0x1014)?, 0xcafe);
+        assert_eq!(m.load_byte(0x1017)?, 0x42);
+        assert!(m.stream_write(2, &[(0, 1, 0)]).is_err());
+        assert!(m.stream_write(0, &[(0, 3, 0)]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    #[should_panic]
+    fn stream_write_misaligned() {
+        let m = Main::new(0, 1);
+        let _ = m.stream_write(0, &[(0x2, 4, 0)]);
+    }
+
+    #[test]
+    fn empty_operations() -> MemoryResult<()> {
+        let m = Main::new(0, 2);
+
+        // at the start, the end, and past the end of the memory
+        for offset in [0, 0x2000, 0x10000] {
+            assert_eq!(m.block_write(offset, &[])?, 0);
+            assert_eq!(m.block_write_masked(offset, &[], &[])?, 0);
+            assert_eq!(m.block_read(offset, &mut [])?, 0);
+            assert_eq!(m.block_read_masked(offset, &mut [], &[])?, 0);
+        }
+        for frame in [0, 2] {
+            assert_eq!(m.stream_write(frame, &[])?, 0);
+            assert_eq!(m.stream_read(frame, &[], &mut [])?, 0);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn stream_read() -> MemoryResult<()> {
+        let m = Main::new(0, 2);
+        m.store_word(0x1010, 0xdeadbeef)?;
+        m.store_half_word(0x1014, 0xcafe)?;
+        m.store_byte(0x1017, 0x42)?;
+
+        let mut dst = [0; 4];
+        let read = m.stream_read(1, &[(0x10, 4), (0x14, 2), (0x17, 1), (0x12, 2)], &mut dst)?;
+        assert_eq!(read, 4);
+        let high = if BIG_ENDIAN { 0xbeef } else { 0xdead };
+        assert_eq!(dst, [0xdeadbeef, 0xcafe, 0x42, high]);
+        Ok(())
+    }
+
+    #[test]
+    #[should_panic]
+    fn stream_read_length_mismatch() {
+        let m = Main::new(0, 1);
+        let _ = m.stream_read(0, &[(0, 4), (4, 4)], &mut [0]);
+    }
+
     #[test]
     fn block_read_write() -> MemoryResult<()> {
         let m = Main::new(0, 1);
@@ -408,4 +944,77 @@ mod tests {
         assert_eq!(c, b, "Write or read failed");
         Ok(())
     }
+
+    #[test]
+    fn block_ops_skip_unbacked_bytes() -> MemoryResult<()> {
+        let m = Main::new(0, 1);
+        let src = [0xaa; 0x20];
+        assert_eq!(m.block_write(0xff0, &src)?, 0x10);
+        assert_eq!(m.block_write(0x2000, &src)?, 0);
+
+        let mut dst = [0x55; 0x20];
+        assert_eq!(m.block_read(0xff0, &mut dst)?, 0x10);
+        assert_eq!(dst[..0x10], [0xaa; 0x10]);
+        assert_eq!(
+            dst[0x10..],
+            [0x55; 0x10],
+            "Unbacked bytes should be untouched"
+        );
+
+        let mask = [0b0000_0101, 0, 0, 0];
+        assert_eq!(m.block_write_masked(0xff0, &[0; 0x20], &mask)?, 2);
+        assert_eq!(m.load_word(0xff0)?, read_bytes(&[0, 0xaa, 0, 0xaa]));
+
+        Ok(())
+    }
+
+    #[test]
+    fn fill_and_clear() -> MemoryResult<()> {
+        let set = addr_to_reservation_set(0x1000);
+        let reservation = AtomicU32::new(set);
+        let m = Main::poisoned(0, 2, 0x55555555);
+        m.register_reservation_set(&reservation);
+
+        // crosses into the second frame
+        m.fill(0xff2, 0x20, 0xaa);
+        assert_eq!(m.load_half_word(0xff0)?, 0x5555);
+        assert_eq!(m.load_half_word(0xff2)?, 0xaaaa);
+        assert_eq!(m.load_word(0xffc)?, 0xaaaaaaaa);
+        assert_eq!(m.load_word(0x100c)?, 0xaaaaaaaa);
+        assert_eq!(m.load_half_word(0x1012)?, 0x5555);
+        assert_eq!(reservation.load(Ordering::Relaxed), u32::MAX);
+
+        // bytes past the end are skipped
+        m.fill(0x1ffc, 8, 0x11);
+        assert_eq!(m.load_word(0x1ffc)?, 0x11111111);
+
+        m.clear();
+        assert!(m.snapshot().iter().flatten().all(|&w| w == 0));
+        Ok(())
+    }
+
+    #[test]
+    fn concurrent_reads() -> MemoryResult<()> {
+        let m = Main::new(0, 2);
+        for i in 0..0x800 {
+            m.store_word(i * 4, i)?;
+        }
+
+        std::thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    let mut buf = [0; 0x2000];
+                    for _ in 0..100 {
+                        for i in 0..0x800 {
+                            assert_eq!(m.load_word(i * 4).unwrap(), i);
+                        }
+                        assert_eq!(m.block_read(0, &mut buf).unwrap(), 0x2000);
+                        assert_eq!(read_bytes(&buf[0x1004..0x1008]), 0x401);
+                    }
+                });
+            }
+        });
+
+        Ok(())
+    }
 }
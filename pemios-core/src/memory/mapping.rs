@@ -17,7 +17,7 @@ pub enum MemoryKind {
 }
 
 #[allow(unused)]
-#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy)]
 pub enum AmoClass {
     /// No atomics; all atomic operations will fail
     None = 0,
@@ -83,7 +83,7 @@ pub enum Cacheability {
 }
 
 #[allow(unused)]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Pma {
     kind: MemoryKind,
     amo: AmoClass,
@@ -109,6 +109,48 @@ impl Pma {
         Self::default()
     }
 
+    /// Attributes for device registers, where every access may have side
+    /// effects and must reach the device.
+    /// Atomics and reservations are not supported.
+    pub fn io() -> Self {
+        Self {
+            kind: MemoryKind::Io,
+            amo: AmoClass::None,
+            reservability: Reservability::None,
+            idempotency: Idempotency::NonIdempotent,
+            cacheability: Cacheability::NonCacheable,
+        }
+    }
+
+    /// These attributes, but supporting only atomics up to `amo`
+    pub fn with_amo(self, amo: AmoClass) -> Self {
+        Self { amo, ..self }
+    }
+
+    /// These attributes, but with the given support for reservations
+    pub fn with_reservability(self, reservability: Reservability) -> Self {
+        Self {
+            reservability,
+            ..self
+        }
+    }
+
+    /// These attributes, but with the given idempotency
+    pub fn with_idempotency(self, idempotency: Idempotency) -> Self {
+        Self {
+            idempotency,
+            ..self
+        }
+    }
+
+    /// These attributes, but with the given cacheability
+    pub fn with_cacheability(self, cacheability: Cacheability) -> Self {
+        Self {
+            cacheability,
+            ..self
+        }
+    }
+
     pub fn packed(&self) -> PmaPacked {
         let (kind, amo, reservability, idempotency, cacheability) = (
             self.kind as u8,
@@ -222,17 +264,40 @@ impl PmaPacked {
 #[allow(unused)]
 #[derive(Debug)]
 pub enum MemoryError {
-    OutOfBoundsAccess { offset: u32 },
-    AmoUnsupported { amo: AmoClass },
-    AmoMisaligned { offset: u32, amo: AmoClass },
-    LoadMisaligned { offset: u32, alignment: u32 },
-    StoreMisaligned { offset: u32, alignment: u32 },
-    SizeUnsupported { offset: u32, size: u32 },
+    OutOfBoundsAccess {
+        offset: u32,
+    },
+    AmoUnsupported {
+        amo: AmoClass,
+    },
+    AmoMisaligned {
+        offset: u32,
+        amo: AmoClass,
+    },
+    LoadMisaligned {
+        offset: u32,
+        alignment: u32,
+    },
+    StoreMisaligned {
+        offset: u32,
+        alignment: u32,
+    },
+    SizeUnsupported {
+        offset: u32,
+        size: u32,
+    },
+    /// The mapping is read-only
+    WriteProtected {
+        offset: u32,
+    },
     BlockOperationUnsupported,
 }
 
 pub type MemoryResult<T> = std::result::Result<T, MemoryError>;
 
+/// Called with the offset of a store, see [`Mapping::register_store_callback`]
+pub type StoreCallback<'a> = &'a (dyn Fn(u32) + Send + Sync);
+
 #[allow(unused)]
 pub struct Properties {
     base_frame: u32,
@@ -405,14 +470,30 @@ pub trait Mapping<'a> {
     fn attributes(&self) -> Pma;
     fn properties(&self) -> Properties;
 
+    /// Register a reservation set that should be invalidated every time a
+    /// change is made to the reservation granule it holds.
+    ///
+    /// This is useful for informing reservation sets when devices make changes
+    /// to memory.
+    fn register_reservation_set(&'a self, reservation: &'a AtomicU32);
+
     /// Register a callback that should be called every time a change is made
     /// to the underlying memory.
-    /// The callback should accept the offset that the store occured at.
+    /// The callback is given the offset that the store occured at, or the
+    /// first offset of a block write.
     ///
-    /// This is useful for informing reservation sets when devices make changes
-    /// to memory or for raising interrupts when operations complete or new
+    /// This is useful for raising interrupts when operations complete or new
     /// data is available.
-    fn register_reservation_set(&'a self, reservation: &'a AtomicU32);
+    ///
+    /// Mappings that have no stores to report ignore the callback.
+    fn register_store_callback(&'a self, _callback: StoreCallback<'a>) {}
+
+    /// Called by the bus with the frame the mapping is mapped at, which is not
+    /// necessarily the base frame given by its properties.
+    ///
+    /// Mappings that name reservation sets by physical address use it to find
+    /// the sets their stores invalidate.
+    fn relocate(&self, _base_frame: u32) {}
 }
 
 pub trait SendSyncMapping<'a>: Send + Sync + Mapping<'a> {}
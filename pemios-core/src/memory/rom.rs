@@ -0,0 +1,371 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// This Source Code Form is "Incompatible With Secondary Licenses", as
+// defined by the Mozilla Public License, v. 2.0.
+//
+// Copyright © 2022 mumblingdrunkard
+
+use std::sync::{atomic::AtomicU32, Arc};
+
+use super::{
+    endian::read_bytes,
+    main::Main,
+    mapping::{AmoClass, Mapping, MemoryError, MemoryResult, Pma, Properties, Reservability},
+};
+
+/// A read-only memory, such as one holding boot code or a device tree.
+///
+/// The contents are shared and never change, so no locking is needed.
+/// The ROM covers as many frames as it takes to hold them, and the bytes past
+/// the end of the contents in the last frame read as 0.
+///
+/// Stores, `sc`, and atomics fail with `MemoryError::WriteProtected`, or are
+/// silently dropped if the ROM was created with `ignore_writes` set.
+/// Note that the ROM is cacheable, so stores by a hart are held in its data
+/// cache and only reach the ROM when the line is written back.
+pub struct Rom {
+    base_frame: u32,
+    data: Arc<[u8]>,
+    ignore_writes: bool,
+}
+
+impl Rom {
+    pub fn new(base_frame: u32, data: Arc<[u8]>, ignore_writes: bool) -> Self {
+        Self {
+            base_frame,
+            data,
+            ignore_writes,
+        }
+    }
+
+    fn frame_count(&self) -> u32 {
+        self.data.len().div_ceil(4096) as u32
+    }
+
+    fn size(&self) -> usize {
+        (self.frame_count() as usize) << 12
+    }
+
+    /// How many of the `len` bytes starting at `offset` are backed by a frame
+    fn backed_len(&self, offset: u32, len: usize) -> usize {
+        std::cmp::min(len, self.size().saturating_sub(offset as usize))
+    }
+
+    /// Copies the bytes at `offset` to `dst`, which must be backed.
+    fn read(&self, offset: usize, dst: &mut [u8]) {
+        let start = std::cmp::min(offset, self.data.len());
+        let end = std::cmp::min(offset + dst.len(), self.data.len());
+        let n = end - start;
+        dst[..n].copy_from_slice(&self.data[start..end]);
+        dst[n..].fill(0);
+    }
+
+    fn load<const W: usize>(&self, offset: u32) -> MemoryResult<u32> {
+        if offset & (W as u32 - 1) != 0 {
+            let alignment = W as u32;
+            return Err(MemoryError::LoadMisaligned { offset, alignment });
+        }
+
+        if self.backed_len(offset, W) < W {
+            return Err(MemoryError::OutOfBoundsAccess { offset });
+        }
+
+        let mut bytes = [0; W];
+        self.read(offset as usize, &mut bytes);
+        Ok(read_bytes(&bytes))
+    }
+
+    /// Rejects or drops a write of `len` bytes at `offset`, returning the
+    /// number of bytes that would have been written.
+    fn write(&self, offset: u32, len: usize) -> MemoryResult<usize> {
+        let backed = self.backed_len(offset, len);
+        if self.ignore_writes || backed == 0 {
+            Ok(backed)
+        } else {
+            Err(MemoryError::WriteProtected { offset })
+        }
+    }
+
+    fn store<const W: usize>(&self, offset: u32) -> MemoryResult<()> {
+        if offset & (W as u32 - 1) != 0 {
+            let alignment = W as u32;
+            return Err(MemoryError::StoreMisaligned { offset, alignment });
+        }
+
+        if self.backed_len(offset, W) < W {
+            return Err(MemoryError::OutOfBoundsAccess { offset });
+        }
+
+        self.write(offset, W).map(|_| ())
+    }
+
+    fn amo(&self, offset: u32) -> MemoryResult<u32> {
+        if self.ignore_writes {
+            Err(MemoryError::AmoUnsupported {
+                amo: AmoClass::None,
+            })
+        } else {
+            Err(MemoryError::WriteProtected { offset })
+        }
+    }
+}
+
+impl<'a> Mapping<'a> for Rom {
+    fn block_write(&self, offset: u32, src: &[u8]) -> MemoryResult<usize> {
+        self.write(offset, src.len())
+    }
+
+    fn block_write_masked(&self, offset: u32, src: &[u8], mask: &[u8]) -> MemoryResult<usize> {
+        assert!(mask.len() * 8 >= src.len(), "Mask is too short");
+
+        // writing nothing is allowed, even to a ROM
+        let masked = (0..src.len()).any(|i| (mask[i >> 3] >> (i & 7)) & 1 == 1);
+        if masked {
+            self.write(offset, src.len())
+        } else {
+            Ok(self.backed_len(offset, src.len()))
+        }
+    }
+
+    fn block_read(&self, offset: u32, dst: &mut [u8]) -> MemoryResult<usize> {
+        // bytes past the last frame are not backed and are left untouched
+        let backed = self.backed_len(offset, dst.len());
+        self.read(offset as usize, &mut dst[..backed]);
+        Ok(backed)
+    }
+
+    fn block_read_masked(&self, offset: u32, dst: &mut [u8], mask: &[u8]) -> MemoryResult<usize> {
+        assert!(mask.len() * 8 >= dst.len(), "Mask is too short");
+
+        let backed = self.backed_len(offset, dst.len());
+        let mut src = vec![0; backed];
+        self.read(offset as usize, &mut src);
+        src.iter()
+            .enumerate()
+            .filter(|&(i, _)| (mask[i >> 3] >> (i & 7)) & 1 == 1)
+            .for_each(|(i, &b)| dst[i] = b);
+
+        Ok(backed)
+    }
+
+    fn stream_write(&self, frame: u32, writes: &[(u16, u8, u32)]) -> MemoryResult<usize> {
+        let base = frame << 12;
+        if frame >= self.frame_count() {
+            return Err(MemoryError::OutOfBoundsAccess { offset: base });
+        }
+
+        for &(offset, width, _) in writes {
+            Main::check_stream_access(base, offset, width, true)?;
+        }
+
+        if let Some(&(offset, ..)) = writes.first() {
+            self.write(base + offset as u32, 1)?;
+        }
+        Ok(writes.len())
+    }
+
+    fn stream_read(&self, frame: u32, reads: &[(u16, u8)], dst: &mut [u32]) -> MemoryResult<usize> {
+        assert_eq!(
+            reads.len(),
+            dst.len(),
+            "dst must have room for exactly one value per read"
+        );
+
+        let base = frame << 12;
+        if frame >= self.frame_count() {
+            return Err(MemoryError::OutOfBoundsAccess { offset: base });
+        }
+
+        for &(offset, width) in reads {
+            Main::check_stream_access(base, offset, width, false)?;
+        }
+
+        for (&(offset, width), d) in reads.iter().zip(dst.iter_mut()) {
+            let mut bytes = [0; 4];
+            let bytes = &mut bytes[..width as usize];
+            self.read((base + offset as u32) as usize, bytes);
+            *d = read_bytes(bytes);
+        }
+
+        Ok(reads.len())
+    }
+
+    fn store_byte(&self, offset: u32, _byte: u8) -> MemoryResult<()> {
+        self.store::<1>(offset)
+    }
+
+    fn store_half_word(&self, offset: u32, _half_word: u16) -> MemoryResult<()> {
+        self.store::<2>(offset)
+    }
+
+    fn store_word(&self, offset: u32, _word: u32) -> MemoryResult<()> {
+        self.store::<4>(offset)
+    }
+
+    fn load_byte(&self, offset: u32) -> MemoryResult<u8> {
+        self.load::<1>(offset).map(|b| b as u8)
+    }
+
+    fn load_half_word(&self, offset: u32) -> MemoryResult<u16> {
+        self.load::<2>(offset).map(|hw| hw as u16)
+    }
+
+    fn load_word(&self, offset: u32) -> MemoryResult<u32> {
+        self.load::<4>(offset)
+    }
+
+    fn store_conditional(
+        &self,
+        offset: u32,
+        _src: u32,
+        _reservation: &AtomicU32,
+        _should_be: u32,
+    ) -> MemoryResult<u32> {
+        // a dropped store is reported as a failed `sc`
+        self.store::<4>(offset).map(|_| 1)
+    }
+
+    fn amoswap_w(&self, offset: u32, _src: u32) -> MemoryResult<u32> {
+        self.amo(offset)
+    }
+
+    fn amoadd_w(&self, offset: u32, _src: u32) -> MemoryResult<u32> {
+        self.amo(offset)
+    }
+
+    fn amoand_w(&self, offset: u32, _src: u32) -> MemoryResult<u32> {
+        self.amo(offset)
+    }
+
+    fn amoor_w(&self, offset: u32, _src: u32) -> MemoryResult<u32> {
+        self.amo(offset)
+    }
+
+    fn amoxor_w(&self, offset: u32, _src: u32) -> MemoryResult<u32> {
+        self.amo(offset)
+    }
+
+    fn amomax_w(&self, offset: u32, _src: u32) -> MemoryResult<u32> {
+        self.amo(offset)
+    }
+
+    fn amomaxu_w(&self, offset: u32, _src: u32) -> MemoryResult<u32> {
+        self.amo(offset)
+    }
+
+    fn amomin_w(&self, offset: u32, _src: u32) -> MemoryResult<u32> {
+        self.amo(offset)
+    }
+
+    fn amominu_w(&self, offset: u32, _src: u32) -> MemoryResult<u32> {
+        self.amo(offset)
+    }
+
+    fn attributes(&self) -> Pma {
+        Pma::main()
+            .with_amo(AmoClass::None)
+            .with_reservability(Reservability::None)
+    }
+
+    fn properties(&self) -> Properties {
+        Properties::new(self.base_frame, self.frame_count())
+    }
+
+    /// The contents never change, so there is nothing to invalidate.
+    fn register_reservation_set(&'a self, _reservation: &'a AtomicU32) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{atomic::AtomicU32, Arc};
+
+    use crate::{
+        bus::Bus,
+        hart::{
+            instruction::{Conclusion, ExceptionKind},
+            step::Step,
+            Hart, Reg,
+        },
+        memory::{
+            endian::BIG_ENDIAN,
+            mapping::{AmoClass, Mapping, MemoryError, MemoryResult},
+        },
+    };
+
+    use super::Rom;
+
+    #[test]
+    fn read_only() -> MemoryResult<()> {
+        let data: Arc<[u8]> = Arc::from(&[0x11, 0x22, 0x33, 0x44, 0x55][..]);
+        let rom = Rom::new(0x80010, data.clone(), false);
+        assert_eq!(rom.properties().frame_count(), 1);
+
+        let expected = if BIG_ENDIAN { 0x11223344 } else { 0x44332211 };
+        assert_eq!(rom.load_word(0)?, expected);
+        assert_eq!(rom.load_byte(4)?, 0x55);
+        assert_eq!(rom.load_word(0xffc)?, 0, "The rest of the frame is zeroed");
+
+        let mut dst = [0xff; 8];
+        assert_eq!(rom.block_read(0xffc, &mut dst)?, 4);
+        assert_eq!(dst, [0, 0, 0, 0, 0xff, 0xff, 0xff, 0xff]);
+
+        assert!(matches!(
+            rom.store_word(0, 0),
+            Err(MemoryError::WriteProtected { offset: 0 })
+        ));
+        assert!(matches!(
+            rom.block_write(2, &[0; 2]),
+            Err(MemoryError::WriteProtected { offset: 2 })
+        ));
+        assert!(matches!(
+            rom.amoadd_w(0, 1),
+            Err(MemoryError::WriteProtected { offset: 0 })
+        ));
+        assert_eq!(rom.block_write_masked(0, &[0; 8], &[0])?, 8);
+
+        let rom = Rom::new(0x80010, data, true);
+        rom.store_word(0, 0)?;
+        assert_eq!(rom.block_write(2, &[0; 2])?, 2);
+        assert_eq!(rom.store_conditional(0, 0, &AtomicU32::new(0), 0)?, 1);
+        assert!(matches!(
+            rom.amoadd_w(0, 1),
+            Err(MemoryError::AmoUnsupported {
+                amo: AmoClass::None
+            })
+        ));
+        assert_eq!(rom.load_word(0)?, expected, "Writes should be dropped");
+
+        Ok(())
+    }
+
+    #[test]
+    fn on_bus() {
+        // loads a word from the ROM at 0x80010000 and tries to swap it back
+        let rom = Rom::new(0x80010, Arc::from(&[0xaa; 8][..]), false);
+        let bus = Bus::builder()
+            .with_main_memory(1)
+            .with_mapping(&rom)
+            .build();
+        bus.set_mm(&[
+            0x37, 0x05, 0x01, 0x80, // lui a0, 0x80010
+            0x83, 0x25, 0x05, 0x00, // lw a1, 0(a0)
+            0x2f, 0x26, 0xb5, 0x08, // amoswap.w a2, a1, (a0)
+        ])
+        .unwrap();
+
+        let reservation = AtomicU32::new(u32::MAX);
+        let mut hart = Hart::new(&bus, &reservation);
+        hart.step();
+        hart.step();
+        assert_eq!(hart.reg[Reg::A1], 0xaaaaaaaa);
+        assert!(matches!(
+            hart.step(),
+            Conclusion::Exception(ExceptionKind::StoreAccessFault { addr: 0x80010000 })
+        ));
+
+        assert!(bus.store_word(0x80010000, 0).is_err());
+        assert_eq!(bus.load_word(0x80010000).unwrap(), 0xaaaaaaaa);
+    }
+}
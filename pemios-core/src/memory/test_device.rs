@@ -0,0 +1,240 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// This Source Code Form is "Incompatible With Secondary Licenses", as
+// defined by the Mozilla Public License, v. 2.0.
+//
+// Copyright © 2022 mumblingdrunkard
+
+use std::sync::{atomic::AtomicU32, Mutex};
+
+use crate::bus::Bus;
+
+use super::{
+    endian::read_bytes,
+    main::Main,
+    mapping::{Mapping, MemoryResult, Pma, Properties},
+};
+
+/// Reads the word at `addr` straight from `bus`, in the byte order of data in
+/// memory.
+pub fn read_word(bus: &Bus, addr: u32) -> u32 {
+    let mut buf = [0; 4];
+    bus.block_read(addr, &mut buf).unwrap();
+    read_bytes(&buf)
+}
+
+/// An operation performed on a `TestDevice`
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Access {
+    Load { offset: u32, width: u8 },
+    Store { offset: u32, width: u8, value: u32 },
+    BlockRead { offset: u32, len: usize },
+    BlockWrite { offset: u32, len: usize },
+    StreamRead { frame: u32, count: usize },
+    StreamWrite { frame: u32, count: usize },
+    Atomic { offset: u32 },
+}
+
+/// A device for tests that is backed by main memory and records every
+/// operation performed on it.
+pub struct TestDevice<'a> {
+    mem: Main<'a>,
+    pma: Pma,
+    log: Mutex<Vec<Access>>,
+}
+
+#[allow(unused)]
+impl<'a> TestDevice<'a> {
+    pub fn new(base_frame: u32, frame_count: u32) -> Self {
+        Self::with_attributes(base_frame, frame_count, Pma::main())
+    }
+
+    pub fn with_attributes(base_frame: u32, frame_count: u32, pma: Pma) -> Self {
+        Self {
+            mem: Main::new(base_frame, frame_count),
+            pma,
+            log: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// The backing memory, for inspecting the device without recording it
+    pub fn mem(&self) -> &Main<'a> {
+        &self.mem
+    }
+
+    /// Takes the operations recorded so far
+    pub fn take_log(&self) -> Vec<Access> {
+        std::mem::take(&mut *self.log.lock().unwrap())
+    }
+
+    fn record(&self, access: Access) {
+        self.log.lock().unwrap().push(access);
+    }
+}
+
+impl<'a> Mapping<'a> for TestDevice<'a> {
+    fn block_write(&self, offset: u32, src: &[u8]) -> MemoryResult<usize> {
+        self.record(Access::BlockWrite {
+            offset,
+            len: src.len(),
+        });
+        self.mem.block_write(offset, src)
+    }
+
+    fn block_write_masked(&self, offset: u32, src: &[u8], mask: &[u8]) -> MemoryResult<usize> {
+        self.record(Access::BlockWrite {
+            offset,
+            len: src.len(),
+        });
+        self.mem.block_write_masked(offset, src, mask)
+    }
+
+    fn block_read(&self, offset: u32, dst: &mut [u8]) -> MemoryResult<usize> {
+        self.record(Access::BlockRead {
+            offset,
+            len: dst.len(),
+        });
+        self.mem.block_read(offset, dst)
+    }
+
+    fn block_read_masked(&self, offset: u32, dst: &mut [u8], mask: &[u8]) -> MemoryResult<usize> {
+        self.record(Access::BlockRead {
+            offset,
+            len: dst.len(),
+        });
+        self.mem.block_read_masked(offset, dst, mask)
+    }
+
+    fn stream_write(&self, frame: u32, writes: &[(u16, u8, u32)]) -> MemoryResult<usize> {
+        self.record(Access::StreamWrite {
+            frame,
+            count: writes.len(),
+        });
+        self.mem.stream_write(frame, writes)
+    }
+
+    fn stream_read(&self, frame: u32, reads: &[(u16, u8)], dst: &mut [u32]) -> MemoryResult<usize> {
+        self.record(Access::StreamRead {
+            frame,
+            count: reads.len(),
+        });
+        self.mem.stream_read(frame, reads, dst)
+    }
+
+    fn store_byte(&self, offset: u32, byte: u8) -> MemoryResult<()> {
+        self.record(Access::Store {
+            offset,
+            width: 1,
+            value: byte as u32,
+        });
+        self.mem.store_byte(offset, byte)
+    }
+
+    fn store_half_word(&self, offset: u32, half_word: u16) -> MemoryResult<()> {
+        self.record(Access::Store {
+            offset,
+            width: 2,
+            value: half_word as u32,
+        });
+        self.mem.store_half_word(offset, half_word)
+    }
+
+    fn store_word(&self, offset: u32, word: u32) -> MemoryResult<()> {
+        self.record(Access::Store {
+            offset,
+            width: 4,
+            value: word,
+        });
+        self.mem.store_word(offset, word)
+    }
+
+    fn load_byte(&self, offset: u32) -> MemoryResult<u8> {
+        self.record(Access::Load { offset, width: 1 });
+        self.mem.load_byte(offset)
+    }
+
+    fn load_half_word(&self, offset: u32) -> MemoryResult<u16> {
+        self.record(Access::Load { offset, width: 2 });
+        self.mem.load_half_word(offset)
+    }
+
+    fn load_word(&self, offset: u32) -> MemoryResult<u32> {
+        self.record(Access::Load { offset, width: 4 });
+        self.mem.load_word(offset)
+    }
+
+    fn store_conditional(
+        &self,
+        offset: u32,
+        src: u32,
+        reservation: &AtomicU32,
+        should_be: u32,
+    ) -> MemoryResult<u32> {
+        self.record(Access::Atomic { offset });
+        self.mem
+            .store_conditional(offset, src, reservation, should_be)
+    }
+
+    fn amoswap_w(&self, offset: u32, src: u32) -> MemoryResult<u32> {
+        self.record(Access::Atomic { offset });
+        self.mem.amoswap_w(offset, src)
+    }
+
+    fn amoadd_w(&self, offset: u32, src: u32) -> MemoryResult<u32> {
+        self.record(Access::Atomic { offset });
+        self.mem.amoadd_w(offset, src)
+    }
+
+    fn amoand_w(&self, offset: u32, src: u32) -> MemoryResult<u32> {
+        self.record(Access::Atomic { offset });
+        self.mem.amoand_w(offset, src)
+    }
+
+    fn amoor_w(&self, offset: u32, src: u32) -> MemoryResult<u32> {
+        self.record(Access::Atomic { offset });
+        self.mem.amoor_w(offset, src)
+    }
+
+    fn amoxor_w(&self, offset: u32, src: u32) -> MemoryResult<u32> {
+        self.record(Access::Atomic { offset });
+        self.mem.amoxor_w(offset, src)
+    }
+
+    fn amomax_w(&self, offset: u32, src: u32) -> MemoryResult<u32> {
+        self.record(Access::Atomic { offset });
+        self.mem.amomax_w(offset, src)
+    }
+
+    fn amomaxu_w(&self, offset: u32, src: u32) -> MemoryResult<u32> {
+        self.record(Access::Atomic { offset });
+        self.mem.amomaxu_w(offset, src)
+    }
+
+    fn amomin_w(&self, offset: u32, src: u32) -> MemoryResult<u32> {
+        self.record(Access::Atomic { offset });
+        self.mem.amomin_w(offset, src)
+    }
+
+    fn amominu_w(&self, offset: u32, src: u32) -> MemoryResult<u32> {
+        self.record(Access::Atomic { offset });
+        self.mem.amominu_w(offset, src)
+    }
+
+    fn attributes(&self) -> Pma {
+        self.pma
+    }
+
+    fn properties(&self) -> Properties {
+        self.mem.properties()
+    }
+
+    fn register_reservation_set(&'a self, reservation: &'a AtomicU32) {
+        self.mem.register_reservation_set(reservation)
+    }
+
+    fn relocate(&self, base_frame: u32) {
+        self.mem.relocate(base_frame)
+    }
+}
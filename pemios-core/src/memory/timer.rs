@@ -0,0 +1,374 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// This Source Code Form is "Incompatible With Secondary Licenses", as
+// defined by the Mozilla Public License, v. 2.0.
+//
+// Copyright © 2022 mumblingdrunkard
+
+use std::sync::{
+    atomic::{AtomicU32, Ordering},
+    Mutex,
+};
+
+use super::mapping::{AmoClass, Mapping, MemoryError, MemoryResult, Pma, Properties};
+
+/// The current count
+const VALUE: u32 = 0;
+/// The count at which the interrupt is raised
+const COMPARE: u32 = 4;
+/// The interrupt flag, cleared by any write
+const STATUS: u32 = 8;
+
+/// The interrupt is pending
+const STATUS_PENDING: u32 = 1;
+
+/// A free-running system timer that raises an interrupt when its count
+/// reaches a compare value.
+///
+/// The count only advances when [`Timer::tick`] is called, so the host
+/// decides how it relates to time or to the steps taken by harts.
+/// The registers are 32 bits wide and only support word accesses.
+///
+/// While the interrupt is pending, the bit `irq` is set in every interrupt
+/// line registered with [`Timer::register_interrupt_line`].
+/// A hart samples such a line with [`crate::hart::Hart::set_interrupt_line`],
+/// so with `irq` set to 7, the timer drives `mip.MTIP`.
+pub struct Timer<'a> {
+    base_frame: u32,
+    irq: u32,
+    value: AtomicU32,
+    compare: AtomicU32,
+    status: AtomicU32,
+    lines: Mutex<Vec<&'a AtomicU32>>,
+}
+
+impl<'a> Timer<'a> {
+    pub fn new(base_frame: u32, irq: u32) -> Self {
+        assert!(
+            irq < 32,
+            "Interrupt {irq} does not fit in an interrupt line"
+        );
+        Self {
+            base_frame,
+            irq,
+            value: AtomicU32::new(0),
+            compare: AtomicU32::new(u32::MAX),
+            status: AtomicU32::new(0),
+            lines: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Registers `line` to have the bit `irq` set while the interrupt is
+    /// pending.
+    pub fn register_interrupt_line(&self, line: &'a AtomicU32) {
+        self.lines
+            .lock()
+            .expect("Failed to lock interrupt lines for registration")
+            .push(line);
+    }
+
+    /// Advances the count by `dt`, raising the interrupt if it passes the
+    /// compare value.
+    pub fn tick(&self, dt: u32) {
+        let old = self.value.fetch_add(dt, Ordering::Relaxed);
+        let compare = self.compare.load(Ordering::Relaxed);
+
+        // the compare value is in (old, old + dt]
+        if compare.wrapping_sub(old).wrapping_sub(1) < dt {
+            self.status.fetch_or(STATUS_PENDING, Ordering::Relaxed);
+            self.update_lines(true);
+        }
+    }
+
+    /// Whether the interrupt is pending
+    pub fn pending(&self) -> bool {
+        self.status.load(Ordering::Relaxed) & STATUS_PENDING != 0
+    }
+
+    fn update_lines(&self, pending: bool) {
+        let lines = self
+            .lines
+            .lock()
+            .expect("Failed to lock interrupt lines for update");
+        for line in lines.iter() {
+            if pending {
+                line.fetch_or(1 << self.irq, Ordering::Relaxed);
+            } else {
+                line.fetch_and(!(1 << self.irq), Ordering::Relaxed);
+            }
+        }
+    }
+
+    fn read(&self, offset: u32) -> u32 {
+        match offset {
+            VALUE => self.value.load(Ordering::Relaxed),
+            COMPARE => self.compare.load(Ordering::Relaxed),
+            STATUS => self.status.load(Ordering::Relaxed),
+            _ => 0,
+        }
+    }
+
+    fn write(&self, offset: u32, val: u32) {
+        match offset {
+            VALUE => self.value.store(val, Ordering::Relaxed),
+            COMPARE => self.compare.store(val, Ordering::Relaxed),
+            STATUS => {
+                self.status.store(0, Ordering::Relaxed);
+                self.update_lines(false);
+            }
+            _ => {}
+        }
+    }
+
+    /// Checks an access of `size` bytes at `offset`, which is a store if
+    /// `STORE` is set.
+    fn check_word<const STORE: bool>(offset: u32, size: u32) -> MemoryResult<()> {
+        if size != 4 {
+            return Err(MemoryError::SizeUnsupported { offset, size });
+        }
+
+        if offset & 3 != 0 {
+            let alignment = 4;
+            return Err(if STORE {
+                MemoryError::StoreMisaligned { offset, alignment }
+            } else {
+                MemoryError::LoadMisaligned { offset, alignment }
+            });
+        }
+
+        Ok(())
+    }
+}
+
+impl<'a> Mapping<'a> for Timer<'a> {
+    fn block_write(&self, _offset: u32, _src: &[u8]) -> MemoryResult<usize> {
+        Err(MemoryError::BlockOperationUnsupported)
+    }
+
+    fn block_write_masked(&self, _offset: u32, _src: &[u8], _mask: &[u8]) -> MemoryResult<usize> {
+        Err(MemoryError::BlockOperationUnsupported)
+    }
+
+    fn block_read(&self, _offset: u32, _dst: &mut [u8]) -> MemoryResult<usize> {
+        Err(MemoryError::BlockOperationUnsupported)
+    }
+
+    fn block_read_masked(
+        &self,
+        _offset: u32,
+        _dst: &mut [u8],
+        _mask: &[u8],
+    ) -> MemoryResult<usize> {
+        Err(MemoryError::BlockOperationUnsupported)
+    }
+
+    fn stream_write(&self, frame: u32, writes: &[(u16, u8, u32)]) -> MemoryResult<usize> {
+        for &(offset, width, _) in writes {
+            Self::check_word::<true>(frame << 12 | offset as u32, width as u32)?;
+        }
+        for &(offset, _, value) in writes {
+            self.write(offset as u32, value);
+        }
+        Ok(writes.len())
+    }
+
+    fn stream_read(&self, frame: u32, reads: &[(u16, u8)], dst: &mut [u32]) -> MemoryResult<usize> {
+        assert_eq!(reads.len(), dst.len(), "Every read needs a destination");
+        for &(offset, width) in reads {
+            Self::check_word::<false>(frame << 12 | offset as u32, width as u32)?;
+        }
+        reads
+            .iter()
+            .zip(dst)
+            .for_each(|(&(offset, _), d)| *d = self.read(offset as u32));
+        Ok(reads.len())
+    }
+
+    fn store_byte(&self, offset: u32, _byte: u8) -> MemoryResult<()> {
+        Self::check_word::<true>(offset, 1)
+    }
+
+    fn store_half_word(&self, offset: u32, _half_word: u16) -> MemoryResult<()> {
+        Self::check_word::<true>(offset, 2)
+    }
+
+    fn store_word(&self, offset: u32, word: u32) -> MemoryResult<()> {
+        Self::check_word::<true>(offset, 4)?;
+        self.write(offset, word);
+        Ok(())
+    }
+
+    fn load_byte(&self, offset: u32) -> MemoryResult<u8> {
+        Self::check_word::<false>(offset, 1).map(|_| 0)
+    }
+
+    fn load_half_word(&self, offset: u32) -> MemoryResult<u16> {
+        Self::check_word::<false>(offset, 2).map(|_| 0)
+    }
+
+    fn load_word(&self, offset: u32) -> MemoryResult<u32> {
+        Self::check_word::<false>(offset, 4)?;
+        Ok(self.read(offset))
+    }
+
+    fn store_conditional(
+        &self,
+        _offset: u32,
+        _src: u32,
+        _reservation: &AtomicU32,
+        _should_be: u32,
+    ) -> MemoryResult<u32> {
+        Ok(1)
+    }
+
+    fn amoswap_w(&self, _offset: u32, _src: u32) -> MemoryResult<u32> {
+        Err(MemoryError::AmoUnsupported {
+            amo: AmoClass::None,
+        })
+    }
+
+    fn amoadd_w(&self, _offset: u32, _src: u32) -> MemoryResult<u32> {
+        Err(MemoryError::AmoUnsupported {
+            amo: AmoClass::None,
+        })
+    }
+
+    fn amoand_w(&self, _offset: u32, _src: u32) -> MemoryResult<u32> {
+        Err(MemoryError::AmoUnsupported {
+            amo: AmoClass::None,
+        })
+    }
+
+    fn amoor_w(&self, _offset: u32, _src: u32) -> MemoryResult<u32> {
+        Err(MemoryError::AmoUnsupported {
+            amo: AmoClass::None,
+        })
+    }
+
+    fn amoxor_w(&self, _offset: u32, _src: u32) -> MemoryResult<u32> {
+        Err(MemoryError::AmoUnsupported {
+            amo: AmoClass::None,
+        })
+    }
+
+    fn amomax_w(&self, _offset: u32, _src: u32) -> MemoryResult<u32> {
+        Err(MemoryError::AmoUnsupported {
+            amo: AmoClass::None,
+        })
+    }
+
+    fn amomaxu_w(&self, _offset: u32, _src: u32) -> MemoryResult<u32> {
+        Err(MemoryError::AmoUnsupported {
+            amo: AmoClass::None,
+        })
+    }
+
+    fn amomin_w(&self, _offset: u32, _src: u32) -> MemoryResult<u32> {
+        Err(MemoryError::AmoUnsupported {
+            amo: AmoClass::None,
+        })
+    }
+
+    fn amominu_w(&self, _offset: u32, _src: u32) -> MemoryResult<u32> {
+        Err(MemoryError::AmoUnsupported {
+            amo: AmoClass::None,
+        })
+    }
+
+    fn attributes(&self) -> Pma {
+        Pma::io()
+    }
+
+    fn properties(&self) -> Properties {
+        Properties::new(self.base_frame, 1)
+    }
+
+    fn register_reservation_set(&'a self, _reservation: &'a AtomicU32) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use crate::{
+        asm::Assembler,
+        bus::Bus,
+        hart::{csr::Csr, instruction::Conclusion, step::Step, Hart, Reg},
+        memory::mapping::Mapping,
+    };
+
+    use super::Timer;
+
+    #[test]
+    fn compare() {
+        let line = AtomicU32::new(0);
+        let timer = Timer::new(0x80020, 7);
+        timer.register_interrupt_line(&line);
+
+        timer.store_word(4, 100).unwrap();
+        timer.tick(99);
+        assert!(!timer.pending());
+        timer.tick(1);
+        assert!(timer.pending());
+        assert_eq!(line.load(Ordering::Relaxed), 1 << 7);
+
+        timer.tick(1000);
+        assert_eq!(timer.load_word(0).unwrap(), 1100);
+        assert!(timer.pending(), "The flag should stay set until cleared");
+
+        timer.store_word(8, 0).unwrap();
+        assert!(!timer.pending());
+        assert_eq!(line.load(Ordering::Relaxed), 0);
+
+        // the count wraps around to reach the compare value again
+        timer.tick(u32::MAX - 1100 + 100);
+        assert!(!timer.pending());
+        timer.tick(1);
+        assert!(timer.pending());
+    }
+
+    #[test]
+    fn interrupt() {
+        // enables the timer interrupt and waits for it at 0x100
+        let mut asm = Assembler::new();
+        let wait = asm.label();
+        asm.lui(Reg::A0, 0x80020)
+            .li(Reg::A1, 50)
+            .sw(Reg::A1, Reg::A0, 4)
+            .li(Reg::A1, 0x100)
+            .csrrw(Reg::ZERO, Csr::MTVec, Reg::A1)
+            .li(Reg::A1, 1 << 7)
+            .csrrw(Reg::ZERO, Csr::Mie, Reg::A1)
+            .csrrsi(Reg::ZERO, Csr::MStatus, 1 << 3)
+            .bind(wait)
+            .beq(Reg::ZERO, Reg::ZERO, wait);
+
+        let line = AtomicU32::new(0);
+        let timer = Timer::new(0x80020, 7);
+        timer.register_interrupt_line(&line);
+
+        let bus = Bus::builder()
+            .with_main_memory(1)
+            .with_mapping(&timer)
+            .build();
+        bus.set_mm(&asm.assemble_bytes()).unwrap();
+
+        let reservation = AtomicU32::new(u32::MAX);
+        let mut hart = Hart::new(&bus, &reservation);
+        hart.set_interrupt_line(&line);
+
+        for _ in 0..10 {
+            assert!(matches!(hart.step(), Conclusion::None | Conclusion::Jumped));
+        }
+        assert_eq!(hart.pc, 0x20, "The hart should be waiting");
+        assert_eq!(hart.read_csr(0x344), 0);
+
+        timer.tick(50);
+        assert_eq!(hart.read_csr(0x344), 1 << 7, "mip.MTIP should be set");
+        assert!(matches!(hart.step(), Conclusion::Jumped));
+        assert_eq!(hart.pc, 0x100);
+        assert_eq!(hart.read_csr(0x342), 0x80000007);
+    }
+}
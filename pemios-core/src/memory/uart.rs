@@ -0,0 +1,349 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// This Source Code Form is "Incompatible With Secondary Licenses", as
+// defined by the Mozilla Public License, v. 2.0.
+//
+// Copyright © 2022 mumblingdrunkard
+
+use std::{
+    collections::VecDeque,
+    io::Write,
+    sync::{atomic::AtomicU32, Mutex},
+};
+
+use super::mapping::{AmoClass, Mapping, MemoryError, MemoryResult, Pma, Properties};
+
+/// Receive buffer (read) and transmit holding register (write)
+const RBR_THR: u32 = 0;
+/// Interrupt enable register
+const IER: u32 = 1;
+/// Interrupt identification register (read) and FIFO control register (write)
+const IIR_FCR: u32 = 2;
+/// Line control register
+const LCR: u32 = 3;
+/// Modem control register
+const MCR: u32 = 4;
+/// Line status register
+const LSR: u32 = 5;
+/// Scratch register
+const SCR: u32 = 7;
+
+/// Data ready
+const LSR_DR: u8 = 1 << 0;
+/// Transmit holding register empty
+const LSR_THRE: u8 = 1 << 5;
+/// Transmitter empty
+const LSR_TEMT: u8 = 1 << 6;
+
+/// No interrupt pending
+const IIR_NONE: u8 = 1;
+
+struct State {
+    rx: VecDeque<u8>,
+    ier: u8,
+    lcr: u8,
+    mcr: u8,
+    scr: u8,
+}
+
+/// A console device with the register layout of an 8250 UART.
+///
+/// Bytes written to the transmit holding register go straight to `sink`, so
+/// the transmitter is always empty, and bytes pushed with
+/// [`Uart::push_input`] are received in order.
+/// The registers are one byte apart, starting at the base of the frame.
+/// Wider accesses read and write the register at their offset.
+/// Interrupts, the divisor latch, and the modem lines are not emulated.
+pub struct Uart<W: Write + Send> {
+    base_frame: u32,
+    state: Mutex<State>,
+    sink: Mutex<W>,
+}
+
+impl<W: Write + Send> Uart<W> {
+    pub fn new(base_frame: u32, sink: W) -> Self {
+        Self {
+            base_frame,
+            state: Mutex::new(State {
+                rx: VecDeque::new(),
+                ier: 0,
+                lcr: 0,
+                mcr: 0,
+                scr: 0,
+            }),
+            sink: Mutex::new(sink),
+        }
+    }
+
+    /// Queues `bytes` to be received by the guest
+    pub fn push_input(&self, bytes: &[u8]) {
+        self.state().rx.extend(bytes);
+    }
+
+    /// Consumes the device, returning the sink
+    pub fn into_sink(self) -> W {
+        self.sink.into_inner().expect("UART sink lock was poisoned")
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().expect("UART state lock was poisoned")
+    }
+
+    fn read(&self, offset: u32) -> u8 {
+        let mut state = self.state();
+        match offset {
+            RBR_THR => state.rx.pop_front().unwrap_or(0),
+            IER => state.ier,
+            IIR_FCR => IIR_NONE,
+            LCR => state.lcr,
+            MCR => state.mcr,
+            LSR => {
+                let dr = if state.rx.is_empty() { 0 } else { LSR_DR };
+                dr | LSR_THRE | LSR_TEMT
+            }
+            SCR => state.scr,
+            _ => 0,
+        }
+    }
+
+    /// Writes `val` to the register at `offset`, returning whether a byte was
+    /// sent to the sink.
+    fn write(&self, offset: u32, val: u8) -> bool {
+        let mut state = self.state();
+        match offset {
+            RBR_THR => {
+                let mut sink = self.sink.lock().expect("UART sink lock was poisoned");
+                // the guest has no way of observing a failing console
+                let _ = sink.write_all(&[val]);
+                return true;
+            }
+            IER => state.ier = val,
+            LCR => state.lcr = val,
+            MCR => state.mcr = val,
+            SCR => state.scr = val,
+            _ => {}
+        }
+        false
+    }
+
+    fn flush(&self) {
+        let _ = self
+            .sink
+            .lock()
+            .expect("UART sink lock was poisoned")
+            .flush();
+    }
+
+    fn store(&self, offset: u32, val: u32) -> MemoryResult<()> {
+        if self.write(offset, val as u8) {
+            self.flush();
+        }
+        Ok(())
+    }
+}
+
+impl<'a, W: Write + Send> Mapping<'a> for Uart<W> {
+    fn block_write(&self, _offset: u32, _src: &[u8]) -> MemoryResult<usize> {
+        Err(MemoryError::BlockOperationUnsupported)
+    }
+
+    fn block_write_masked(&self, _offset: u32, _src: &[u8], _mask: &[u8]) -> MemoryResult<usize> {
+        Err(MemoryError::BlockOperationUnsupported)
+    }
+
+    fn block_read(&self, _offset: u32, _dst: &mut [u8]) -> MemoryResult<usize> {
+        Err(MemoryError::BlockOperationUnsupported)
+    }
+
+    fn block_read_masked(
+        &self,
+        _offset: u32,
+        _dst: &mut [u8],
+        _mask: &[u8],
+    ) -> MemoryResult<usize> {
+        Err(MemoryError::BlockOperationUnsupported)
+    }
+
+    /// Performs the writes in order, flushing the sink once at the end
+    fn stream_write(&self, _frame: u32, writes: &[(u16, u8, u32)]) -> MemoryResult<usize> {
+        let mut sent = false;
+        for &(offset, _, value) in writes {
+            sent |= self.write(offset as u32, value as u8);
+        }
+
+        if sent {
+            self.flush();
+        }
+        Ok(writes.len())
+    }
+
+    fn stream_read(
+        &self,
+        _frame: u32,
+        reads: &[(u16, u8)],
+        dst: &mut [u32],
+    ) -> MemoryResult<usize> {
+        assert_eq!(reads.len(), dst.len(), "Every read needs a destination");
+        reads
+            .iter()
+            .zip(dst)
+            .for_each(|(&(offset, _), d)| *d = self.read(offset as u32) as u32);
+        Ok(reads.len())
+    }
+
+    fn store_byte(&self, offset: u32, byte: u8) -> MemoryResult<()> {
+        self.store(offset, byte as u32)
+    }
+
+    fn store_half_word(&self, offset: u32, half_word: u16) -> MemoryResult<()> {
+        self.store(offset, half_word as u32)
+    }
+
+    fn store_word(&self, offset: u32, word: u32) -> MemoryResult<()> {
+        self.store(offset, word)
+    }
+
+    fn load_byte(&self, offset: u32) -> MemoryResult<u8> {
+        Ok(self.read(offset))
+    }
+
+    fn load_half_word(&self, offset: u32) -> MemoryResult<u16> {
+        Ok(self.read(offset) as u16)
+    }
+
+    fn load_word(&self, offset: u32) -> MemoryResult<u32> {
+        Ok(self.read(offset) as u32)
+    }
+
+    fn store_conditional(
+        &self,
+        _offset: u32,
+        _src: u32,
+        _reservation: &AtomicU32,
+        _should_be: u32,
+    ) -> MemoryResult<u32> {
+        Ok(1)
+    }
+
+    fn amoswap_w(&self, _offset: u32, _src: u32) -> MemoryResult<u32> {
+        Err(MemoryError::AmoUnsupported {
+            amo: AmoClass::None,
+        })
+    }
+
+    fn amoadd_w(&self, _offset: u32, _src: u32) -> MemoryResult<u32> {
+        Err(MemoryError::AmoUnsupported {
+            amo: AmoClass::None,
+        })
+    }
+
+    fn amoand_w(&self, _offset: u32, _src: u32) -> MemoryResult<u32> {
+        Err(MemoryError::AmoUnsupported {
+            amo: AmoClass::None,
+        })
+    }
+
+    fn amoor_w(&self, _offset: u32, _src: u32) -> MemoryResult<u32> {
+        Err(MemoryError::AmoUnsupported {
+            amo: AmoClass::None,
+        })
+    }
+
+    fn amoxor_w(&self, _offset: u32, _src: u32) -> MemoryResult<u32> {
+        Err(MemoryError::AmoUnsupported {
+            amo: AmoClass::None,
+        })
+    }
+
+    fn amomax_w(&self, _offset: u32, _src: u32) -> MemoryResult<u32> {
+        Err(MemoryError::AmoUnsupported {
+            amo: AmoClass::None,
+        })
+    }
+
+    fn amomaxu_w(&self, _offset: u32, _src: u32) -> MemoryResult<u32> {
+        Err(MemoryError::AmoUnsupported {
+            amo: AmoClass::None,
+        })
+    }
+
+    fn amomin_w(&self, _offset: u32, _src: u32) -> MemoryResult<u32> {
+        Err(MemoryError::AmoUnsupported {
+            amo: AmoClass::None,
+        })
+    }
+
+    fn amominu_w(&self, _offset: u32, _src: u32) -> MemoryResult<u32> {
+        Err(MemoryError::AmoUnsupported {
+            amo: AmoClass::None,
+        })
+    }
+
+    fn attributes(&self) -> Pma {
+        Pma::io()
+    }
+
+    fn properties(&self) -> Properties {
+        Properties::new(self.base_frame, 1)
+    }
+
+    fn register_reservation_set(&'a self, _reservation: &'a AtomicU32) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicU32;
+
+    use crate::{
+        asm::Assembler,
+        bus::Bus,
+        hart::{instruction::Conclusion, step::Step, Hart, Reg},
+        memory::mapping::Mapping,
+    };
+
+    use super::Uart;
+
+    #[test]
+    fn console() {
+        let mut asm = Assembler::new();
+        asm.lui(Reg::A0, 0x80010)
+            .li(Reg::A1, 'H' as i32)
+            .sw(Reg::A1, Reg::A0, 0)
+            .li(Reg::A1, 'i' as i32)
+            .sb(Reg::A1, Reg::A0, 0)
+            .lbu(Reg::A2, Reg::A0, 5)
+            .lbu(Reg::A3, Reg::A0, 0)
+            .lbu(Reg::A4, Reg::A0, 5)
+            .ebreak();
+
+        let uart = Uart::new(0x80010, Vec::new());
+        uart.push_input(b"!");
+        {
+            let bus = Bus::builder()
+                .with_main_memory(1)
+                .with_mapping(&uart)
+                .build();
+            bus.set_mm(&asm.assemble_bytes()).unwrap();
+
+            let reservation = AtomicU32::new(u32::MAX);
+            let mut hart = Hart::new(&bus, &reservation);
+            while !matches!(hart.step(), Conclusion::Exception(_)) {}
+
+            assert_eq!(hart.reg[Reg::A2], 0x61, "Input should be ready");
+            assert_eq!(hart.reg[Reg::A3], b'!' as u32);
+            assert_eq!(hart.reg[Reg::A4], 0x60, "Input should be drained");
+        }
+
+        assert_eq!(uart.into_sink(), b"Hi");
+    }
+
+    #[test]
+    fn stream_write() {
+        let uart = Uart::new(0x80010, Vec::new());
+        let writes = b"abc".map(|b| (0, 1, b as u32));
+        assert_eq!(uart.stream_write(0, &writes).unwrap(), 3);
+        assert_eq!(uart.into_sink(), b"abc");
+    }
+}
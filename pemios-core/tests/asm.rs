@@ -0,0 +1,46 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// This Source Code Form is "Incompatible With Secondary Licenses", as
+// defined by the Mozilla Public License, v. 2.0.
+//
+// Copyright © 2022 mumblingdrunkard
+
+#![allow(incomplete_features)]
+#![feature(generic_const_exprs)]
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicU32;
+
+    use pemios_core::{
+        asm::Assembler,
+        bus::Bus,
+        hart::{instruction::Conclusion, step::Step, Hart, Reg},
+    };
+
+    #[test]
+    fn sum_loop() {
+        // a0 = 10 + 9 + ... + 1
+        let mut asm = Assembler::new();
+        let top = asm.label();
+        asm.li(Reg::A0, 0)
+            .li(Reg::A1, 10)
+            .bind(top)
+            .add(Reg::A0, Reg::A0, Reg::A1)
+            .addi(Reg::A1, Reg::A1, -1)
+            .bne(Reg::A1, Reg::ZERO, top)
+            .ebreak();
+
+        let bus = Bus::builder().with_main_memory(1).build();
+        bus.set_mm(&asm.assemble_bytes()).unwrap();
+
+        let reservation = AtomicU32::new(u32::MAX);
+        let mut hart = Hart::new(&bus, &reservation);
+        while !matches!(hart.step(), Conclusion::Exception(_)) {}
+
+        assert_eq!(hart.reg[Reg::A0], 55);
+        assert_eq!(hart.reg[Reg::A1], 0);
+    }
+}
@@ -97,4 +97,73 @@ mod tests {
             });
         });
     }
+
+    #[test]
+    fn run_until_trap() {
+        use pemios_core::hart::{instruction::ExceptionKind, step::RunResult, Hart};
+
+        let program = std::fs::read("resources/test_programs/fib").unwrap();
+        let reservation = AtomicU32::new(u32::MAX);
+        let bus = Bus::builder().with_main_memory(2).build();
+        bus.set_mm(&program).unwrap();
+
+        let run = |max| {
+            let mut hart = Hart::new(&bus, &reservation);
+            hart.reg[Reg::SP] = 0x1000;
+            hart.run(max)
+        };
+
+        let RunResult::Trapped {
+            exception: ExceptionKind::EcallFromM,
+            retired,
+        } = run(None)
+        else {
+            panic!("fib should end with an ecall");
+        };
+
+        assert_eq!(run(None).retired(), retired);
+        assert!(matches!(
+            run(Some(1000)),
+            RunResult::BudgetExhausted { retired: 1000 }
+        ));
+    }
+
+    #[test]
+    fn watchpoints() {
+        use pemios_core::hart::{mmu::Access, step::RunResult, Hart};
+
+        let program = std::fs::read("resources/test_programs/fib").unwrap();
+        let reservation = AtomicU32::new(u32::MAX);
+        let bus = Bus::builder().with_main_memory(2).build();
+        bus.set_mm(&program).unwrap();
+
+        let mut hart = Hart::new(&bus, &reservation);
+        hart.reg[Reg::SP] = 0x1000;
+
+        // addi sp, sp, -16; sw ra, 12(sp)
+        hart.add_watchpoint(0xffc, Access::Write);
+        assert!(matches!(
+            hart.run(None),
+            RunResult::Watchpoint {
+                addr: 0xffc,
+                kind: Access::Write,
+                retired: 2
+            }
+        ));
+        assert_eq!(hart.pc, 8, "The store should complete");
+
+        // the entry of the recursive function, which is tail called from 0x70
+        assert!(hart.remove_watchpoint(0xffc, Access::Write));
+        hart.add_watchpoint(0x18, Access::Execute);
+        assert!(matches!(
+            hart.run(None),
+            RunResult::Watchpoint {
+                addr: 0x18,
+                kind: Access::Execute,
+                ..
+            }
+        ));
+        assert_eq!(hart.pc, 0x18, "The instruction should not execute");
+        assert_eq!(hart.reg[Reg::SP], 0xff0);
+    }
 }
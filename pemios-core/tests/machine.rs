@@ -0,0 +1,189 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// This Source Code Form is "Incompatible With Secondary Licenses", as
+// defined by the Mozilla Public License, v. 2.0.
+//
+// Copyright © 2022 mumblingdrunkard
+
+#![allow(incomplete_features)]
+#![feature(generic_const_exprs)]
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use pemios_core::{
+        asm::Assembler,
+        bus::Bus,
+        hart::{
+            instruction::{Conclusion, Instruction},
+            step::Step,
+            Reg,
+        },
+        machine::Machine,
+        memory::mapping::Mapping,
+    };
+
+    #[test]
+    fn hart_ids() {
+        let bus = Bus::builder().with_main_memory(1).build();
+        let machine = Machine::new(3);
+        let ids = machine
+            .harts(&bus)
+            .iter()
+            .map(|hart| hart.hart_id())
+            .collect::<Vec<_>>();
+        assert_eq!(ids, [0, 1, 2]);
+    }
+
+    #[test]
+    fn shared_counter() {
+        // adds 1 to the counter at 0x400 a hundred times
+        let mut asm = Assembler::new();
+        let top = asm.label();
+        asm.li(Reg::A0, 0x400)
+            .li(Reg::A1, 1)
+            .li(Reg::A2, 100)
+            .bind(top)
+            .instruction(Instruction::AmoAddw {
+                rd: Reg::ZERO,
+                rs1: Reg::A0,
+                rs2: Reg::A1,
+                aq: false,
+                rl: false,
+            })
+            .addi(Reg::A2, Reg::A2, -1)
+            .bne(Reg::A2, Reg::ZERO, top)
+            .ebreak();
+
+        let bus = Bus::builder().with_main_memory(1).build();
+        bus.set_mm(&asm.assemble_bytes()).unwrap();
+
+        let machine = Machine::new(4);
+        thread::scope(|s| {
+            for mut hart in machine.harts(&bus) {
+                s.spawn(move || while !matches!(hart.step(), Conclusion::Exception(_)) {});
+            }
+        });
+
+        assert_eq!(bus.load_word(0x400).unwrap(), 400);
+    }
+
+    /// A stress test of release and acquire orderings.
+    ///
+    /// The producer stores increasing values to `data` and publishes each one
+    /// by swapping it into `flag` with `rl` set.
+    /// The consumer reads `flag` with `aq` set and then loads `data`, which
+    /// must be at least as recent as the flag, even though the store to it
+    /// was only performed in the producer's data cache.
+    #[test]
+    fn message_passing() {
+        const FLAG: i32 = 0x400;
+        const DATA: i32 = 0x600;
+        const ROUNDS: i32 = 1000;
+        const COUNTER: i32 = 0x700;
+        const CONSUMER: u32 = 0x200;
+
+        // both harts also increment `counter` with lr.w/sc.w in every round
+        fn increment(asm: &mut Assembler) -> &mut Assembler {
+            let retry = asm.label();
+            asm.li(Reg::A5, COUNTER)
+                .bind(retry)
+                .instruction(Instruction::Lrw {
+                    rd: Reg::T2,
+                    rs1: Reg::A5,
+                    aq: false,
+                    rl: false,
+                })
+                .addi(Reg::T2, Reg::T2, 1)
+                .instruction(Instruction::Scw {
+                    rd: Reg::T3,
+                    rs1: Reg::A5,
+                    rs2: Reg::T2,
+                    aq: false,
+                    rl: false,
+                })
+                .bne(Reg::T3, Reg::ZERO, retry)
+        }
+
+        let mut producer = Assembler::new();
+        let top = producer.label();
+        producer
+            .li(Reg::A0, FLAG)
+            .li(Reg::A1, DATA)
+            .li(Reg::A2, 1)
+            .li(Reg::A3, ROUNDS)
+            .bind(top)
+            .sw(Reg::A2, Reg::A1, 0)
+            .instruction(Instruction::AmoSwapw {
+                rd: Reg::ZERO,
+                rs1: Reg::A0,
+                rs2: Reg::A2,
+                aq: false,
+                rl: true,
+            });
+        increment(&mut producer)
+            .addi(Reg::A2, Reg::A2, 1)
+            .bge(Reg::A3, Reg::A2, top)
+            .ebreak();
+
+        // sets a4 if `data` is older than `flag` and counts its rounds in a6
+        let mut consumer = Assembler::new();
+        let (top, done) = (consumer.label(), consumer.label());
+        consumer
+            .li(Reg::A0, FLAG)
+            .li(Reg::A1, DATA)
+            .li(Reg::A3, ROUNDS)
+            .bind(top)
+            .instruction(Instruction::AmoOrw {
+                rd: Reg::T0,
+                rs1: Reg::A0,
+                rs2: Reg::ZERO,
+                aq: true,
+                rl: false,
+            })
+            .lw(Reg::T1, Reg::A1, 0)
+            .sltu(Reg::A4, Reg::T1, Reg::T0)
+            .bne(Reg::A4, Reg::ZERO, done);
+        increment(&mut consumer)
+            .addi(Reg::A6, Reg::A6, 1)
+            .bne(Reg::T0, Reg::A3, top)
+            .bind(done)
+            .ebreak();
+
+        let bus = Bus::builder().with_main_memory(1).build();
+        bus.set_mm(&producer.assemble_bytes()).unwrap();
+        bus.block_write(CONSUMER, &consumer.assemble_bytes())
+            .unwrap();
+
+        let machine = Machine::new(2);
+        let mut harts = machine.harts(&bus);
+        harts[1].pc = CONSUMER;
+        let consumer = thread::scope(|s| {
+            let handles = harts
+                .into_iter()
+                .map(|mut hart| {
+                    s.spawn(move || {
+                        while !matches!(hart.step(), Conclusion::Exception(_)) {}
+                        hart
+                    })
+                })
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .map(|h| h.join().unwrap())
+                .last()
+                .unwrap()
+        });
+
+        assert_eq!(consumer.reg[Reg::T0], ROUNDS as u32);
+        assert_eq!(consumer.reg[Reg::A4], 0, "A stale value was observed");
+        assert_eq!(
+            bus.load_word(COUNTER as u32).unwrap(),
+            ROUNDS as u32 + consumer.reg[Reg::A6],
+            "An increment was lost"
+        );
+    }
+}
@@ -0,0 +1,209 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// This Source Code Form is "Incompatible With Secondary Licenses", as
+// defined by the Mozilla Public License, v. 2.0.
+//
+// Copyright © 2022 mumblingdrunkard
+
+#![allow(incomplete_features)]
+#![feature(generic_const_exprs)]
+
+//! A runner for the `riscv-tests` ISA tests.
+//!
+//! The tests are linked at 0x80000000 and report their result by writing
+//! `(test_number << 1) | 1` to the `tohost` symbol, where a test number of 0
+//! means every test passed.
+//!
+//! The binaries are not part of this repository.
+//! Build them from <https://github.com/riscv-software-src/riscv-tests> and
+//! point `RISCV_TESTS_DIR` at the `isa` directory, then run
+//! `cargo test --test riscv_tests -- --ignored`.
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicU32;
+
+    use pemios_core::{
+        asm::Assembler,
+        bus::Bus,
+        hart::{csr::Csr, mmu::Access, step::RunResult, Hart, Reg},
+        loader::load_elf,
+        memory::{endian::read_bytes, main::Main},
+    };
+
+    /// The frame the tests are linked at
+    const DRAM_FRAME: u32 = 0x80000;
+    const DRAM_FRAMES: u32 = 16;
+
+    /// Steps a test may take before it is considered stuck
+    const BUDGET: usize = 1_000_000;
+
+    const SHT_SYMTAB: u32 = 2;
+
+    const RV32UI: &[&str] = &[
+        "simple", "add", "addi", "and", "andi", "auipc", "beq", "bge", "bgeu", "blt", "bltu",
+        "bne", "jal", "jalr", "lb", "lbu", "lh", "lhu", "lw", "lui", "or", "ori", "sb", "sh", "sw",
+        "sll", "slli", "slt", "slti", "sltiu", "sltu", "sra", "srai", "srl", "srli", "sub", "xor",
+        "xori",
+    ];
+
+    fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+    }
+
+    fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    /// Looks up the value of the symbol `name` in the symbol table of `elf`.
+    fn symbol(elf: &[u8], name: &str) -> Option<u32> {
+        let shoff = u32_at(elf, 32) as usize;
+        let shentsize = u16_at(elf, 46) as usize;
+        let shnum = u16_at(elf, 48) as usize;
+        let section = |i: usize| shoff + i * shentsize;
+
+        let symtab = (0..shnum)
+            .map(section)
+            .find(|&sh| u32_at(elf, sh + 4) == SHT_SYMTAB)?;
+        let strtab = u32_at(elf, section(u32_at(elf, symtab + 24) as usize) + 16) as usize;
+
+        let offset = u32_at(elf, symtab + 16) as usize;
+        let size = u32_at(elf, symtab + 20) as usize;
+        elf[offset..offset + size].chunks_exact(16).find_map(|sym| {
+            let start = strtab + u32_at(sym, 0) as usize;
+            let len = elf[start..].iter().position(|&b| b == 0)?;
+            (&elf[start..start + len] == name.as_bytes()).then(|| u32_at(sym, 4))
+        })
+    }
+
+    /// Runs the test executable `elf` until it writes `tohost`, returning the
+    /// number of the failing test if it did not pass.
+    fn run(elf: &[u8]) -> Result<(), u32> {
+        let tohost = symbol(elf, "tohost").expect("The test should define `tohost`");
+
+        let dram = Main::new(DRAM_FRAME, DRAM_FRAMES);
+        let bus = Bus::builder()
+            .with_main_memory(1)
+            .with_mapping(&dram)
+            .build();
+        let entry = load_elf(&bus, elf).unwrap();
+
+        let reservation = AtomicU32::new(u32::MAX);
+        let mut hart = Hart::new(&bus, &reservation);
+        hart.pc = entry;
+        hart.add_watchpoint(tohost, Access::Write);
+
+        let mut retired = 0;
+        loop {
+            match hart.run(Some(BUDGET - retired)) {
+                // the tests handle their own traps
+                RunResult::Trapped { retired: r, .. } => retired += r + 1,
+                RunResult::Watchpoint { addr, .. } if addr == tohost => break,
+                result => panic!("The test did not write `tohost`: {result:?}"),
+            }
+        }
+
+        let mut buf = [0; 4];
+        hart.read_memory(tohost, &mut buf).unwrap();
+        match read_bytes(&buf) {
+            1 => Ok(()),
+            val => Err(val >> 1),
+        }
+    }
+
+    /// Builds an executable shaped like a `riscv-tests` binary, which takes an
+    /// environment call whose handler writes `gp` to `tohost`.
+    fn elf(gp: i32) -> Vec<u8> {
+        let mut asm = Assembler::new();
+        let (reset, trap_vector) = (asm.label(), asm.label());
+        asm.beq(Reg::ZERO, Reg::ZERO, reset)
+            .bind(trap_vector)
+            .lui(Reg::T5, 0x80001)
+            .sw(Reg::GP, Reg::T5, 0)
+            .beq(Reg::ZERO, Reg::ZERO, trap_vector)
+            .bind(reset)
+            .lui(Reg::T0, 0x80000)
+            .addi(Reg::T0, Reg::T0, 4)
+            .csrrw(Reg::ZERO, Csr::MTVec, Reg::T0)
+            .li(Reg::GP, gp)
+            .ecall();
+        let code = asm.assemble_bytes();
+
+        let strtab = b"\0tohost\0";
+        let code_offset = 52 + 32;
+        let symtab_offset = code_offset + code.len();
+        let strtab_offset = symtab_offset + 32;
+        let shoff = strtab_offset + strtab.len();
+
+        let mut elf = vec![0; 52];
+        elf[..4].copy_from_slice(b"\x7fELF");
+        elf[4] = 1; // ELFCLASS32
+        elf[5] = 1; // ELFDATA2LSB
+        elf[6] = 1; // EV_CURRENT
+        elf[16..18].copy_from_slice(&2u16.to_le_bytes()); // ET_EXEC
+        elf[18..20].copy_from_slice(&243u16.to_le_bytes()); // EM_RISCV
+        elf[24..28].copy_from_slice(&0x80000000u32.to_le_bytes()); // e_entry
+        elf[28..32].copy_from_slice(&52u32.to_le_bytes()); // e_phoff
+        elf[32..36].copy_from_slice(&(shoff as u32).to_le_bytes()); // e_shoff
+        elf[42..44].copy_from_slice(&32u16.to_le_bytes()); // e_phentsize
+        elf[44..46].copy_from_slice(&1u16.to_le_bytes()); // e_phnum
+        elf[46..48].copy_from_slice(&40u16.to_le_bytes()); // e_shentsize
+        elf[48..50].copy_from_slice(&3u16.to_le_bytes()); // e_shnum
+
+        // a segment with the code, followed by `tohost` at 0x80001000
+        let (offset, len) = (code_offset as u32, code.len() as u32);
+        for field in [1, offset, 0x80000000, 0x80000000, len, 0x1008, 7, 4] {
+            elf.extend(u32::to_le_bytes(field));
+        }
+        elf.extend(code);
+
+        // the null symbol and `tohost`
+        elf.extend([0; 16]);
+        for field in [1, 0x80001000, 8] {
+            elf.extend(u32::to_le_bytes(field));
+        }
+        elf.extend([0x11, 0, 1, 0]);
+        elf.extend(strtab);
+
+        // the null section, `.symtab`, and `.strtab`
+        elf.extend([0; 40]);
+        let (offset, len) = (symtab_offset as u32, 32);
+        for field in [0, SHT_SYMTAB, 0, 0, offset, len, 2, 1, 4, 16] {
+            elf.extend(u32::to_le_bytes(field));
+        }
+        let (offset, len) = (strtab_offset as u32, strtab.len() as u32);
+        for field in [0, 3, 0, 0, offset, len, 0, 0, 1, 0] {
+            elf.extend(u32::to_le_bytes(field));
+        }
+
+        elf
+    }
+
+    #[test]
+    fn harness() {
+        assert_eq!(symbol(&elf(1), "tohost"), Some(0x80001000));
+        assert_eq!(symbol(&elf(1), "fromhost"), None);
+
+        assert_eq!(run(&elf(1)), Ok(()));
+        assert_eq!(run(&elf(3 << 1 | 1)), Err(3));
+    }
+
+    #[test]
+    #[ignore = "needs the riscv-tests binaries in RISCV_TESTS_DIR"]
+    fn rv32ui() {
+        let dir = std::env::var("RISCV_TESTS_DIR").expect("RISCV_TESTS_DIR should be set");
+
+        let failed = RV32UI
+            .iter()
+            .filter_map(|test| {
+                let path = format!("{dir}/rv32ui-p-{test}");
+                let elf = std::fs::read(&path).unwrap_or_else(|e| panic!("{path}: {e}"));
+                run(&elf).err().map(|n| format!("{test} failed test {n}"))
+            })
+            .collect::<Vec<_>>();
+
+        assert!(failed.is_empty(), "{failed:#?}");
+    }
+}